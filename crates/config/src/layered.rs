@@ -0,0 +1,476 @@
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{self, Deserialize, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+use serde_json::{json, Map, Value};
+
+use super::{parse_file, ConfigFormat, InnerServerConfig, ServerConfig};
+
+/// Field of the config files holding their profiles, by name.
+const PROFILES: &str = "profiles";
+
+/// Separates the keys of nested fields in environment variables, eg. `TSN_POSTGRESQL__PORT`.
+const ENV_SEPARATOR: &str = "__";
+
+/// Builds a `ServerConfig` from layers, each overriding the fields set by the previous ones, so
+/// that none has to be complete. Starts with the defaults of a local development setup, without
+/// the credentials of PostgreSQL.
+///
+/// Config files may hold `profiles`, eg. `dev`, `staging` and `prod`, each setting the fields
+/// that differ from the rest of the file. The one selected by `profile` is applied over the rest,
+/// the others are ignored.
+#[derive(Clone, Debug)]
+pub struct LayeredConfig {
+    value: Value,
+    profile: Option<String>,
+}
+
+impl Default for LayeredConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LayeredConfig {
+    pub fn new() -> Self {
+        Self {
+            value: json!({
+                "listening_addr": "[::1]:50051",
+                "scylladb": {
+                    "hostnames": ["127.0.0.1:9042"],
+                    "keyspace": "my_social_network"
+                },
+                "postgresql": {
+                    "host": "127.0.0.1",
+                    "port": "5432",
+                    "database": "my_social_network_db",
+                    "ssl_strategy": "prefer"
+                },
+                "nats": {
+                    "host": "nats://127.0.0.1:4222"
+                }
+            }),
+            profile: None,
+        }
+    }
+
+    /// Applied by the files added next, which must all have it.
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.profile = Some(name.into());
+        self
+    }
+
+    /// In the format of its extension, JSON when it is unknown.
+    pub fn file(self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let format = ConfigFormat::from_path(&path).unwrap_or(ConfigFormat::Json);
+
+        self.file_as(path, format)
+    }
+
+    pub fn file_as(mut self, path: impl AsRef<Path>, format: ConfigFormat) -> Result<Self, Error> {
+        let mut layer: Value = parse_file(&path, format)?;
+        let mut profiles = layer
+            .as_object_mut()
+            .and_then(|layer| layer.remove(PROFILES));
+
+        merge(&mut self.value, layer);
+
+        if let Some(name) = &self.profile {
+            let profile = profiles
+                .as_mut()
+                .and_then(|profiles| profiles.get_mut(name.as_str()))
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::NotFound,
+                        format!("no profile `{name}` in {}", path.as_ref().display()),
+                    )
+                })?;
+
+            merge(&mut self.value, profile.take());
+        }
+
+        Ok(self)
+    }
+
+    /// The environment variables starting with `prefix`, eg. `TSN_LISTENING_ADDR` for
+    /// `listening_addr` with the `TSN_` prefix, or `TSN_POSTGRESQL__PASSWORD` for the
+    /// `password` of `postgresql`. Their values are read as by `set`.
+    pub fn env(mut self, prefix: &str) -> Result<Self, Error> {
+        for (name, value) in std::env::vars() {
+            if let Some(key) = name.strip_prefix(prefix) {
+                let key: Vec<String> = key
+                    .split(ENV_SEPARATOR)
+                    .map(str::to_ascii_lowercase)
+                    .collect();
+
+                self.set_path(&key, &value)?;
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Sets the field at `key`, dotted for the nested ones, eg. `postgresql.port`. `value` is
+    /// read as the type of the field once built: as is for strings, eg. `1234` for a password,
+    /// parsed as JSON for the others, eg. `["a", "b"]` for a list or `30` for a number. The
+    /// sections holding flattened fields, `telemetry`, only take strings.
+    pub fn set(mut self, key: &str, value: &str) -> Result<Self, Error> {
+        let key: Vec<String> = key.split('.').map(str::to_string).collect();
+
+        self.set_path(&key, value)?;
+        Ok(self)
+    }
+
+    fn set_path(&mut self, key: &[String], value: &str) -> Result<(), Error> {
+        let (last, parents) = key
+            .split_last()
+            .filter(|(last, _)| !last.is_empty())
+            .ok_or_else(|| invalid_key(key))?;
+
+        let mut object = &mut self.value;
+        for parent in parents {
+            object = object
+                .as_object_mut()
+                .ok_or_else(|| invalid_key(key))?
+                .entry(parent.as_str())
+                .or_insert_with(|| Value::Object(Map::new()));
+        }
+
+        let object = object.as_object_mut().ok_or_else(|| invalid_key(key))?;
+        object.insert(last.clone(), Value::String(value.to_string()));
+
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<ServerConfig, Error> {
+        let inner = InnerServerConfig::deserialize(Coerced(self.value))?;
+
+        Ok(ServerConfig {
+            inner: Arc::new(inner),
+        })
+    }
+}
+
+/// Deserializes the strings of `set` and `env` as the type asked for, the other values as they
+/// are. Flattened fields are buffered by serde as they are, without asking for a type.
+struct Coerced(Value);
+
+impl Coerced {
+    /// Of a string that is not one, as it was set, eg. `30` or `["a", "b"]`. Left as a string
+    /// when it can't be parsed, for the error to tell the type that was expected.
+    fn parsed(self) -> Self {
+        match self.0 {
+            Value::String(s) => Self(serde_json::from_str(&s).unwrap_or(Value::String(s))),
+            value => Self(value),
+        }
+    }
+}
+
+impl<'de> IntoDeserializer<'de, serde_json::Error> for Coerced {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! parsed_to_any {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.parsed().deserialize_any(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Coerced {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Array(values) => {
+                let mut values = SeqDeserializer::new(values.into_iter().map(Coerced));
+                let value = visitor.visit_seq(&mut values)?;
+                values.end()?;
+                Ok(value)
+            }
+            Value::Object(fields) => {
+                let mut fields =
+                    MapDeserializer::new(fields.into_iter().map(|(k, v)| (k, Coerced(v))));
+                let value = visitor.visit_map(&mut fields)?;
+                fields.end()?;
+                Ok(value)
+            }
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    parsed_to_any! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_seq deserialize_map
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            value => visitor.visit_some(Self(value)),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.parsed().deserialize_any(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.parsed().deserialize_any(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.parsed().deserialize_any(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct identifier ignored_any
+    }
+}
+
+fn invalid_key(key: &[String]) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("invalid config key `{}`", key.join(".")),
+    )
+}
+
+/// Objects are merged field by field, the other values of `layer` replace the ones of `base`.
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(base) => merge(base, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// A config file of the test `name`, removed once dropped.
+#[cfg(test)]
+struct TestFile(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestFile {
+    fn new(name: &str, value: Value) -> Self {
+        let path = std::env::temp_dir().join(format!("tsn-{}-{name}.json", std::process::id()));
+        std::fs::write(&path, value.to_string()).unwrap();
+
+        Self(path)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn layered_precedence_test() {
+    let file = TestFile::new(
+        "precedence",
+        json!({
+            "postgresql": { "username": "tsn", "password": "file", "database": "from_file" },
+            "nats": { "host": "nats://nats:4222" }
+        }),
+    );
+    std::env::set_var("TSN_PRECEDENCE_POSTGRESQL__PASSWORD", "env");
+    std::env::set_var("TSN_PRECEDENCE_NATS__HOST", "nats://env:4222");
+
+    let config = ServerConfig::layered()
+        .file(&file.0)
+        .unwrap()
+        .env("TSN_PRECEDENCE_")
+        .unwrap()
+        .set("nats.host", "nats://flag:4222")
+        .unwrap()
+        .build()
+        .unwrap();
+
+    // Defaults, unless a file sets them, unless the environment does, unless a flag does.
+    assert_eq!(config.scylladb.keyspace, "my_social_network");
+    assert_eq!(config.postgresql.database, "from_file");
+    assert_eq!(config.postgresql.password, "env");
+    assert_eq!(config.nats.host.0, "nats://flag:4222");
+}
+
+#[cfg(test)]
+#[test]
+fn layered_coercion_test() {
+    let file = TestFile::new(
+        "coercion",
+        json!({ "postgresql": { "username": "tsn", "password": "file" } }),
+    );
+    std::env::set_var("TSN_COERCION_POSTGRESQL__PASSWORD", "123456");
+    std::env::set_var("TSN_COERCION_POSTGRESQL__MAX_CONNECTIONS", "20");
+    let layered = || {
+        ServerConfig::layered()
+            .file(&file.0)
+            .unwrap()
+            .env("TSN_COERCION_")
+            .unwrap()
+    };
+
+    let config = layered()
+        .set("postgresql.port", "5433")
+        .unwrap()
+        .set("scylladb.hostnames", r#"["a:9042", "b:9042"]"#)
+        .unwrap()
+        .set("lazy_connections", "true")
+        .unwrap()
+        .set("moderation", r#"{"words": ["spam"]}"#)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    // Read as the type of the field: strings stay so, even when they look like numbers.
+    assert_eq!(config.postgresql.password, "123456");
+    assert_eq!(config.postgresql.max_connections, Some(20));
+    assert_eq!(config.postgresql.port, 5433);
+    assert_eq!(config.scylladb.hostnames.len(), 2);
+    assert!(config.lazy_connections);
+    assert_eq!(config.moderation.as_ref().unwrap().words, vec!["spam"]);
+
+    assert!(layered()
+        .set("postgresql.max_connections", "many")
+        .unwrap()
+        .build()
+        .is_err());
+    assert!(layered()
+        .set("lazy_connections", "yes")
+        .unwrap()
+        .build()
+        .is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn layered_set_key_test() {
+    assert!(ServerConfig::layered().set("", "x").is_err());
+    assert!(ServerConfig::layered().set("postgresql.", "x").is_err());
+    // Not an object.
+    assert!(ServerConfig::layered()
+        .set("listening_addr.port", "1")
+        .is_err());
+
+    let layered = ServerConfig::layered()
+        .set("archive.period_secs", "3")
+        .unwrap();
+    assert_eq!(layered.value["archive"]["period_secs"], "3");
+}
+
+#[cfg(test)]
+#[test]
+fn layered_profile_test() {
+    let file = TestFile::new(
+        "profiles",
+        json!({
+            "postgresql": { "username": "tsn", "password": "dev", "max_connections": 5 },
+            "profiles": {
+                "prod": {
+                    "postgresql": { "password": "prod", "host": "db.internal" },
+                    "lazy_connections": true
+                },
+                "staging": { "postgresql": { "password": "staging" } }
+            }
+        }),
+    );
+
+    // Over the rest of the file, the other profiles being ignored.
+    let config = ServerConfig::layered()
+        .profile("prod")
+        .file(&file.0)
+        .unwrap()
+        .build()
+        .unwrap();
+    assert_eq!(config.postgresql.password, "prod");
+    assert_eq!(config.postgresql.host.0, "db.internal");
+    assert_eq!(config.postgresql.max_connections, Some(5));
+    assert!(config.lazy_connections);
+
+    let config = ServerConfig::layered()
+        .file(&file.0)
+        .unwrap()
+        .build()
+        .unwrap();
+    assert_eq!(config.postgresql.password, "dev");
+    assert!(!config.lazy_connections);
+
+    // Overridden by the layers after the file.
+    let config = ServerConfig::layered()
+        .profile("staging")
+        .file(&file.0)
+        .unwrap()
+        .set("postgresql.password", "flag")
+        .unwrap()
+        .build()
+        .unwrap();
+    assert_eq!(config.postgresql.password, "flag");
+
+    let unknown = ServerConfig::layered()
+        .profile("qa")
+        .file(&file.0)
+        .unwrap_err();
+    assert_eq!(unknown.kind(), ErrorKind::NotFound);
+    assert!(unknown.to_string().contains("no profile `qa`"));
+
+    let without = TestFile::new(
+        "no-profiles",
+        json!({ "postgresql": { "username": "tsn" } }),
+    );
+    assert!(ServerConfig::layered()
+        .profile("prod")
+        .file(&without.0)
+        .is_err());
+}
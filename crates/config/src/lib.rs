@@ -1,8 +1,30 @@
-use std::{fs::File, net::SocketAddr, ops::Deref, path::Path, str::FromStr, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Error, ErrorKind, Read},
+    net::SocketAddr,
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    ops::Deref,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
-use async_nats::ConnectOptions as NatsConnectOptions;
+use async_nats::{ConnectOptions as NatsConnectOptions, Event as NatsEvent, ServerAddr};
+use scylla::load_balancing::DefaultPolicy;
+use scylla::statement::Consistency;
+use scylla::transport::session::PoolSize;
+use scylla::ExecutionProfile;
 use serde::{de, ser, Deserialize, Serialize};
-use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgSslMode};
+
+mod layered;
+mod reload;
+mod validate;
+
+pub use layered::LayeredConfig;
+pub use validate::TEMPLATE;
 
 fn deserialize_from_str<'de, T: FromStr, D>(deserializer: D) -> Result<T, D::Error>
 where
@@ -36,22 +58,178 @@ pub struct PgHost(String);
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NatsHost(String);
 
+/// Consistency of the requests to ScyllaDB, `local_quorum` by default. Local ones only wait for
+/// the nodes of the preferred datacenter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScyllaConsistency {
+    One,
+    Quorum,
+    All,
+    #[default]
+    LocalQuorum,
+    EachQuorum,
+    LocalOne,
+}
+
+impl From<ScyllaConsistency> for Consistency {
+    fn from(consistency: ScyllaConsistency) -> Self {
+        match consistency {
+            ScyllaConsistency::One => Consistency::One,
+            ScyllaConsistency::Quorum => Consistency::Quorum,
+            ScyllaConsistency::All => Consistency::All,
+            ScyllaConsistency::LocalQuorum => Consistency::LocalQuorum,
+            ScyllaConsistency::EachQuorum => Consistency::EachQuorum,
+            ScyllaConsistency::LocalOne => Consistency::LocalOne,
+        }
+    }
+}
+
+/// Replication of the keyspace created by `auto_create_keyspace`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScyllaReplicationStrategy {
+    /// For a single datacenter, eg. in development.
+    #[default]
+    Simple,
+    /// `replication_factor` replicas in each datacenter.
+    NetworkTopology,
+}
+
+/// Connects to ScyllaDB over TLS, verifying its certificate with `ca`, or the system ones when
+/// unset. `certificate` and `key` are the PEM encoded identity of the server, for clusters that
+/// require client certificates. Needs the server to be built with the `scylla-tls` feature.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScyllaTlsConfig {
+    #[serde(default)]
+    pub ca: Option<PathBuf>,
+    #[serde(default)]
+    pub certificate: Option<PathBuf>,
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+}
+
+/// `username` and `password` are only needed when authentication is enabled on the cluster.
+/// Each node is connected to `connections_per_shard` times, 1 by default. Requests go to the
+/// nodes of `datacenter` first when set, with `consistency`.
+///
+/// In new environments, `auto_create_keyspace` creates the keyspace and its tables when they
+/// are missing, with `replication_strategy` and `replication_factor`, 1 by default, 3 with
+/// `network_topology`.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ScyllaDbConfig {
     pub hostnames: Vec<ScyllaHost>,
     pub keyspace: String,
+    #[serde(default, skip_serializing)]
+    username: Option<String>,
+    #[serde(default, skip_serializing)]
+    password: Option<String>,
+    #[serde(default)]
+    pub tls: Option<ScyllaTlsConfig>,
+    #[serde(default)]
+    pub connections_per_shard: Option<NonZeroUsize>,
+    #[serde(default)]
+    pub connection_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub consistency: ScyllaConsistency,
+    #[serde(default)]
+    pub datacenter: Option<String>,
+    #[serde(default)]
+    pub auto_create_keyspace: bool,
+    #[serde(default)]
+    pub replication_strategy: ScyllaReplicationStrategy,
+    #[serde(default)]
+    pub replication_factor: Option<NonZeroU32>,
 }
 
 impl ScyllaDbConfig {
-    pub fn into_session_builder(&self) -> scylla::SessionBuilder {
+    /// Of the keyspace, as a CQL map.
+    pub fn replication(&self) -> String {
+        let (class, default_factor) = match self.replication_strategy {
+            ScyllaReplicationStrategy::Simple => ("SimpleStrategy", 1),
+            ScyllaReplicationStrategy::NetworkTopology => ("NetworkTopologyStrategy", 3),
+        };
+        let factor = self.replication_factor.map_or(default_factor, NonZeroU32::get);
+
+        format!("{{'class': '{class}', 'replication_factor': {factor}}}")
+    }
+
+    /// Without a keyspace when it is to be created, `repository::schema` sets it once it is.
+    pub fn into_session_builder(&self) -> Result<scylla::SessionBuilder, Error> {
         let known_nodes: Vec<&String> = self.hostnames.iter().map(|n| &n.0).collect();
 
-        scylla::SessionBuilder::new()
+        let mut load_balancing = DefaultPolicy::builder();
+        if let Some(datacenter) = &self.datacenter {
+            load_balancing = load_balancing.prefer_datacenter(datacenter.clone());
+        }
+        let profile = ExecutionProfile::builder()
+            .consistency(self.consistency.into())
+            .load_balancing_policy(load_balancing.build())
+            .build();
+
+        let mut builder = scylla::SessionBuilder::new()
             .known_nodes(known_nodes.as_slice())
-            .use_keyspace(&self.keyspace, false)
+            .default_execution_profile_handle(profile.into_handle());
+
+        if !self.auto_create_keyspace {
+            builder = builder.use_keyspace(&self.keyspace, false);
+        }
+
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            builder = builder.user(username, password);
+        }
+        if let Some(connections) = self.connections_per_shard {
+            builder = builder.pool_size(PoolSize::PerShard(connections));
+        }
+        if let Some(timeout_ms) = self.connection_timeout_ms {
+            builder = builder.connection_timeout(Duration::from_millis(timeout_ms));
+        }
+        if let Some(tls) = &self.tls {
+            builder = with_scylla_tls(builder, tls)?;
+        }
+
+        Ok(builder)
     }
 }
 
+#[cfg(feature = "scylla-tls")]
+fn with_scylla_tls(
+    builder: scylla::SessionBuilder,
+    tls: &ScyllaTlsConfig,
+) -> Result<scylla::SessionBuilder, Error> {
+    use openssl::ssl::{SslContextBuilder, SslFiletype, SslMethod, SslVerifyMode};
+
+    let tls_error = |e| Error::new(ErrorKind::InvalidData, e);
+
+    let mut context = SslContextBuilder::new(SslMethod::tls()).map_err(tls_error)?;
+    context.set_verify(SslVerifyMode::PEER);
+    match &tls.ca {
+        Some(ca) => context.set_ca_file(ca).map_err(tls_error)?,
+        None => context.set_default_verify_paths().map_err(tls_error)?,
+    }
+    if let (Some(certificate), Some(key)) = (&tls.certificate, &tls.key) {
+        context
+            .set_certificate_chain_file(certificate)
+            .map_err(tls_error)?;
+        context
+            .set_private_key_file(key, SslFiletype::PEM)
+            .map_err(tls_error)?;
+    }
+
+    Ok(builder.ssl_context(Some(context.build())))
+}
+
+#[cfg(not(feature = "scylla-tls"))]
+fn with_scylla_tls(
+    _builder: scylla::SessionBuilder,
+    _tls: &ScyllaTlsConfig,
+) -> Result<scylla::SessionBuilder, Error> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "TLS to ScyllaDB is configured but the server was built without the `scylla-tls` feature",
+    ))
+}
+
 // FIXME: Find a better way to store user/password
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PostgreSqlConfig {
@@ -68,55 +246,467 @@ pub struct PostgreSqlConfig {
         serialize_with = "serialize_pg_ssl_mode"
     )]
     pub ssl_strategy: PgSslMode,
+    /// The pool keeps between `min_connections` and `max_connections` connections, 0 and 10
+    /// by default.
+    #[serde(default)]
+    pub max_connections: Option<u32>,
+    #[serde(default)]
+    pub min_connections: Option<u32>,
+    /// How long a request waits for a connection of the pool, 30s by default.
+    #[serde(default)]
+    pub acquire_timeout_ms: Option<u64>,
+    /// Connections idle for longer are closed, above `min_connections`. 10min by default.
+    #[serde(default)]
+    pub idle_timeout_secs: Option<u64>,
+    /// Statements running for longer are cancelled by PostgreSQL. None by default.
+    #[serde(default)]
+    pub statement_timeout_ms: Option<u64>,
 }
 
 impl PostgreSqlConfig {
     pub fn into_connect_options(&self) -> PgConnectOptions {
-        PgConnectOptions::new()
+        let options = PgConnectOptions::new()
             .host(self.host.0.as_str())
             .port(self.port)
             .username(&self.username)
             .password(&self.password)
             .database(&self.database)
-            .ssl_mode(self.ssl_strategy)
+            .ssl_mode(self.ssl_strategy);
+
+        match self.statement_timeout_ms {
+            Some(timeout_ms) => options.options([("statement_timeout", timeout_ms.to_string())]),
+            None => options,
+        }
+    }
+
+    /// The fields that are not set keep the defaults of sqlx.
+    pub fn into_pool_options(&self) -> PgPoolOptions {
+        let mut options = PgPoolOptions::new();
+
+        if let Some(max_connections) = self.max_connections {
+            options = options.max_connections(max_connections);
+        }
+        if let Some(min_connections) = self.min_connections {
+            options = options.min_connections(min_connections);
+        }
+        if let Some(timeout_ms) = self.acquire_timeout_ms {
+            options = options.acquire_timeout(Duration::from_millis(timeout_ms));
+        }
+        if let Some(timeout_secs) = self.idle_timeout_secs {
+            options = options.idle_timeout(Duration::from_secs(timeout_secs));
+        }
+
+        options
     }
 }
 
+/// `host` and the other `hosts` of the cluster are connected to in a random order, or in this
+/// one when `retain_order` is set, and reconnected to when the server is lost. Servers discovered
+/// from the cluster are tried too. Meanwhile, up to `client_capacity` commands are buffered,
+/// 128 by default. A ping is sent every `ping_interval_secs`, 60 by default, to detect that the
+/// server is lost. Reconnections are attempted without limit, async-nats 0.29 doesn't allow
+/// setting one.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NatsConfig {
     pub host: NatsHost,
+    #[serde(default)]
+    pub hosts: Vec<NatsHost>,
+    #[serde(default)]
+    pub retain_order: bool,
+    #[serde(default)]
+    pub connection_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub ping_interval_secs: Option<u64>,
+    #[serde(default)]
+    pub client_capacity: Option<usize>,
 }
 
 impl NatsConfig {
+    /// Connection events are logged, so that failovers can be followed.
     pub fn into_connect_options(&self) -> NatsConnectOptionsWrapper {
-        let options =
-            NatsConnectOptions::new().connection_timeout(std::time::Duration::from_secs(3));
-        NatsConnectOptionsWrapper {
-            host: self.host.clone(),
-            options,
+        let mut options = NatsConnectOptions::new()
+            .connection_timeout(Duration::from_secs(
+                self.connection_timeout_secs.unwrap_or(3),
+            ))
+            .event_callback(|event| async move { log_nats_event(&event) });
+
+        if self.retain_order {
+            options = options.retain_servers_order();
+        }
+        if let Some(ping_interval_secs) = self.ping_interval_secs {
+            options = options.ping_interval(Duration::from_secs(ping_interval_secs));
         }
+        if let Some(client_capacity) = self.client_capacity {
+            options = options.client_capacity(client_capacity);
+        }
+
+        let mut hosts = vec![self.host.clone()];
+        hosts.extend(self.hosts.iter().cloned());
+
+        NatsConnectOptionsWrapper { hosts, options }
+    }
+}
+
+fn log_nats_event(event: &NatsEvent) {
+    match event {
+        NatsEvent::Connected => tracing::info!("Connected to NATS"),
+        event => tracing::warn!(%event, "NATS connection event"),
     }
 }
 
 /// The way `async_nats::ConnectOptions` is implemented is not compatible with creating
 /// the connect options apart than connection.
 pub struct NatsConnectOptionsWrapper {
-    pub host: NatsHost,
+    pub hosts: Vec<NatsHost>,
     pub options: NatsConnectOptions,
 }
 
 impl NatsConnectOptionsWrapper {
+    /// `on_event` is called with the connection events too, once logged.
+    pub fn on_event(self, on_event: impl Fn(&NatsEvent) + Send + Sync + 'static) -> Self {
+        let on_event = Arc::new(on_event);
+        let options = self.options.event_callback(move |event| {
+            let on_event = on_event.clone();
+
+            async move {
+                log_nats_event(&event);
+                on_event(&event);
+            }
+        });
+
+        Self { options, ..self }
+    }
+
+    /// `connect` returns at once, the client connects in the background until a server can be
+    /// reached.
+    pub fn retry_on_initial_connect(self) -> Self {
+        Self {
+            options: self.options.retry_on_initial_connect(),
+            ..self
+        }
+    }
+
     pub async fn connect(self) -> Result<async_nats::Client, async_nats::ConnectError> {
-        self.options.connect(self.host.0).await
+        let addrs = self
+            .hosts
+            .iter()
+            .map(|host| host.0.parse())
+            .collect::<Result<Vec<ServerAddr>, _>>()?;
+
+        self.options.connect(addrs).await
+    }
+}
+
+/// Archival of old time buckets. Buckets older than `retention_days` are exported to `directory`
+/// and removed from ScyllaDB every `period_secs`, which can't be 0.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArchiveConfig {
+    pub retention_days: u32,
+    pub period_secs: NonZeroU64,
+    pub directory: PathBuf,
+}
+
+/// Attachments are stored in `directory`, each at most `max_bytes` long and of one of
+/// `content_types`, eg. `image/*`, or of any type when empty. They are served from `base_url`
+/// when set.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AttachmentConfig {
+    pub directory: PathBuf,
+    pub max_bytes: u64,
+    #[serde(default)]
+    pub content_types: Vec<String>,
+    #[serde(default)]
+    pub base_url: Option<String>,
+}
+
+/// Posting rate limit per user: `burst` messages at once, then `per_minute` messages per minute.
+/// Limits are kept in memory unless `nats_kv_bucket` names a NATS key-value bucket shared by
+/// every server.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub burst: u32,
+    pub per_minute: u32,
+    #[serde(default)]
+    pub nats_kv_bucket: Option<String>,
+}
+
+/// Messages containing one of `words` or matching one of the regex `patterns` are rejected.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ModerationConfig {
+    #[serde(default)]
+    pub words: Vec<String>,
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+/// Applied to database requests. Each attempt times out after `timeout_ms`, transient errors are
+/// retried `retries` times, waiting `backoff_ms` then twice as long each time, except for writes
+/// that can't be applied twice. After
+/// `failure_threshold` consecutive failures, requests fail right away for `open_secs`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PolicyConfig {
+    pub timeout_ms: u64,
+    pub retries: u32,
+    pub backoff_ms: u64,
+    pub failure_threshold: u32,
+    pub open_secs: u64,
+}
+
+/// Requests must carry a token issued by the `Login` RPC, signed with `secret` and valid for
+/// `token_ttl_secs`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuthConfig {
+    #[serde(skip_serializing)]
+    secret: String,
+    pub token_ttl_secs: u64,
+}
+
+impl AuthConfig {
+    pub fn secret(&self) -> &str {
+        self.secret.as_str()
+    }
+}
+
+/// Serves over TLS with the PEM encoded `certificate` and `key`. When `client_ca` is set, clients
+/// must present a certificate signed by it: `require_client_cert` makes it explicit, it can't be
+/// optional with tonic 0.8. Needs the server to be built with the `tls` feature.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+    #[serde(default)]
+    pub require_client_cert: Option<bool>,
+}
+
+/// Serves gRPC-web too, so that browsers can call the server. `allowed_origins` are the CORS
+/// origins, any origin when empty. Preflight responses are cached by browsers for `max_age_secs`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GrpcWebConfig {
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+}
+
+/// HTTP/2 settings of the transport, the defaults of tonic when unset. A keepalive ping is sent
+/// every `keepalive_interval_secs` so that intermediaries don't drop idle streams, and connections
+/// are closed when it isn't acknowledged within `keepalive_timeout_secs`. Requests whose message is
+/// longer than `max_message_bytes` are rejected, client streams excepted.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TransportConfig {
+    #[serde(default)]
+    pub keepalive_interval_secs: Option<u64>,
+    #[serde(default)]
+    pub keepalive_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub max_concurrent_streams: Option<u32>,
+    #[serde(default)]
+    pub initial_stream_window_size: Option<u32>,
+    #[serde(default)]
+    pub initial_connection_window_size: Option<u32>,
+    #[serde(default)]
+    pub max_message_bytes: Option<usize>,
+}
+
+/// Accepts gzip compressed requests, and compresses with gzip the responses of `methods`, every
+/// method when empty, for clients accepting it. Methods are named as in the proto file.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CompressionConfig {
+    #[serde(default)]
+    pub methods: Vec<String>,
+}
+
+/// Timeouts of the RPCs in milliseconds, by method name as in the proto file, `default_ms` for the
+/// others when set. Once reached, clients get `DEADLINE_EXCEEDED` and their streams end. Shorter
+/// deadlines set by clients are kept. Streaming methods, such as `RealTimeNotifications`, `Chat`
+/// or `Timeline`, only get the timeout of their name, never `default_ms`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TimeoutConfig {
+    #[serde(default)]
+    pub default_ms: Option<u64>,
+    #[serde(default)]
+    pub per_method_ms: HashMap<String, u64>,
+}
+
+/// Limits of the requests in flight, streams included until they end. Over `max_in_flight`
+/// requests in total, or over the limit of their method in `per_method`, requests are rejected
+/// with `UNAVAILABLE`. Methods are named as in the proto file, such as `RealTimeNotifications`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ConcurrencyConfig {
+    #[serde(default)]
+    pub max_in_flight: Option<usize>,
+    #[serde(default)]
+    pub per_method: HashMap<String, usize>,
+}
+
+/// Contents of posted messages are normalized, then must be at most `max_chars` characters long,
+/// 500 by default. Control characters are removed when `strip_control_chars` is set.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ContentConfig {
+    #[serde(default)]
+    pub max_chars: Option<usize>,
+    #[serde(default)]
+    pub strip_control_chars: bool,
+}
+
+/// Served when listed in `services`. When empty, a server serves the service of its binary:
+/// `social_network` for `server` and `notifier` for `notifier`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceKind {
+    /// Every RPC.
+    SocialNetwork,
+    /// Only the notification streams.
+    Notifier,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the current spans.
+    Json,
+}
+
+/// `level` is a filter directive such as `info` or `tsn=debug,sqlx=warn`, overridden by the
+/// `RUST_LOG` environment variable when set.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogConfig {
+    #[serde(default = "LogConfig::default_level")]
+    pub level: String,
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+impl LogConfig {
+    fn default_level() -> String {
+        "info".to_string()
     }
 }
 
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: Self::default_level(),
+            format: LogFormat::default(),
+        }
+    }
+}
+
+/// Logs, traces and metrics. The log settings are the ones of `log` when the section is unset.
+/// Spans are exported to the OTLP/gRPC collector at `otlp_endpoint`, eg. `http://localhost:4317`,
+/// when the server is built with the `otlp` feature. `sampling_ratio` of the traces are kept, all
+/// of them by default. The counters of the RPCs are served to Prometheus on `/metrics` at
+/// `metrics_addr`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    #[serde(flatten)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    #[serde(default)]
+    pub sampling_ratio: Option<f64>,
+    #[serde(default)]
+    pub metrics_addr: Option<SocketAddr>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InnerServerConfig {
     pub listening_addr: SocketAddr,
     pub scylladb: ScyllaDbConfig,
     pub postgresql: PostgreSqlConfig,
     pub nats: NatsConfig,
+    #[serde(default)]
+    pub archive: Option<ArchiveConfig>,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    #[serde(default)]
+    pub moderation: Option<ModerationConfig>,
+    #[serde(default)]
+    pub policy: Option<PolicyConfig>,
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Kept for the configs written before `telemetry`, which overrides it.
+    #[serde(default)]
+    pub log: Option<LogConfig>,
+    #[serde(default)]
+    pub telemetry: Option<TelemetryConfig>,
+    #[serde(default)]
+    pub grpc_web: Option<GrpcWebConfig>,
+    #[serde(default)]
+    pub concurrency: Option<ConcurrencyConfig>,
+    #[serde(default)]
+    pub content: Option<ContentConfig>,
+    #[serde(default)]
+    pub transport: Option<TransportConfig>,
+    #[serde(default)]
+    pub compression: Option<CompressionConfig>,
+    #[serde(default)]
+    pub services: Vec<ServiceKind>,
+    /// Starts before the databases and NATS can be reached, connected to in the background.
+    #[serde(default)]
+    pub lazy_connections: bool,
+    #[serde(default)]
+    pub timeouts: Option<TimeoutConfig>,
+    #[serde(default)]
+    pub attachments: Option<AttachmentConfig>,
+}
+
+impl InnerServerConfig {
+    pub fn telemetry(&self) -> TelemetryConfig {
+        match &self.telemetry {
+            Some(telemetry) => telemetry.clone(),
+            None => TelemetryConfig {
+                log: self.log.clone().unwrap_or_default(),
+                ..TelemetryConfig::default()
+            },
+        }
+    }
+}
+
+/// Formats of the configuration files. TOML and YAML allow comments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// By extension: `.toml`, `.yaml` or `.yml`, `.json`. `None` for the others.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+
+        match extension.to_ascii_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            _ => Err(format!(
+                "unknown config format `{s}`, expected json, toml or yaml"
+            )),
+        }
+    }
 }
 
 /// Can be shared between threads by using `Clone`. uses an `Arc` internally so cloning is cheap
@@ -135,10 +725,38 @@ impl Deref for ServerConfig {
 }
 
 impl ServerConfig {
-    pub fn load_from_file(path: impl AsRef<Path>) -> Result<Self, std::io::Error> {
-        let file_content = File::open(path)?;
+    /// In the format of its extension, JSON when it is unknown.
+    pub fn load_from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let format = ConfigFormat::from_path(&path).unwrap_or(ConfigFormat::Json);
 
-        let config = serde_json::from_reader(file_content)?;
-        Ok(config)
+        Self::load_from_file_as(path, format)
     }
+
+    pub fn load_from_file_as(path: impl AsRef<Path>, format: ConfigFormat) -> Result<Self, Error> {
+        parse_file(path, format)
+    }
+
+    /// Built-in defaults, overridden by the layers added to the returned builder, eg. a file,
+    /// then environment variables, then flags.
+    pub fn layered() -> LayeredConfig {
+        LayeredConfig::new()
+    }
+}
+
+fn parse_file<T: de::DeserializeOwned>(
+    path: impl AsRef<Path>,
+    format: ConfigFormat,
+) -> Result<T, Error> {
+    let mut file_content = String::new();
+    File::open(path)?.read_to_string(&mut file_content)?;
+
+    let parsed = match format {
+        ConfigFormat::Json => serde_json::from_str(&file_content)?,
+        ConfigFormat::Toml => {
+            toml::from_str(&file_content).map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+        }
+        ConfigFormat::Yaml => serde_yaml::from_str(&file_content)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?,
+    };
+    Ok(parsed)
 }
@@ -0,0 +1,71 @@
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::watch;
+
+use super::ServerConfig;
+
+/// How often the config file is checked for changes.
+const POLL_PERIOD: Duration = Duration::from_secs(2);
+
+impl ServerConfig {
+    /// Loaded from `path`, then again each time the file changes.
+    pub fn watch(path: impl Into<PathBuf>) -> Result<watch::Receiver<ServerConfig>, Error> {
+        let path = path.into();
+        let config = Self::load_from_file(&path)?;
+
+        Ok(Self::watch_with(path.clone(), config, move || {
+            Self::load_from_file(&path)
+        }))
+    }
+
+    /// `config`, then the one of `load` each time the file at `path` changes, eg. to apply the
+    /// same overrides again. When it fails, the error is logged and the previous config is kept.
+    /// The file is polled rather than notified of: notifications are lost when the file is a
+    /// symbolic link replaced by another, as mounted ConfigMaps are. Stops once the receivers
+    /// are dropped.
+    pub fn watch_with(
+        path: impl Into<PathBuf>,
+        config: ServerConfig,
+        load: impl Fn() -> Result<ServerConfig, Error> + Send + 'static,
+    ) -> watch::Receiver<ServerConfig> {
+        let path = path.into();
+        let (sender, receiver) = watch::channel(config);
+
+        tokio::spawn(async move {
+            let mut version = file_version(&path);
+            let mut interval = tokio::time::interval(POLL_PERIOD);
+
+            while !sender.is_closed() {
+                interval.tick().await;
+
+                let current = file_version(&path);
+                if current == version {
+                    continue;
+                }
+                version = current;
+
+                match load() {
+                    Ok(config) => {
+                        tracing::info!(path = %path.display(), "Configuration reloaded");
+                        sender.send_replace(config);
+                    }
+                    Err(e) => {
+                        tracing::warn!(path = %path.display(), error = %e, "Configuration not reloaded")
+                    }
+                }
+            }
+        });
+
+        receiver
+    }
+}
+
+/// Changes when the file is written, or replaced through a symbolic link.
+fn file_version(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+
+    Some((metadata.modified().ok()?, metadata.len()))
+}
@@ -0,0 +1,257 @@
+//! What deserialization can't tell about a config, so that it can be checked before a deploy.
+
+use std::path::Path;
+
+use super::{ServerConfig, TlsConfig};
+
+/// A config with every field and what it does, the optional ones commented out. In TOML, which
+/// allows comments.
+pub const TEMPLATE: &str = include_str!("../template.toml");
+
+impl TlsConfig {
+    /// Fails on the client authentication tonic can't do: without a CA, or optional.
+    pub fn check_client_auth(&self) -> Result<(), &'static str> {
+        match (&self.client_ca, self.require_client_cert) {
+            (None, Some(true)) => Err("`require_client_cert` is set but there is no `client_ca`"),
+            (Some(_), Some(false)) => {
+                Err("client certificates can't be optional with a `client_ca`")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// What would make the servers fail or misbehave, empty when all is well. Files are only
+    /// checked to exist.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.scylladb.hostnames.is_empty() {
+            problems.push("`scylladb.hostnames` is empty".to_string());
+        }
+        if !is_cql_identifier(&self.scylladb.keyspace) {
+            problems.push(format!(
+                "`scylladb.keyspace` ({}) must be at most 48 letters, digits or underscores",
+                self.scylladb.keyspace
+            ));
+        }
+        if let Some(tls) = &self.scylladb.tls {
+            check_file(&mut problems, "scylladb.tls.ca", tls.ca.as_deref());
+            check_file(
+                &mut problems,
+                "scylladb.tls.certificate",
+                tls.certificate.as_deref(),
+            );
+            check_file(&mut problems, "scylladb.tls.key", tls.key.as_deref());
+            if tls.certificate.is_some() != tls.key.is_some() {
+                problems.push("`scylladb.tls` needs both a `certificate` and a `key`".to_string());
+            }
+        }
+
+        let postgresql = &self.postgresql;
+        if postgresql.max_connections == Some(0) {
+            problems.push("`postgresql.max_connections` must be at least 1".to_string());
+        }
+        if let (Some(min), Some(max)) = (postgresql.min_connections, postgresql.max_connections) {
+            if min > max {
+                problems.push(format!(
+                    "`postgresql.min_connections` ({min}) is over `max_connections` ({max})"
+                ));
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            if let Err(e) = tls.check_client_auth() {
+                problems.push(format!("`tls`: {e}"));
+            }
+            check_file(&mut problems, "tls.certificate", Some(&tls.certificate));
+            check_file(&mut problems, "tls.key", Some(&tls.key));
+            check_file(&mut problems, "tls.client_ca", tls.client_ca.as_deref());
+        }
+
+        if self.log.is_some() && self.telemetry.is_some() {
+            problems.push("`log` is ignored, the log settings of `telemetry` are used".to_string());
+        }
+        if let Some(ratio) = self.telemetry().sampling_ratio {
+            if !(0.0..=1.0).contains(&ratio) {
+                problems.push(format!(
+                    "`telemetry.sampling_ratio` ({ratio}) must be between 0 and 1"
+                ));
+            }
+        }
+
+        if let Some(rate_limit) = &self.rate_limit {
+            if rate_limit.burst == 0 || rate_limit.per_minute == 0 {
+                problems.push(
+                    "`rate_limit` with a zero `burst` or `per_minute` rejects every message"
+                        .to_string(),
+                );
+            }
+        }
+        if let Some(content) = &self.content {
+            if content.max_chars == Some(0) {
+                problems.push("`content.max_chars` of 0 rejects every message".to_string());
+            }
+        }
+        if let Some(concurrency) = &self.concurrency {
+            if concurrency.max_in_flight == Some(0) {
+                problems.push("`concurrency.max_in_flight` of 0 rejects every request".to_string());
+            }
+            for (method, limit) in &concurrency.per_method {
+                if *limit == 0 {
+                    problems.push(format!(
+                        "`concurrency.per_method.{method}` of 0 rejects every request"
+                    ));
+                }
+            }
+        }
+
+        problems
+    }
+}
+
+/// Names of keyspaces that need no quoting.
+fn is_cql_identifier(name: &str) -> bool {
+    (1..=48).contains(&name.len())
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn check_file(problems: &mut Vec<String>, key: &str, path: Option<&Path>) {
+    if let Some(path) = path {
+        if !path.is_file() {
+            problems.push(format!("`{key}`: no file at {}", path.display()));
+        }
+    }
+}
+
+/// The problems of the template, once `edit`ed.
+#[cfg(test)]
+fn template_problems(edit: impl FnOnce(&mut serde_json::Value)) -> Vec<String> {
+    let mut value: serde_json::Value = toml::from_str(TEMPLATE).unwrap();
+    edit(&mut value);
+
+    serde_json::from_value::<ServerConfig>(value)
+        .unwrap()
+        .problems()
+}
+
+#[cfg(test)]
+#[test]
+fn template_test() {
+    let config: ServerConfig = toml::from_str(TEMPLATE).unwrap();
+
+    assert_eq!(config.problems(), Vec::<String>::new());
+    assert_eq!(config.postgresql.port, 5432);
+}
+
+#[cfg(test)]
+#[test]
+fn problems_test() {
+    use serde_json::json;
+
+    let file = concat!(env!("CARGO_MANIFEST_DIR"), "/template.toml");
+    let missing = concat!(env!("CARGO_MANIFEST_DIR"), "/missing.pem");
+    fn rejected(edit: impl FnOnce(&mut serde_json::Value), expected: &str) {
+        let problems = template_problems(edit);
+        assert!(
+            problems.len() == 1 && problems[0].contains(expected),
+            "expected `{expected}`, got {problems:?}"
+        );
+    }
+
+    rejected(
+        |c| c["scylladb"]["hostnames"] = json!([]),
+        "`scylladb.hostnames`",
+    );
+    rejected(
+        |c| c["scylladb"]["keyspace"] = json!("my-keyspace"),
+        "`scylladb.keyspace`",
+    );
+    rejected(
+        |c| c["scylladb"]["keyspace"] = json!("k".repeat(49)),
+        "`scylladb.keyspace`",
+    );
+    rejected(
+        |c| c["scylladb"]["keyspace"] = json!("1st"),
+        "`scylladb.keyspace`",
+    );
+    rejected(
+        |c| c["scylladb"]["tls"] = json!({ "ca": missing }),
+        "`scylladb.tls.ca`",
+    );
+    rejected(
+        |c| c["scylladb"]["tls"] = json!({ "certificate": file }),
+        "both a `certificate` and a `key`",
+    );
+    rejected(
+        |c| c["postgresql"]["max_connections"] = json!(0),
+        "`postgresql.max_connections`",
+    );
+    rejected(
+        |c| {
+            c["postgresql"]["min_connections"] = json!(5);
+            c["postgresql"]["max_connections"] = json!(2);
+        },
+        "`postgresql.min_connections` (5)",
+    );
+    rejected(
+        |c| c["tls"] = json!({ "certificate": file, "key": file, "require_client_cert": true }),
+        "there is no `client_ca`",
+    );
+    rejected(
+        |c| {
+            c["tls"] = json!({
+                "certificate": file,
+                "key": file,
+                "client_ca": file,
+                "require_client_cert": false
+            })
+        },
+        "can't be optional",
+    );
+    rejected(
+        |c| c["tls"] = json!({ "certificate": file, "key": missing }),
+        "`tls.key`: no file",
+    );
+    rejected(
+        |c| {
+            c["log"] = json!({ "level": "debug" });
+            c["telemetry"] = json!({ "level": "info" });
+        },
+        "`log` is ignored",
+    );
+    rejected(
+        |c| c["telemetry"] = json!({ "sampling_ratio": 1.5 }),
+        "`telemetry.sampling_ratio`",
+    );
+    rejected(
+        |c| c["rate_limit"] = json!({ "burst": 0, "per_minute": 30 }),
+        "`rate_limit`",
+    );
+    rejected(
+        |c| c["content"] = json!({ "max_chars": 0 }),
+        "`content.max_chars`",
+    );
+    rejected(
+        |c| c["concurrency"] = json!({ "max_in_flight": 0 }),
+        "`concurrency.max_in_flight`",
+    );
+    rejected(
+        |c| c["concurrency"] = json!({ "per_method": { "Timeline": 0 } }),
+        "`concurrency.per_method.Timeline`",
+    );
+
+    // Rejected once parsed.
+    let mut value: serde_json::Value = toml::from_str(TEMPLATE).unwrap();
+    value["archive"] = json!({ "retention_days": 30, "period_secs": 0, "directory": "/tmp" });
+    assert!(serde_json::from_value::<ServerConfig>(value).is_err());
+
+    // Accepted when set right.
+    let problems = template_problems(|c| {
+        c["tls"] = json!({ "certificate": file, "key": file, "client_ca": file });
+        c["telemetry"] = json!({ "sampling_ratio": 0.1 });
+    });
+    assert_eq!(problems, Vec::<String>::new());
+}
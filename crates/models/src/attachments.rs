@@ -0,0 +1,191 @@
+use std::{fmt::Display, str::FromStr};
+
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::messages::MessageId;
+use crate::users::{UserId, Userlike};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct AttachmentId(Uuid);
+
+#[derive(Error, Debug)]
+#[error(transparent)]
+pub struct AttachmentIdParsingError(#[from] uuid::Error);
+
+impl Display for AttachmentId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for AttachmentId {
+    type Err = AttachmentIdParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Uuid::from_str(s)?))
+    }
+}
+
+impl From<Uuid> for AttachmentId {
+    fn from(value: Uuid) -> Self {
+        Self(value)
+    }
+}
+
+impl From<AttachmentId> for Uuid {
+    fn from(value: AttachmentId) -> Self {
+        value.0
+    }
+}
+
+impl AttachmentId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    pub fn try_parse(s: impl AsRef<str>) -> Result<Self, AttachmentIdParsingError> {
+        s.as_ref().parse()
+    }
+}
+
+impl Default for AttachmentId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A file uploaded by `user_id`, to be attached to one of their messages, `message_id` once it
+/// is. `size` is in bytes and `checksum` is the hex SHA-256 of the data, both set once uploaded.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Attachment {
+    pub id: AttachmentId,
+    pub user_id: UserId,
+    pub message_id: Option<MessageId>,
+    pub filename: String,
+    pub content_type: String,
+    pub size: u64,
+    pub checksum: String,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AttachmentError {
+    #[error("content type `{0}` is not allowed")]
+    ContentType(String),
+    #[error("attachments are limited to {max} bytes")]
+    TooLarge { max: u64 },
+}
+
+/// Which attachments can be uploaded: at most `max_bytes` long, of one of `content_types`, or
+/// of any type when empty. A type such as `image/*` allows all of its subtypes.
+#[derive(Clone, Debug)]
+pub struct AttachmentPolicy {
+    pub max_bytes: u64,
+    pub content_types: Vec<String>,
+}
+
+impl AttachmentPolicy {
+    pub fn new(max_bytes: u64, content_types: Vec<String>) -> Self {
+        Self {
+            max_bytes,
+            content_types,
+        }
+    }
+
+    /// Parameters such as `; charset=utf-8` are ignored, and types are case-insensitive.
+    pub fn check_content_type(&self, content_type: &str) -> Result<(), AttachmentError> {
+        if self.content_types.is_empty() {
+            return Ok(());
+        }
+
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let allowed = self.content_types.iter().any(|allowed| {
+            let allowed = allowed.to_ascii_lowercase();
+
+            match allowed.strip_suffix("/*") {
+                Some(kind) => essence.split_once('/').is_some_and(|(k, _)| k == kind),
+                None => allowed == essence,
+            }
+        });
+
+        match allowed {
+            true => Ok(()),
+            false => Err(AttachmentError::ContentType(content_type.to_string())),
+        }
+    }
+
+    pub fn check_size(&self, size: u64) -> Result<(), AttachmentError> {
+        match size > self.max_bytes {
+            true => Err(AttachmentError::TooLarge {
+                max: self.max_bytes,
+            }),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Attachment {
+    /// Size of the `filename` column.
+    pub const MAX_FILENAME_CHARS: usize = 255;
+    /// Size of the `content_type` column.
+    pub const MAX_CONTENT_TYPE_CHARS: usize = 127;
+
+    /// Empty until its data is uploaded.
+    pub fn new(user: impl Userlike, filename: String, content_type: String) -> Self {
+        Self {
+            id: AttachmentId::new(),
+            user_id: user.get_id(),
+            message_id: None,
+            filename,
+            content_type,
+            size: 0,
+            checksum: String::new(),
+        }
+    }
+
+    /// Where it is served from, by the server at `base_url`.
+    pub fn url(&self, base_url: &str) -> String {
+        format!("{}/{}", base_url.trim_end_matches('/'), self.id)
+    }
+}
+
+#[test]
+fn attachment_id_round_trip() {
+    let id = AttachmentId::new();
+
+    assert_eq!(AttachmentId::try_parse(id.to_string()).unwrap(), id);
+    assert!(AttachmentId::try_parse("not-an-id").is_err());
+}
+
+#[test]
+fn attachment_policy_test() {
+    let policy = AttachmentPolicy::new(
+        1024,
+        vec!["image/*".to_string(), "application/pdf".to_string()],
+    );
+
+    assert!(policy.check_content_type("image/png").is_ok());
+    assert!(policy
+        .check_content_type("Application/PDF; name=a.pdf")
+        .is_ok());
+    assert_eq!(
+        policy.check_content_type("imagery/png"),
+        Err(AttachmentError::ContentType("imagery/png".to_string()))
+    );
+    assert!(policy.check_content_type("text/plain").is_err());
+    assert!(AttachmentPolicy::new(1024, vec![])
+        .check_content_type("text/plain")
+        .is_ok());
+
+    assert!(policy.check_size(1024).is_ok());
+    assert_eq!(
+        policy.check_size(1025),
+        Err(AttachmentError::TooLarge { max: 1024 })
+    );
+}
@@ -0,0 +1,120 @@
+//! Cleaning of the content of messages before it is screened and stored.
+
+use thiserror::Error;
+
+use crate::messages::Message;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ContentError {
+    #[error("empty content")]
+    Empty,
+    #[error("content of {chars} characters, longer than {max}")]
+    TooLong { chars: usize, max: usize },
+}
+
+/// Limits and normalization of the contents of messages.
+#[derive(Clone, Copy, Debug)]
+pub struct ContentPolicy {
+    pub max_chars: usize,
+    /// Control characters other than whitespace are removed instead of being stored.
+    pub strip_control: bool,
+}
+
+impl Default for ContentPolicy {
+    fn default() -> Self {
+        Self {
+            max_chars: Message::MAX_CONTENT_CHARS,
+            strip_control: false,
+        }
+    }
+}
+
+impl ContentPolicy {
+    pub fn new(max_chars: usize, strip_control: bool) -> Self {
+        Self {
+            max_chars,
+            strip_control,
+        }
+    }
+
+    /// `content` with each run of whitespace in a line collapsed into a space, lines trimmed and
+    /// at most one blank line between paragraphs. Limits apply to the normalized content.
+    ///
+    /// Characters that can hide or disguise text are always removed: `NUL`, and the overrides and
+    /// isolates of the bidirectional algorithm that can display text in another order than it is
+    /// read.
+    pub fn normalize(&self, content: &str) -> Result<String, ContentError> {
+        let content: String = content
+            .chars()
+            .filter(|c| !is_dangerous(*c))
+            .filter(|c| !self.strip_control || !c.is_control() || c.is_whitespace())
+            .collect();
+
+        let mut normalized = String::with_capacity(content.len());
+        let mut blank = false;
+
+        for line in content.lines() {
+            let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+
+            if line.is_empty() {
+                blank = !normalized.is_empty();
+                continue;
+            }
+            if !normalized.is_empty() {
+                normalized.push_str(if blank { "\n\n" } else { "\n" });
+            }
+            normalized.push_str(&line);
+            blank = false;
+        }
+
+        match normalized.chars().count() {
+            0 => Err(ContentError::Empty),
+            chars if chars > self.max_chars => Err(ContentError::TooLong {
+                chars,
+                max: self.max_chars,
+            }),
+            _ => Ok(normalized),
+        }
+    }
+}
+
+fn is_dangerous(c: char) -> bool {
+    matches!(c, '\0' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+#[cfg(test)]
+#[test]
+fn normalize_test() {
+    let policy = ContentPolicy::new(12, false);
+
+    assert_eq!(
+        policy.normalize("  Hello \t world ").unwrap(),
+        "Hello world"
+    );
+    assert_eq!(
+        ContentPolicy::default()
+            .normalize("\n\nFirst  line\r\nsecond\n\n\n\nlast \n")
+            .unwrap(),
+        "First line\nsecond\n\nlast"
+    );
+
+    assert_eq!(policy.normalize(" \n\t "), Err(ContentError::Empty));
+    assert_eq!(
+        policy.normalize("Hello world!!"),
+        Err(ContentError::TooLong { chars: 13, max: 12 })
+    );
+    // Whitespace does not count once collapsed.
+    assert!(policy.normalize("Hello          you").is_ok());
+
+    assert_eq!(policy.normalize("bell\u{7}").unwrap(), "bell\u{7}");
+    assert_eq!(
+        ContentPolicy::new(12, true).normalize("bell\u{7}").unwrap(),
+        "bell"
+    );
+
+    assert_eq!(policy.normalize("abc\u{202E}fed\0").unwrap(), "abcfed");
+    assert_eq!(
+        policy.normalize("\u{2066}\u{2069}"),
+        Err(ContentError::Empty)
+    );
+}
@@ -0,0 +1,253 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::messages::{parse_legacy, Message, MessageId, MessageIdParsingError};
+use crate::users::{UserId, Userlike};
+
+/// Creator's UUID and creation timestamp (milli-seconds precision), displayed like the legacy
+/// `MessageId`s.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct ConversationId {
+    creator: UserId,
+    timestamp: u64,
+}
+
+#[derive(Error, Debug)]
+#[error(transparent)]
+pub struct ConversationIdParsingError(#[from] MessageIdParsingError);
+
+impl FromStr for ConversationId {
+    type Err = ConversationIdParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (creator, timestamp) = parse_legacy(s)?;
+
+        Ok(Self { creator, timestamp })
+    }
+}
+
+impl Display for ConversationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{:016X}", self.creator, self.timestamp)
+    }
+}
+
+impl ConversationId {
+    pub fn try_parse(s: impl AsRef<str>) -> Result<Self, ConversationIdParsingError> {
+        s.as_ref().parse()
+    }
+
+    pub fn new_now(creator: impl Userlike) -> Self {
+        Self {
+            creator: creator.get_id(),
+            timestamp: Utc::now().timestamp_millis() as u64,
+        }
+    }
+
+    pub fn from_tuple_i64((creator, timestamp): (Uuid, i64)) -> Self {
+        Self {
+            creator: creator.into(),
+            timestamp: timestamp as u64,
+        }
+    }
+
+    pub fn as_tuple_i64(self) -> (Uuid, i64) {
+        (self.creator.into(), self.timestamp as i64)
+    }
+
+    pub fn creator(&self) -> UserId {
+        self.creator
+    }
+
+    /// Creation date, nothing can be posted before.
+    pub fn datetime(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.timestamp as i64).expect("timestamp out of range")
+    }
+}
+
+impl PartialOrd for ConversationId {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// By creation date.
+impl Ord for ConversationId {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.timestamp
+            .cmp(&other.timestamp)
+            .then_with(|| Into::<Uuid>::into(self.creator).cmp(&other.creator.into()))
+    }
+}
+
+pub trait Conversationlike: Sized {
+    fn get_id(&self) -> ConversationId;
+}
+
+impl Conversationlike for ConversationId {
+    fn get_id(&self) -> ConversationId {
+        *self
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Conversation {
+    pub id: ConversationId,
+    pub members: Vec<UserId>,
+}
+
+impl Conversation {
+    pub fn is_member(&self, user: impl Userlike) -> bool {
+        self.members.contains(&user.get_id())
+    }
+
+    /// `None` when `user` isn't a member.
+    pub fn member(&self, user: impl Userlike) -> Option<ConversationMember> {
+        let user_id = user.get_id();
+
+        self.is_member(user_id).then_some(ConversationMember {
+            conversation_id: self.id,
+            user_id,
+        })
+    }
+
+    pub fn iter_members(&self) -> impl Iterator<Item = ConversationMember> + '_ {
+        self.members.iter().map(|user_id| ConversationMember {
+            conversation_id: self.id,
+            user_id: *user_id,
+        })
+    }
+}
+
+impl Conversationlike for Conversation {
+    fn get_id(&self) -> ConversationId {
+        self.id
+    }
+}
+
+/// A user of a conversation, who can read and send its direct messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConversationMember {
+    pub conversation_id: ConversationId,
+    pub user_id: UserId,
+}
+
+impl Conversationlike for ConversationMember {
+    fn get_id(&self) -> ConversationId {
+        self.conversation_id
+    }
+}
+
+impl Userlike for ConversationMember {
+    fn get_id(&self) -> UserId {
+        self.user_id
+    }
+}
+
+/// A message only visible by the members of a conversation.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DirectMessage {
+    pub conversation_id: ConversationId,
+    pub message: Message,
+}
+
+impl PartialEq for DirectMessage {
+    fn eq(&self, other: &Self) -> bool {
+        self.message == other.message
+    }
+}
+
+impl Eq for DirectMessage {}
+
+impl PartialOrd for DirectMessage {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// By date then id, like `Message`.
+impl Ord for DirectMessage {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.message.cmp(&other.message)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChatSignalKind {
+    Typing,
+    /// The user received every message up to this one.
+    Ack(MessageId),
+}
+
+/// Ephemeral event of a member of a conversation, only sent in real time.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChatSignal {
+    pub conversation_id: ConversationId,
+    pub user_id: UserId,
+    pub kind: ChatSignalKind,
+}
+
+/// What the members of a conversation receive in real time.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChatEvent {
+    Message(DirectMessage),
+    Signal(ChatSignal),
+}
+
+#[cfg(test)]
+#[test]
+fn conversation_id_round_trip() {
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let id = ConversationId::new_now(user_id);
+
+    assert_eq!(ConversationId::try_parse(id.to_string()).unwrap(), id);
+    assert_eq!(id.creator(), user_id);
+}
+
+#[cfg(test)]
+#[test]
+fn conversations_sort_by_time() {
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let first = ConversationId::new_now(user_id);
+    let second = ConversationId {
+        timestamp: first.timestamp + 1,
+        ..first
+    };
+    assert!(first < second);
+
+    let conversation = Conversation {
+        id: first,
+        members: vec![user_id],
+    };
+    assert_eq!(
+        conversation.member(user_id),
+        Some(ConversationMember {
+            conversation_id: first,
+            user_id,
+        })
+    );
+    assert_eq!(conversation.member(UserId::from(Uuid::nil())), None);
+
+    let direct_message = |date| DirectMessage {
+        conversation_id: first,
+        message: Message {
+            date,
+            ..Message::new(user_id, String::new())
+        },
+    };
+    let mut direct_messages = [
+        direct_message(second.datetime()),
+        direct_message(first.datetime()),
+    ];
+    direct_messages.sort();
+    assert_eq!(direct_messages[0].message.date, first.datetime());
+}
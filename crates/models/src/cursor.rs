@@ -0,0 +1,316 @@
+//! Page tokens of the timeline and of the user search, opaque to the clients.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::messages::{Message, MessageId, Messagelike};
+
+/// Where a page of the timeline ended: the last message sent and the time bucket it is stored
+/// in, so that the next page starts scanning from there, right after that message.
+///
+/// Encoded in URL-safe base64 without padding, of the version, the days of the bucket since
+/// 0001-01-01 (4 bytes), the date of the message in milli-seconds (8 bytes), the binary
+/// `MessageId` (24 bytes) then an HMAC-SHA256 of the rest with the `CursorKey` of the server
+/// (16 bytes), so that clients can't forge them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Cursor {
+    bucket: NaiveDate,
+    date: DateTime<Utc>,
+    message_id: MessageId,
+}
+
+const VERSION: u8 = 2;
+/// With the high bit set, so that the tokens of the timeline and of the search are not taken
+/// for one another.
+const SEARCH_VERSION: u8 = 0x81;
+const PAYLOAD_BYTES: usize = 1 + 4 + 8 + 24;
+const TAG_BYTES: usize = 16;
+
+#[derive(Error, Debug)]
+pub enum CursorError {
+    #[error("expected URL-safe base64")]
+    Base64,
+    #[error("wrong size")]
+    Size,
+    #[error("unsupported version `{0}`")]
+    Version(u8),
+    #[error("wrong signature, the token was altered or issued by another cluster")]
+    Signature,
+    #[error("bucket out of range")]
+    Bucket,
+    #[error("date out of range")]
+    Date,
+    #[error("invalid name")]
+    Name,
+}
+
+/// Signs the page tokens of the server, so that they can only be used with the servers that share
+/// it.
+#[derive(Clone)]
+pub struct CursorKey(Hmac<Sha256>);
+
+impl CursorKey {
+    /// Shared between the servers of the same `secret`, derived from it so that it signs nothing
+    /// else the secret does.
+    pub fn new(secret: &[u8]) -> Self {
+        let mut derive = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes any key size");
+        derive.update(b"page tokens");
+        let key = derive.finalize().into_bytes();
+
+        Self(Hmac::new_from_slice(&key).expect("HMAC takes any key size"))
+    }
+
+    /// Of a single server: its tokens are not valid on the others, nor once it restarts.
+    pub fn random() -> Self {
+        let (a, b) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+
+        Self::new(&[*a.as_bytes(), *b.as_bytes()].concat())
+    }
+
+    fn tag(&self, payload: &[u8]) -> [u8; TAG_BYTES] {
+        let mut mac = self.0.clone();
+        mac.update(payload);
+        let tag = mac.finalize().into_bytes();
+
+        tag[..TAG_BYTES].try_into().expect("truncated tag")
+    }
+
+    fn sign(&self, mut payload: Vec<u8>) -> String {
+        let tag = self.tag(&payload);
+        payload.extend_from_slice(&tag);
+
+        URL_SAFE_NO_PAD.encode(payload)
+    }
+
+    /// The payload of `token`, once its tag is checked.
+    fn verify(&self, token: &str) -> Result<Vec<u8>, CursorError> {
+        let mut bytes = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| CursorError::Base64)?;
+        if bytes.len() <= TAG_BYTES {
+            return Err(CursorError::Size);
+        }
+
+        let tag = bytes.split_off(bytes.len() - TAG_BYTES);
+        let mut mac = self.0.clone();
+        mac.update(&bytes);
+        mac.verify_truncated_left(&tag)
+            .map_err(|_| CursorError::Signature)?;
+
+        Ok(bytes)
+    }
+}
+
+impl std::fmt::Debug for CursorKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CursorKey(..)")
+    }
+}
+
+impl Cursor {
+    pub fn new(bucket: NaiveDate, message: &Message) -> Self {
+        Self {
+            bucket,
+            date: message.date,
+            message_id: message.id,
+        }
+    }
+
+    pub fn encode(&self, key: &CursorKey) -> String {
+        let mut bytes = Vec::with_capacity(PAYLOAD_BYTES + TAG_BYTES);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.bucket.num_days_from_ce().to_be_bytes());
+        bytes.extend_from_slice(&self.date.timestamp_millis().to_be_bytes());
+        bytes.extend_from_slice(&self.message_id.to_bytes());
+
+        key.sign(bytes)
+    }
+
+    /// Of a token encoded with the same `key`.
+    pub fn decode(token: impl AsRef<str>, key: &CursorKey) -> Result<Self, CursorError> {
+        let payload = key.verify(token.as_ref())?;
+        if payload[0] != VERSION {
+            return Err(CursorError::Version(payload[0]));
+        }
+        if payload.len() != PAYLOAD_BYTES {
+            return Err(CursorError::Size);
+        }
+
+        let days = i32::from_be_bytes(payload[1..5].try_into().expect("4 bytes"));
+        let bucket = NaiveDate::from_num_days_from_ce_opt(days).ok_or(CursorError::Bucket)?;
+        let millis = i64::from_be_bytes(payload[5..13].try_into().expect("8 bytes"));
+        let date = DateTime::from_timestamp_millis(millis).ok_or(CursorError::Date)?;
+        let message_id = MessageId::from_bytes(payload[13..].try_into().expect("24 bytes"));
+
+        Ok(Self {
+            bucket,
+            date,
+            message_id,
+        })
+    }
+
+    pub fn bucket(&self) -> NaiveDate {
+        self.bucket
+    }
+
+    pub fn message_id(&self) -> MessageId {
+        self.message_id
+    }
+
+    /// Whether `message` comes after the last one of the page, the timeline being sorted most
+    /// recent first, like `Message` is ordered. Dates are compared to the milli-second, as stored.
+    pub fn is_after(&self, message: &Message) -> bool {
+        let date = message.date.timestamp_millis();
+
+        (date, message.id) < (self.date.timestamp_millis(), self.message_id)
+    }
+}
+
+impl Messagelike for Cursor {
+    fn get_id(&self) -> MessageId {
+        self.message_id
+    }
+}
+
+/// Where a page of the user search ended: the name of the last user sent, since they are sorted
+/// by their unique name.
+///
+/// Encoded as `Cursor`, of the version and the name in UTF-8.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct SearchCursor {
+    after: String,
+}
+
+impl SearchCursor {
+    pub fn new(after: impl Into<String>) -> Self {
+        Self {
+            after: after.into(),
+        }
+    }
+
+    pub fn encode(&self, key: &CursorKey) -> String {
+        let mut bytes = Vec::with_capacity(1 + self.after.len() + TAG_BYTES);
+        bytes.push(SEARCH_VERSION);
+        bytes.extend_from_slice(self.after.as_bytes());
+
+        key.sign(bytes)
+    }
+
+    /// Of a token encoded with the same `key`.
+    pub fn decode(token: impl AsRef<str>, key: &CursorKey) -> Result<Self, CursorError> {
+        let payload = key.verify(token.as_ref())?;
+        if payload[0] != SEARCH_VERSION {
+            return Err(CursorError::Version(payload[0]));
+        }
+
+        let after = String::from_utf8(payload[1..].to_vec()).map_err(|_| CursorError::Name)?;
+
+        Ok(Self { after })
+    }
+
+    /// The name the next page starts after.
+    pub fn after(&self) -> &str {
+        &self.after
+    }
+
+    pub fn into_after(self) -> String {
+        self.after
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn cursor_round_trip() {
+    use crate::users::UserId;
+
+    let key = CursorKey::new(b"secret");
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let message = Message::new(user_id, String::new());
+    let cursor = Cursor::new(NaiveDate::from_ymd_opt(2023, 3, 6).unwrap(), &message);
+
+    let encoded = cursor.encode(&key);
+    assert_eq!(encoded.len(), 71);
+    assert!(encoded
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    let decoded = Cursor::decode(&encoded, &key).unwrap();
+    assert_eq!(decoded.bucket(), cursor.bucket());
+    assert_eq!(decoded.message_id(), message.id);
+    assert_eq!(
+        decoded.date.timestamp_millis(),
+        message.date.timestamp_millis()
+    );
+
+    // Any edit is caught, and tokens of other keys are rejected.
+    let mut edited = encoded.clone().into_bytes();
+    edited[10] = if edited[10] == b'A' { b'B' } else { b'A' };
+    assert!(matches!(
+        Cursor::decode(String::from_utf8(edited).unwrap(), &key),
+        Err(CursorError::Signature)
+    ));
+    assert!(matches!(
+        Cursor::decode(&encoded, &CursorKey::new(b"other")),
+        Err(CursorError::Signature)
+    ));
+    assert!(matches!(
+        Cursor::decode("not a cursor", &key),
+        Err(CursorError::Base64)
+    ));
+}
+
+/// The page after a cursor starts right after its message, even among messages of the same
+/// milli-second.
+#[cfg(test)]
+#[test]
+fn cursor_is_after() {
+    use crate::users::UserId;
+
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let date = Utc::now();
+    let mut messages: Vec<Message> = (0..3)
+        .map(|_| Message {
+            date,
+            ..Message::new(user_id, String::new())
+        })
+        .collect();
+    messages.sort_by(|a, b| b.cmp(a));
+
+    let cursor = Cursor::new(date.date_naive(), &messages[1]);
+    let after: Vec<bool> = messages.iter().map(|m| cursor.is_after(m)).collect();
+    assert_eq!(after, vec![false, false, true]);
+}
+
+#[cfg(test)]
+#[test]
+fn search_cursor_round_trip() {
+    let key = CursorKey::random();
+    let cursor = SearchCursor::new("élodie");
+
+    let encoded = cursor.encode(&key);
+    assert_eq!(
+        SearchCursor::decode(&encoded, &key).unwrap().after(),
+        "élodie"
+    );
+
+    // Nor taken for timeline ones, and the other way around.
+    assert!(matches!(
+        Cursor::decode(&encoded, &key),
+        Err(CursorError::Version(SEARCH_VERSION))
+    ));
+    let user_id = crate::users::UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let timeline = Cursor::new(
+        NaiveDate::from_ymd_opt(2023, 3, 6).unwrap(),
+        &Message::new(user_id, String::new()),
+    );
+    assert!(matches!(
+        SearchCursor::decode(timeline.encode(&key), &key),
+        Err(CursorError::Version(VERSION))
+    ));
+    assert!(matches!(
+        SearchCursor::decode("", &key),
+        Err(CursorError::Size)
+    ));
+}
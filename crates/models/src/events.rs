@@ -0,0 +1,195 @@
+//! Envelope of the realtime events, so that consumers can drop the ones they already received
+//! and match them with the request that produced them.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::OnceLock;
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct EventId(Uuid);
+
+#[derive(Error, Debug)]
+#[error(transparent)]
+pub struct EventIdParsingError(#[from] uuid::Error);
+
+impl Display for EventId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for EventId {
+    type Err = EventIdParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Uuid::from_str(s)?))
+    }
+}
+
+impl EventId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    pub fn try_parse(s: impl AsRef<str>) -> Result<Self, EventIdParsingError> {
+        s.as_ref().parse()
+    }
+}
+
+impl Default for EventId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The server or notifier instance that published an event, drawn when the process starts.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct ProducerId(Uuid);
+
+impl Display for ProducerId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for ProducerId {
+    type Err = EventIdParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Uuid::from_str(s)?))
+    }
+}
+
+impl ProducerId {
+    /// The one of this process.
+    pub fn current() -> Self {
+        static CURRENT: OnceLock<ProducerId> = OnceLock::new();
+
+        *CURRENT.get_or_init(|| Self(Uuid::new_v4()))
+    }
+
+    /// Of events published without an envelope, by older producers.
+    pub fn unknown() -> Self {
+        Self(Uuid::nil())
+    }
+
+    pub fn is_unknown(&self) -> bool {
+        self.0.is_nil()
+    }
+}
+
+/// `payload` published by `producer_id` at `occurred_at`. `trace_id` is the request ID
+/// of the RPC it comes from, if any.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Event<T> {
+    pub event_id: EventId,
+    pub occurred_at: DateTime<Utc>,
+    pub trace_id: Option<String>,
+    pub producer_id: ProducerId,
+    pub payload: T,
+}
+
+impl<T> Event<T> {
+    /// A new event of this process, occurring now.
+    pub fn new(payload: T) -> Self {
+        Self {
+            event_id: EventId::new(),
+            occurred_at: Utc::now(),
+            trace_id: None,
+            producer_id: ProducerId::current(),
+            payload,
+        }
+    }
+
+    pub fn with_trace_id(self, trace_id: Option<String>) -> Self {
+        Self { trace_id, ..self }
+    }
+
+    /// The same event, of another representation of its payload.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Event<U> {
+        Event {
+            event_id: self.event_id,
+            occurred_at: self.occurred_at,
+            trace_id: self.trace_id,
+            producer_id: self.producer_id,
+            payload: f(self.payload),
+        }
+    }
+
+    pub fn into_payload(self) -> T {
+        self.payload
+    }
+
+    /// Whether this process published it.
+    pub fn is_local(&self) -> bool {
+        self.producer_id == ProducerId::current()
+    }
+}
+
+/// The IDs of the last `capacity` events received, to drop the ones received twice.
+#[derive(Debug)]
+pub struct RecentEvents {
+    capacity: usize,
+    ids: HashSet<EventId>,
+    order: VecDeque<EventId>,
+}
+
+impl RecentEvents {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ids: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Whether `id` was not received yet. The oldest ID is forgotten when full.
+    pub fn insert(&mut self, id: EventId) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        if !self.ids.insert(id) {
+            return false;
+        }
+
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.order.push_back(id);
+
+        true
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn recent_events_test() {
+    let event = Event::new("hello").with_trace_id(Some("request".to_string()));
+    assert!(event.is_local() && !event.producer_id.is_unknown());
+    assert_eq!(
+        EventId::try_parse(event.event_id.to_string()).unwrap(),
+        event.event_id
+    );
+
+    let mapped = event.clone().map(str::len);
+    assert_eq!(mapped.event_id, event.event_id);
+    assert_eq!(mapped.trace_id.as_deref(), Some("request"));
+    assert_eq!(mapped.into_payload(), 5);
+
+    let (a, b, c) = (EventId::new(), EventId::new(), EventId::new());
+    let mut recent = RecentEvents::new(2);
+    assert!(recent.insert(a));
+    assert!(!recent.insert(a));
+    assert!(recent.insert(b));
+    // `a` is forgotten to make room.
+    assert!(recent.insert(c));
+    assert!(recent.insert(a));
+    assert!(!recent.insert(c));
+}
@@ -1,11 +1,175 @@
-use crate::users::UserId;
+use std::{fmt::Display, str::FromStr};
 
+use thiserror::Error;
+
+use crate::users::{UserId, Userlike};
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FriendUpdate {
     New(UserId),
     Removed(UserId),
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FriendshipUpdate {
     New(UserId, UserId),
     Removed(UserId, UserId),
-}
\ No newline at end of file
+}
+
+/// The user that blocked, then the blocked one.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockUpdate {
+    Blocked(UserId, UserId),
+    Unblocked(UserId, UserId),
+}
+
+/// Where a friendship stands. It starts `Pending` when requested, until the other user accepts
+/// or declines it. `Blocked` when one of the users blocked the other, whatever it was before.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FriendshipState {
+    Pending,
+    Accepted,
+    Declined,
+    Blocked,
+}
+
+#[derive(Error, Debug)]
+#[error("unknown friendship state `{0}`")]
+pub struct FriendshipStateParsingError(String);
+
+impl FriendshipState {
+    /// How it is stored.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FriendshipState::Pending => "pending",
+            FriendshipState::Accepted => "accepted",
+            FriendshipState::Declined => "declined",
+            FriendshipState::Blocked => "blocked",
+        }
+    }
+
+    /// Whether a friendship in this state can go to `next`. A declined request can be made again,
+    /// and blocking works from any state but `Blocked`, which only unblocking leaves.
+    pub fn can_become(&self, next: FriendshipState) -> bool {
+        use FriendshipState::*;
+
+        matches!(
+            (self, next),
+            (Pending, Accepted | Declined)
+                | (Declined, Pending)
+                | (Pending | Accepted | Declined, Blocked)
+        )
+    }
+}
+
+impl Display for FriendshipState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FriendshipState {
+    type Err = FriendshipStateParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "pending" => FriendshipState::Pending,
+            "accepted" => FriendshipState::Accepted,
+            "declined" => FriendshipState::Declined,
+            "blocked" => FriendshipState::Blocked,
+            other => return Err(FriendshipStateParsingError(other.to_string())),
+        })
+    }
+}
+
+/// The friendship between `initiator` and `target` went to `state`, because of `initiator`: it
+/// requested it, accepted or declined the request of `target`, or blocked it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FriendshipEvent {
+    pub initiator: UserId,
+    pub target: UserId,
+    pub state: FriendshipState,
+}
+
+impl FriendshipEvent {
+    pub fn new(initiator: impl Userlike, target: impl Userlike, state: FriendshipState) -> Self {
+        Self {
+            initiator: initiator.get_id(),
+            target: target.get_id(),
+            state,
+        }
+    }
+
+    pub fn involves(&self, user: impl Userlike) -> bool {
+        let user = user.get_id();
+
+        self.initiator == user || self.target == user
+    }
+
+    /// The other user of the friendship, `None` if `user` is not one of them.
+    pub fn other(&self, user: impl Userlike) -> Option<UserId> {
+        let user = user.get_id();
+
+        match user {
+            _ if user == self.initiator => Some(self.target),
+            _ if user == self.target => Some(self.initiator),
+            _ => None,
+        }
+    }
+
+    /// Whether `user` received a request it has to answer.
+    pub fn is_request_to(&self, user: impl Userlike) -> bool {
+        self.state == FriendshipState::Pending && self.target == user.get_id()
+    }
+
+    /// How it changes the friends of the users: an accepted request makes them friends,
+    /// blocking removes their friendship. Pending and declined requests change nothing.
+    pub fn update(&self) -> Option<FriendshipUpdate> {
+        match self.state {
+            FriendshipState::Accepted => Some(FriendshipUpdate::New(self.initiator, self.target)),
+            FriendshipState::Blocked => {
+                Some(FriendshipUpdate::Removed(self.initiator, self.target))
+            }
+            FriendshipState::Pending | FriendshipState::Declined => None,
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn friendship_event_test() {
+    let alice = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let bob = UserId::try_parse("21234567-1234-5678-1234-567812345678").unwrap();
+    let carol = UserId::try_parse("31234567-1234-5678-1234-567812345678").unwrap();
+
+    for state in [
+        FriendshipState::Pending,
+        FriendshipState::Accepted,
+        FriendshipState::Declined,
+        FriendshipState::Blocked,
+    ] {
+        assert_eq!(state.as_str().parse::<FriendshipState>().unwrap(), state);
+    }
+    assert!("added".parse::<FriendshipState>().is_err());
+
+    assert!(FriendshipState::Pending.can_become(FriendshipState::Accepted));
+    assert!(FriendshipState::Declined.can_become(FriendshipState::Pending));
+    assert!(!FriendshipState::Accepted.can_become(FriendshipState::Pending));
+    assert!(!FriendshipState::Blocked.can_become(FriendshipState::Accepted));
+
+    let request = FriendshipEvent::new(alice, bob, FriendshipState::Pending);
+    assert!(request.is_request_to(bob));
+    assert!(!request.is_request_to(alice));
+    assert_eq!(request.other(bob), Some(alice));
+    assert_eq!(request.other(carol), None);
+    assert!(request.update().is_none());
+
+    let accepted = FriendshipEvent::new(bob, alice, FriendshipState::Accepted);
+    assert!(accepted.involves(alice) && !accepted.involves(carol));
+    assert!(matches!(
+        accepted.update(),
+        Some(FriendshipUpdate::New(user, friend)) if user == bob && friend == alice
+    ));
+}
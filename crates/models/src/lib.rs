@@ -1,6 +1,78 @@
+//! The domain types of the network, shared by the other crates. How they are stored is up to
+//! `repository`, and how they are published to `realtime`: both map them from and into their own
+//! representations, so that there is a single `Message`, `User`, ... type.
+
+use thiserror::Error;
+
+pub mod attachments;
+pub mod content;
 pub mod users;
+pub mod conversations;
+pub mod cursor;
+pub mod events;
 pub mod friendships;
 pub mod messages;
+pub mod notifications;
+pub mod reactions;
 
 #[cfg(feature = "proto")]
-pub mod proto;
\ No newline at end of file
+pub mod proto;
+#[cfg(feature = "serde")]
+mod serialization;
+
+/// Any error of the models, so that the callers handling them alike, eg. behind an
+/// `anyhow::Error`, can still match on their kind. Each converts into it.
+#[derive(Error, Debug)]
+pub enum ModelError {
+    #[error(transparent)]
+    UserId(#[from] users::UserIdParsingError),
+    #[error(transparent)]
+    MessageId(#[from] messages::MessageIdParsingError),
+    #[error(transparent)]
+    MessageBuild(#[from] messages::MessageBuildError),
+    #[error(transparent)]
+    ConversationId(#[from] conversations::ConversationIdParsingError),
+    #[error(transparent)]
+    AttachmentId(#[from] attachments::AttachmentIdParsingError),
+    #[error(transparent)]
+    Attachment(#[from] attachments::AttachmentError),
+    #[error(transparent)]
+    Content(#[from] content::ContentError),
+    #[error(transparent)]
+    Cursor(#[from] cursor::CursorError),
+    #[error(transparent)]
+    EventId(#[from] events::EventIdParsingError),
+    #[error(transparent)]
+    FriendshipState(#[from] friendships::FriendshipStateParsingError),
+    #[error(transparent)]
+    Reaction(#[from] reactions::ReactionError),
+    #[error(transparent)]
+    UserProfile(#[from] users::UserProfileError),
+    #[cfg(feature = "proto")]
+    #[error(transparent)]
+    Proto(#[from] proto::ProtoDecodeMessageError),
+}
+
+#[test]
+fn model_error_kinds() {
+    fn parse(user_id: &str, emoji: &str) -> Result<reactions::Reaction, ModelError> {
+        let user_id = users::UserId::try_parse(user_id)?;
+        let message_id = messages::MessageId::new_now(user_id);
+
+        Ok(reactions::Reaction::new(
+            message_id,
+            user_id,
+            emoji.to_string(),
+        )?)
+    }
+
+    assert!(matches!(
+        parse("not-an-id", "👍"),
+        Err(ModelError::UserId(_))
+    ));
+    assert!(matches!(
+        parse("11234567-1234-5678-1234-567812345678", ""),
+        Err(ModelError::Reaction(reactions::ReactionError::Empty))
+    ));
+    assert!(parse("11234567-1234-5678-1234-567812345678", "👍").is_ok());
+}
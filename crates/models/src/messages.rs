@@ -1,64 +1,163 @@
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::{fmt::Display, str::FromStr};
 
-use chrono::{NaiveDateTime, Utc};
+use chrono::{DateTime, Utc};
 use thiserror::Error;
 use uuid::Uuid;
 
-use crate::users::{UserId, UserIdParsingError, Userlike};
+use crate::attachments::{Attachment, AttachmentId};
+use crate::content::{ContentError, ContentPolicy};
+use crate::reactions::ReactionSummary;
+use crate::users::{User, UserId, UserIdParsingError, UserRefByName, Userlike};
 
-/// UUID and timestamp (milli-seconds precision).
-/// Displayed it looks like this (53 bytes):
+/// Author and a 64-bit key ordered by time: the milli-seconds since `KEY_EPOCH_MS`, followed by
+/// `SEQUENCE_BITS` of a random sequence. The sequence is incremented when the last id generated
+/// by the process is not older, so that the messages of a same milli-second don't collide.
+///
+/// Displayed in Crockford's base32, the key first so that ids sort by time as strings too
+/// (39 bytes):
+/// 0DWZ7BYFDK0440H4D2PE4HMASW14D2PF0938NKR
+/// In binary, the key then the UUID, big-endian (24 bytes).
+///
+/// Ids generated before hold a timestamp as key, lower than `LEGACY_KEY_LIMIT`. They are still
+/// parsed when displayed as they were (53 bytes):
 /// 11234567-1234-5678-1234-567812345678x0000000064371AB8
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 pub struct MessageId {
     user_id: UserId,
-    timestamp: u64,
+    key: u64,
 }
 
+/// 2023-01-01T00:00:00Z, keys fit in 63 bits until 2092.
+const KEY_EPOCH_MS: u64 = 1_672_531_200_000;
+const SEQUENCE_BITS: u32 = 22;
+/// The random part of a sequence, the rest is left for the increments.
+const SEQUENCE_RANDOM_MASK: u64 = (1 << (SEQUENCE_BITS - 1)) - 1;
+/// Timestamps until 2109, keys generated since 2023-01-01T00:18:00Z.
+const LEGACY_KEY_LIMIT: u64 = 1 << 42;
+
+const KEY_CHARS: usize = 13;
+const USER_ID_CHARS: usize = 26;
+const LEGACY_SIZE: usize = 36 + 1 + 16;
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// The last key generated by the process.
+static LAST_KEY: AtomicU64 = AtomicU64::new(0);
+
 #[derive(Error, Debug)]
 pub enum MessageIdParsingError {
-    #[error("wrong size of str `{0}`, expected `53` bytes long str")]
+    #[error("wrong size of str `{0}`, expected `39` bytes long str, or `53` for a legacy id")]
     Size(usize),
     #[error("wrong format of str, got `{0}`, expected `x` at byte 36")]
     Format(char),
+    #[error("wrong character `{0}`, expected Crockford's base32")]
+    Base32(char),
+    #[error("base32 value out of range")]
+    Overflow,
     #[error("wrong encoding of str")]
     Utf8Error(#[from] std::str::Utf8Error),
     #[error("wrong format or value for timestamp")]
     Timestamp(#[from] std::num::ParseIntError),
+    #[error("timestamp `{0}` is out of range")]
+    TimestampRange(u64),
     #[error("error parsing user id")]
     UserId(#[from] UserIdParsingError),
+    #[error("wrong size of bytes `{0}`, expected `24` bytes")]
+    Bytes(usize),
 }
 
 impl FromStr for MessageId {
     type Err = MessageIdParsingError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let bytes = s.as_bytes();
-        let size = bytes.len();
+        let id = match s.len() {
+            LEGACY_SIZE => {
+                let (user_id, key) = parse_legacy(s)?;
 
-        if size != 36 + 1 + 16 {
-            return Err(MessageIdParsingError::Size(size));
-        }
+                Self { user_id, key }
+            }
+            size if size == KEY_CHARS + USER_ID_CHARS => {
+                let (key, user_id) = s.split_at(KEY_CHARS);
+                let key = u64::try_from(decode_base32(key)?)
+                    .map_err(|_| MessageIdParsingError::Overflow)?;
+                let uuid = Uuid::from_u128(decode_base32(user_id)?);
 
-        let sep = bytes[36] as char;
-        if sep != 'x' {
-            return Err(MessageIdParsingError::Format(sep));
-        }
+                Self {
+                    user_id: uuid.into(),
+                    key,
+                }
+            }
+            size => return Err(MessageIdParsingError::Size(size)),
+        };
 
-        // Actually safe because is comes from a str.
-        let user_id_str = std::str::from_utf8(&bytes[0..36])?;
-        let timestamp_str = std::str::from_utf8(&bytes[37..63])?;
-        let user_id = UserId::from_str(user_id_str)?;
-        let timestamp = u64::from_str_radix(timestamp_str, 16)?;
+        let timestamp = id.timestamp();
+        if DateTime::from_timestamp_millis(timestamp as i64).is_none() {
+            return Err(MessageIdParsingError::TimestampRange(timestamp));
+        }
 
-        Ok(Self { user_id, timestamp })
+        Ok(id)
     }
 }
 
 impl Display for MessageId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}x{:#016x}", self.user_id, self.timestamp)
+        let user_id: Uuid = self.user_id.into();
+        let mut displayed = String::with_capacity(KEY_CHARS + USER_ID_CHARS);
+        encode_base32(self.key.into(), KEY_CHARS, &mut displayed);
+        encode_base32(user_id.as_u128(), USER_ID_CHARS, &mut displayed);
+
+        f.write_str(&displayed)
+    }
+}
+
+/// Author and timestamp of a 53 bytes long id, also the layout of a `ConversationId`.
+pub(crate) fn parse_legacy(s: &str) -> Result<(UserId, u64), MessageIdParsingError> {
+    let bytes = s.as_bytes();
+    let size = bytes.len();
+
+    if size != LEGACY_SIZE {
+        return Err(MessageIdParsingError::Size(size));
     }
+
+    let sep = bytes[36] as char;
+    if sep != 'x' {
+        return Err(MessageIdParsingError::Format(sep));
+    }
+
+    // Actually safe because is comes from a str.
+    let user_id_str = std::str::from_utf8(&bytes[0..36])?;
+    let timestamp_str = std::str::from_utf8(&bytes[37..53])?;
+    let user_id = UserId::from_str(user_id_str)?;
+    let timestamp = u64::from_str_radix(timestamp_str, 16)?;
+
+    if DateTime::from_timestamp_millis(timestamp as i64).is_none() {
+        return Err(MessageIdParsingError::TimestampRange(timestamp));
+    }
+
+    Ok((user_id, timestamp))
+}
+
+/// The `chars` last base32 digits of `value`.
+fn encode_base32(value: u128, chars: usize, out: &mut String) {
+    for i in (0..chars).rev() {
+        let digit = (value >> (5 * i)) & 0x1F;
+        out.push(CROCKFORD[digit as usize] as char);
+    }
+}
+
+/// Case insensitive.
+fn decode_base32(s: &str) -> Result<u128, MessageIdParsingError> {
+    s.chars().try_fold(0u128, |value, c| {
+        let digit = CROCKFORD
+            .iter()
+            .position(|digit| *digit as char == c.to_ascii_uppercase())
+            .ok_or(MessageIdParsingError::Base32(c))?;
+
+        value
+            .checked_mul(32)
+            .and_then(|value| value.checked_add(digit as u128))
+            .ok_or(MessageIdParsingError::Overflow)
+    })
 }
 
 impl MessageId {
@@ -67,32 +166,98 @@ impl MessageId {
     }
 
     pub fn new_now(user_id: UserId) -> Self {
-        let datetime = Utc::now().naive_local();
-        let timestamp = datetime.timestamp_millis() as u64;
+        let timestamp = Utc::now().timestamp_millis() as u64;
+        let random = Uuid::new_v4().as_u64_pair().1 & SEQUENCE_RANDOM_MASK;
+        let candidate = (timestamp.saturating_sub(KEY_EPOCH_MS) << SEQUENCE_BITS) | random;
 
-        Self { user_id, timestamp }
+        let mut key = candidate;
+        let _ = LAST_KEY.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+            key = candidate.max(last + 1);
+            Some(key)
+        });
+
+        Self { user_id, key }
     }
 
-    pub fn from_tuple((user_id, timestamp): (Uuid, u64)) -> Self {
+    pub fn from_tuple((user_id, key): (Uuid, u64)) -> Self {
         Self {
             user_id: user_id.into(),
-            timestamp,
+            key,
         }
     }
 
-    pub fn from_tuple_i64((user_id, timestamp): (Uuid, i64)) -> Self {
+    pub fn from_tuple_i64((user_id, key): (Uuid, i64)) -> Self {
         Self {
             user_id: user_id.into(),
-            timestamp: timestamp as u64,
+            key: key as u64,
+        }
+    }
+
+    pub fn from_bytes(bytes: [u8; 24]) -> Self {
+        let (key, user_id) = bytes.split_at(8);
+
+        Self {
+            user_id: Uuid::from_slice(user_id).expect("16 bytes").into(),
+            key: u64::from_be_bytes(key.try_into().expect("8 bytes")),
+        }
+    }
+
+    /// Fails unless `bytes` is 24 bytes long.
+    pub fn try_from_slice(bytes: &[u8]) -> Result<Self, MessageIdParsingError> {
+        let bytes = bytes
+            .try_into()
+            .map_err(|_| MessageIdParsingError::Bytes(bytes.len()))?;
+
+        Ok(Self::from_bytes(bytes))
+    }
+
+    pub fn to_bytes(self) -> [u8; 24] {
+        let user_id: Uuid = self.user_id.into();
+        let mut bytes = [0; 24];
+        bytes[..8].copy_from_slice(&self.key.to_be_bytes());
+        bytes[8..].copy_from_slice(user_id.as_bytes());
+
+        bytes
+    }
+
+    pub fn user_id(&self) -> UserId {
+        self.user_id
+    }
+
+    /// Milli-seconds since epoch.
+    pub fn timestamp(&self) -> u64 {
+        match self.key {
+            key if key < LEGACY_KEY_LIMIT => key,
+            key => (key >> SEQUENCE_BITS) + KEY_EPOCH_MS,
         }
     }
 
+    pub fn datetime(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.timestamp() as i64).expect("timestamp out of range")
+    }
+
+    /// Stored in ScyllaDB, the key in place of the timestamp of legacy ids.
     pub fn as_tuple(self) -> (Uuid, u64) {
-        (self.user_id.into(), self.timestamp)
+        (self.user_id.into(), self.key)
     }
 
     pub fn as_tuple_i64(self) -> (Uuid, i64) {
-        (self.user_id.into(), self.timestamp as i64)
+        (self.user_id.into(), self.key as i64)
+    }
+}
+
+impl PartialOrd for MessageId {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// By key then author, so by time for the ids generated by a same process.
+impl Ord for MessageId {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key
+            .cmp(&other.key)
+            .then_with(|| Into::<Uuid>::into(self.user_id).cmp(&other.user_id.into()))
     }
 }
 
@@ -106,28 +271,237 @@ impl Messagelike for MessageId {
     }
 }
 
+/// What a message holds besides its `content`, which is its text whatever the body.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MessageBody {
+    /// Only the text.
+    #[default]
+    Text,
+    /// Fetched by the client that posts the link.
+    LinkPreview {
+        url: String,
+        title: String,
+        description: String,
+    },
+    /// One of the attachments of the message, the text is its caption.
+    Media {
+        attachment_id: AttachmentId,
+        content_type: String,
+    },
+    /// Written by the server rather than by a user.
+    SystemNotice,
+}
+
+impl MessageBody {
+    pub const MAX_URL_CHARS: usize = 2048;
+    pub const MAX_TITLE_CHARS: usize = 200;
+    pub const MAX_DESCRIPTION_CHARS: usize = 1000;
+}
+
+/// `mentions` are the ones of `content`, see `extract_mentions`. `reply_to` is the message it
+/// answers, in its thread.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Message {
     pub id: MessageId,
     pub user_id: UserId,
-    pub date: NaiveDateTime,
+    pub date: DateTime<Utc>,
     pub content: String,
+    pub body: MessageBody,
+    pub mentions: Vec<UserRefByName>,
+    pub reply_to: Option<MessageId>,
 }
 
 impl Message {
+    pub const MAX_CONTENT_CHARS: usize = 500;
+
     pub fn new(user: impl Userlike, content: String) -> Self {
         Self {
             id: MessageId::new_now(user.get_id()),
             user_id: user.get_id(),
-            date: chrono::offset::Local::now().naive_local(),
+            date: Utc::now(),
+            mentions: extract_mentions(&content),
             content,
+            body: MessageBody::Text,
+            reply_to: None,
+        }
+    }
+
+    /// With `content` normalized by `policy`, the way users post messages.
+    pub fn try_new(
+        user: impl Userlike,
+        content: &str,
+        policy: &ContentPolicy,
+    ) -> Result<Self, ContentError> {
+        Ok(Self::new(user, policy.normalize(content)?))
+    }
+
+    pub fn with_body(self, body: MessageBody) -> Self {
+        Self { body, ..self }
+    }
+
+    pub fn mentions_user(&self, user: &User) -> bool {
+        self.mentions.iter().any(|mention| mention.refers_to(user))
+    }
+
+    /// Composes a message of `user`, for the fields with a default to be left out.
+    pub fn builder(user: impl Userlike) -> MessageBuilder {
+        MessageBuilder {
+            user_id: user.get_id(),
+            id: None,
+            content: String::new(),
+            body: MessageBody::Text,
+            reply_to: None,
+            policy: None,
         }
     }
 }
 
+/// The names of the `@name` words of `content`, once each, trailing punctuation allowed: in
+/// `hello @bob!` but not in `bob@example.com`.
+pub fn extract_mentions(content: &str) -> Vec<UserRefByName> {
+    let mut mentions: Vec<UserRefByName> = Vec::new();
+
+    for word in content.split_whitespace() {
+        let Some(name) = word.strip_prefix('@') else {
+            continue;
+        };
+        let name = name.trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_');
+
+        let valid = !name.is_empty()
+            && name.chars().count() <= User::MAX_NAME_CHARS
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if valid && !mentions.iter().any(|mention| mention.name() == name) {
+            mentions.push(UserRefByName::new(name));
+        }
+    }
+
+    mentions
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum MessageBuildError {
+    #[error(transparent)]
+    Content(#[from] ContentError),
+    #[error("message id of another user")]
+    OtherUser,
+}
+
+/// A message being composed, from `Message::builder`. Its id is generated when built unless it
+/// is chosen with `id`, and it is plain text unless it has a body.
+#[derive(Clone, Debug)]
+pub struct MessageBuilder {
+    user_id: UserId,
+    id: Option<MessageId>,
+    content: String,
+    body: MessageBody,
+    reply_to: Option<MessageId>,
+    policy: Option<ContentPolicy>,
+}
+
+impl MessageBuilder {
+    pub fn content(self, content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            ..self
+        }
+    }
+
+    /// An id chosen by the client, so that retries produce the exact same message. The date is
+    /// the one of the id.
+    pub fn id(self, id: MessageId) -> Self {
+        Self {
+            id: Some(id),
+            ..self
+        }
+    }
+
+    pub fn body(self, body: MessageBody) -> Self {
+        Self { body, ..self }
+    }
+
+    /// In the thread of `message`, answering it.
+    pub fn reply_to(self, message: impl Messagelike) -> Self {
+        Self {
+            reply_to: Some(message.get_id()),
+            ..self
+        }
+    }
+
+    /// With `attachment` as its body, the content being its caption.
+    pub fn attachment(self, attachment: &Attachment) -> Self {
+        self.body(MessageBody::Media {
+            attachment_id: attachment.id,
+            content_type: attachment.content_type.clone(),
+        })
+    }
+
+    /// The content is normalized by `policy`, the way users post messages.
+    pub fn policy(self, policy: ContentPolicy) -> Self {
+        Self {
+            policy: Some(policy),
+            ..self
+        }
+    }
+
+    pub fn build(self) -> Result<Message, MessageBuildError> {
+        let content = match &self.policy {
+            Some(policy) => policy.normalize(&self.content)?,
+            None => self.content,
+        };
+
+        let message = match self.id {
+            Some(id) if id.user_id() != self.user_id => return Err(MessageBuildError::OtherUser),
+            Some(id) => Message::from_id(id, content),
+            None => Message::new(self.user_id, content),
+        };
+
+        Ok(Message {
+            reply_to: self.reply_to,
+            ..message.with_body(self.body)
+        })
+    }
+}
+
+impl Message {
+    /// Rebuilds a message from an already generated id, the date is the one of the id. Used when
+    /// the id is supplied by the client so that retries produce the exact same message.
+    pub fn from_id(id: MessageId, content: String) -> Self {
+        Self {
+            id,
+            user_id: id.user_id(),
+            date: id.datetime(),
+            mentions: extract_mentions(&content),
+            content,
+            body: MessageBody::Text,
+            reply_to: None,
+        }
+    }
+
+    /// Like `try_new`, the id being chosen by the client.
+    pub fn try_from_id(
+        id: MessageId,
+        content: &str,
+        policy: &ContentPolicy,
+    ) -> Result<Self, ContentError> {
+        Ok(Self::from_id(id, policy.normalize(content)?))
+    }
+}
+
+/// A message as seen by a given user.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimelineEntry {
+    pub message: Message,
+    pub read: bool,
+    /// Left empty unless asked for.
+    pub reactions: ReactionSummary,
+}
+
 impl PartialEq for Message {
     fn eq(&self, other: &Self) -> bool {
-        self.date == other.date
+        self.cmp(other).is_eq()
     }
 }
 
@@ -135,12 +509,167 @@ impl Eq for Message {}
 
 impl PartialOrd for Message {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.date.partial_cmp(&other.date)
+        Some(self.cmp(other))
     }
 }
 
+/// By date, then id for the messages of a same date: only the same message is equal.
 impl Ord for Message {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        self.date
+            .cmp(&other.date)
+            .then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn message_id_round_trip() {
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let id = MessageId::new_now(user_id);
+    let displayed = id.to_string();
+
+    assert_eq!(displayed.len(), 39);
+    assert_eq!(MessageId::try_parse(&displayed).unwrap(), id);
+    assert_eq!(MessageId::try_parse(displayed.to_lowercase()).unwrap(), id);
+    assert_eq!(MessageId::from_bytes(id.to_bytes()), id);
+    assert_eq!(MessageId::try_from_slice(&id.to_bytes()).unwrap(), id);
+    assert!(matches!(
+        MessageId::try_from_slice(&id.to_bytes()[..16]),
+        Err(MessageIdParsingError::Bytes(16))
+    ));
+    assert_eq!(UserId::from_bytes(user_id.to_bytes()), user_id);
+    assert!(UserId::try_from_slice(&[0; 24]).is_err());
+    assert_eq!(MessageId::from_tuple_i64(id.as_tuple_i64()), id);
+    assert!(
+        id.timestamp()
+            .abs_diff(Utc::now().timestamp_millis() as u64)
+            < 60_000
+    );
+
+    let legacy =
+        MessageId::try_parse("11234567-1234-5678-1234-567812345678x0000000064371AB8").unwrap();
+    assert_eq!(legacy.timestamp(), 0x64371AB8);
+    assert_eq!(legacy.user_id(), user_id);
+    assert_eq!(MessageId::try_parse(legacy.to_string()).unwrap(), legacy);
+}
+
+#[cfg(test)]
+#[test]
+fn messages_total_order() {
+    use std::collections::BTreeSet;
+
+    let alice = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let bob = UserId::try_parse("21234567-1234-5678-1234-567812345678").unwrap();
+    let date = Utc::now();
+
+    // Several messages of each date, of both users.
+    let messages: Vec<Message> = (0..30)
+        .map(|i| Message {
+            date: date + chrono::Duration::seconds(i % 3),
+            ..Message::new(if i % 2 == 0 { alice } else { bob }, i.to_string())
+        })
+        .collect();
+
+    for a in &messages {
+        for b in &messages {
+            assert_eq!(a == b, a.id == b.id);
+            assert_eq!(a.cmp(b), b.cmp(a).reverse());
+            if a.date != b.date {
+                assert_eq!(a.cmp(b), a.date.cmp(&b.date));
+            }
+            for c in &messages {
+                if a <= b && b <= c {
+                    assert!(a <= c);
+                }
+            }
+        }
     }
+
+    let set: BTreeSet<&Message> = messages.iter().collect();
+    assert_eq!(set.len(), messages.len());
+    assert!(set
+        .iter()
+        .zip(set.iter().skip(1))
+        .all(|(a, b)| a.date < b.date || (a.date == b.date && a.id < b.id)));
+}
+
+#[cfg(test)]
+#[test]
+fn message_builder_test() {
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let other = UserId::try_parse("21234567-1234-5678-1234-567812345678").unwrap();
+    let attachment = Attachment::new(user_id, "cat.png".to_string(), "image/png".to_string());
+
+    let message = Message::builder(user_id)
+        .content("  A   cat ")
+        .attachment(&attachment)
+        .policy(ContentPolicy::default())
+        .build()
+        .unwrap();
+    assert_eq!(message.content, "A cat");
+    assert_eq!(message.user_id, user_id);
+    assert!(matches!(
+        message.body,
+        MessageBody::Media { attachment_id, .. } if attachment_id == attachment.id
+    ));
+
+    let id = MessageId::new_now(user_id);
+    let retried = Message::builder(user_id).content("Hello").id(id).build();
+    assert_eq!(retried.unwrap().date, id.datetime());
+
+    let reply = Message::builder(other)
+        .content("Hi @Alice")
+        .reply_to(id)
+        .build()
+        .unwrap();
+    assert_eq!(reply.reply_to, Some(id));
+    assert!(reply.mentions_user(&User {
+        id: user_id,
+        name: "Alice".to_string()
+    }));
+    assert_eq!(
+        Message::builder(other)
+            .content("Hello")
+            .id(id)
+            .build()
+            .err(),
+        Some(MessageBuildError::OtherUser)
+    );
+    assert!(matches!(
+        Message::builder(user_id)
+            .policy(ContentPolicy::default())
+            .build(),
+        Err(MessageBuildError::Content(ContentError::Empty))
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn message_ids_sort_by_time() {
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let ids: Vec<MessageId> = (0..1000).map(|_| MessageId::new_now(user_id)).collect();
+
+    for pair in ids.windows(2) {
+        assert!(pair[0].to_string() < pair[1].to_string());
+        assert!(pair[0].timestamp() <= pair[1].timestamp());
+        assert!(pair[0] < pair[1]);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn extract_mentions_test() {
+    let names = |content: &str| -> Vec<String> {
+        extract_mentions(content)
+            .iter()
+            .map(|mention| mention.name().to_string())
+            .collect()
+    };
+
+    assert_eq!(names("Best music by @ChineseMan ouai"), ["ChineseMan"]);
+    assert_eq!(names("hello @bob! and @bob, @alice_2."), ["bob", "alice_2"]);
+    assert_eq!(names("hello @bobby"), ["bobby"]);
+    assert!(names("mail me at bob@bob.com").is_empty());
+    assert!(names("@ alone, @bob's and @averyveryverylongname").is_empty());
 }
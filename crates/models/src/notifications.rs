@@ -0,0 +1,85 @@
+use crate::conversations::DirectMessage;
+use crate::messages::{Message, MessageId};
+use crate::users::{Presence, UserId};
+
+/// Everything a user can be notified about in real time.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Notification {
+    /// A friend posted a message.
+    NewMessage(Message),
+    NewFriend(UserId),
+    FriendRemoved(UserId),
+    /// Someone asked to be friend with the user.
+    FriendRequest(UserId),
+    /// A request of the user was accepted, sent along the `NewFriend` of the friendship.
+    FriendAccepted(UserId),
+    /// One of the user's messages has been seen by `by`.
+    MessageSeen {
+        message: MessageId,
+        by: UserId,
+    },
+    /// `by` tagged one of the user's messages as unread again, withdrawing a `MessageSeen`.
+    MessageUnseen {
+        message: MessageId,
+        by: UserId,
+    },
+    /// Someone mentioned the user with `@name`, friend or not.
+    Mention(Message),
+    /// Another member posted in one of the user's conversations.
+    DirectMessage(DirectMessage),
+    /// A notice of the operators of the network to every user, eg. of a maintenance.
+    System(String),
+    /// A friend came online or went offline.
+    Presence(Presence),
+}
+
+/// What a `Notification` is about, for clients to only receive some of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NotificationKind {
+    NewMessage,
+    NewFriend,
+    FriendRemoved,
+    MessageSeen,
+    Mention,
+    MessageUnseen,
+    DirectMessage,
+    FriendRequest,
+    FriendAccepted,
+    System,
+    Presence,
+}
+
+impl Notification {
+    pub fn kind(&self) -> NotificationKind {
+        match self {
+            Notification::NewMessage(_) => NotificationKind::NewMessage,
+            Notification::NewFriend(_) => NotificationKind::NewFriend,
+            Notification::FriendRemoved(_) => NotificationKind::FriendRemoved,
+            Notification::MessageSeen { .. } => NotificationKind::MessageSeen,
+            Notification::Mention(_) => NotificationKind::Mention,
+            Notification::MessageUnseen { .. } => NotificationKind::MessageUnseen,
+            Notification::DirectMessage(_) => NotificationKind::DirectMessage,
+            Notification::FriendRequest(_) => NotificationKind::FriendRequest,
+            Notification::FriendAccepted(_) => NotificationKind::FriendAccepted,
+            Notification::System(_) => NotificationKind::System,
+            Notification::Presence(_) => NotificationKind::Presence,
+        }
+    }
+
+    /// The user the notification comes from, `None` for system notices.
+    pub fn user_id(&self) -> Option<UserId> {
+        Some(match self {
+            Notification::NewMessage(message) | Notification::Mention(message) => message.user_id,
+            Notification::NewFriend(user)
+            | Notification::FriendRemoved(user)
+            | Notification::FriendRequest(user)
+            | Notification::FriendAccepted(user) => *user,
+            Notification::MessageSeen { by, .. } | Notification::MessageUnseen { by, .. } => *by,
+            Notification::DirectMessage(direct_message) => direct_message.message.user_id,
+            Notification::Presence(presence) => presence.user_id,
+            Notification::System(_) => return None,
+        })
+    }
+}
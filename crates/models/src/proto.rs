@@ -1,8 +1,19 @@
 //! From/Into proto::Message;
 
-use crate::messages::{Message, MessageId, MessageIdParsingError};
-use crate::users::{UserId, UserIdParsingError};
-use chrono::NaiveDateTime;
+use crate::attachments::{Attachment, AttachmentId, AttachmentIdParsingError};
+use crate::conversations::{
+    ChatEvent, ChatSignal, ChatSignalKind, Conversation, ConversationId,
+    ConversationIdParsingError, ConversationMember, DirectMessage,
+};
+use crate::friendships::{FriendshipEvent, FriendshipState};
+use crate::messages::{
+    extract_mentions, Message, MessageBody, MessageId, MessageIdParsingError, TimelineEntry,
+};
+use crate::notifications::{Notification, NotificationKind};
+use crate::reactions::{Reaction, ReactionCount, ReactionError, ReactionSummary, ReactionUpdate};
+use crate::users::{Presence, UserId, UserIdParsingError, UserProfile, UserProfileError};
+use chrono::{DateTime, Utc};
+use proto::prost_types::Timestamp;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -12,32 +23,825 @@ pub enum ProtoDecodeMessageError {
     #[error("invalid UserId")]
     UserId(#[from] UserIdParsingError),
     #[error("invalid timestamp")]
-    Timestamp(u64),
+    Timestamp(i64),
+    #[error("missing timestamp")]
+    MissingTimestamp,
+    #[error("invalid ConversationId")]
+    ConversationId(#[from] ConversationIdParsingError),
+    #[error("missing message")]
+    MissingMessage,
+    #[error("invalid kind {0}")]
+    Kind(i32),
+    #[error("invalid Reaction")]
+    Reaction(#[from] ReactionError),
+    #[error("invalid AttachmentId")]
+    AttachmentId(#[from] AttachmentIdParsingError),
+    #[error("invalid encoding")]
+    Encoding(#[from] prost::DecodeError),
+    #[error("invalid UserProfile")]
+    UserProfile(#[from] UserProfileError),
+}
+
+/// The ID in `bytes` when set, else the one displayed in `string`: only the producers knowing
+/// binary IDs set them, the others only write strings.
+pub fn decode_user_id(bytes: &[u8], string: &str) -> Result<UserId, UserIdParsingError> {
+    match bytes.is_empty() {
+        true => UserId::try_parse(string),
+        false => UserId::try_from_slice(bytes),
+    }
+}
+
+/// As `decode_user_id`.
+pub fn decode_message_id(bytes: &[u8], string: &str) -> Result<MessageId, MessageIdParsingError> {
+    match bytes.is_empty() {
+        true => MessageId::try_parse(string),
+        false => MessageId::try_from_slice(bytes),
+    }
+}
+
+/// The `Timestamp` of `date`, as the dates are sent since `social_network.v1`.
+pub fn encode_timestamp(date: DateTime<Utc>) -> Timestamp {
+    Timestamp {
+        seconds: date.timestamp(),
+        nanos: date.timestamp_subsec_nanos() as i32,
+    }
+}
+
+/// The date of `timestamp`, rejected out of the range of `DateTime`.
+pub fn decode_timestamp(timestamp: Timestamp) -> Result<DateTime<Utc>, ProtoDecodeMessageError> {
+    u32::try_from(timestamp.nanos)
+        .ok()
+        .and_then(|nanos| DateTime::from_timestamp(timestamp.seconds, nanos))
+        .ok_or(ProtoDecodeMessageError::Timestamp(timestamp.seconds))
+}
+
+/// Seconds since the epoch of `timestamp`, 0 when unset, as the dates were sent before
+/// `social_network.v1`: for the code that still handles them, until it is migrated.
+pub fn timestamp_secs(timestamp: Option<&Timestamp>) -> u64 {
+    timestamp.map_or(0, |timestamp| timestamp.seconds.max(0) as u64)
+}
+
+/// The `Timestamp` of seconds since the epoch, unset for 0, as `timestamp_secs`.
+pub fn timestamp_from_secs(secs: u64) -> Option<Timestamp> {
+    (secs != 0).then_some(Timestamp {
+        seconds: secs as i64,
+        nanos: 0,
+    })
 }
 
 impl TryFrom<proto::Message> for Message {
     type Error = ProtoDecodeMessageError;
 
+    /// Mentions are extracted from the content again, rather than trusted.
     fn try_from(value: proto::Message) -> Result<Self, Self::Error> {
         Ok(Message {
-            id: MessageId::try_parse(value.message_id.as_str())?,
-            user_id: UserId::try_parse(value.user_id.as_str())?,
-            date: NaiveDateTime::from_timestamp_opt(value.timestamp as i64, 0)
-                .ok_or_else(|| ProtoDecodeMessageError::Timestamp(value.timestamp))?,
+            id: decode_message_id(&value.message_id_bytes, &value.message_id)?,
+            user_id: decode_user_id(&value.user_id_bytes, &value.user_id)?,
+            date: decode_timestamp(
+                value
+                    .timestamp
+                    .ok_or(ProtoDecodeMessageError::MissingTimestamp)?,
+            )?,
+            mentions: extract_mentions(&value.content),
             content: value.content,
+            body: value
+                .body
+                .map(MessageBody::try_from)
+                .transpose()?
+                .unwrap_or_default(),
+            reply_to: value.reply_to.map(MessageId::try_parse).transpose()?,
         })
     }
 }
 
 #[cfg(feature = "proto")]
-impl Into<proto::Message> for Message {
-    fn into(self) -> proto::Message {
+impl From<Message> for proto::Message {
+    fn from(value: Message) -> Self {
         proto::Message {
-            message_id: self.id.to_string(),
-            user_id: self.user_id.to_string(),
-            timestamp: self.date.timestamp() as u64,
-            content: self.content.clone(),
+            message_id: value.id.to_string(),
+            user_id: value.user_id.to_string(),
+            timestamp: Some(encode_timestamp(value.date)),
+            content: value.content.clone(),
             read: false,
+            reactions: Vec::new(),
+            body: Some(value.body.into()).filter(|body: &proto::MessageBody| body.kind.is_some()),
+            user_id_bytes: Vec::new(),
+            message_id_bytes: Vec::new(),
+            mentions: value
+                .mentions
+                .iter()
+                .map(|mention| mention.name().to_string())
+                .collect(),
+            reply_to: value.reply_to.map(|reply_to| reply_to.to_string()),
+        }
+    }
+}
+
+impl TryFrom<proto::MessageBody> for MessageBody {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: proto::MessageBody) -> Result<Self, Self::Error> {
+        use proto::message_body::Kind;
+
+        Ok(match value.kind {
+            None => MessageBody::Text,
+            Some(Kind::LinkPreview(link)) => MessageBody::LinkPreview {
+                url: link.url,
+                title: link.title,
+                description: link.description,
+            },
+            Some(Kind::Media(media)) => MessageBody::Media {
+                attachment_id: media.attachment_id.parse()?,
+                content_type: media.content_type,
+            },
+            Some(Kind::SystemNotice(_)) => MessageBody::SystemNotice,
+        })
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<MessageBody> for proto::MessageBody {
+    fn from(value: MessageBody) -> Self {
+        use proto::message_body::Kind;
+
+        let kind = match value {
+            MessageBody::Text => None,
+            MessageBody::LinkPreview {
+                url,
+                title,
+                description,
+            } => Some(Kind::LinkPreview(proto::LinkPreview {
+                url,
+                title,
+                description,
+            })),
+            MessageBody::Media {
+                attachment_id,
+                content_type,
+            } => Some(Kind::Media(proto::MediaReference {
+                attachment_id: attachment_id.to_string(),
+                content_type,
+            })),
+            MessageBody::SystemNotice => Some(Kind::SystemNotice(proto::SystemNotice {})),
+        };
+
+        proto::MessageBody { kind }
+    }
+}
+
+/// How bodies are stored, as their protobuf encoding.
+impl MessageBody {
+    /// `None` for `Text`, which needs nothing stored.
+    pub fn encode(&self) -> Option<Vec<u8>> {
+        use prost::Message;
+
+        match self {
+            MessageBody::Text => None,
+            body => Some(Into::<proto::MessageBody>::into(body.clone()).encode_to_vec()),
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtoDecodeMessageError> {
+        use prost::Message;
+
+        proto::MessageBody::decode(bytes)?.try_into()
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<TimelineEntry> for proto::Message {
+    fn from(value: TimelineEntry) -> Self {
+        proto::Message {
+            read: value.read,
+            reactions: value.reactions.counts.into_iter().map(Into::into).collect(),
+            ..value.message.into()
+        }
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<ReactionCount> for proto::ReactionCount {
+    fn from(value: ReactionCount) -> Self {
+        proto::ReactionCount {
+            emoji: value.emoji,
+            count: value.count,
+        }
+    }
+}
+
+impl From<proto::ReactionCount> for ReactionCount {
+    fn from(value: proto::ReactionCount) -> Self {
+        ReactionCount {
+            emoji: value.emoji,
+            count: value.count,
+        }
+    }
+}
+
+impl TryFrom<proto::ReactionSummary> for ReactionSummary {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: proto::ReactionSummary) -> Result<Self, Self::Error> {
+        Ok(
+            ReactionSummary::new(MessageId::try_parse(value.message_id.as_str())?)
+                .with_counts(value.counts.into_iter().map(Into::into).collect()),
+        )
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<ReactionSummary> for proto::ReactionSummary {
+    fn from(value: ReactionSummary) -> Self {
+        proto::ReactionSummary {
+            message_id: value.message_id.to_string(),
+            counts: value.counts.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::ReactionUpdate> for ReactionUpdate {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: proto::ReactionUpdate) -> Result<Self, Self::Error> {
+        let reaction = Reaction::new(
+            MessageId::try_parse(value.message_id.as_str())?,
+            UserId::try_parse(value.user_id.as_str())?,
+            value.emoji,
+        )?
+        .with_added_at(decode_timestamp(
+            value
+                .added_at
+                .ok_or(ProtoDecodeMessageError::MissingTimestamp)?,
+        )?);
+
+        Ok(match value.removed {
+            false => ReactionUpdate::Added(reaction),
+            true => ReactionUpdate::Removed(reaction),
+        })
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<ReactionUpdate> for proto::ReactionUpdate {
+    fn from(value: ReactionUpdate) -> Self {
+        let (reaction, removed) = match value {
+            ReactionUpdate::Added(reaction) => (reaction, false),
+            ReactionUpdate::Removed(reaction) => (reaction, true),
+        };
+
+        proto::ReactionUpdate {
+            message_id: reaction.message_id.to_string(),
+            user_id: reaction.user_id.to_string(),
+            emoji: reaction.emoji,
+            removed,
+            added_at: Some(encode_timestamp(reaction.added_at)),
+        }
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<Notification> for proto::NotificationsResponse {
+    fn from(value: Notification) -> Self {
+        use proto::notifications_response::Event;
+
+        let kind: proto::NotificationKind = value.kind().into();
+        let friendship = |friend: UserId| {
+            Event::Friendship(proto::Friendship {
+                user: String::new(),
+                friend: friend.to_string(),
+                user_bytes: Vec::new(),
+                friend_bytes: Vec::new(),
+            })
+        };
+        let read_receipt = |message: MessageId, by: UserId| {
+            Event::ReadReceipt(proto::MessageRead {
+                message_id: message.to_string(),
+                user_id: by.to_string(),
+            })
+        };
+
+        let event = match value {
+            Notification::NewMessage(message) | Notification::Mention(message) => {
+                Event::NewMessage(message.into())
+            }
+            Notification::NewFriend(friend)
+            | Notification::FriendRemoved(friend)
+            | Notification::FriendRequest(friend)
+            | Notification::FriendAccepted(friend) => friendship(friend),
+            Notification::MessageSeen { message, by }
+            | Notification::MessageUnseen { message, by } => read_receipt(message, by),
+            Notification::DirectMessage(direct_message) => {
+                Event::DirectMessage(direct_message.into())
+            }
+            Notification::System(text) => Event::Notice(proto::BroadcastNotice { text }),
+            Notification::Presence(presence) => Event::Presence(presence.into()),
+        };
+
+        proto::NotificationsResponse {
+            kind: kind.into(),
+            event_id: String::new(),
+            event: Some(event),
+        }
+    }
+}
+
+impl TryFrom<proto::NotificationsResponse> for Notification {
+    type Error = ProtoDecodeMessageError;
+
+    /// The event must be the one of the kind.
+    fn try_from(value: proto::NotificationsResponse) -> Result<Self, Self::Error> {
+        use proto::notifications_response::Event;
+
+        let kind = NotificationKind::try_from(value.kind)?;
+        let event = value.event.ok_or(ProtoDecodeMessageError::MissingMessage)?;
+
+        Ok(match (kind, event) {
+            (NotificationKind::NewMessage, Event::NewMessage(message)) => {
+                Notification::NewMessage(message.try_into()?)
+            }
+            (NotificationKind::Mention, Event::NewMessage(message)) => {
+                Notification::Mention(message.try_into()?)
+            }
+            (kind, Event::Friendship(friendship)) => {
+                let friend = decode_user_id(&friendship.friend_bytes, &friendship.friend)?;
+
+                match kind {
+                    NotificationKind::NewFriend => Notification::NewFriend(friend),
+                    NotificationKind::FriendRemoved => Notification::FriendRemoved(friend),
+                    NotificationKind::FriendRequest => Notification::FriendRequest(friend),
+                    NotificationKind::FriendAccepted => Notification::FriendAccepted(friend),
+                    _ => return Err(ProtoDecodeMessageError::Kind(value.kind)),
+                }
+            }
+            (kind, Event::ReadReceipt(read)) => {
+                let message = MessageId::try_parse(read.message_id.as_str())?;
+                let by = UserId::try_parse(read.user_id.as_str())?;
+
+                match kind {
+                    NotificationKind::MessageSeen => Notification::MessageSeen { message, by },
+                    NotificationKind::MessageUnseen => Notification::MessageUnseen { message, by },
+                    _ => return Err(ProtoDecodeMessageError::Kind(value.kind)),
+                }
+            }
+            (NotificationKind::DirectMessage, Event::DirectMessage(direct_message)) => {
+                Notification::DirectMessage(direct_message.try_into()?)
+            }
+            (NotificationKind::System, Event::Notice(notice)) => Notification::System(notice.text),
+            (NotificationKind::Presence, Event::Presence(presence)) => {
+                Notification::Presence(presence.try_into()?)
+            }
+            _ => return Err(ProtoDecodeMessageError::Kind(value.kind)),
+        })
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<NotificationKind> for proto::NotificationKind {
+    fn from(value: NotificationKind) -> Self {
+        use proto::NotificationKind as Kind;
+
+        match value {
+            NotificationKind::NewMessage => Kind::NewMessage,
+            NotificationKind::NewFriend => Kind::NewFriend,
+            NotificationKind::FriendRemoved => Kind::FriendRemoved,
+            NotificationKind::MessageSeen => Kind::MessageSeen,
+            NotificationKind::Mention => Kind::Mention,
+            NotificationKind::MessageUnseen => Kind::MessageUnseen,
+            NotificationKind::DirectMessage => Kind::DirectMessage,
+            NotificationKind::FriendRequest => Kind::FriendRequest,
+            NotificationKind::FriendAccepted => Kind::FriendAccepted,
+            NotificationKind::System => Kind::System,
+            NotificationKind::Presence => Kind::Presence,
+        }
+    }
+}
+
+impl TryFrom<i32> for NotificationKind {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        use proto::NotificationKind as Kind;
+
+        Ok(match Kind::from_i32(value) {
+            Some(Kind::NewMessage) => NotificationKind::NewMessage,
+            Some(Kind::NewFriend) => NotificationKind::NewFriend,
+            Some(Kind::FriendRemoved) => NotificationKind::FriendRemoved,
+            Some(Kind::MessageSeen) => NotificationKind::MessageSeen,
+            Some(Kind::Mention) => NotificationKind::Mention,
+            Some(Kind::MessageUnseen) => NotificationKind::MessageUnseen,
+            Some(Kind::DirectMessage) => NotificationKind::DirectMessage,
+            Some(Kind::FriendRequest) => NotificationKind::FriendRequest,
+            Some(Kind::FriendAccepted) => NotificationKind::FriendAccepted,
+            Some(Kind::System) => NotificationKind::System,
+            Some(Kind::Presence) => NotificationKind::Presence,
+            None => return Err(ProtoDecodeMessageError::Kind(value)),
+        })
+    }
+}
+
+impl TryFrom<proto::DirectMessage> for DirectMessage {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: proto::DirectMessage) -> Result<Self, Self::Error> {
+        let message = value
+            .message
+            .ok_or(ProtoDecodeMessageError::MissingMessage)?;
+
+        Ok(DirectMessage {
+            conversation_id: ConversationId::try_parse(value.conversation_id.as_str())?,
+            message: Message::try_from(message)?,
+        })
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<DirectMessage> for proto::DirectMessage {
+    fn from(value: DirectMessage) -> Self {
+        proto::DirectMessage {
+            conversation_id: value.conversation_id.to_string(),
+            message: Some(value.message.into()),
+        }
+    }
+}
+
+impl TryFrom<proto::ConversationResponse> for Conversation {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: proto::ConversationResponse) -> Result<Self, Self::Error> {
+        Ok(Conversation {
+            id: ConversationId::try_parse(value.conversation_id.as_str())?,
+            members: value
+                .member_ids
+                .iter()
+                .map(UserId::try_parse)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<Conversation> for proto::ConversationResponse {
+    fn from(value: Conversation) -> Self {
+        proto::ConversationResponse {
+            conversation_id: value.id.to_string(),
+            member_ids: value.members.iter().map(ToString::to_string).collect(),
         }
     }
 }
+
+impl TryFrom<proto::ConversationMember> for ConversationMember {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: proto::ConversationMember) -> Result<Self, Self::Error> {
+        Ok(ConversationMember {
+            conversation_id: ConversationId::try_parse(value.conversation_id.as_str())?,
+            user_id: UserId::try_parse(value.user_id.as_str())?,
+        })
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<ConversationMember> for proto::ConversationMember {
+    fn from(value: ConversationMember) -> Self {
+        proto::ConversationMember {
+            conversation_id: value.conversation_id.to_string(),
+            user_id: value.user_id.to_string(),
+        }
+    }
+}
+
+impl TryFrom<proto::ChatSignal> for ChatSignal {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: proto::ChatSignal) -> Result<Self, Self::Error> {
+        let kind = match proto::ChatSignalKind::from_i32(value.kind) {
+            Some(proto::ChatSignalKind::Typing) => ChatSignalKind::Typing,
+            Some(proto::ChatSignalKind::Ack) => {
+                ChatSignalKind::Ack(MessageId::try_parse(value.message_id.as_str())?)
+            }
+            None => return Err(ProtoDecodeMessageError::Kind(value.kind)),
+        };
+
+        Ok(ChatSignal {
+            conversation_id: ConversationId::try_parse(value.conversation_id.as_str())?,
+            user_id: UserId::try_parse(value.user_id.as_str())?,
+            kind,
+        })
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<ChatSignal> for proto::ChatSignal {
+    fn from(value: ChatSignal) -> Self {
+        let (kind, message_id) = match value.kind {
+            ChatSignalKind::Typing => (proto::ChatSignalKind::Typing, None),
+            ChatSignalKind::Ack(message) => (proto::ChatSignalKind::Ack, Some(message)),
+        };
+
+        proto::ChatSignal {
+            conversation_id: value.conversation_id.to_string(),
+            user_id: value.user_id.to_string(),
+            kind: kind.into(),
+            message_id: message_id.map(|m| m.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<ChatEvent> for proto::ChatServerEvent {
+    fn from(value: ChatEvent) -> Self {
+        use proto::chat_server_event::Event;
+
+        let event = match value {
+            ChatEvent::Message(message) => Event::Message(message.into()),
+            ChatEvent::Signal(signal) => Event::Signal(signal.into()),
+        };
+
+        proto::ChatServerEvent { event: Some(event) }
+    }
+}
+
+impl TryFrom<proto::UserProfile> for UserProfile {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: proto::UserProfile) -> Result<Self, Self::Error> {
+        let profile = UserProfile {
+            user_id: UserId::try_parse(value.user_id.as_str())?,
+            display_name: value.display_name,
+            bio: value.bio,
+            avatar_url: value.avatar_url.filter(|url| !url.is_empty()),
+            created_at: decode_timestamp(
+                value
+                    .created_at
+                    .ok_or(ProtoDecodeMessageError::MissingTimestamp)?,
+            )?,
+            last_seen: value.last_seen.map(decode_timestamp).transpose()?,
+        };
+        profile.validate()?;
+
+        Ok(profile)
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<UserProfile> for proto::UserProfile {
+    fn from(value: UserProfile) -> Self {
+        proto::UserProfile {
+            user_id: value.user_id.to_string(),
+            display_name: value.display_name,
+            bio: value.bio,
+            avatar_url: value.avatar_url,
+            created_at: Some(encode_timestamp(value.created_at)),
+            last_seen: value.last_seen.map(encode_timestamp),
+        }
+    }
+}
+
+impl TryFrom<proto::Presence> for Presence {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: proto::Presence) -> Result<Self, Self::Error> {
+        Ok(Presence {
+            user_id: UserId::try_parse(value.user_id.as_str())?,
+            online: value.online,
+            last_seen_at: value.last_seen_at.map(decode_timestamp).transpose()?,
+        })
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<Presence> for proto::Presence {
+    fn from(value: Presence) -> Self {
+        proto::Presence {
+            user_id: value.user_id.to_string(),
+            online: value.online,
+            last_seen_at: value.last_seen_at.map(encode_timestamp),
+        }
+    }
+}
+
+impl TryFrom<i32> for FriendshipState {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        Ok(match proto::FriendshipState::from_i32(value) {
+            Some(proto::FriendshipState::Pending) => FriendshipState::Pending,
+            Some(proto::FriendshipState::Accepted) => FriendshipState::Accepted,
+            Some(proto::FriendshipState::Declined) => FriendshipState::Declined,
+            Some(proto::FriendshipState::Blocked) => FriendshipState::Blocked,
+            Some(proto::FriendshipState::NotFriends) | None => {
+                return Err(ProtoDecodeMessageError::Kind(value))
+            }
+        })
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<FriendshipState> for proto::FriendshipState {
+    fn from(value: FriendshipState) -> Self {
+        match value {
+            FriendshipState::Pending => proto::FriendshipState::Pending,
+            FriendshipState::Accepted => proto::FriendshipState::Accepted,
+            FriendshipState::Declined => proto::FriendshipState::Declined,
+            FriendshipState::Blocked => proto::FriendshipState::Blocked,
+        }
+    }
+}
+
+impl TryFrom<proto::FriendshipEvent> for FriendshipEvent {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: proto::FriendshipEvent) -> Result<Self, Self::Error> {
+        Ok(FriendshipEvent {
+            initiator: decode_user_id(&value.initiator_id_bytes, &value.initiator_id)?,
+            target: decode_user_id(&value.target_id_bytes, &value.target_id)?,
+            state: FriendshipState::try_from(value.state)?,
+        })
+    }
+}
+
+#[cfg(feature = "proto")]
+impl From<FriendshipEvent> for proto::FriendshipEvent {
+    fn from(value: FriendshipEvent) -> Self {
+        let state: proto::FriendshipState = value.state.into();
+
+        proto::FriendshipEvent {
+            initiator_id: value.initiator.to_string(),
+            target_id: value.target.to_string(),
+            state: state.into(),
+            initiator_id_bytes: Vec::new(),
+            target_id_bytes: Vec::new(),
+        }
+    }
+}
+
+impl TryFrom<proto::Attachment> for Attachment {
+    type Error = ProtoDecodeMessageError;
+
+    fn try_from(value: proto::Attachment) -> Result<Self, Self::Error> {
+        Ok(Attachment {
+            id: AttachmentId::try_parse(value.attachment_id.as_str())?,
+            user_id: UserId::try_parse(value.user_id.as_str())?,
+            message_id: value.message_id.map(MessageId::try_parse).transpose()?,
+            filename: value.filename,
+            content_type: value.content_type,
+            size: value.size,
+            checksum: value.checksum,
+        })
+    }
+}
+
+/// `url` is left empty, for the server to fill.
+#[cfg(feature = "proto")]
+impl From<Attachment> for proto::Attachment {
+    fn from(value: Attachment) -> Self {
+        proto::Attachment {
+            attachment_id: value.id.to_string(),
+            user_id: value.user_id.to_string(),
+            message_id: value.message_id.map(|message_id| message_id.to_string()),
+            filename: value.filename,
+            content_type: value.content_type,
+            size: value.size,
+            checksum: value.checksum,
+            url: String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn message_body_storage_round_trip() {
+    use crate::attachments::AttachmentId;
+
+    assert_eq!(MessageBody::Text.encode(), None);
+
+    let bodies = [
+        MessageBody::LinkPreview {
+            url: "https://example.com".to_string(),
+            title: "Example".to_string(),
+            description: String::new(),
+        },
+        MessageBody::Media {
+            attachment_id: AttachmentId::try_parse("11234567-1234-5678-1234-567812345678")
+                .unwrap(),
+            content_type: "image/png".to_string(),
+        },
+        MessageBody::SystemNotice,
+    ];
+    for body in bodies {
+        let encoded = body.encode().unwrap();
+        assert_eq!(MessageBody::decode(&encoded).unwrap(), body);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn message_date_round_trip() {
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    // Whatever the offset it was written with, the date is the same instant.
+    let date = DateTime::parse_from_rfc3339("2023-11-14T14:00:00+02:00")
+        .unwrap()
+        .to_utc();
+    let message = Message {
+        date,
+        ..Message::new(user_id, "Hello".to_string())
+    };
+
+    let encoded: proto::Message = message.into();
+    assert_eq!(timestamp_secs(encoded.timestamp.as_ref()), 1_699_963_200);
+    assert_eq!(Message::try_from(encoded).unwrap().date, date);
+
+    // Not rounded to the second anymore.
+    let precise = DateTime::from_timestamp(1_699_963_200, 250_000_000).unwrap();
+    assert_eq!(decode_timestamp(encode_timestamp(precise)).unwrap(), precise);
+    assert!(decode_timestamp(Timestamp {
+        seconds: 0,
+        nanos: -1
+    })
+    .is_err());
+    assert_eq!(timestamp_from_secs(0), None);
+    assert_eq!(timestamp_secs(timestamp_from_secs(60).as_ref()), 60);
+}
+
+#[cfg(test)]
+#[test]
+fn notification_kinds_round_trip() {
+    use proto::notifications_response::Event;
+
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+
+    let accepted: proto::NotificationsResponse = Notification::FriendAccepted(user_id).into();
+    assert!(matches!(
+        &accepted.event,
+        Some(Event::Friendship(friendship)) if friendship.friend == user_id.to_string()
+    ));
+    assert!(matches!(
+        Notification::try_from(accepted),
+        Ok(Notification::FriendAccepted(friend)) if friend == user_id
+    ));
+
+    let notice: proto::NotificationsResponse =
+        Notification::System("Maintenance at 22:00".to_string()).into();
+    assert!(matches!(
+        NotificationKind::try_from(notice.kind),
+        Ok(NotificationKind::System)
+    ));
+    assert!(matches!(
+        Notification::try_from(notice),
+        Ok(Notification::System(text)) if text == "Maintenance at 22:00"
+    ));
+
+    let message = MessageId::new_now(user_id);
+    let unseen: proto::NotificationsResponse = Notification::MessageUnseen {
+        message,
+        by: user_id,
+    }
+    .into();
+    assert!(matches!(
+        Notification::try_from(unseen),
+        Ok(Notification::MessageUnseen { message: m, by }) if m == message && by == user_id
+    ));
+
+    let presence = Presence {
+        user_id,
+        online: true,
+        last_seen_at: DateTime::from_timestamp(1_699_963_200, 0),
+    };
+    let online: proto::NotificationsResponse = Notification::Presence(presence).into();
+    assert!(matches!(
+        Notification::try_from(online),
+        Ok(Notification::Presence(decoded)) if decoded == presence
+    ));
+
+    // The event must be of the kind.
+    let mismatched = proto::NotificationsResponse {
+        kind: proto::NotificationKind::Presence.into(),
+        event_id: String::new(),
+        event: Some(Event::Notice(proto::BroadcastNotice::default())),
+    };
+    assert!(Notification::try_from(mismatched).is_err());
+    assert!(NotificationKind::try_from(-1).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn attachment_round_trip() {
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let attachment = Attachment {
+        message_id: Some(MessageId::new_now(user_id)),
+        size: 3,
+        checksum: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string(),
+        ..Attachment::new(user_id, "abc.txt".to_string(), "text/plain".to_string())
+    };
+
+    let encoded: proto::Attachment = attachment.clone().into();
+    let decoded = Attachment::try_from(encoded).unwrap();
+    assert_eq!(decoded.id, attachment.id);
+    assert_eq!(decoded.message_id, attachment.message_id);
+    assert_eq!(decoded.checksum, attachment.checksum);
+
+    let unattached: proto::Attachment =
+        Attachment::new(user_id, "abc.txt".to_string(), "text/plain".to_string()).into();
+    assert!(unattached.message_id.is_none());
+    assert!(Attachment::try_from(unattached).unwrap().message_id.is_none());
+}
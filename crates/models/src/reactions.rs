@@ -0,0 +1,191 @@
+//! Emoji reactions of users on messages.
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+use crate::messages::{MessageId, Messagelike};
+use crate::users::{UserId, Userlike};
+
+#[derive(Error, Debug)]
+pub enum ReactionError {
+    #[error("empty emoji")]
+    Empty,
+    #[error("emoji longer than {} characters", Reaction::MAX_EMOJI_CHARS)]
+    TooLong,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reaction {
+    pub message_id: MessageId,
+    pub user_id: UserId,
+    pub emoji: String,
+    pub added_at: DateTime<Utc>,
+}
+
+impl Reaction {
+    /// Enough for emojis made of several code points (skin tones, flags, families...).
+    pub const MAX_EMOJI_CHARS: usize = 16;
+
+    pub fn new(
+        message: impl Messagelike,
+        user: impl Userlike,
+        emoji: String,
+    ) -> Result<Self, ReactionError> {
+        match emoji.chars().count() {
+            0 => return Err(ReactionError::Empty),
+            n if n > Self::MAX_EMOJI_CHARS => return Err(ReactionError::TooLong),
+            _ => {}
+        }
+
+        Ok(Self {
+            message_id: message.get_id(),
+            user_id: user.get_id(),
+            emoji,
+            added_at: Utc::now(),
+        })
+    }
+
+    pub fn with_added_at(self, added_at: DateTime<Utc>) -> Self {
+        Self { added_at, ..self }
+    }
+}
+
+/// Number of users who reacted to a message with `emoji`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: u64,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReactionUpdate {
+    Added(Reaction),
+    Removed(Reaction),
+}
+
+/// The reactions of a message, counted by emoji, the most used first.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReactionSummary {
+    pub message_id: MessageId,
+    pub counts: Vec<ReactionCount>,
+}
+
+impl ReactionSummary {
+    /// Without any reaction.
+    pub fn new(message: impl Messagelike) -> Self {
+        Self {
+            message_id: message.get_id(),
+            counts: Vec::new(),
+        }
+    }
+
+    pub fn with_counts(self, counts: Vec<ReactionCount>) -> Self {
+        let mut summary = Self { counts, ..self };
+        summary.sort();
+
+        summary
+    }
+
+    /// Ties by emoji, for a stable order.
+    fn sort(&mut self) {
+        self.counts
+            .sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.emoji.cmp(&b.emoji)));
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().map(|count| count.count).sum()
+    }
+
+    /// Keeps the summary up to date with the updates of its message, others are ignored.
+    pub fn apply(&mut self, update: &ReactionUpdate) {
+        let (reaction, added) = match update {
+            ReactionUpdate::Added(reaction) => (reaction, true),
+            ReactionUpdate::Removed(reaction) => (reaction, false),
+        };
+        if reaction.message_id != self.message_id {
+            return;
+        }
+
+        let position = self
+            .counts
+            .iter()
+            .position(|count| count.emoji == reaction.emoji);
+        match (position, added) {
+            (Some(i), true) => self.counts[i].count += 1,
+            (Some(i), false) if self.counts[i].count > 1 => self.counts[i].count -= 1,
+            (Some(i), false) => {
+                self.counts.remove(i);
+            }
+            (None, true) => self.counts.push(ReactionCount {
+                emoji: reaction.emoji.clone(),
+                count: 1,
+            }),
+            (None, false) => return,
+        }
+
+        self.sort();
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn reaction_emoji_test() {
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let message_id = MessageId::new_now(user_id);
+
+    assert!(Reaction::new(message_id, user_id, "👍".to_string()).is_ok());
+    // Family emoji, several code points joined together.
+    assert!(Reaction::new(message_id, user_id, "👨‍👩‍👧‍👦".to_string()).is_ok());
+    assert!(matches!(
+        Reaction::new(message_id, user_id, String::new()),
+        Err(ReactionError::Empty)
+    ));
+    assert!(matches!(
+        Reaction::new(message_id, user_id, "not an emoji at all".to_string()),
+        Err(ReactionError::TooLong)
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn reaction_summary_test() {
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let message_id = MessageId::new_now(user_id);
+    let reaction = |emoji: &str| Reaction::new(message_id, user_id, emoji.to_string()).unwrap();
+
+    let mut summary = ReactionSummary::new(message_id).with_counts(vec![
+        ReactionCount {
+            emoji: "👍".to_string(),
+            count: 1,
+        },
+        ReactionCount {
+            emoji: "🎉".to_string(),
+            count: 2,
+        },
+    ]);
+    assert_eq!(summary.counts[0].emoji, "🎉");
+    assert_eq!(summary.total(), 3);
+
+    summary.apply(&ReactionUpdate::Added(reaction("👍")));
+    summary.apply(&ReactionUpdate::Added(reaction("👍")));
+    summary.apply(&ReactionUpdate::Removed(reaction("🎉")));
+    summary.apply(&ReactionUpdate::Removed(reaction("🎉")));
+    summary.apply(&ReactionUpdate::Removed(reaction("🔥")));
+    // Of another message.
+    summary.apply(&ReactionUpdate::Added(Reaction {
+        message_id: MessageId::new_now(user_id),
+        ..reaction("🎉")
+    }));
+
+    assert_eq!(
+        summary.counts,
+        vec![ReactionCount {
+            emoji: "👍".to_string(),
+            count: 3,
+        }]
+    );
+}
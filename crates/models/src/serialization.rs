@@ -0,0 +1,71 @@
+//! `serde` support of the identifiers, serialized as they are displayed so that they read the
+//! same in JSON as in the gRPC API. The other models derive theirs.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::attachments::AttachmentId;
+use crate::conversations::ConversationId;
+use crate::events::{EventId, ProducerId};
+use crate::messages::MessageId;
+use crate::users::UserId;
+
+fn serialize_displayed<S: Serializer>(
+    value: &impl Display,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+fn deserialize_parsed<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+
+    s.parse().map_err(de::Error::custom)
+}
+
+macro_rules! displayed {
+    ($($t:ty),*) => {$(
+        impl Serialize for $t {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serialize_displayed(self, serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $t {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserialize_parsed(deserializer)
+            }
+        }
+    )*};
+}
+
+displayed!(UserId, MessageId, ConversationId, AttachmentId, EventId, ProducerId);
+
+#[cfg(test)]
+#[test]
+fn serde_round_trip() {
+    use crate::messages::{Message, MessageBody};
+
+    let user_id = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let message = Message::new(user_id, "Hello".to_string()).with_body(MessageBody::LinkPreview {
+        url: "https://example.com".to_string(),
+        title: "Example".to_string(),
+        description: String::new(),
+    });
+
+    let json = serde_json::to_value(&message).unwrap();
+    assert_eq!(json["id"], message.id.to_string());
+    assert_eq!(json["user_id"], "11234567-1234-5678-1234-567812345678");
+
+    let parsed: Message = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed.id, message.id);
+    assert_eq!(parsed.body, message.body);
+    assert!(serde_json::from_str::<UserId>("\"not an id\"").is_err());
+}
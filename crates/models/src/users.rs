@@ -1,10 +1,11 @@
 use std::{fmt::Display, str::FromStr};
 
+use chrono::{DateTime, Utc};
 use thiserror::Error;
 
 use uuid::Uuid;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct UserId(Uuid);
 
 #[derive(Error, Debug)]
@@ -41,6 +42,19 @@ impl UserId {
     pub fn try_parse(s: impl AsRef<str>) -> Result<Self, UserIdParsingError> {
         s.as_ref().parse()
     }
+
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(Uuid::from_bytes(bytes))
+    }
+
+    /// Fails unless `bytes` is 16 bytes long.
+    pub fn try_from_slice(bytes: &[u8]) -> Result<Self, UserIdParsingError> {
+        Ok(Self(Uuid::from_slice(bytes)?))
+    }
+
+    pub fn to_bytes(self) -> [u8; 16] {
+        self.0.into_bytes()
+    }
 }
 
 pub trait Userlike: Sized {
@@ -54,7 +68,188 @@ impl Userlike for UserId {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct User {
     pub id: UserId,
     pub name: String,
 }
+
+impl User {
+    /// Size of the `name` column.
+    pub const MAX_NAME_CHARS: usize = 16;
+    /// Of the passwords of new users.
+    pub const MIN_PASSWORD_CHARS: usize = 8;
+    pub const MAX_PASSWORD_CHARS: usize = 128;
+}
+
+/// A user referred to by name, as in `@name` mentions, not resolved to its id. The user may not
+/// exist.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UserRefByName(String);
+
+impl UserRefByName {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    pub fn refers_to(&self, user: &User) -> bool {
+        self.0 == user.name
+    }
+}
+
+impl Display for UserRefByName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "@{}", self.0)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum UserProfileError {
+    #[error("empty display name")]
+    EmptyDisplayName,
+    #[error(
+        "display name longer than {} characters",
+        UserProfile::MAX_DISPLAY_NAME_CHARS
+    )]
+    DisplayNameTooLong,
+    #[error("bio longer than {} characters", UserProfile::MAX_BIO_CHARS)]
+    BioTooLong,
+    #[error(
+        "avatar URL must be an http(s) URL of at most {} characters",
+        UserProfile::MAX_AVATAR_URL_CHARS
+    )]
+    AvatarUrl,
+}
+
+/// What users show of themselves, apart from the `User` identifying them.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UserProfile {
+    pub user_id: UserId,
+    pub display_name: String,
+    pub bio: String,
+    pub avatar_url: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// `None` if the user never connected.
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl UserProfile {
+    pub const MAX_DISPLAY_NAME_CHARS: usize = 64;
+    pub const MAX_BIO_CHARS: usize = 500;
+    pub const MAX_AVATAR_URL_CHARS: usize = 2048;
+
+    /// The display name defaults to the name of the user.
+    pub fn new(user: &User, created_at: DateTime<Utc>) -> Self {
+        Self {
+            user_id: user.id,
+            display_name: user.name.clone(),
+            bio: String::new(),
+            avatar_url: None,
+            created_at,
+            last_seen: None,
+        }
+    }
+
+    pub fn with_display_name(self, display_name: String) -> Self {
+        Self {
+            display_name,
+            ..self
+        }
+    }
+
+    pub fn with_bio(self, bio: String) -> Self {
+        Self { bio, ..self }
+    }
+
+    pub fn with_avatar_url(self, avatar_url: String) -> Self {
+        Self {
+            avatar_url: Some(avatar_url),
+            ..self
+        }
+    }
+
+    pub fn with_last_seen(self, last_seen: DateTime<Utc>) -> Self {
+        Self {
+            last_seen: Some(last_seen),
+            ..self
+        }
+    }
+
+    /// Checks what users can edit, before it is stored.
+    pub fn validate(&self) -> Result<(), UserProfileError> {
+        match self.display_name.trim().chars().count() {
+            0 => return Err(UserProfileError::EmptyDisplayName),
+            n if n > Self::MAX_DISPLAY_NAME_CHARS => {
+                return Err(UserProfileError::DisplayNameTooLong)
+            }
+            _ => {}
+        }
+        if self.bio.chars().count() > Self::MAX_BIO_CHARS {
+            return Err(UserProfileError::BioTooLong);
+        }
+        if let Some(url) = &self.avatar_url {
+            let http = url.starts_with("https://") || url.starts_with("http://");
+            if !http || url.chars().count() > Self::MAX_AVATAR_URL_CHARS {
+                return Err(UserProfileError::AvatarUrl);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Userlike for UserProfile {
+    fn get_id(&self) -> UserId {
+        self.user_id
+    }
+}
+
+/// `last_seen_at` is the last heartbeat of the user, `None` if it never connected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Presence {
+    pub user_id: UserId,
+    pub online: bool,
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+#[test]
+fn user_profile_validation() {
+    let user = User {
+        id: UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap(),
+        name: "alice".to_string(),
+    };
+    let profile = UserProfile::new(&user, DateTime::default());
+
+    assert_eq!(profile.display_name, "alice");
+    assert!(profile.validate().is_ok());
+    assert!(matches!(
+        profile
+            .clone()
+            .with_display_name("  ".to_string())
+            .validate(),
+        Err(UserProfileError::EmptyDisplayName)
+    ));
+    assert!(matches!(
+        profile.clone().with_bio("a".repeat(501)).validate(),
+        Err(UserProfileError::BioTooLong)
+    ));
+    assert!(matches!(
+        profile
+            .clone()
+            .with_avatar_url("javascript:alert(1)".to_string())
+            .validate(),
+        Err(UserProfileError::AvatarUrl)
+    ));
+    assert!(profile
+        .with_avatar_url("https://example.com/alice.png".to_string())
+        .validate()
+        .is_ok());
+}
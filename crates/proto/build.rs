@@ -1,4 +1,16 @@
+use std::{env, path::PathBuf};
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("thesocialnetwork.proto")?;
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("social_network_descriptor.bin"))
+        .compile(
+            &[
+                "social_network/v1/social_network.proto",
+                "grpc/health/v1/health.proto",
+            ],
+            &["."],
+        )?;
     Ok(())
 }
@@ -1 +1,23 @@
-tonic::include_proto!("thesocialnetwork");
+//! The gRPC API and the NATS payloads, by version of the `social_network` package.
+
+pub mod v1 {
+    tonic::include_proto!("social_network.v1");
+}
+
+/// The standard gRPC health checking service.
+pub mod health {
+    pub mod v1 {
+        tonic::include_proto!("grpc.health.v1");
+    }
+}
+
+/// The current version, so that `proto::Message` and the like keep working.
+pub use v1::*;
+
+/// The well-known types, such as the `Timestamp` of the messages.
+pub use prost_types;
+
+/// The serialized `FileDescriptorSet` of the proto files, imports included: for gRPC
+/// reflection, and to generate the clients of other languages from the same definitions.
+pub const FILE_DESCRIPTOR_SET: &[u8] =
+    tonic::include_file_descriptor_set!("social_network_descriptor");
@@ -1,5 +1,15 @@
-pub static CHANNEL_MESSAGE: &'static str = "message";
-pub static CHANNEL_NEW_FRIENDSHIP: &'static str = "friendship";
-pub static CHANNEL_REMOVED_FRIENDSHIP: &'static str = "remove_friendship";
-pub static CHANNEL_MESSAGE_SEEN: &'static str = "seen_message";
-pub static CHANNEL_MESSAGE_UNSEEN: &'static str = "unseen_message";
+pub static CHANNEL_MESSAGE: &str = "message";
+pub static CHANNEL_NEW_FRIENDSHIP: &str = "friendship";
+pub static CHANNEL_REMOVED_FRIENDSHIP: &str = "remove_friendship";
+pub static CHANNEL_FRIENDSHIP_EVENT: &str = "friendship_event";
+pub static CHANNEL_MESSAGE_SEEN: &str = "seen_message";
+pub static CHANNEL_MESSAGES_SEEN: &str = "seen_messages";
+pub static CHANNEL_MESSAGE_UNSEEN: &str = "unseen_message";
+pub static CHANNEL_REMOVED_USER: &str = "removed_user";
+pub static CHANNEL_DIRECT_MESSAGE: &str = "direct_message";
+pub static CHANNEL_PRESENCE: &str = "presence";
+pub static CHANNEL_CHAT_SIGNAL: &str = "chat_signal";
+pub static CHANNEL_REACTION: &str = "reaction";
+pub static CHANNEL_BLOCK: &str = "block";
+pub static CHANNEL_UNBLOCK: &str = "unblock";
+pub static CHANNEL_SYSTEM_NOTICE: &str = "system_notice";
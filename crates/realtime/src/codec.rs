@@ -1,10 +1,17 @@
-use std::str::FromStr;
+use std::fmt::Display;
 
+use async_nats::HeaderMap;
+use chrono::DateTime;
 use thiserror::Error;
 use prost::Message as ProstMessage;
 
+use models::conversations::*;
+use models::events::*;
+use models::friendships::*;
 use models::users::*;
 use models::messages::*;
+use models::proto::{decode_message_id, decode_user_id};
+use models::reactions::*;
 
 #[derive(Error, Debug)]
 pub enum ProtoDecodingError {
@@ -18,6 +25,17 @@ pub enum ProtoDecodingError {
     MessageId(#[from] MessageIdParsingError),
 }
 
+/// IDs are written in binary, and displayed too for the consumers only reading the strings until
+/// every one of them is upgraded. The binary ones are read first when set.
+const STRING_IDS: bool = true;
+
+fn string_id(id: impl Display) -> String {
+    match STRING_IDS {
+        true => id.to_string(),
+        false => String::new(),
+    }
+}
+
 pub(crate) fn decode_proto_message(payload: prost::bytes::Bytes) -> Result<Message, ProtoDecodingError> {
     let m = proto::Message::decode(payload)?;
 
@@ -31,25 +49,127 @@ pub(crate) fn decode_proto_friendship(
 ) -> Result<(UserId, UserId), ProtoDecodingError> {
     let friendship = proto::Friendship::decode(payload)?;
 
-    let user = UserId::from_str(friendship.user.as_str())?;
-    let friend = UserId::from_str(friendship.friend.as_str())?;
+    let user = decode_user_id(&friendship.user_bytes, &friendship.user)?;
+    let friend = decode_user_id(&friendship.friend_bytes, &friendship.friend)?;
 
     Ok((user, friend))
 }
 
+pub(crate) fn decode_proto_friendship_event(
+    payload: prost::bytes::Bytes,
+) -> Result<FriendshipEvent, ProtoDecodingError> {
+    let m = proto::FriendshipEvent::decode(payload)?;
+
+    let event = FriendshipEvent::try_from(m)?;
+
+    Ok(event)
+}
+
+pub(crate) fn decode_proto_block(
+    payload: prost::bytes::Bytes,
+) -> Result<(UserId, UserId), ProtoDecodingError> {
+    let block = proto::BlockRequest::decode(payload)?;
+
+    let user = decode_user_id(&block.user_id_bytes, &block.user_id)?;
+    let blocked = decode_user_id(&block.blocked_id_bytes, &block.blocked_id)?;
+
+    Ok((user, blocked))
+}
+
 pub(crate) fn decode_proto_message_tag_request(
     payload: prost::bytes::Bytes,
 ) -> Result<(UserId, MessageId), ProtoDecodingError> {
     let tag = proto::MessageTagRequest::decode(payload)?;
 
-    let user = UserId::from_str(tag.user_id.as_str())?;
-    let message = MessageId::try_parse(tag.message_id.as_str())?;
+    let user = decode_user_id(&tag.user_id_bytes, &tag.user_id)?;
+    let message = decode_message_id(&tag.message_id_bytes, &tag.message_id)?;
 
     Ok((user, message))
 }
 
+pub(crate) fn decode_proto_user_removed(
+    payload: prost::bytes::Bytes,
+) -> Result<UserId, ProtoDecodingError> {
+    let removed = proto::UserRemoved::decode(payload)?;
+
+    let user = decode_user_id(&removed.user_id_bytes, &removed.user_id)?;
+
+    Ok(user)
+}
+
+pub(crate) fn decode_proto_system_notice(
+    payload: prost::bytes::Bytes,
+) -> Result<String, ProtoDecodingError> {
+    let notice = proto::BroadcastNotice::decode(payload)?;
+
+    Ok(notice.text)
+}
+
+pub(crate) fn decode_proto_direct_message(
+    payload: prost::bytes::Bytes,
+) -> Result<DirectMessage, ProtoDecodingError> {
+    let m = proto::DirectMessage::decode(payload)?;
+
+    let message = DirectMessage::try_from(m)?;
+
+    Ok(message)
+}
+
+pub(crate) fn decode_proto_chat_signal(
+    payload: prost::bytes::Bytes,
+) -> Result<ChatSignal, ProtoDecodingError> {
+    let m = proto::ChatSignal::decode(payload)?;
+
+    let signal = ChatSignal::try_from(m)?;
+
+    Ok(signal)
+}
+
+pub(crate) fn decode_proto_reaction_update(
+    payload: prost::bytes::Bytes,
+) -> Result<ReactionUpdate, ProtoDecodingError> {
+    let m = proto::ReactionUpdate::decode(payload)?;
+
+    let update = ReactionUpdate::try_from(m)?;
+
+    Ok(update)
+}
+
+pub(crate) fn decode_proto_message_tags(
+    payload: prost::bytes::Bytes,
+) -> Result<Vec<(UserId, MessageId)>, ProtoDecodingError> {
+    let tags = proto::MessageTags::decode(payload)?;
+
+    tags.tags
+        .into_iter()
+        .map(|tag| {
+            let user = decode_user_id(&tag.user_id_bytes, &tag.user_id)?;
+            let message = decode_message_id(&tag.message_id_bytes, &tag.message_id)?;
+
+            Ok((user, message))
+        })
+        .collect()
+}
+
+pub(crate) fn decode_proto_presence(
+    payload: prost::bytes::Bytes,
+) -> Result<Presence, ProtoDecodingError> {
+    let m = proto::Presence::decode(payload)?;
+
+    let presence = Presence::try_from(m)?;
+
+    Ok(presence)
+}
+
 pub(crate) fn encode_proto_message(message: Message) -> prost::bytes::Bytes {
-    let m: proto::Message = message.into();
+    let (user, id) = (message.user_id, message.id);
+    let m = proto::Message {
+        user_id: string_id(user),
+        message_id: string_id(id),
+        user_id_bytes: user.to_bytes().to_vec(),
+        message_id_bytes: id.to_bytes().to_vec(),
+        ..message.into()
+    };
 
     m.encode_to_vec().into()
 }
@@ -58,19 +178,180 @@ pub(crate) fn encode_proto_message_tag_request(
     user: UserId,
     message: MessageId,
 ) -> prost::bytes::Bytes {
-    let m = proto::MessageTagRequest {
-        user_id: user.get_id().to_string(),
-        message_id: message.get_id().to_string(),
+    let m = encode_message_tag(user, message);
+
+    m.encode_to_vec().into()
+}
+
+pub(crate) fn encode_proto_message_tags(tags: Vec<(UserId, MessageId)>) -> prost::bytes::Bytes {
+    let m = proto::MessageTags {
+        tags: tags
+            .into_iter()
+            .map(|(user, message)| encode_message_tag(user, message))
+            .collect(),
     };
 
     m.encode_to_vec().into()
 }
 
+fn encode_message_tag(user: UserId, message: MessageId) -> proto::MessageTagRequest {
+    proto::MessageTagRequest {
+        user_id: string_id(user),
+        message_id: string_id(message),
+        user_id_bytes: user.to_bytes().to_vec(),
+        message_id_bytes: message.to_bytes().to_vec(),
+    }
+}
+
 pub(crate) fn encode_proto_friendship(user: UserId, friend: UserId) -> prost::bytes::Bytes {
     let m = proto::Friendship {
-        user: user.get_id().to_string(),
-        friend: friend.get_id().to_string(),
+        user: string_id(user),
+        friend: string_id(friend),
+        user_bytes: user.to_bytes().to_vec(),
+        friend_bytes: friend.to_bytes().to_vec(),
+    };
+
+    m.encode_to_vec().into()
+}
+
+pub(crate) fn encode_proto_friendship_event(event: FriendshipEvent) -> prost::bytes::Bytes {
+    let m = proto::FriendshipEvent {
+        initiator_id: string_id(event.initiator),
+        target_id: string_id(event.target),
+        initiator_id_bytes: event.initiator.to_bytes().to_vec(),
+        target_id_bytes: event.target.to_bytes().to_vec(),
+        ..event.into()
+    };
+
+    m.encode_to_vec().into()
+}
+
+pub(crate) fn encode_proto_block(user: UserId, blocked: UserId) -> prost::bytes::Bytes {
+    let m = proto::BlockRequest {
+        user_id: string_id(user),
+        blocked_id: string_id(blocked),
+        user_id_bytes: user.to_bytes().to_vec(),
+        blocked_id_bytes: blocked.to_bytes().to_vec(),
     };
 
     m.encode_to_vec().into()
 }
+
+pub(crate) fn encode_proto_user_removed(user: UserId) -> prost::bytes::Bytes {
+    let m = proto::UserRemoved {
+        user_id: string_id(user),
+        user_id_bytes: user.to_bytes().to_vec(),
+    };
+
+    m.encode_to_vec().into()
+}
+
+pub(crate) fn encode_proto_system_notice(text: String) -> prost::bytes::Bytes {
+    let m = proto::BroadcastNotice { text };
+
+    m.encode_to_vec().into()
+}
+
+pub(crate) fn encode_proto_direct_message(message: DirectMessage) -> prost::bytes::Bytes {
+    let m: proto::DirectMessage = message.into();
+
+    m.encode_to_vec().into()
+}
+
+pub(crate) fn encode_proto_chat_signal(signal: ChatSignal) -> prost::bytes::Bytes {
+    let m: proto::ChatSignal = signal.into();
+
+    m.encode_to_vec().into()
+}
+
+pub(crate) fn encode_proto_reaction_update(update: ReactionUpdate) -> prost::bytes::Bytes {
+    let m: proto::ReactionUpdate = update.into();
+
+    m.encode_to_vec().into()
+}
+
+pub(crate) fn encode_proto_presence(presence: Presence) -> prost::bytes::Bytes {
+    let m: proto::Presence = presence.into();
+
+    m.encode_to_vec().into()
+}
+
+const EVENT_ID_HEADER: &str = "Tsn-Event-Id";
+/// Milliseconds since the epoch.
+const OCCURRED_AT_HEADER: &str = "Tsn-Occurred-At";
+const TRACE_ID_HEADER: &str = "Tsn-Trace-Id";
+const PRODUCER_ID_HEADER: &str = "Tsn-Producer-Id";
+
+/// The envelope of `event`. In headers, so that the payloads stay the same for the consumers not
+/// reading them.
+pub(crate) fn encode_event_headers<T>(event: &Event<T>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    headers.insert(EVENT_ID_HEADER, event.event_id.to_string().as_str());
+    headers.insert(
+        OCCURRED_AT_HEADER,
+        event.occurred_at.timestamp_millis().to_string().as_str(),
+    );
+    if let Some(trace_id) = &event.trace_id {
+        headers.insert(TRACE_ID_HEADER, trace_id.as_str());
+    }
+    headers.insert(PRODUCER_ID_HEADER, event.producer_id.to_string().as_str());
+
+    headers
+}
+
+/// The envelope in `headers`, of the event they were received with. Events of older producers
+/// have none: they get a new ID, the time they are received and an unknown producer, and so does
+/// each malformed header.
+pub(crate) fn decode_event_headers(headers: Option<&HeaderMap>) -> Event<()> {
+    let header = |name: &str| headers.and_then(|headers| headers.get(name)).map(|v| v.as_str());
+    let mut event = Event::new(());
+
+    if let Some(event_id) = header(EVENT_ID_HEADER).and_then(|id| id.parse().ok()) {
+        event.event_id = event_id;
+    }
+    if let Some(occurred_at) = header(OCCURRED_AT_HEADER)
+        .and_then(|millis| millis.parse().ok())
+        .and_then(DateTime::from_timestamp_millis)
+    {
+        event.occurred_at = occurred_at;
+    }
+    event.trace_id = header(TRACE_ID_HEADER).map(str::to_string);
+    event.producer_id = header(PRODUCER_ID_HEADER)
+        .and_then(|id| id.parse().ok())
+        .unwrap_or_else(ProducerId::unknown);
+
+    event
+}
+
+/// The event of a NATS message, its payload decoded with `decode`.
+pub(crate) fn decode_event<T>(
+    message: async_nats::Message,
+    decode: impl FnOnce(prost::bytes::Bytes) -> Result<T, ProtoDecodingError>,
+) -> Result<Event<T>, ProtoDecodingError> {
+    let payload = decode(message.payload)?;
+
+    Ok(decode_event_headers(message.headers.as_ref()).map(|()| payload))
+}
+
+#[cfg(test)]
+#[test]
+fn binary_ids_test() {
+    let user = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let message = MessageId::new_now(user);
+
+    let tags = encode_proto_message_tags(vec![(user, message)]);
+    assert_eq!(decode_proto_message_tags(tags).unwrap(), vec![(user, message)]);
+
+    // Payloads of the producers that only write strings.
+    let legacy = proto::MessageTagRequest {
+        user_id: user.to_string(),
+        message_id: message.to_string(),
+        ..Default::default()
+    };
+    let decoded = decode_proto_message_tag_request(legacy.encode_to_vec().into()).unwrap();
+    assert_eq!(decoded, (user, message));
+
+    let encoded = encode_proto_message(Message::new(user, "Hello".to_string()));
+    assert_eq!(decode_proto_message(encoded).unwrap().user_id, user);
+}
@@ -0,0 +1,166 @@
+//! State of the NATS connection, from its events. Subscriptions are restored by the client
+//! once reconnected, but what was published meanwhile is lost to them: the streams that can't
+//! miss anything end on reconnection, to be subscribed again.
+
+use std::sync::Arc;
+
+use async_nats::Event;
+use futures::{Stream, StreamExt};
+use tokio::sync::watch;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// Until the client reconnects, to any server of the cluster.
+    Disconnected,
+    /// The server is about to shut down, the client reconnects to another one.
+    LameDuck,
+}
+
+impl ConnectionState {
+    /// `None` of the events that don't change it.
+    pub fn of_event(event: &Event) -> Option<Self> {
+        match event {
+            Event::Connected => Some(ConnectionState::Connected),
+            Event::Disconnected => Some(ConnectionState::Disconnected),
+            Event::LameDuckMode => Some(ConnectionState::LameDuck),
+            Event::SlowConsumer(_) | Event::ServerError(_) | Event::ClientError(_) => None,
+        }
+    }
+}
+
+/// The state of a connection, updated with `record` from its event callback. Disconnected until
+/// the first connection. Can be shared between threads by using `Clone`.
+#[derive(Clone, Debug)]
+pub struct ConnectionMonitor {
+    /// Along with the number of connections, so that none is missed between two polls.
+    state: Arc<watch::Sender<(ConnectionState, u64)>>,
+}
+
+impl Default for ConnectionMonitor {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(watch::channel((ConnectionState::Disconnected, 0)).0),
+        }
+    }
+}
+
+impl ConnectionMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, event: &Event) {
+        if let Some(state) = ConnectionState::of_event(event) {
+            self.set(state);
+        }
+    }
+
+    pub fn set(&self, state: ConnectionState) {
+        self.state.send_if_modified(|(current, connections)| {
+            if *current == state {
+                return false;
+            }
+            if state == ConnectionState::Connected {
+                *connections += 1;
+            }
+            *current = state;
+            true
+        });
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state.borrow().0
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state() == ConnectionState::Connected
+    }
+
+    /// Whether it was connected once, it may be lost since.
+    pub fn has_connected(&self) -> bool {
+        self.state.borrow().1 > 0
+    }
+
+    /// Once it was connected once.
+    pub async fn first_connected(&self) {
+        // The sender is kept by `self`, it can't fail.
+        let _ = self
+            .state
+            .subscribe()
+            .wait_for(|(_, connections)| *connections > 0)
+            .await;
+    }
+
+    /// The current state, then each change. Ends once every clone of the monitor is dropped.
+    pub fn states(&self) -> impl Stream<Item = ConnectionState> {
+        let receiver = self.state.subscribe();
+
+        futures::stream::unfold((receiver, None), |(mut receiver, last)| async move {
+            loop {
+                let state = receiver.borrow_and_update().0;
+                if last != Some(state) {
+                    return Some((state, (receiver, Some(state))));
+                }
+                receiver.changed().await.ok()?;
+            }
+        })
+    }
+
+    /// Once the connection was lost, or is lost now, then came back.
+    pub async fn reconnected(&self) {
+        let mut state = self.state.subscribe();
+        let (current, connections) = *state.borrow();
+        let next = match current {
+            ConnectionState::Connected => connections + 1,
+            // Already counted as connected, else the first connection is not a reconnection.
+            ConnectionState::Disconnected | ConnectionState::LameDuck => connections.max(1) + 1,
+        };
+
+        // The sender is kept by `self`, it can't fail.
+        let _ = state
+            .wait_for(|(_, connections)| *connections >= next)
+            .await;
+    }
+}
+
+/// `stream` until `connection` is reconnected, so that it is subscribed again along with what
+/// was missed meanwhile.
+pub fn until_reconnected<S: Stream>(
+    stream: S,
+    connection: &ConnectionMonitor,
+) -> impl Stream<Item = S::Item> {
+    let connection = connection.clone();
+
+    stream.take_until(async move { connection.reconnected().await })
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn until_reconnected_test() {
+    let connection = ConnectionMonitor::new();
+    assert!(!connection.has_connected());
+    connection.record(&Event::Connected);
+    connection.first_connected().await;
+    connection.record(&Event::SlowConsumer(1));
+    assert!(connection.is_connected());
+
+    let (tx, rx) = futures::channel::mpsc::unbounded();
+    let mut stream = Box::pin(until_reconnected(rx, &connection));
+    tx.unbounded_send(1).unwrap();
+    assert_eq!(stream.next().await, Some(1));
+
+    // Lost until reconnected, to any server.
+    connection.record(&Event::LameDuckMode);
+    tx.unbounded_send(2).unwrap();
+    assert_eq!(stream.next().await, Some(2));
+    assert_eq!(connection.state(), ConnectionState::LameDuck);
+
+    connection.record(&Event::Disconnected);
+    connection.record(&Event::Connected);
+    tx.unbounded_send(3).unwrap();
+    assert_eq!(stream.next().await, None);
+
+    let states: Vec<_> = connection.states().take(1).collect().await;
+    assert_eq!(states, vec![ConnectionState::Connected]);
+}
@@ -2,6 +2,7 @@
 
 mod channels;
 mod codec;
+pub mod connection;
 pub mod receivers;
 pub mod senders;
 
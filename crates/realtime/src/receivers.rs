@@ -5,15 +5,18 @@ use futures::future::Either;
 use futures::stream::select;
 use futures::{FutureExt, Stream, TryFutureExt};
 use futures::{StreamExt, TryStreamExt};
-use models::friendships::FriendshipUpdate;
+use models::friendships::{BlockUpdate, FriendshipEvent, FriendshipUpdate};
 use thiserror::Error;
 
 use super::channels::*;
 use super::codec::*;
 
 use models::{
+    conversations::{ChatSignal, ConversationId, DirectMessage},
+    events::Event,
     messages::{Message, MessageId},
-    users::{UserId, Userlike},
+    reactions::ReactionUpdate,
+    users::{Presence, UserId, Userlike},
 };
 
 #[derive(Error, Debug)]
@@ -49,29 +52,38 @@ impl<E: std::error::Error + Send + Sync> From<ReceiverError> for ParReceiverErro
 
 async fn inner_new_messages(
     client: Client,
-) -> Result<impl Stream<Item = Result<Message, ProtoDecodingError>>, NatsError> {
+) -> Result<impl Stream<Item = Result<Event<Message>, ProtoDecodingError>>, NatsError> {
     let subscription = client.subscribe(CHANNEL_MESSAGE.into()).await?;
 
-    let stream = subscription.map(|proto_message| decode_proto_message(proto_message.payload));
+    let stream =
+        subscription.map(|proto_message| decode_event(proto_message, decode_proto_message));
 
     Ok(stream)
 }
 
 /// Stream of all new messages from all users. Connected to NATS.
 pub fn new_messages<'a>(client: Client) -> impl Stream<Item = Result<Message, ReceiverError>> + 'a {
+    new_messages_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `new_messages`, with the envelope of each message.
+pub fn new_messages_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<Message>, ReceiverError>> + 'a {
     inner_new_messages(client)
-        .map_err(|e| ReceiverError::Nats(e))
-        .map_ok(|stream| stream.map_err(|e| ReceiverError::Decoding(e)))
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
         .into_stream()
         .try_flatten()
 }
 
 async fn inner_new_friendships(
     client: Client,
-) -> Result<impl Stream<Item = Result<(UserId, UserId), ProtoDecodingError>>, NatsError> {
+) -> Result<impl Stream<Item = Result<Event<(UserId, UserId)>, ProtoDecodingError>>, NatsError> {
     let subscription = client.subscribe(CHANNEL_NEW_FRIENDSHIP.into()).await?;
 
-    let stream = subscription.map(|proto_message| decode_proto_friendship(proto_message.payload));
+    let stream =
+        subscription.map(|proto_message| decode_event(proto_message, decode_proto_friendship));
 
     Ok(stream)
 }
@@ -80,19 +92,27 @@ async fn inner_new_friendships(
 pub fn new_friendships<'a>(
     client: Client,
 ) -> impl Stream<Item = Result<(UserId, UserId), ReceiverError>> + 'a {
+    new_friendships_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `new_friendships`, with the envelope of each friendship.
+pub fn new_friendships_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<(UserId, UserId)>, ReceiverError>> + 'a {
     inner_new_friendships(client)
-        .map_err(|e| ReceiverError::Nats(e))
-        .map_ok(|stream| stream.map_err(|e| ReceiverError::Decoding(e)))
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
         .into_stream()
         .try_flatten()
 }
 
 async fn inner_removed_friendships(
     client: Client,
-) -> Result<impl Stream<Item = Result<(UserId, UserId), ProtoDecodingError>>, NatsError> {
+) -> Result<impl Stream<Item = Result<Event<(UserId, UserId)>, ProtoDecodingError>>, NatsError> {
     let subscription = client.subscribe(CHANNEL_REMOVED_FRIENDSHIP.into()).await?;
 
-    let stream = subscription.map(|proto_message| decode_proto_friendship(proto_message.payload));
+    let stream =
+        subscription.map(|proto_message| decode_event(proto_message, decode_proto_friendship));
 
     Ok(stream)
 }
@@ -101,42 +121,88 @@ async fn inner_removed_friendships(
 pub fn removed_friendships<'a>(
     client: Client,
 ) -> impl Stream<Item = Result<(UserId, UserId), ReceiverError>> + 'a {
+    removed_friendships_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `removed_friendships`, with the envelope of each friendship.
+pub fn removed_friendships_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<(UserId, UserId)>, ReceiverError>> + 'a {
     inner_removed_friendships(client)
-        .map_err(|e| ReceiverError::Nats(e))
-        .map_ok(|stream| stream.map_err(|e| ReceiverError::Decoding(e)))
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
         .into_stream()
         .try_flatten()
 }
 
 async fn inner_seen_messages(
     client: Client,
-) -> Result<impl Stream<Item = Result<(UserId, MessageId), ProtoDecodingError>>, NatsError> {
+) -> Result<
+    impl Stream<Item = Result<Event<Vec<(UserId, MessageId)>>, ProtoDecodingError>>,
+    NatsError,
+> {
     let subscription = client.subscribe(CHANNEL_MESSAGE_SEEN.into()).await?;
 
+    let stream = subscription.map(|proto_message| {
+        decode_event(proto_message, |payload| {
+            decode_proto_message_tag_request(payload).map(|tag| vec![tag])
+        })
+    });
+
+    Ok(stream)
+}
+
+async fn inner_seen_messages_batches(
+    client: Client,
+) -> Result<
+    impl Stream<Item = Result<Event<Vec<(UserId, MessageId)>>, ProtoDecodingError>>,
+    NatsError,
+> {
+    let subscription = client.subscribe(CHANNEL_MESSAGES_SEEN.into()).await?;
+
     let stream =
-        subscription.map(|proto_message| decode_proto_message_tag_request(proto_message.payload));
+        subscription.map(|proto_message| decode_event(proto_message, decode_proto_message_tags));
 
     Ok(stream)
 }
 
-/// Stream of all seen notification for all messages from all users. Connected to NATS.
+/// Stream of all seen notification for all messages from all users, batches are flattened.
+/// Connected to NATS.
 pub fn seen_messages<'a>(
     client: Client,
 ) -> impl Stream<Item = Result<(UserId, MessageId), ReceiverError>> + 'a {
-    inner_seen_messages(client)
-        .map_err(|e| ReceiverError::Nats(e))
-        .map_ok(|stream| stream.map_err(|e| ReceiverError::Decoding(e)))
-        .into_stream()
+    seen_messages_enveloped(client)
+        .map_ok(|event| futures::stream::iter(event.into_payload().into_iter().map(Ok)))
         .try_flatten()
 }
 
+/// Like `seen_messages`, with the envelope of each NATS message: the tags of a batch share the
+/// same one.
+pub fn seen_messages_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<Vec<(UserId, MessageId)>>, ReceiverError>> + 'a {
+    let single = inner_seen_messages(client.clone())
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
+        .into_stream()
+        .try_flatten();
+
+    let batches = inner_seen_messages_batches(client)
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
+        .into_stream()
+        .try_flatten();
+
+    select(single, batches)
+}
+
 async fn inner_unseen_messages(
     client: Client,
-) -> Result<impl Stream<Item = Result<(UserId, MessageId), ProtoDecodingError>>, NatsError> {
+) -> Result<impl Stream<Item = Result<Event<(UserId, MessageId)>, ProtoDecodingError>>, NatsError> {
     let subscription = client.subscribe(CHANNEL_MESSAGE_UNSEEN.into()).await?;
 
-    let stream =
-        subscription.map(|proto_message| decode_proto_message_tag_request(proto_message.payload));
+    let stream = subscription
+        .map(|proto_message| decode_event(proto_message, decode_proto_message_tag_request));
 
     Ok(stream)
 }
@@ -145,9 +211,263 @@ async fn inner_unseen_messages(
 pub fn unseen_messages<'a>(
     client: Client,
 ) -> impl Stream<Item = Result<(UserId, MessageId), ReceiverError>> + 'a {
+    unseen_messages_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `unseen_messages`, with the envelope of each tag.
+pub fn unseen_messages_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<(UserId, MessageId)>, ReceiverError>> + 'a {
     inner_unseen_messages(client)
-        .map_err(|e| ReceiverError::Nats(e))
-        .map_ok(|stream| stream.map_err(|e| ReceiverError::Decoding(e)))
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
+        .into_stream()
+        .try_flatten()
+}
+
+async fn inner_direct_messages(
+    client: Client,
+) -> Result<impl Stream<Item = Result<Event<DirectMessage>, ProtoDecodingError>>, NatsError> {
+    let subscription = client.subscribe(CHANNEL_DIRECT_MESSAGE.into()).await?;
+
+    let stream =
+        subscription.map(|proto_message| decode_event(proto_message, decode_proto_direct_message));
+
+    Ok(stream)
+}
+
+/// Stream of all direct messages of all conversations. Connected to NATS.
+pub fn direct_messages<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<DirectMessage, ReceiverError>> + 'a {
+    direct_messages_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `direct_messages`, with the envelope of each message.
+pub fn direct_messages_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<DirectMessage>, ReceiverError>> + 'a {
+    inner_direct_messages(client)
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
+        .into_stream()
+        .try_flatten()
+}
+
+/// Stream of direct messages of a specific conversation.
+pub fn direct_messages_of_conversation<'a>(
+    conversation: ConversationId,
+    client: Client,
+) -> impl Stream<Item = Result<DirectMessage, ReceiverError>> + 'a {
+    direct_messages_of_conversation_enveloped(conversation, client).map_ok(Event::into_payload)
+}
+
+/// Like `direct_messages_of_conversation`, with the envelope of each message.
+pub fn direct_messages_of_conversation_enveloped<'a>(
+    conversation: ConversationId,
+    client: Client,
+) -> impl Stream<Item = Result<Event<DirectMessage>, ReceiverError>> + 'a {
+    direct_messages_enveloped(client).try_filter(move |event| {
+        futures::future::ready(event.payload.conversation_id == conversation)
+    })
+}
+
+async fn inner_chat_signals(
+    client: Client,
+) -> Result<impl Stream<Item = Result<Event<ChatSignal>, ProtoDecodingError>>, NatsError> {
+    let subscription = client.subscribe(CHANNEL_CHAT_SIGNAL.into()).await?;
+
+    let stream =
+        subscription.map(|proto_message| decode_event(proto_message, decode_proto_chat_signal));
+
+    Ok(stream)
+}
+
+/// Stream of typing and acknowledgement signals of all conversations. Connected to NATS.
+pub fn chat_signals<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<ChatSignal, ReceiverError>> + 'a {
+    chat_signals_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `chat_signals`, with the envelope of each signal.
+pub fn chat_signals_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<ChatSignal>, ReceiverError>> + 'a {
+    inner_chat_signals(client)
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
+        .into_stream()
+        .try_flatten()
+}
+
+/// Stream of signals of a specific conversation.
+pub fn chat_signals_of_conversation<'a>(
+    conversation: ConversationId,
+    client: Client,
+) -> impl Stream<Item = Result<ChatSignal, ReceiverError>> + 'a {
+    chat_signals_of_conversation_enveloped(conversation, client).map_ok(Event::into_payload)
+}
+
+/// Like `chat_signals_of_conversation`, with the envelope of each signal.
+pub fn chat_signals_of_conversation_enveloped<'a>(
+    conversation: ConversationId,
+    client: Client,
+) -> impl Stream<Item = Result<Event<ChatSignal>, ReceiverError>> + 'a {
+    chat_signals_enveloped(client).try_filter(move |event| {
+        futures::future::ready(event.payload.conversation_id == conversation)
+    })
+}
+
+async fn inner_reaction_updates(
+    client: Client,
+) -> Result<impl Stream<Item = Result<Event<ReactionUpdate>, ProtoDecodingError>>, NatsError> {
+    let subscription = client.subscribe(CHANNEL_REACTION.into()).await?;
+
+    let stream =
+        subscription.map(|proto_message| decode_event(proto_message, decode_proto_reaction_update));
+
+    Ok(stream)
+}
+
+/// Stream of reactions added or removed on all messages. Connected to NATS.
+pub fn reaction_updates<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<ReactionUpdate, ReceiverError>> + 'a {
+    reaction_updates_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `reaction_updates`, with the envelope of each update.
+pub fn reaction_updates_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<ReactionUpdate>, ReceiverError>> + 'a {
+    inner_reaction_updates(client)
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
+        .into_stream()
+        .try_flatten()
+}
+
+async fn inner_blocks(
+    client: Client,
+    channel: &'static str,
+) -> Result<impl Stream<Item = Result<Event<(UserId, UserId)>, ProtoDecodingError>>, NatsError> {
+    let subscription = client.subscribe(channel.into()).await?;
+
+    let stream = subscription.map(|proto_message| decode_event(proto_message, decode_proto_block));
+
+    Ok(stream)
+}
+
+fn blocks<'a>(
+    client: Client,
+    channel: &'static str,
+) -> impl Stream<Item = Result<Event<(UserId, UserId)>, ReceiverError>> + 'a {
+    inner_blocks(client, channel)
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
+        .into_stream()
+        .try_flatten()
+}
+
+/// Stream of all blocks and unblocks of all users. Connected to NATS.
+pub fn blocks_updates<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<BlockUpdate, ReceiverError>> + 'a {
+    blocks_updates_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `blocks_updates`, with the envelope of each update.
+pub fn blocks_updates_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<BlockUpdate>, ReceiverError>> + 'a {
+    select(
+        blocks(client.clone(), CHANNEL_BLOCK)
+            .map_ok(|event| event.map(|(a, b)| BlockUpdate::Blocked(a, b))),
+        blocks(client, CHANNEL_UNBLOCK)
+            .map_ok(|event| event.map(|(a, b)| BlockUpdate::Unblocked(a, b))),
+    )
+}
+
+async fn inner_removed_users(
+    client: Client,
+) -> Result<impl Stream<Item = Result<Event<UserId>, ProtoDecodingError>>, NatsError> {
+    let subscription = client.subscribe(CHANNEL_REMOVED_USER.into()).await?;
+
+    let stream =
+        subscription.map(|proto_message| decode_event(proto_message, decode_proto_user_removed));
+
+    Ok(stream)
+}
+
+/// Stream of all deleted users. Connected to NATS.
+pub fn removed_users<'a>(client: Client) -> impl Stream<Item = Result<UserId, ReceiverError>> + 'a {
+    removed_users_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `removed_users`, with the envelope of each deletion.
+pub fn removed_users_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<UserId>, ReceiverError>> + 'a {
+    inner_removed_users(client)
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
+        .into_stream()
+        .try_flatten()
+}
+
+async fn inner_system_notices(
+    client: Client,
+) -> Result<impl Stream<Item = Result<Event<String>, ProtoDecodingError>>, NatsError> {
+    let subscription = client.subscribe(CHANNEL_SYSTEM_NOTICE.into()).await?;
+
+    let stream =
+        subscription.map(|proto_message| decode_event(proto_message, decode_proto_system_notice));
+
+    Ok(stream)
+}
+
+/// Stream of the notices to every user. Connected to NATS.
+pub fn system_notices<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<String, ReceiverError>> + 'a {
+    system_notices_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `system_notices`, with the envelope of each notice.
+pub fn system_notices_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<String>, ReceiverError>> + 'a {
+    inner_system_notices(client)
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
+        .into_stream()
+        .try_flatten()
+}
+
+async fn inner_presences(
+    client: Client,
+) -> Result<impl Stream<Item = Result<Event<Presence>, ProtoDecodingError>>, NatsError> {
+    let subscription = client.subscribe(CHANNEL_PRESENCE.into()).await?;
+
+    let stream =
+        subscription.map(|proto_message| decode_event(proto_message, decode_proto_presence));
+
+    Ok(stream)
+}
+
+/// Stream of presence changes of all users. Connected to NATS.
+pub fn presences<'a>(client: Client) -> impl Stream<Item = Result<Presence, ReceiverError>> + 'a {
+    presences_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `presences`, with the envelope of each change.
+pub fn presences_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<Presence>, ReceiverError>> + 'a {
+    inner_presences(client)
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
         .into_stream()
         .try_flatten()
 }
@@ -228,16 +548,52 @@ pub fn removed_friends_of_user<'a, U: Userlike>(
     stream
 }
 
+async fn inner_friendship_events(
+    client: Client,
+) -> Result<impl Stream<Item = Result<Event<FriendshipEvent>, ProtoDecodingError>>, NatsError> {
+    let subscription = client.subscribe(CHANNEL_FRIENDSHIP_EVENT.into()).await?;
+
+    let stream = subscription
+        .map(|proto_message| decode_event(proto_message, decode_proto_friendship_event));
+
+    Ok(stream)
+}
+
+/// Stream of friend requests and their answers, of all users. Connected to NATS.
+pub fn friendship_events<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<FriendshipEvent, ReceiverError>> + 'a {
+    friendship_events_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `friendship_events`, with the envelope of each event.
+pub fn friendship_events_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<FriendshipEvent>, ReceiverError>> + 'a {
+    inner_friendship_events(client)
+        .map_err(ReceiverError::Nats)
+        .map_ok(|stream| stream.map_err(ReceiverError::Decoding))
+        .into_stream()
+        .try_flatten()
+}
+
 /// Stream of removed friendships of a specific user.
 pub fn friendships_updates<'a>(
     client: Client,
 ) -> impl Stream<Item = Result<FriendshipUpdate, ReceiverError>> + 'a {
-    let new_friendships = new_friendships(client.clone());
-    let removed_friendships = removed_friendships(client);
+    friendships_updates_enveloped(client).map_ok(Event::into_payload)
+}
+
+/// Like `friendships_updates`, with the envelope of each update.
+pub fn friendships_updates_enveloped<'a>(
+    client: Client,
+) -> impl Stream<Item = Result<Event<FriendshipUpdate>, ReceiverError>> + 'a {
+    let new_friendships = new_friendships_enveloped(client.clone());
+    let removed_friendships = removed_friendships_enveloped(client);
 
     let stream = select(
-        new_friendships.map_ok(|(a, b)| FriendshipUpdate::New(a, b)),
-        removed_friendships.map_ok(|(a, b)| FriendshipUpdate::Removed(a, b)),
+        new_friendships.map_ok(|event| event.map(|(a, b)| FriendshipUpdate::New(a, b))),
+        removed_friendships.map_ok(|event| event.map(|(a, b)| FriendshipUpdate::Removed(a, b))),
     );
 
     stream
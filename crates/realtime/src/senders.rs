@@ -1,12 +1,17 @@
 use async_nats::{Client, PublishError};
+use prost::bytes::Bytes;
 use thiserror::Error;
 
 use super::channels::*;
 use super::codec::*;
 
 use models::{
+    conversations::{ChatSignal, DirectMessage},
+    events::Event,
+    friendships::FriendshipEvent,
     messages::{Message, MessageId, Messagelike},
-    users::{UserId, Userlike},
+    reactions::ReactionUpdate,
+    users::{Presence, UserId, Userlike},
 };
 
 #[derive(Error, Debug)]
@@ -15,6 +20,19 @@ pub enum SenderError {
     Nats(#[from] PublishError),
 }
 
+/// Publishes `payload` on `channel` as a new event, with the trace ID of the running task.
+async fn publish_event(
+    client: Client,
+    channel: &'static str,
+    payload: Bytes,
+) -> Result<(), SenderError> {
+    let event = Event::new(()).with_trace_id(task_manager::trace_id());
+
+    Ok(client
+        .publish_with_headers(channel.into(), encode_event_headers(&event), payload)
+        .await?)
+}
+
 pub struct PublishMessage {
     pub message: Message,
 }
@@ -25,9 +43,7 @@ impl<'a> PublishMessage {
     }
 
     pub async fn publish(self, client: Client) -> Result<(), SenderError> {
-        Ok(client
-            .publish(CHANNEL_MESSAGE.into(), encode_proto_message(self.message))
-            .await?)
+        publish_event(client, CHANNEL_MESSAGE, encode_proto_message(self.message)).await
     }
 }
 
@@ -45,12 +61,55 @@ impl PublishSeenMessage {
     }
 
     pub async fn publish(self, client: Client) -> Result<(), SenderError> {
-        Ok(client
-            .publish(
-                CHANNEL_MESSAGE_SEEN.into(),
-                encode_proto_message_tag_request(self.user, self.message),
-            )
-            .await?)
+        publish_event(
+            client,
+            CHANNEL_MESSAGE_SEEN,
+            encode_proto_message_tag_request(self.user, self.message),
+        )
+        .await
+    }
+}
+
+pub struct PublishUnseenMessage {
+    pub user: UserId,
+    pub message: MessageId,
+}
+
+impl PublishUnseenMessage {
+    pub fn new(message: impl Messagelike, user: impl Userlike) -> Self {
+        Self {
+            user: user.get_id(),
+            message: message.get_id(),
+        }
+    }
+
+    pub async fn publish(self, client: Client) -> Result<(), SenderError> {
+        publish_event(
+            client,
+            CHANNEL_MESSAGE_UNSEEN,
+            encode_proto_message_tag_request(self.user, self.message),
+        )
+        .await
+    }
+}
+
+/// Many read tags in a single NATS message.
+pub struct PublishSeenMessages {
+    pub tags: Vec<(UserId, MessageId)>,
+}
+
+impl PublishSeenMessages {
+    pub fn new(tags: Vec<(UserId, MessageId)>) -> Self {
+        Self { tags }
+    }
+
+    pub async fn publish(self, client: Client) -> Result<(), SenderError> {
+        publish_event(
+            client,
+            CHANNEL_MESSAGES_SEEN,
+            encode_proto_message_tags(self.tags),
+        )
+        .await
     }
 }
 
@@ -68,12 +127,12 @@ impl PublishFriendship {
     }
 
     pub async fn publish(self, client: Client) -> Result<(), SenderError> {
-        Ok(client
-            .publish(
-                CHANNEL_NEW_FRIENDSHIP.into(),
-                encode_proto_friendship(self.user, self.friend),
-            )
-            .await?)
+        publish_event(
+            client,
+            CHANNEL_NEW_FRIENDSHIP,
+            encode_proto_friendship(self.user, self.friend),
+        )
+        .await
     }
 }
 
@@ -91,11 +150,195 @@ impl PublishRemoveFriendship {
     }
 
     pub async fn publish(self, client: Client) -> Result<(), SenderError> {
-        Ok(client
-            .publish(
-                CHANNEL_REMOVED_FRIENDSHIP.into(),
-                encode_proto_friendship(self.user, self.friend),
-            )
-            .await?)
+        publish_event(
+            client,
+            CHANNEL_REMOVED_FRIENDSHIP,
+            encode_proto_friendship(self.user, self.friend),
+        )
+        .await
+    }
+}
+
+/// Friend requests and their answers. Once accepted, the friendship is also published with
+/// `PublishFriendship`.
+pub struct PublishFriendshipEvent {
+    pub event: FriendshipEvent,
+}
+
+impl PublishFriendshipEvent {
+    pub fn new(event: FriendshipEvent) -> Self {
+        Self { event }
+    }
+
+    pub async fn publish(self, client: Client) -> Result<(), SenderError> {
+        publish_event(
+            client,
+            CHANNEL_FRIENDSHIP_EVENT,
+            encode_proto_friendship_event(self.event),
+        )
+        .await
+    }
+}
+
+pub struct PublishBlock {
+    pub user: UserId,
+    pub blocked: UserId,
+}
+
+impl PublishBlock {
+    pub fn new(user: impl Userlike, blocked: impl Userlike) -> Self {
+        Self {
+            user: user.get_id(),
+            blocked: blocked.get_id(),
+        }
+    }
+
+    pub async fn publish(self, client: Client) -> Result<(), SenderError> {
+        publish_event(
+            client,
+            CHANNEL_BLOCK,
+            encode_proto_block(self.user, self.blocked),
+        )
+        .await
+    }
+}
+
+pub struct PublishUnblock {
+    pub user: UserId,
+    pub blocked: UserId,
+}
+
+impl PublishUnblock {
+    pub fn new(user: impl Userlike, blocked: impl Userlike) -> Self {
+        Self {
+            user: user.get_id(),
+            blocked: blocked.get_id(),
+        }
+    }
+
+    pub async fn publish(self, client: Client) -> Result<(), SenderError> {
+        publish_event(
+            client,
+            CHANNEL_UNBLOCK,
+            encode_proto_block(self.user, self.blocked),
+        )
+        .await
+    }
+}
+
+pub struct PublishUserRemoved {
+    pub user: UserId,
+}
+
+impl PublishUserRemoved {
+    pub fn new(user: impl Userlike) -> Self {
+        Self {
+            user: user.get_id(),
+        }
+    }
+
+    pub async fn publish(self, client: Client) -> Result<(), SenderError> {
+        publish_event(
+            client,
+            CHANNEL_REMOVED_USER,
+            encode_proto_user_removed(self.user),
+        )
+        .await
+    }
+}
+
+/// A notice to every user, from the operators.
+pub struct PublishSystemNotice {
+    pub text: String,
+}
+
+impl PublishSystemNotice {
+    pub fn new(text: String) -> Self {
+        Self { text }
+    }
+
+    pub async fn publish(self, client: Client) -> Result<(), SenderError> {
+        publish_event(
+            client,
+            CHANNEL_SYSTEM_NOTICE,
+            encode_proto_system_notice(self.text),
+        )
+        .await
+    }
+}
+
+pub struct PublishDirectMessage {
+    pub message: DirectMessage,
+}
+
+impl PublishDirectMessage {
+    pub fn new(message: DirectMessage) -> Self {
+        Self { message }
+    }
+
+    pub async fn publish(self, client: Client) -> Result<(), SenderError> {
+        publish_event(
+            client,
+            CHANNEL_DIRECT_MESSAGE,
+            encode_proto_direct_message(self.message),
+        )
+        .await
+    }
+}
+
+pub struct PublishChatSignal {
+    pub signal: ChatSignal,
+}
+
+impl PublishChatSignal {
+    pub fn new(signal: ChatSignal) -> Self {
+        Self { signal }
+    }
+
+    pub async fn publish(self, client: Client) -> Result<(), SenderError> {
+        publish_event(
+            client,
+            CHANNEL_CHAT_SIGNAL,
+            encode_proto_chat_signal(self.signal),
+        )
+        .await
+    }
+}
+
+pub struct PublishReactionUpdate {
+    pub update: ReactionUpdate,
+}
+
+impl PublishReactionUpdate {
+    pub fn new(update: ReactionUpdate) -> Self {
+        Self { update }
+    }
+
+    pub async fn publish(self, client: Client) -> Result<(), SenderError> {
+        publish_event(
+            client,
+            CHANNEL_REACTION,
+            encode_proto_reaction_update(self.update),
+        )
+        .await
+    }
+}
+
+pub struct PublishPresence {
+    pub presence: Presence,
+}
+
+impl PublishPresence {
+    pub fn new(presence: Presence) -> Self {
+        Self { presence }
+    }
+
+    pub async fn publish(self, client: Client) -> Result<(), SenderError> {
+        publish_event(
+            client,
+            CHANNEL_PRESENCE,
+            encode_proto_presence(self.presence),
+        )
+        .await
     }
 }
@@ -0,0 +1,242 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use futures::StreamExt;
+use scylla::frame::value::Timestamp;
+use scylla::Session;
+use tokio::fs::{create_dir_all, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use uuid::Uuid;
+
+use models::messages::{extract_mentions, Message, MessageId};
+use models::users::UserId;
+use tracing::instrument;
+
+use super::{decode_body, timestamp_to_datetime, RepositoryError, TimeBucket};
+
+/// Destination of archived buckets. Implementations can write to a local file, an object storage…
+#[async_trait]
+pub trait ArchiveSink: Send {
+    async fn write_bucket(
+        &mut self,
+        bucket: TimeBucket,
+        messages: &[Message],
+    ) -> Result<(), RepositoryError>;
+}
+
+/// Writes one tab separated file per bucket in `directory`, one message per line:
+/// `message_id  user_id  timestamp  content`. Files are opened in append mode so a job that failed
+/// before deletion can safely be run again.
+#[derive(Clone, Debug)]
+pub struct FileArchiveSink {
+    pub directory: PathBuf,
+}
+
+impl FileArchiveSink {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    pub fn bucket_path(&self, bucket: TimeBucket) -> PathBuf {
+        self.directory
+            .join(format!("messages-{}.tsv", bucket.date()))
+    }
+}
+
+#[async_trait]
+impl ArchiveSink for FileArchiveSink {
+    async fn write_bucket(
+        &mut self,
+        bucket: TimeBucket,
+        messages: &[Message],
+    ) -> Result<(), RepositoryError> {
+        create_dir_all(&self.directory).await?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.bucket_path(bucket))
+            .await?;
+        let mut writer = BufWriter::new(file);
+
+        for message in messages {
+            let line = format!(
+                "{}\t{}\t{}\t{}\n",
+                message.id,
+                message.user_id,
+                message.date.timestamp(),
+                message.content.escape_default()
+            );
+            writer.write_all(line.as_bytes()).await?;
+        }
+
+        writer.flush().await?;
+
+        Ok(())
+    }
+}
+
+/// Reads every bucket older than the retention threshold, down to `ends_at` included, exports its
+/// messages to an `ArchiveSink` and deletes them from ScyllaDB. A bucket is only deleted once the
+/// sink accepted it. Buckets are read by partition, one per user, rather than scanned.
+#[derive(Clone, Copy, Debug)]
+pub struct ArchiveOldBucketsRequest {
+    pub retention: Duration,
+    pub ends_at: Option<TimeBucket>,
+}
+
+impl ArchiveOldBucketsRequest {
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            retention,
+            ends_at: None,
+        }
+    }
+
+    pub fn ends_at(self, time_bucket: TimeBucket) -> Self {
+        Self {
+            ends_at: Some(time_bucket),
+            ..self
+        }
+    }
+
+    /// Buckets that are entirely older than the retention threshold, most recent first, down to
+    /// `ends_at` or the first bucket of all.
+    pub fn buckets(&self) -> impl Iterator<Item = TimeBucket> {
+        self.buckets_at(Utc::now())
+    }
+
+    fn buckets_at(&self, now: DateTime<Utc>) -> impl Iterator<Item = TimeBucket> {
+        let threshold = now - self.retention;
+        // `iter_past_to` stops before its end.
+        let end = self.ends_at.unwrap_or_default().previous();
+
+        TimeBucket::from_datetime(threshold)
+            .previous()
+            .iter_past_to(end)
+    }
+
+    /// Returns the number of archived messages, of `users`.
+    #[instrument(name = "ArchiveOldBucketsRequest", skip_all, fields(retention_days = self.retention.num_days()))]
+    pub async fn execute(
+        self,
+        users: &[UserId],
+        session: &Session,
+        sink: &mut impl ArchiveSink,
+    ) -> Result<usize, RepositoryError> {
+        let mut archived = 0;
+
+        for bucket in self.buckets() {
+            let mut messages = Vec::new();
+            for user in users {
+                messages.extend(Self::read_partition(session, *user, bucket).await?);
+            }
+
+            if messages.is_empty() {
+                continue;
+            }
+
+            sink.write_bucket(bucket, &messages).await?;
+
+            let users: HashSet<Uuid> = messages.iter().map(|m| m.user_id.into()).collect();
+            for user in users {
+                session
+                    .query(
+                        "DELETE FROM messages WHERE user_id = ? AND date_bucket = ?",
+                        (user, bucket.get_timestamp()),
+                    )
+                    .await?;
+            }
+
+            archived += messages.len();
+        }
+
+        Ok(archived)
+    }
+
+    async fn read_partition(
+        session: &Session,
+        user: UserId,
+        bucket: TimeBucket,
+    ) -> Result<Vec<Message>, RepositoryError> {
+        let user: Uuid = user.into();
+        let mut rows = session
+            .query_iter(
+                r#"SELECT user_id, message_id, date, content, body, reply_to FROM messages
+                        WHERE user_id = ? AND date_bucket = ?"#,
+                (user, bucket.get_timestamp()),
+            )
+            .await?
+            .into_typed::<(
+                Uuid,
+                (Uuid, i64),
+                Timestamp,
+                String,
+                Option<Vec<u8>>,
+                Option<(Uuid, i64)>,
+            )>();
+
+        let mut messages = Vec::new();
+        while let Some(row) = rows.next().await {
+            let (user_id, message_id, date, content, body, reply_to) = row?;
+
+            messages.push(Message {
+                id: MessageId::from_tuple_i64(message_id),
+                user_id: user_id.into(),
+                date: timestamp_to_datetime(date),
+                mentions: extract_mentions(&content),
+                content,
+                body: decode_body(body)?,
+                reply_to: reply_to.map(MessageId::from_tuple_i64),
+            });
+        }
+
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn archived_buckets_test() {
+    use chrono::{NaiveDate, TimeZone};
+
+    let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    let buckets = |request: ArchiveOldBucketsRequest, now| -> Vec<NaiveDate> {
+        request.buckets_at(now).map(TimeBucket::date).collect()
+    };
+    // A Wednesday, 30 days after Monday 2023-01-30.
+    let now = Utc.with_ymd_and_hms(2023, 3, 1, 12, 0, 0).unwrap();
+
+    // The bucket of the threshold has messages to keep, the first bucket of all is archived too.
+    let request = ArchiveOldBucketsRequest::new(Duration::days(30));
+    assert_eq!(
+        buckets(request, now),
+        vec![
+            date(2023, 1, 23),
+            date(2023, 1, 16),
+            date(2023, 1, 9),
+            date(2023, 1, 2)
+        ]
+    );
+
+    // Until the retention is over for the end of the bucket of 2023-01-30, on 2023-02-06.
+    let cutoff = Utc.with_ymd_and_hms(2023, 3, 8, 0, 0, 0).unwrap();
+    assert_eq!(buckets(request, cutoff - Duration::seconds(1)).len(), 4);
+    let archived = buckets(request, cutoff);
+    assert_eq!((archived[0], archived.len()), (date(2023, 1, 30), 5));
+
+    // Down to `ends_at` included.
+    let ends_at = TimeBucket::from_date(date(2023, 1, 16));
+    assert_eq!(
+        buckets(request.ends_at(ends_at), now),
+        vec![date(2023, 1, 23), date(2023, 1, 16)]
+    );
+
+    // None while the retention covers every bucket.
+    let request = ArchiveOldBucketsRequest::new(Duration::days(365));
+    assert!(buckets(request, now).is_empty());
+}
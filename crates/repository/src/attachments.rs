@@ -0,0 +1,232 @@
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use sqlx::postgres::PgExecutor;
+use sqlx::PgPool;
+use tokio::fs::{create_dir_all, remove_file, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::instrument;
+use uuid::Uuid;
+
+use models::attachments::{Attachment, AttachmentId};
+use models::messages::MessageId;
+use models::users::{UserId, Userlike};
+
+use super::{PgTransaction, RepositoryError};
+
+/// Where the data of attachments is stored. Implementations can write to a local directory, an
+/// object storage…
+#[async_trait]
+pub trait AttachmentStorage: Send + Sync {
+    /// Appends `data` to the attachment, created by the first call.
+    async fn append(&self, id: AttachmentId, data: &[u8]) -> Result<(), RepositoryError>;
+
+    /// Removes the data of an attachment, if any, eg. of an upload that failed.
+    async fn remove(&self, id: AttachmentId) -> Result<(), RepositoryError>;
+}
+
+/// Stores each attachment in a file of `directory` named after its id.
+#[derive(Clone, Debug)]
+pub struct FileAttachmentStorage {
+    pub directory: PathBuf,
+}
+
+impl FileAttachmentStorage {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    pub fn attachment_path(&self, id: AttachmentId) -> PathBuf {
+        self.directory.join(id.to_string())
+    }
+}
+
+#[async_trait]
+impl AttachmentStorage for FileAttachmentStorage {
+    async fn append(&self, id: AttachmentId, data: &[u8]) -> Result<(), RepositoryError> {
+        create_dir_all(&self.directory)
+            .await
+            .map_err(RepositoryError::Storage)?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.attachment_path(id))
+            .await
+            .map_err(RepositoryError::Storage)?;
+        file.write_all(data)
+            .await
+            .map_err(RepositoryError::Storage)?;
+        // Written in the background, flushed so that its errors are not lost.
+        file.flush().await.map_err(RepositoryError::Storage)
+    }
+
+    async fn remove(&self, id: AttachmentId) -> Result<(), RepositoryError> {
+        match remove_file(self.attachment_path(id)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(RepositoryError::Storage(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Metadata of an uploaded attachment, once its data is stored.
+#[derive(Clone)]
+pub struct InsertAttachmentRequest {
+    pub attachment: Attachment,
+}
+
+impl InsertAttachmentRequest {
+    pub fn new(attachment: Attachment) -> Self {
+        Self { attachment }
+    }
+
+    pub async fn execute(self, conn: &PgPool) -> Result<(), RepositoryError> {
+        self.execute_with(conn).await
+    }
+
+    pub async fn execute_in(self, tx: &mut PgTransaction<'_>) -> Result<(), RepositoryError> {
+        self.execute_with(&mut **tx).await
+    }
+
+    #[instrument(name = "InsertAttachmentRequest", skip_all, fields(attachment_id = %self.attachment.id, user_id = %self.attachment.user_id))]
+    async fn execute_with<'e>(self, executor: impl PgExecutor<'e>) -> Result<(), RepositoryError> {
+        let attachment = self.attachment;
+        let attachment_id: Uuid = attachment.id.into();
+        let user_id: Uuid = attachment.user_id.into();
+
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                INSERT INTO attachments
+                    (attachment_id, user_id, filename, content_type, size, checksum)
+                    VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            attachment_id,
+            user_id,
+            attachment.filename,
+            attachment.content_type,
+            attachment.size as i64,
+            attachment.checksum,
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Attaches attachments of the user to one of their messages. Fails with `NotFound` when one of
+/// them is not an attachment of the user, or is already attached to another message. Attaching
+/// them again to the same message is a no-op, so that posts can be retried.
+#[derive(Clone)]
+pub struct AttachToMessageRequest {
+    pub user_id: UserId,
+    pub message_id: MessageId,
+    pub attachments: Vec<AttachmentId>,
+}
+
+impl AttachToMessageRequest {
+    pub fn new(
+        user: impl Userlike,
+        message_id: MessageId,
+        attachments: impl IntoIterator<Item = AttachmentId>,
+    ) -> Self {
+        Self {
+            user_id: user.get_id(),
+            message_id,
+            attachments: attachments.into_iter().collect(),
+        }
+    }
+
+    pub async fn execute(self, conn: &PgPool) -> Result<(), RepositoryError> {
+        let mut tx = conn.begin().await?;
+        self.execute_in(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "AttachToMessageRequest", skip_all, fields(user_id = %self.user_id, message_id = %self.message_id))]
+    pub async fn execute_in(self, tx: &mut PgTransaction<'_>) -> Result<(), RepositoryError> {
+        let user_id: Uuid = self.user_id.into();
+        let message_id = self.message_id.to_string();
+        let mut attachments: Vec<Uuid> = self.attachments.into_iter().map(Into::into).collect();
+        attachments.sort();
+        attachments.dedup();
+
+        let res = sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                UPDATE attachments SET message_id = $1
+                    WHERE attachment_id = ANY($2)
+                    AND user_id = $3
+                    AND (message_id IS NULL OR message_id = $1)
+            "#,
+            message_id,
+            &attachments,
+            user_id,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        match res.rows_affected() as usize == attachments.len() {
+            true => Ok(()),
+            // Rolled back by the caller.
+            false => Err(RepositoryError::NotFound),
+        }
+    }
+}
+
+/// Undoes an `AttachToMessageRequest` of a message that could not be posted, so that the
+/// attachments can be posted with another one.
+#[derive(Clone)]
+pub struct DetachFromMessageRequest {
+    pub user_id: UserId,
+    pub message_id: MessageId,
+    pub attachments: Vec<AttachmentId>,
+}
+
+impl DetachFromMessageRequest {
+    pub fn new(attach: &AttachToMessageRequest) -> Self {
+        Self {
+            user_id: attach.user_id,
+            message_id: attach.message_id,
+            attachments: attach.attachments.clone(),
+        }
+    }
+
+    pub async fn execute(self, conn: &PgPool) -> Result<(), RepositoryError> {
+        self.execute_with(conn).await
+    }
+
+    pub async fn execute_in(self, tx: &mut PgTransaction<'_>) -> Result<(), RepositoryError> {
+        self.execute_with(&mut **tx).await
+    }
+
+    #[instrument(name = "DetachFromMessageRequest", skip_all, fields(user_id = %self.user_id, message_id = %self.message_id))]
+    async fn execute_with<'e>(self, executor: impl PgExecutor<'e>) -> Result<(), RepositoryError> {
+        let user_id: Uuid = self.user_id.into();
+        let message_id = self.message_id.to_string();
+        let attachments: Vec<Uuid> = self.attachments.into_iter().map(Into::into).collect();
+
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                UPDATE attachments SET message_id = NULL
+                    WHERE attachment_id = ANY($1)
+                    AND user_id = $2
+                    AND message_id = $3
+            "#,
+            &attachments,
+            user_id,
+            message_id,
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use futures::{stream::StreamExt, Stream};
+use sqlx::postgres::PgExecutor;
+use sqlx::PgPool;
+
+use models::users::{UserId, Userlike};
+use tracing::instrument;
+use tracing_futures::Instrument;
+use uuid::Uuid;
+
+use super::{PgTransaction, RepositoryError};
+
+/// Blocks a user and removes their friendship or friend requests, if any, with its audit row as
+/// a transaction.
+/// Blocking twice is a no-op.
+#[derive(Copy, Clone)]
+pub struct BlockUserRequest {
+    pub user_id: UserId,
+    pub blocked_id: UserId,
+}
+
+impl BlockUserRequest {
+    pub fn new(user: impl Userlike, blocked: impl Userlike) -> Self {
+        Self {
+            user_id: user.get_id(),
+            blocked_id: blocked.get_id(),
+        }
+    }
+
+    /// Whether an accepted friendship was removed.
+    pub async fn execute(self, conn: &PgPool) -> Result<bool, RepositoryError> {
+        let mut tx = conn.begin().await?;
+        let removed = self.execute_in(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(removed)
+    }
+
+    #[instrument(name = "BlockUserRequest", skip_all, fields(user_id = %self.user_id, blocked_id = %self.blocked_id))]
+    pub async fn execute_in(self, tx: &mut PgTransaction<'_>) -> Result<bool, RepositoryError> {
+        let uuid_a: Uuid = self.user_id.into();
+        let uuid_b: Uuid = self.blocked_id.into();
+
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                INSERT INTO blocks (user_id, blocked_id)
+                    VALUES ($1, $2)
+                    ON CONFLICT DO NOTHING
+            "#,
+            uuid_a,
+            uuid_b,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        // Pending requests are removed too, only friends get a `removed` event.
+        let removed = sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                DELETE FROM friendships
+                    WHERE (user_id = $1
+                        AND friend_id = $2)
+                    OR (user_id = $2
+                        AND friend_id = $1)
+                    RETURNING state
+            "#,
+            uuid_a,
+            uuid_b,
+        )
+        .fetch_all(&mut **tx)
+        .await?;
+
+        if !removed.iter().any(|row| row.state == "accepted") {
+            return Ok(false);
+        }
+
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                INSERT INTO friendship_events (user_id, friend_id, kind)
+                    VALUES ($1, $2, 'removed');
+            "#,
+            uuid_a,
+            uuid_b,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(true)
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct UnblockUserRequest {
+    pub user_id: UserId,
+    pub blocked_id: UserId,
+}
+
+impl UnblockUserRequest {
+    pub fn new(user: impl Userlike, blocked: impl Userlike) -> Self {
+        Self {
+            user_id: user.get_id(),
+            blocked_id: blocked.get_id(),
+        }
+    }
+
+    pub async fn execute(self, conn: &PgPool) -> Result<(), RepositoryError> {
+        self.execute_with(conn).await
+    }
+
+    pub async fn execute_in(self, tx: &mut PgTransaction<'_>) -> Result<(), RepositoryError> {
+        self.execute_with(&mut **tx).await
+    }
+
+    #[instrument(name = "UnblockUserRequest", skip_all, fields(user_id = %self.user_id, blocked_id = %self.blocked_id))]
+    async fn execute_with<'e>(self, executor: impl PgExecutor<'e>) -> Result<(), RepositoryError> {
+        let uuid_a: Uuid = self.user_id.into();
+        let uuid_b: Uuid = self.blocked_id.into();
+
+        let res = sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                DELETE FROM blocks WHERE user_id = $1 AND blocked_id = $2
+            "#,
+            uuid_a,
+            uuid_b,
+        )
+        .execute(executor)
+        .await?;
+
+        match res.rows_affected() {
+            0 => Err(RepositoryError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether one of the users blocked the other.
+#[derive(Copy, Clone)]
+pub struct IsBlockedRequest {
+    pub user_a: UserId,
+    pub user_b: UserId,
+}
+
+impl IsBlockedRequest {
+    pub fn new(user_a: impl Userlike, user_b: impl Userlike) -> Self {
+        Self {
+            user_a: user_a.get_id(),
+            user_b: user_b.get_id(),
+        }
+    }
+
+    pub async fn execute(self, conn: &PgPool) -> Result<bool, RepositoryError> {
+        self.execute_with(conn).await
+    }
+
+    pub async fn execute_in(self, tx: &mut PgTransaction<'_>) -> Result<bool, RepositoryError> {
+        self.execute_with(&mut **tx).await
+    }
+
+    #[instrument(name = "IsBlockedRequest", skip_all, fields(user_id = %self.user_a, other_id = %self.user_b))]
+    async fn execute_with<'e>(
+        self,
+        executor: impl PgExecutor<'e>,
+    ) -> Result<bool, RepositoryError> {
+        let uuid_a: Uuid = self.user_a.into();
+        let uuid_b: Uuid = self.user_b.into();
+
+        let res = sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM blocks
+                        WHERE (user_id = $1
+                            AND blocked_id = $2)
+                        OR (user_id = $2
+                            AND blocked_id = $1)
+                ) AS "blocked!"
+            "#,
+            uuid_a,
+            uuid_b,
+        )
+        .fetch_one(executor)
+        .await?;
+
+        Ok(res.blocked)
+    }
+}
+
+/// Blocks by the user and of the user, as `(user, blocked)`.
+#[derive(Copy, Clone)]
+pub struct GetBlocksOfUserRequest {
+    pub user_id: UserId,
+}
+
+impl GetBlocksOfUserRequest {
+    pub fn new(user: impl Userlike) -> Self {
+        Self {
+            user_id: user.get_id(),
+        }
+    }
+
+    pub fn stream<'a>(
+        self,
+        conn: &'a PgPool,
+    ) -> impl Stream<Item = Result<(UserId, UserId), RepositoryError>> + 'a {
+        self.stream_with(conn)
+    }
+
+    pub fn stream_in<'a>(
+        self,
+        tx: &'a mut PgTransaction<'_>,
+    ) -> impl Stream<Item = Result<(UserId, UserId), RepositoryError>> + 'a {
+        self.stream_with(&mut **tx)
+    }
+
+    fn stream_with<'a>(
+        self,
+        executor: impl PgExecutor<'a> + 'a,
+    ) -> impl Stream<Item = Result<(UserId, UserId), RepositoryError>> + 'a {
+        let uuid: Uuid = self.user_id.into();
+
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                SELECT user_id, blocked_id FROM blocks WHERE user_id = $1 OR blocked_id = $1
+            "#,
+            uuid,
+        )
+        .fetch(executor)
+        .map(|record| {
+            Ok(record.map(|record| {
+                (
+                    UserId::from(record.user_id),
+                    UserId::from(record.blocked_id),
+                )
+            })?)
+        })
+        .instrument(tracing::info_span!("GetBlocksOfUserRequest", user_id = %uuid))
+    }
+}
@@ -0,0 +1,379 @@
+use futures::{FutureExt, Stream, StreamExt};
+use scylla::frame::value::Timestamp;
+use scylla::Session;
+use uuid::Uuid;
+
+use models::conversations::{Conversation, ConversationId, Conversationlike, DirectMessage};
+use models::messages::{extract_mentions, Message, MessageId};
+use models::users::{UserId, Userlike};
+use tracing::instrument;
+use tracing_futures::Instrument;
+
+use super::{decode_body, datetime_to_timestamp, timestamp_to_datetime, RepositoryError, TimeBucket};
+
+/// Creates a conversation between `creator` and `members`. The creator is always a member.
+#[derive(Clone, Debug)]
+pub struct CreateConversationRequest {
+    pub creator: UserId,
+    pub members: Vec<UserId>,
+}
+
+impl CreateConversationRequest {
+    pub fn new(creator: impl Userlike, members: Vec<UserId>) -> Self {
+        Self {
+            creator: creator.get_id(),
+            members,
+        }
+    }
+
+    #[instrument(name = "CreateConversationRequest", skip_all, fields(creator = %self.creator))]
+    pub async fn execute(self, session: &Session) -> Result<Conversation, RepositoryError> {
+        let id = ConversationId::new_now(self.creator);
+
+        let mut members = self.members;
+        members.push(self.creator);
+        members.sort_by_key(|m| Into::<Uuid>::into(*m));
+        members.dedup();
+
+        for member in members.iter() {
+            let uuid: Uuid = (*member).into();
+
+            session
+                .query(
+                    "INSERT INTO conversations (conversation_id, user_id) VALUES (?, ?)",
+                    (id.as_tuple_i64(), uuid),
+                )
+                .await?;
+
+            session
+                .query(
+                    "INSERT INTO conversations_of_user (user_id, conversation_id) VALUES (?, ?)",
+                    (uuid, id.as_tuple_i64()),
+                )
+                .await?;
+        }
+
+        Ok(Conversation { id, members })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GetConversationRequest {
+    pub conversation_id: ConversationId,
+}
+
+impl GetConversationRequest {
+    pub fn new(conversation: impl Conversationlike) -> Self {
+        Self {
+            conversation_id: conversation.get_id(),
+        }
+    }
+
+    #[instrument(name = "GetConversationRequest", skip_all, fields(conversation_id = %self.conversation_id))]
+    pub async fn execute(self, session: &Session) -> Result<Conversation, RepositoryError> {
+        let mut rows = session
+            .query_iter(
+                "SELECT user_id FROM conversations WHERE conversation_id = ?",
+                (self.conversation_id.as_tuple_i64(),),
+            )
+            .await?
+            .into_typed::<(Uuid,)>();
+
+        let mut members = Vec::new();
+        while let Some(row) = rows.next().await {
+            let (user_id,) = row?;
+            members.push(user_id.into());
+        }
+
+        match members.is_empty() {
+            true => Err(RepositoryError::NotFound),
+            false => Ok(Conversation {
+                id: self.conversation_id,
+                members,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GetConversationsOfUserRequest {
+    pub user_id: UserId,
+}
+
+impl GetConversationsOfUserRequest {
+    pub fn new(user: impl Userlike) -> Self {
+        Self {
+            user_id: user.get_id(),
+        }
+    }
+
+    pub fn stream<'a>(
+        self,
+        session: &'a Session,
+    ) -> impl Stream<Item = Result<ConversationId, RepositoryError>> + 'a {
+        let uuid: Uuid = self.user_id.into();
+
+        session
+            .query_iter(
+                "SELECT conversation_id FROM conversations_of_user WHERE user_id = ?",
+                (uuid,),
+            )
+            .map(|res| match res {
+                Ok(rows) => rows
+                    .into_typed::<((Uuid, i64),)>()
+                    .map(|row| {
+                        let (conversation_id,) = row?;
+                        Ok(ConversationId::from_tuple_i64(conversation_id))
+                    })
+                    .left_stream(),
+                Err(e) => futures::stream::iter([Err(RepositoryError::from(e))]).right_stream(),
+            })
+            .flatten_stream()
+            .instrument(tracing::info_span!("GetConversationsOfUserRequest", user_id = %uuid))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InsertDirectMessageRequest {
+    pub conversation_id: ConversationId,
+    pub message: Message,
+}
+
+impl InsertDirectMessageRequest {
+    pub fn new(conversation: impl Conversationlike, message: Message) -> Self {
+        Self {
+            conversation_id: conversation.get_id(),
+            message,
+        }
+    }
+
+    #[instrument(name = "InsertDirectMessageRequest", skip_all, fields(conversation_id = %self.conversation_id, message_id = %self.message.id))]
+    pub async fn execute(self, session: &Session) -> Result<DirectMessage, RepositoryError> {
+        let message = self.message;
+        let uuid: Uuid = message.user_id.into();
+
+        session
+            .query(
+                r#"INSERT INTO direct_messages
+                        (conversation_id, date_bucket, date, message_id, user_id, content, body)
+                        VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+                (
+                    self.conversation_id.as_tuple_i64(),
+                    TimeBucket::from_datetime(message.date).get_timestamp(),
+                    datetime_to_timestamp(message.date),
+                    message.id.as_tuple_i64(),
+                    uuid,
+                    message.content.as_str(),
+                    message.body.encode(),
+                ),
+            )
+            .await?;
+
+        Ok(DirectMessage {
+            conversation_id: self.conversation_id,
+            message,
+        })
+    }
+}
+
+/// Scrolls through time buckets of a conversation, most recent first, down to its creation.
+#[derive(Clone, Copy, Debug)]
+pub struct GetDirectMessagesRequest {
+    pub conversation_id: ConversationId,
+    pub starting_from: Option<TimeBucket>,
+}
+
+impl GetDirectMessagesRequest {
+    pub fn new(conversation: impl Conversationlike) -> Self {
+        Self {
+            conversation_id: conversation.get_id(),
+            starting_from: None,
+        }
+    }
+
+    pub fn starting_from(self, time_bucket: TimeBucket) -> Self {
+        Self {
+            starting_from: Some(time_bucket),
+            ..self
+        }
+    }
+
+    pub fn stream<'a>(
+        self,
+        session: &'a Session,
+    ) -> impl Stream<Item = Result<DirectMessage, RepositoryError>> + 'a {
+        let conversation_id = self.conversation_id;
+        let created = TimeBucket::from_datetime(conversation_id.datetime()).previous();
+
+        let buckets = self
+            .starting_from
+            .unwrap_or_else(TimeBucket::current)
+            .iter_past_to(created);
+
+        futures::stream::iter(buckets)
+            .then(move |bucket| async move {
+                session
+                    .query(
+                        r#"SELECT message_id, user_id, date, content, body FROM direct_messages
+                            WHERE conversation_id = ? AND date_bucket = ?"#,
+                        (conversation_id.as_tuple_i64(), bucket.get_timestamp()),
+                    )
+                    .await
+            })
+            .map(move |res| {
+                let messages: Vec<Result<DirectMessage, RepositoryError>> = match res {
+                    Ok(res) => res
+                        .rows_or_empty()
+                        .into_iter()
+                        .map(|row| {
+                            let (message_id, user_id, date, content, body): (
+                                (Uuid, i64),
+                                Uuid,
+                                Timestamp,
+                                String,
+                                Option<Vec<u8>>,
+                            ) = row.into_typed()?;
+
+                            Ok(DirectMessage {
+                                conversation_id,
+                                message: Message {
+                                    id: MessageId::from_tuple_i64(message_id),
+                                    user_id: user_id.into(),
+                                    date: timestamp_to_datetime(date),
+                                    mentions: extract_mentions(&content),
+                                    content,
+                                    body: decode_body(body)?,
+                                    reply_to: None,
+                                },
+                            })
+                        })
+                        .collect(),
+                    Err(e) => vec![Err(RepositoryError::from(e))],
+                };
+
+                futures::stream::iter(messages)
+            })
+            .flatten()
+            .instrument(tracing::info_span!(
+                "GetDirectMessagesRequest",
+                conversation_id = %conversation_id
+            ))
+    }
+}
+
+/// Removes a user from each of its conversations, with the direct messages it sent, or only
+/// their content when anonymized so that the conversations stay readable.
+#[derive(Clone, Copy, Debug)]
+pub struct DeleteConversationsOfUserRequest {
+    pub user_id: UserId,
+    pub anonymize: bool,
+}
+
+impl DeleteConversationsOfUserRequest {
+    pub fn new(user: impl Userlike) -> Self {
+        Self {
+            user_id: user.get_id(),
+            anonymize: false,
+        }
+    }
+
+    pub fn anonymize(self) -> Self {
+        Self {
+            anonymize: true,
+            ..self
+        }
+    }
+
+    #[instrument(name = "DeleteConversationsOfUserRequest", skip_all, fields(user_id = %self.user_id, anonymize = self.anonymize))]
+    pub async fn execute(self, session: &Session) -> Result<(), RepositoryError> {
+        let uuid: Uuid = self.user_id.into();
+
+        let mut conversations = Vec::new();
+        let mut rows = session
+            .query_iter(
+                "SELECT conversation_id FROM conversations_of_user WHERE user_id = ?",
+                (uuid,),
+            )
+            .await?
+            .into_typed::<((Uuid, i64),)>();
+        while let Some(row) = rows.next().await {
+            let (conversation_id,) = row?;
+            conversations.push(ConversationId::from_tuple_i64(conversation_id));
+        }
+
+        for conversation_id in conversations {
+            let created = TimeBucket::from_datetime(conversation_id.datetime()).previous();
+
+            for bucket in TimeBucket::current().iter_past_to(created) {
+                self.clear_bucket(session, conversation_id, bucket).await?;
+            }
+
+            session
+                .query(
+                    "DELETE FROM conversations WHERE conversation_id = ? AND user_id = ?",
+                    (conversation_id.as_tuple_i64(), uuid),
+                )
+                .await?;
+        }
+
+        session
+            .query(
+                "DELETE FROM conversations_of_user WHERE user_id = ?",
+                (uuid,),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn clear_bucket(
+        self,
+        session: &Session,
+        conversation_id: ConversationId,
+        bucket: TimeBucket,
+    ) -> Result<(), RepositoryError> {
+        let uuid: Uuid = self.user_id.into();
+
+        let mut rows = session
+            .query_iter(
+                r#"SELECT date, message_id, user_id FROM direct_messages
+                    WHERE conversation_id = ? AND date_bucket = ?"#,
+                (conversation_id.as_tuple_i64(), bucket.get_timestamp()),
+            )
+            .await?
+            .into_typed::<(Timestamp, (Uuid, i64), Uuid)>();
+
+        while let Some(row) = rows.next().await {
+            let (date, message_id, user_id) = row?;
+            if user_id != uuid {
+                continue;
+            }
+
+            let key = (conversation_id.as_tuple_i64(), bucket.get_timestamp(), date, message_id);
+            match self.anonymize {
+                true => {
+                    session
+                        .query(
+                            r#"UPDATE direct_messages SET content = '', body = null
+                                WHERE conversation_id = ? AND date_bucket = ? AND date = ?
+                                    AND message_id = ?"#,
+                            key,
+                        )
+                        .await?;
+                }
+                false => {
+                    session
+                        .query(
+                            r#"DELETE FROM direct_messages
+                                WHERE conversation_id = ? AND date_bucket = ? AND date = ?
+                                    AND message_id = ?"#,
+                            key,
+                        )
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,16 +1,174 @@
 use std::{iter::from_fn, ops::Deref};
 
-use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc};
+use models::cursor::Cursor;
+use models::messages::{Message, MessageBody};
+use models::friendships::FriendshipStateParsingError;
+use models::proto::ProtoDecodeMessageError;
+use scylla::cql_to_rust::FromRowError;
 use scylla::frame::value::Timestamp;
+use scylla::transport::errors::{DbError as ScyllaDbError, QueryError};
+use scylla::transport::iterator::NextRowError;
+use thiserror::Error;
 
+pub mod archive;
+pub mod attachments;
+pub mod blocks;
+pub mod conversations;
 pub mod messages;
+pub mod reactions;
+pub mod schema;
 pub mod users;
 
 // Re-exports
 pub use scylla::Session;
 pub use sqlx::PgPool;
 
-/// A bucket is used to group rows in ScyllaDB. We group them by week starting on monday 00:00.
+/// Postgres requests can run on their own with `execute(&PgPool)` or as part of a bigger operation
+/// with `execute_in(&mut PgTransaction)`. Nothing is persisted until the caller commits.
+pub type PgTransaction<'c> = sqlx::Transaction<'c, sqlx::Postgres>;
+
+/// Errors of the underlying databases that have no meaning for the caller besides "something went wrong".
+#[derive(Error, Debug)]
+pub enum DbError {
+    #[error("PostgreSQL error")]
+    Postgres(#[from] sqlx::Error),
+    #[error("ScyllaDB query error")]
+    Scylla(#[from] QueryError),
+    #[error("invalid ScyllaDB row")]
+    ScyllaRow(#[from] FromRowError),
+    #[error("invalid stored message body")]
+    MessageBody(#[from] ProtoDecodeMessageError),
+    #[error("invalid stored friendship state")]
+    FriendshipState(#[from] FriendshipStateParsingError),
+}
+
+#[derive(Error, Debug)]
+pub enum RepositoryError {
+    #[error("entity not found")]
+    NotFound,
+    #[error("entity already exists or conflicts with an existing one")]
+    Conflict,
+    #[error("one of the users blocked the other")]
+    Blocked,
+    #[error("database operation timed out")]
+    Timeout,
+    #[error("database unavailable, too many recent failures")]
+    Unavailable,
+    #[error("database error")]
+    Db(#[from] DbError),
+    #[error("archive sink error")]
+    Archive(#[from] std::io::Error),
+    #[error("attachment storage error")]
+    Storage(std::io::Error),
+}
+
+impl RepositoryError {
+    /// The same request may succeed if retried.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Timeout => true,
+            Self::Db(e) => e.is_transient(),
+            _ => false,
+        }
+    }
+}
+
+impl DbError {
+    /// Of the connection to the database rather than of the request: invalid rows and queries
+    /// fail the same way each time.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Postgres(
+                sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed,
+            ) => true,
+            Self::Postgres(sqlx::Error::Database(e)) => e
+                .code()
+                .is_some_and(|code| code.starts_with(PG_CONNECTION_EXCEPTION)),
+            Self::Scylla(
+                QueryError::IoError(_)
+                | QueryError::TooManyOrphanedStreamIds(_)
+                | QueryError::UnableToAllocStreamId
+                | QueryError::DbError(
+                    ScyllaDbError::Unavailable { .. }
+                    | ScyllaDbError::Overloaded
+                    | ScyllaDbError::IsBootstrapping,
+                    _,
+                ),
+            ) => true,
+            Self::Postgres(_)
+            | Self::Scylla(_)
+            | Self::ScyllaRow(_)
+            | Self::MessageBody(_)
+            | Self::FriendshipState(_) => false,
+        }
+    }
+}
+
+/// https://www.postgresql.org/docs/current/errcodes-appendix.html
+const PG_UNIQUE_VIOLATION: &str = "23505";
+const PG_FOREIGN_KEY_VIOLATION: &str = "23503";
+/// The class of the errors of the connection.
+const PG_CONNECTION_EXCEPTION: &str = "08";
+
+impl From<sqlx::Error> for RepositoryError {
+    fn from(value: sqlx::Error) -> Self {
+        match &value {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            sqlx::Error::PoolTimedOut => Self::Timeout,
+            sqlx::Error::Database(e) => match e.code().as_deref() {
+                Some(PG_UNIQUE_VIOLATION) => Self::Conflict,
+                // Referencing a user that does not exist.
+                Some(PG_FOREIGN_KEY_VIOLATION) => Self::NotFound,
+                _ => Self::Db(value.into()),
+            },
+            _ => Self::Db(value.into()),
+        }
+    }
+}
+
+impl From<QueryError> for RepositoryError {
+    fn from(value: QueryError) -> Self {
+        match &value {
+            QueryError::TimeoutError
+            | QueryError::RequestTimeout(_)
+            | QueryError::DbError(
+                ScyllaDbError::ReadTimeout { .. } | ScyllaDbError::WriteTimeout { .. },
+                _,
+            ) => Self::Timeout,
+            _ => Self::Db(value.into()),
+        }
+    }
+}
+
+impl From<FromRowError> for RepositoryError {
+    fn from(value: FromRowError) -> Self {
+        Self::Db(value.into())
+    }
+}
+
+impl From<ProtoDecodeMessageError> for RepositoryError {
+    fn from(value: ProtoDecodeMessageError) -> Self {
+        Self::Db(value.into())
+    }
+}
+
+impl From<FriendshipStateParsingError> for RepositoryError {
+    fn from(value: FriendshipStateParsingError) -> Self {
+        Self::Db(value.into())
+    }
+}
+
+impl From<NextRowError> for RepositoryError {
+    fn from(value: NextRowError) -> Self {
+        match value {
+            NextRowError::QueryError(e) => e.into(),
+            NextRowError::FromRowError(e) => e.into(),
+        }
+    }
+}
+
+/// A bucket is used to group rows in ScyllaDB. We group them by week starting on monday 00:00 UTC.
 /// This is not generic yet.
 #[derive(Clone, Copy, Debug)]
 pub struct TimeBucket(NaiveDate);
@@ -29,13 +187,19 @@ impl Default for TimeBucket {
     }
 }
 
+impl From<Cursor> for TimeBucket {
+    fn from(value: Cursor) -> Self {
+        Self::from_date(value.bucket())
+    }
+}
+
 impl TimeBucket {
     pub fn current() -> Self {
-        Self::from_date(chrono::offset::Local::now().date_naive())
+        Self::from_date(Utc::now().date_naive())
     }
 
-    pub fn from_datetime(datetime: NaiveDateTime) -> Self {
-        Self::from_date(datetime.date())
+    pub fn from_datetime(datetime: DateTime<Utc>) -> Self {
+        Self::from_date(datetime.date_naive())
     }
 
     pub fn from_date(datetime: NaiveDate) -> Self {
@@ -45,21 +209,28 @@ impl TimeBucket {
     }
 
     pub fn get_timestamp(self) -> Timestamp {
-        Timestamp(Duration::seconds(
-            self.0.and_time(NaiveTime::default()).timestamp(),
-        ))
+        Timestamp(Duration::seconds(self.datetime().timestamp()))
     }
 
     pub fn previous(self) -> Self {
         Self(self.0 - Duration::days(7))
     }
 
+    pub fn next(self) -> Self {
+        Self(self.0 + Duration::days(7))
+    }
+
     pub fn date(self) -> NaiveDate {
         self.0
     }
 
-    pub fn datetime(self) -> NaiveDateTime {
-        self.0.and_time(NaiveTime::default())
+    pub fn datetime(self) -> DateTime<Utc> {
+        self.0.and_time(NaiveTime::default()).and_utc()
+    }
+
+    /// Page token after `message`, stored in this bucket.
+    pub fn cursor(self, message: &Message) -> Cursor {
+        Cursor::new(self.0, message)
     }
 
     pub fn iter_past_to(mut self, end: TimeBucket) -> impl Iterator<Item = TimeBucket> {
@@ -89,6 +260,50 @@ impl TimeBucket {
     }
 }
 
-fn timestamp_to_naive(ts: Timestamp) -> NaiveDateTime {
-    NaiveDateTime::from_timestamp_millis(ts.0.num_seconds()).unwrap()
+fn timestamp_to_datetime(ts: Timestamp) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(ts.0.num_milliseconds()).unwrap()
+}
+
+fn datetime_to_timestamp(datetime: DateTime<Utc>) -> Timestamp {
+    Timestamp(Duration::milliseconds(datetime.timestamp_millis()))
+}
+
+/// Plain text bodies aren't stored.
+fn decode_body(body: Option<Vec<u8>>) -> Result<MessageBody, ProtoDecodeMessageError> {
+    body.map_or(Ok(MessageBody::Text), |body| MessageBody::decode(&body))
+}
+
+#[cfg(test)]
+#[test]
+fn transient_errors_test() {
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use models::friendships::FriendshipState;
+
+    let io = || std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+    assert!(RepositoryError::Timeout.is_transient());
+    assert!(RepositoryError::from(sqlx::Error::Io(io())).is_transient());
+    assert!(RepositoryError::from(QueryError::IoError(Arc::new(io()))).is_transient());
+    assert!(RepositoryError::from(QueryError::DbError(
+        ScyllaDbError::Overloaded,
+        String::new()
+    ))
+    .is_transient());
+
+    // Retried, they fail again.
+    assert!(!RepositoryError::from(sqlx::Error::ColumnNotFound("name".into())).is_transient());
+    assert!(!RepositoryError::from(QueryError::DbError(
+        ScyllaDbError::SyntaxError,
+        String::new()
+    ))
+    .is_transient());
+    assert!(!RepositoryError::from(FromRowError::WrongRowSize {
+        expected: 2,
+        actual: 1
+    })
+    .is_transient());
+    let state = FriendshipState::from_str("friends").unwrap_err();
+    assert!(!RepositoryError::from(state).is_transient());
+    assert!(!RepositoryError::NotFound.is_transient());
 }
@@ -1,14 +1,20 @@
-use anyhow::Error;
-use chrono::{Duration, NaiveDateTime};
-use futures::{FutureExt, Stream, StreamExt};
+use std::collections::HashSet;
+
+use chrono::{DateTime, Duration, Utc};
+use futures::{FutureExt, Stream, StreamExt, TryStreamExt};
+use scylla::batch::{Batch, BatchType};
 use scylla::frame::value::Timestamp;
 use scylla::Session;
 use uuid::Uuid;
 
-use models::messages::{Message, MessageId, Messagelike};
+use models::messages::{extract_mentions, Message, MessageBody, MessageId, Messagelike};
 use models::users::{UserId, Userlike};
+use tracing::instrument;
+use tracing_futures::Instrument;
 
-use super::{timestamp_to_naive, TimeBucket};
+use super::{
+    datetime_to_timestamp, decode_body, timestamp_to_datetime, RepositoryError, TimeBucket,
+};
 
 /// FIXME: Timestamp and time_bucket are calculated by requester and not by DB.
 /// It should be calculated in DB using a **User Defined Function** in Lua.
@@ -19,11 +25,13 @@ pub struct InsertMessageRequest {
     pub message_id: Option<MessageId>,
     pub user_id: UserId,
     pub content: String,
-    pub datetime: Option<NaiveDateTime>,
+    pub body: MessageBody,
+    pub datetime: Option<DateTime<Utc>>,
+    pub reply_to: Option<MessageId>,
 }
 
 impl InsertMessageRequest {
-    fn get_timestamps(datetime: NaiveDateTime) -> (Timestamp, Timestamp) {
+    fn get_timestamps(datetime: DateTime<Utc>) -> (Timestamp, Timestamp) {
         let timestamp = datetime.timestamp();
 
         (
@@ -38,11 +46,17 @@ impl InsertMessageRequest {
             message_id: None,
             user_id,
             content,
+            body: MessageBody::Text,
             datetime: None,
+            reply_to: None,
         }
     }
 
-    pub fn with_datetime(self, datetime: NaiveDateTime) -> Self {
+    pub fn with_body(self, body: MessageBody) -> Self {
+        Self { body, ..self }
+    }
+
+    pub fn with_datetime(self, datetime: DateTime<Utc>) -> Self {
         Self {
             datetime: Some(datetime),
             ..self
@@ -56,36 +70,95 @@ impl InsertMessageRequest {
         }
     }
 
-    pub async fn execute(self, session: &Session) -> Result<MessageId, Error> {
+    pub fn with_reply_to(self, reply_to: Option<MessageId>) -> Self {
+        Self { reply_to, ..self }
+    }
+
+    /// Idempotent when the id is supplied: the date is derived from the id so a retried request targets
+    /// the same row, which `IF NOT EXISTS` refuses with `RepositoryError::Conflict`. Once inserted,
+    /// its mentions are indexed for `GetMentionsRequest`, the message is kept if they can't be.
+    #[instrument(name = "InsertMessageRequest", skip_all, fields(user_id = %self.user_id))]
+    pub async fn execute(self, session: &Session) -> Result<MessageId, RepositoryError> {
         let datetime = self
             .datetime
-            .unwrap_or_else(|| chrono::offset::Local::now().naive_local());
+            .or_else(|| self.message_id.map(|id| id.datetime()))
+            .unwrap_or_else(Utc::now);
         let message_id = self
             .message_id
             .unwrap_or_else(|| MessageId::new_now(self.user_id));
         let (timestamp, bucket_timestamp) = Self::get_timestamps(datetime);
         let uuid: Uuid = self.user_id.into();
+        let mentions = extract_mentions(&self.content);
 
-        session
-            .query("INSERT INTO messages (message_id, user_id, date_bucket, date, content) VALUES (?, ?, ?, ?, ?)", (
+        let res = session
+            .query("INSERT INTO messages (message_id, user_id, date_bucket, date, content, body, reply_to) VALUES (?, ?, ?, ?, ?, ?, ?) IF NOT EXISTS", (
                 message_id.as_tuple_i64(),
                 uuid,
                 bucket_timestamp,
                 timestamp,
-                self.content
+                self.content,
+                self.body.encode(),
+                self.reply_to.map(|reply_to| reply_to.as_tuple_i64()),
             ))
             .await?;
 
+        // Lightweight transactions answer with an `[applied]` column first.
+        let applied = res
+            .rows_or_empty()
+            .first()
+            .and_then(|row| row.columns.first())
+            .and_then(|column| column.as_ref())
+            .and_then(|value| value.as_boolean())
+            .unwrap_or(true);
+
+        if !applied {
+            return Err(RepositoryError::Conflict);
+        }
+
+        for mention in mentions {
+            let res = session
+                .query(
+                    r#"INSERT INTO mentions (name, date_bucket, date, message_id, user_id)
+                        VALUES (?, ?, ?, ?, ?)"#,
+                    (
+                        mention.name(),
+                        bucket_timestamp,
+                        timestamp,
+                        message_id.as_tuple_i64(),
+                        uuid,
+                    ),
+                )
+                .await;
+
+            if let Err(e) = res {
+                tracing::warn!(error = %e, %message_id, %mention, "Can't index the mention");
+            }
+        }
+
         Ok(message_id)
     }
 }
 
+/// `message_id, date, content, body, reply_to` of the `messages` table.
+type MessageRow = (
+    (Uuid, i64),
+    Timestamp,
+    String,
+    Option<Vec<u8>>,
+    Option<(Uuid, i64)>,
+);
+
+/// `user_id, date, message_id` of the `mentions` table.
+type MentionRow = (Uuid, Timestamp, (Uuid, i64));
+
 /// Scrolls through time buckets and returns the messages.
 #[derive(Clone, Copy, Debug)]
 pub struct GetLastMessagesOfUserRequest {
     pub user_id: UserId,
     pub starting_from: Option<TimeBucket>,
     pub ends_at: Option<TimeBucket>,
+    /// `[start, end)` range on the message date.
+    pub range: Option<(DateTime<Utc>, DateTime<Utc>)>,
 }
 
 impl GetLastMessagesOfUserRequest {
@@ -94,6 +167,17 @@ impl GetLastMessagesOfUserRequest {
             user_id: user.get_id(),
             starting_from: None,
             ends_at: None,
+            range: None,
+        }
+    }
+
+    /// Only messages posted in `[start, end)`. Scrolls exactly through the buckets covering the range.
+    pub fn between(self, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        Self {
+            starting_from: Some(TimeBucket::from_datetime(end)),
+            ends_at: Some(TimeBucket::from_datetime(start).previous()),
+            range: Some((start, end)),
+            ..self
         }
     }
 
@@ -114,7 +198,7 @@ impl GetLastMessagesOfUserRequest {
     pub fn stream<'a>(
         self,
         session: &'a Session,
-    ) -> impl Stream<Item = Result<Message, Error>> + 'a {
+    ) -> impl Stream<Item = Result<Message, RepositoryError>> + 'a {
         let user_id = self.user_id;
         let uuid: Uuid = self.user_id.into();
         let time_bucket_iter = self
@@ -122,14 +206,26 @@ impl GetLastMessagesOfUserRequest {
             .unwrap_or_else(|| TimeBucket::current())
             .iter_past_to(self.ends_at.unwrap_or_default());
 
+        let range = self.range;
+
         let time_bucket_stream = futures::stream::iter(time_bucket_iter);
 
         let bucketted_result = time_bucket_stream.map(move |bucket| {
+            let (start, end) =
+                range.unwrap_or_else(|| (bucket.datetime(), bucket.next().datetime()));
+
             session.query(
-                r#"SELECT message_id, date, content FROM messages
+                r#"SELECT message_id, date, content, body, reply_to FROM messages
                         WHERE   user_id = ?
-                            AND date_bucket = ?"#,
-                (uuid, bucket.get_timestamp()),
+                            AND date_bucket = ?
+                            AND date >= ?
+                            AND date < ?"#,
+                (
+                    uuid,
+                    bucket.get_timestamp(),
+                    datetime_to_timestamp(start),
+                    datetime_to_timestamp(end),
+                ),
             )
         });
 
@@ -137,34 +233,131 @@ impl GetLastMessagesOfUserRequest {
             .map(move |res| {
                 res.into_stream().map(move |res| match res {
                     Ok(res) => {
-                        let messages: Vec<Result<Message, Error>> = res
+                        let messages: Vec<Result<Message, RepositoryError>> = res
                             .rows_or_empty()
                             .into_iter()
                             .map(|row| {
-                                let (message_id, date, content): ((Uuid, i64), Timestamp, String) =
+                                let (message_id, date, content, body, reply_to): MessageRow =
                                     row.into_typed()?;
 
                                 Result::Ok(Message {
                                     id: MessageId::from_tuple_i64(message_id),
-                                    date: timestamp_to_naive(date),
+                                    date: timestamp_to_datetime(date),
+                                    mentions: extract_mentions(&content),
                                     content,
+                                    body: decode_body(body)?,
                                     user_id,
+                                    reply_to: reply_to.map(MessageId::from_tuple_i64),
                                 })
                             })
                             .collect();
 
                         futures::stream::iter(messages)
                     }
-                    Err(e) => futures::stream::iter(vec![Err(Error::from(e))]),
+                    Err(e) => futures::stream::iter(vec![Err(RepositoryError::from(e))]),
                 })
             })
             .flatten()
-            .flatten();
+            .flatten()
+            .instrument(tracing::info_span!("GetLastMessagesOfUserRequest", user_id = %user_id));
 
         stream
     }
 }
 
+/// The messages that mention `name` posted after `since`, most recent first, from the `mentions`
+/// index. The ones deleted since are skipped.
+#[derive(Clone, Debug)]
+pub struct GetMentionsRequest {
+    pub name: String,
+    pub since: DateTime<Utc>,
+}
+
+impl GetMentionsRequest {
+    pub fn new(name: impl Into<String>, since: DateTime<Utc>) -> Self {
+        Self {
+            name: name.into(),
+            since,
+        }
+    }
+
+    pub fn stream<'a>(
+        self,
+        session: &'a Session,
+    ) -> impl Stream<Item = Result<Message, RepositoryError>> + 'a {
+        let since = self.since;
+        let name = self.name;
+        let buckets =
+            TimeBucket::current().iter_past_to(TimeBucket::from_datetime(since).previous());
+        let span = tracing::info_span!("GetMentionsRequest", name = %name);
+
+        futures::stream::iter(buckets)
+            .then(move |bucket| {
+                let name = name.clone();
+
+                async move {
+                    session
+                        .query(
+                            r#"SELECT user_id, date, message_id FROM mentions
+                                WHERE name = ? AND date_bucket = ? AND date > ?"#,
+                            (name, bucket.get_timestamp(), datetime_to_timestamp(since)),
+                        )
+                        .await
+                }
+            })
+            .map(|res| {
+                let mentions: Vec<Result<MentionRow, RepositoryError>> = match res {
+                    Ok(res) => res
+                        .rows_or_empty()
+                        .into_iter()
+                        .map(|row| Ok(row.into_typed()?))
+                        .collect(),
+                    Err(e) => vec![Err(RepositoryError::from(e))],
+                };
+
+                futures::stream::iter(mentions)
+            })
+            .flatten()
+            .and_then(move |(user_id, date, message_id)| {
+                Self::get_message(session, user_id, date, message_id)
+            })
+            .try_filter_map(futures::future::ok)
+            .instrument(span)
+    }
+
+    async fn get_message(
+        session: &Session,
+        user_id: Uuid,
+        date: Timestamp,
+        message_id: (Uuid, i64),
+    ) -> Result<Option<Message>, RepositoryError> {
+        let bucket = TimeBucket::from_datetime(timestamp_to_datetime(date));
+
+        let res = session
+            .query(
+                r#"SELECT message_id, date, content, body, reply_to FROM messages
+                    WHERE user_id = ? AND date_bucket = ? AND date = ? AND message_id = ?"#,
+                (user_id, bucket.get_timestamp(), date, message_id),
+            )
+            .await?;
+
+        let Some(row) = res.rows_or_empty().into_iter().next() else {
+            return Ok(None);
+        };
+        let (message_id, date, content, body, reply_to): MessageRow = row.into_typed()?;
+
+        Ok(Some(Message {
+            id: MessageId::from_tuple_i64(message_id),
+            date: timestamp_to_datetime(date),
+            mentions: extract_mentions(&content),
+            content,
+            body: decode_body(body)?,
+            user_id: user_id.into(),
+            reply_to: reply_to.map(MessageId::from_tuple_i64),
+        }))
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct AddSeenTagRequest {
     pub user_id: UserId,
@@ -179,7 +372,8 @@ impl AddSeenTagRequest {
         }
     }
 
-    pub async fn execute(self, session: &Session) -> Result<(), Error> {
+    #[instrument(name = "AddSeenTagRequest", skip_all, fields(user_id = %self.user_id, message_id = %self.message_id))]
+    pub async fn execute(self, session: &Session) -> Result<(), RepositoryError> {
         let uuid: Uuid = self.user_id.into();
 
         let _ = session
@@ -194,6 +388,51 @@ impl AddSeenTagRequest {
     }
 }
 
+/// Writes many read tags at once, in unlogged batches of `BATCH_SIZE` rows.
+#[derive(Clone, Debug)]
+pub struct AddSeenTagsRequest {
+    pub tags: Vec<(UserId, MessageId)>,
+}
+
+impl AddSeenTagsRequest {
+    const BATCH_SIZE: usize = 100;
+
+    pub fn new(tags: Vec<(UserId, MessageId)>) -> Self {
+        Self { tags }
+    }
+
+    pub fn by_user(user: impl Userlike, messages: impl IntoIterator<Item = MessageId>) -> Self {
+        let user = user.get_id();
+
+        Self::new(messages.into_iter().map(|m| (user, m)).collect())
+    }
+
+    pub fn by_users(message: impl Messagelike, users: impl IntoIterator<Item = UserId>) -> Self {
+        let message = message.get_id();
+
+        Self::new(users.into_iter().map(|u| (u, message)).collect())
+    }
+
+    #[instrument(name = "AddSeenTagsRequest", skip_all, fields(count = self.tags.len()))]
+    pub async fn execute(self, session: &Session) -> Result<(), RepositoryError> {
+        for chunk in self.tags.chunks(Self::BATCH_SIZE) {
+            let mut batch = Batch::new(BatchType::Unlogged);
+            let values: Vec<(Uuid, (Uuid, i64))> = chunk
+                .iter()
+                .map(|(user, message)| ((*user).into(), message.as_tuple_i64()))
+                .collect();
+
+            for _ in values.iter() {
+                batch.append_statement("INSERT INTO read_tags (user_id, message_id) VALUES (?, ?)");
+            }
+
+            session.batch(&batch, values).await?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct RemoveSeenTagRequest {
     pub user_id: UserId,
@@ -208,7 +447,8 @@ impl RemoveSeenTagRequest {
         }
     }
 
-    pub async fn execute(self, session: &Session) -> Result<(), Error> {
+    #[instrument(name = "RemoveSeenTagRequest", skip_all, fields(user_id = %self.user_id, message_id = %self.message_id))]
+    pub async fn execute(self, session: &Session) -> Result<(), RepositoryError> {
         let uuid: Uuid = self.user_id.into();
 
         let _ = session
@@ -221,3 +461,194 @@ impl RemoveSeenTagRequest {
         Ok(())
     }
 }
+
+/// Every message seen by a user, used to filter out read messages.
+#[derive(Clone, Copy, Debug)]
+pub struct GetReadTagsOfUserRequest {
+    pub user_id: UserId,
+}
+
+impl GetReadTagsOfUserRequest {
+    pub fn new(user: impl Userlike) -> Self {
+        Self {
+            user_id: user.get_id(),
+        }
+    }
+
+    #[instrument(name = "GetReadTagsOfUserRequest", skip_all, fields(user_id = %self.user_id))]
+    pub async fn execute(self, session: &Session) -> Result<HashSet<MessageId>, RepositoryError> {
+        let uuid: Uuid = self.user_id.into();
+
+        let mut rows = session
+            .query_iter(
+                r#"SELECT message_id FROM read_tags WHERE user_id = ?"#,
+                (uuid,),
+            )
+            .await?
+            .into_typed::<((Uuid, i64),)>();
+
+        let mut tags = HashSet::new();
+        while let Some(row) = rows.next().await {
+            let (message_id,) = row?;
+            tags.insert(MessageId::from_tuple_i64(message_id));
+        }
+
+        Ok(tags)
+    }
+}
+
+/// Removes (or anonymizes) every message posted by a user, bucket by bucket, along with their
+/// mentions. Removed messages lose their reactions too.
+#[derive(Clone, Copy, Debug)]
+pub struct DeleteMessagesOfUserRequest {
+    pub user_id: UserId,
+    pub ends_at: Option<TimeBucket>,
+    pub anonymize: bool,
+}
+
+impl DeleteMessagesOfUserRequest {
+    pub fn new(user: impl Userlike) -> Self {
+        Self {
+            user_id: user.get_id(),
+            ends_at: None,
+            anonymize: false,
+        }
+    }
+
+    pub fn ends_at(self, time_bucket: TimeBucket) -> Self {
+        Self {
+            ends_at: Some(time_bucket),
+            ..self
+        }
+    }
+
+    /// Keeps the rows so that the conversations stay readable, but blanks their content.
+    pub fn anonymize(self) -> Self {
+        Self {
+            anonymize: true,
+            ..self
+        }
+    }
+
+    #[instrument(name = "DeleteMessagesOfUserRequest", skip_all, fields(user_id = %self.user_id, anonymize = self.anonymize))]
+    pub async fn execute(self, session: &Session) -> Result<(), RepositoryError> {
+        let uuid: Uuid = self.user_id.into();
+
+        for bucket in TimeBucket::current().iter_past_to(self.ends_at.unwrap_or_default()) {
+            self.clear_bucket(session, uuid, bucket).await?;
+
+            if !self.anonymize {
+                session
+                    .query(
+                        "DELETE FROM messages WHERE user_id = ? AND date_bucket = ?",
+                        (uuid, bucket.get_timestamp()),
+                    )
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// What refers to the messages of the bucket, and their content when anonymized.
+    async fn clear_bucket(
+        self,
+        session: &Session,
+        uuid: Uuid,
+        bucket: TimeBucket,
+    ) -> Result<(), RepositoryError> {
+        let mut rows = session
+            .query_iter(
+                r#"SELECT date, message_id, content FROM messages
+                        WHERE user_id = ? AND date_bucket = ?"#,
+                (uuid, bucket.get_timestamp()),
+            )
+            .await?
+            .into_typed::<(Timestamp, (Uuid, i64), String)>();
+
+        while let Some(row) = rows.next().await {
+            let (date, message_id, content) = row?;
+
+            for mention in extract_mentions(&content) {
+                session
+                    .query(
+                        r#"DELETE FROM mentions
+                            WHERE name = ? AND date_bucket = ? AND date = ? AND message_id = ?"#,
+                        (mention.name(), bucket.get_timestamp(), date, message_id),
+                    )
+                    .await?;
+            }
+
+            match self.anonymize {
+                true => {
+                    session
+                        .query(
+                            r#"UPDATE messages SET content = '', body = null
+                                WHERE user_id = ? AND date_bucket = ? AND date = ? AND message_id = ?"#,
+                            (uuid, bucket.get_timestamp(), date, message_id),
+                        )
+                        .await?;
+                }
+                false => {
+                    session
+                        .query("DELETE FROM reactions WHERE message_id = ?", (message_id,))
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes the mentions of a name, of a user that is deleted, so that the next user of the name
+/// is not told about them.
+#[derive(Clone, Debug)]
+pub struct DeleteMentionsOfNameRequest {
+    pub name: String,
+}
+
+impl DeleteMentionsOfNameRequest {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into() }
+    }
+
+    #[instrument(name = "DeleteMentionsOfNameRequest", skip_all, fields(name = %self.name))]
+    pub async fn execute(self, session: &Session) -> Result<(), RepositoryError> {
+        for bucket in TimeBucket::current().iter_past_to(TimeBucket::default()) {
+            session
+                .query(
+                    "DELETE FROM mentions WHERE name = ? AND date_bucket = ?",
+                    (self.name.as_str(), bucket.get_timestamp()),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes every read tag of a user.
+#[derive(Clone, Copy, Debug)]
+pub struct DeleteReadTagsOfUserRequest {
+    pub user_id: UserId,
+}
+
+impl DeleteReadTagsOfUserRequest {
+    pub fn new(user: impl Userlike) -> Self {
+        Self {
+            user_id: user.get_id(),
+        }
+    }
+
+    #[instrument(name = "DeleteReadTagsOfUserRequest", skip_all, fields(user_id = %self.user_id))]
+    pub async fn execute(self, session: &Session) -> Result<(), RepositoryError> {
+        let uuid: Uuid = self.user_id.into();
+
+        let _ = session
+            .query(r#"DELETE FROM read_tags WHERE user_id = ?"#, (uuid,))
+            .await?;
+
+        Ok(())
+    }
+}
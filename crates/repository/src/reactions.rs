@@ -0,0 +1,172 @@
+use futures::StreamExt;
+use scylla::batch::{Batch, BatchType};
+use scylla::Session;
+use uuid::Uuid;
+
+use models::messages::{MessageId, Messagelike};
+use models::reactions::{Reaction, ReactionCount, ReactionSummary};
+use models::users::{UserId, Userlike};
+use tracing::instrument;
+
+use super::{datetime_to_timestamp, RepositoryError};
+
+/// Reacting twice with the same emoji is a no-op. Indexed by user in `reactions_of_user`, in the
+/// same batch.
+#[derive(Clone, Debug)]
+pub struct AddReactionRequest {
+    pub reaction: Reaction,
+}
+
+impl AddReactionRequest {
+    pub fn new(reaction: Reaction) -> Self {
+        Self { reaction }
+    }
+
+    #[instrument(name = "AddReactionRequest", skip_all, fields(user_id = %self.reaction.user_id, message_id = %self.reaction.message_id))]
+    pub async fn execute(self, session: &Session) -> Result<(), RepositoryError> {
+        let uuid: Uuid = self.reaction.user_id.into();
+
+        let message_id = self.reaction.message_id.as_tuple_i64();
+
+        let mut batch = Batch::new(BatchType::Logged);
+        batch.append_statement(
+            "INSERT INTO reactions (message_id, emoji, user_id, added_at) VALUES (?, ?, ?, ?)",
+        );
+        batch.append_statement(
+            "INSERT INTO reactions_of_user (user_id, message_id, emoji) VALUES (?, ?, ?)",
+        );
+
+        let emoji = self.reaction.emoji.as_str();
+        let added_at = datetime_to_timestamp(self.reaction.added_at);
+        session
+            .batch(
+                &batch,
+                (
+                    (message_id, emoji, uuid, added_at),
+                    (uuid, message_id, emoji),
+                ),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RemoveReactionRequest {
+    pub reaction: Reaction,
+}
+
+impl RemoveReactionRequest {
+    pub fn new(reaction: Reaction) -> Self {
+        Self { reaction }
+    }
+
+    #[instrument(name = "RemoveReactionRequest", skip_all, fields(user_id = %self.reaction.user_id, message_id = %self.reaction.message_id))]
+    pub async fn execute(self, session: &Session) -> Result<(), RepositoryError> {
+        let uuid: Uuid = self.reaction.user_id.into();
+
+        let message_id = self.reaction.message_id.as_tuple_i64();
+
+        let mut batch = Batch::new(BatchType::Logged);
+        batch.append_statement(
+            "DELETE FROM reactions WHERE message_id = ? AND emoji = ? AND user_id = ?",
+        );
+        batch.append_statement(
+            "DELETE FROM reactions_of_user WHERE user_id = ? AND message_id = ? AND emoji = ?",
+        );
+
+        let emoji = self.reaction.emoji.as_str();
+        session
+            .batch(
+                &batch,
+                ((message_id, emoji, uuid), (uuid, message_id, emoji)),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Number of reactions of a message, for each emoji.
+#[derive(Clone, Copy, Debug)]
+pub struct GetReactionSummaryRequest {
+    pub message_id: MessageId,
+}
+
+impl GetReactionSummaryRequest {
+    pub fn new(message: impl Messagelike) -> Self {
+        Self {
+            message_id: message.get_id(),
+        }
+    }
+
+    #[instrument(name = "GetReactionSummaryRequest", skip_all, fields(message_id = %self.message_id))]
+    pub async fn execute(self, session: &Session) -> Result<ReactionSummary, RepositoryError> {
+        let mut rows = session
+            .query_iter(
+                r#"SELECT emoji, COUNT(*) FROM reactions WHERE message_id = ? GROUP BY emoji"#,
+                (self.message_id.as_tuple_i64(),),
+            )
+            .await?
+            .into_typed::<(String, i64)>();
+
+        let mut counts = Vec::new();
+        while let Some(row) = rows.next().await {
+            let (emoji, count) = row?;
+            counts.push(ReactionCount {
+                emoji,
+                count: count as u64,
+            });
+        }
+
+        Ok(ReactionSummary::new(self.message_id).with_counts(counts))
+    }
+}
+
+/// Removes every reaction of a user, found with `reactions_of_user`.
+#[derive(Clone, Copy, Debug)]
+pub struct DeleteReactionsOfUserRequest {
+    pub user_id: UserId,
+}
+
+impl DeleteReactionsOfUserRequest {
+    pub fn new(user: impl Userlike) -> Self {
+        Self {
+            user_id: user.get_id(),
+        }
+    }
+
+    #[instrument(name = "DeleteReactionsOfUserRequest", skip_all, fields(user_id = %self.user_id))]
+    pub async fn execute(self, session: &Session) -> Result<(), RepositoryError> {
+        let uuid: Uuid = self.user_id.into();
+
+        let mut rows = session
+            .query_iter(
+                r#"SELECT message_id, emoji FROM reactions_of_user WHERE user_id = ?"#,
+                (uuid,),
+            )
+            .await?
+            .into_typed::<((Uuid, i64), String)>();
+
+        while let Some(row) = rows.next().await {
+            let (message_id, emoji) = row?;
+
+            session
+                .query(
+                    r#"DELETE FROM reactions WHERE message_id = ? AND emoji = ? AND user_id = ?"#,
+                    (message_id, emoji, uuid),
+                )
+                .await?;
+        }
+
+        session
+            .query(
+                r#"DELETE FROM reactions_of_user WHERE user_id = ?"#,
+                (uuid,),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
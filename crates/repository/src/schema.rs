@@ -0,0 +1,111 @@
+//! The keyspace and tables of ScyllaDB, created on the first run of new environments. The ones
+//! of `migration/init_dev` are the same, with sample rows.
+
+use scylla::transport::errors::QueryError;
+use scylla::Session;
+
+const TABLES: [&str; 8] = [
+    "CREATE TABLE IF NOT EXISTS messages (
+        message_id TUPLE<UUID, TIMESTAMP>,
+        user_id UUID,
+        date_bucket TIMESTAMP,
+        date TIMESTAMP,
+        content TEXT,
+        body BLOB,
+        reply_to TUPLE<UUID, TIMESTAMP>,
+        PRIMARY KEY ((user_id, date_bucket), date, message_id)
+    ) WITH CLUSTERING ORDER BY (date DESC)",
+    "CREATE TABLE IF NOT EXISTS read_tags (
+        user_id UUID,
+        message_id TUPLE<UUID, TIMESTAMP>,
+        PRIMARY KEY (user_id, message_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS mentions (
+        name TEXT,
+        date_bucket TIMESTAMP,
+        date TIMESTAMP,
+        message_id TUPLE<UUID, TIMESTAMP>,
+        user_id UUID,
+        PRIMARY KEY ((name, date_bucket), date, message_id)
+    ) WITH CLUSTERING ORDER BY (date DESC)",
+    "CREATE TABLE IF NOT EXISTS reactions (
+        message_id TUPLE<UUID, TIMESTAMP>,
+        emoji TEXT,
+        user_id UUID,
+        added_at TIMESTAMP,
+        PRIMARY KEY (message_id, emoji, user_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS reactions_of_user (
+        user_id UUID,
+        message_id TUPLE<UUID, TIMESTAMP>,
+        emoji TEXT,
+        PRIMARY KEY (user_id, message_id, emoji)
+    )",
+    "CREATE TABLE IF NOT EXISTS conversations (
+        conversation_id TUPLE<UUID, TIMESTAMP>,
+        user_id UUID,
+        PRIMARY KEY (conversation_id, user_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS conversations_of_user (
+        user_id UUID,
+        conversation_id TUPLE<UUID, TIMESTAMP>,
+        PRIMARY KEY (user_id, conversation_id)
+    )",
+    "CREATE TABLE IF NOT EXISTS direct_messages (
+        conversation_id TUPLE<UUID, TIMESTAMP>,
+        date_bucket TIMESTAMP,
+        date TIMESTAMP,
+        message_id TUPLE<UUID, TIMESTAMP>,
+        user_id UUID,
+        content TEXT,
+        body BLOB,
+        PRIMARY KEY ((conversation_id, date_bucket), date, message_id)
+    ) WITH CLUSTERING ORDER BY (date DESC)",
+];
+
+/// Columns added to the tables after they were first created, added to existing ones too.
+const ADDED_COLUMNS: [(&str, &str, &str); 4] = [
+    ("messages", "body", "BLOB"),
+    ("messages", "reply_to", "TUPLE<UUID, TIMESTAMP>"),
+    ("direct_messages", "body", "BLOB"),
+    ("reactions", "added_at", "TIMESTAMP"),
+];
+
+/// Creates `keyspace` with `replication`, a CQL map such as
+/// `{'class': 'SimpleStrategy', 'replication_factor': 1}`, and its tables, unless they exist:
+/// existing ones only get the columns they miss. `keyspace` is then the one of `session`.
+pub async fn create_scylla_schema(
+    session: &Session,
+    keyspace: &str,
+    replication: &str,
+) -> Result<(), QueryError> {
+    session
+        .query(
+            format!("CREATE KEYSPACE IF NOT EXISTS {keyspace} WITH REPLICATION = {replication}"),
+            &[],
+        )
+        .await?;
+    session.use_keyspace(keyspace, false).await?;
+
+    for table in TABLES {
+        session.query(table, &[]).await?;
+    }
+
+    for (table, column, kind) in ADDED_COLUMNS {
+        let existing = session
+            .query(
+                "SELECT column_name FROM system_schema.columns
+                    WHERE keyspace_name = ? AND table_name = ? AND column_name = ?",
+                (keyspace, table, column),
+            )
+            .await?;
+
+        if existing.rows_or_empty().is_empty() {
+            session
+                .query(format!("ALTER TABLE {table} ADD {column} {kind}"), &[])
+                .await?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,10 +1,21 @@
-use anyhow::Error;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
 use futures::{stream::StreamExt, Stream};
-use sqlx::PgPool;
+use sqlx::postgres::PgExecutor;
+use sqlx::{Acquire, PgPool};
 
-use models::users::{Userlike, UserId, User};
+use models::attachments::AttachmentId;
+use models::friendships::{FriendshipEvent, FriendshipState};
+use models::users::{User, UserId, Userlike};
+use tracing::instrument;
+use tracing_futures::Instrument;
 use uuid::Uuid;
 
+use super::blocks::IsBlockedRequest;
+use super::{PgTransaction, RepositoryError};
+
+#[derive(Copy, Clone)]
 pub struct GetUser {
     pub user_id: UserId,
 }
@@ -16,7 +27,19 @@ impl GetUser {
         }
     }
 
-    pub async fn execute(self, conn: &PgPool) -> Result<User, Error> {
+    pub async fn execute(self, conn: &PgPool) -> Result<User, RepositoryError> {
+        self.execute_with(conn).await
+    }
+
+    pub async fn execute_in(self, tx: &mut PgTransaction<'_>) -> Result<User, RepositoryError> {
+        self.execute_with(&mut **tx).await
+    }
+
+    #[instrument(name = "GetUser", skip_all, fields(user_id = %self.user_id))]
+    async fn execute_with<'e>(
+        self,
+        executor: impl PgExecutor<'e>,
+    ) -> Result<User, RepositoryError> {
         let uuid: Uuid = self.user_id.into();
 
         let res = sqlx::query!(
@@ -26,7 +49,7 @@ impl GetUser {
             "#,
             uuid,
         )
-        .fetch_one(conn)
+        .fetch_one(executor)
         .await?;
 
         Ok(User {
@@ -36,28 +59,45 @@ impl GetUser {
     }
 }
 
-/// Insert a user in database
+/// Insert a user in database, along with the hash of its password
 #[derive(Clone)]
 pub struct InsertUserRequest {
     pub name: String,
+    pub password_hash: String,
 }
 
 impl InsertUserRequest {
-    pub fn new(name: String) -> Self {
-        Self { name }
+    pub fn new(name: String, password_hash: String) -> Self {
+        Self {
+            name,
+            password_hash,
+        }
     }
 
-    pub async fn execute(self, conn: &PgPool) -> Result<User, Error> {
+    pub async fn execute(self, conn: &PgPool) -> Result<User, RepositoryError> {
+        self.execute_with(conn).await
+    }
+
+    pub async fn execute_in(self, tx: &mut PgTransaction<'_>) -> Result<User, RepositoryError> {
+        self.execute_with(&mut **tx).await
+    }
+
+    #[instrument(name = "InsertUserRequest", skip_all, fields(name = %self.name))]
+    async fn execute_with<'e>(
+        self,
+        executor: impl PgExecutor<'e>,
+    ) -> Result<User, RepositoryError> {
         let res = sqlx::query!(
             // language=PostgreSQL
             r#"
-                INSERT INTO users (name)
-                    values ($1)
+                INSERT INTO users (name, password_hash)
+                    values ($1, $2)
                 RETURNING user_id, name
             "#,
             self.name,
+            self.password_hash,
         )
-        .fetch_one(conn)
+        .fetch_one(executor)
         .await?;
 
         Ok(User {
@@ -67,7 +107,17 @@ impl InsertUserRequest {
     }
 }
 
-/// Delete a user in database
+/// What `DeleteUserRequest` removed along with the user.
+#[derive(Clone, Debug, Default)]
+pub struct DeletedUser {
+    /// Each with a `removed` audit row.
+    pub friends: Vec<UserId>,
+    /// Their data is still in the `AttachmentStorage`, to be removed once committed.
+    pub attachments: Vec<AttachmentId>,
+}
+
+/// Delete a user, its friendships, its blocks and its attachments in database as a transaction.
+/// Its friends get a `removed` audit row each, like with `RemoveFriendshipRequest`.
 #[derive(Copy, Clone)]
 pub struct DeleteUserRequest {
     pub user_id: UserId,
@@ -80,26 +130,98 @@ impl DeleteUserRequest {
         }
     }
 
-    pub async fn execute(self, conn: &PgPool) -> Result<(), Error> {
+    pub async fn execute(self, conn: &PgPool) -> Result<DeletedUser, RepositoryError> {
+        let mut tx = conn.begin().await?;
+        let deleted = self.execute_in(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(deleted)
+    }
+
+    #[instrument(name = "DeleteUserRequest", skip_all, fields(user_id = %self.user_id))]
+    pub async fn execute_in(
+        self,
+        tx: &mut PgTransaction<'_>,
+    ) -> Result<DeletedUser, RepositoryError> {
         let uuid: Uuid = self.user_id.into();
 
-        let _res = sqlx::query!(
+        let friends = sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                INSERT INTO friendship_events (user_id, friend_id, kind)
+                    SELECT $1, CASE WHEN user_id = $1 THEN friend_id ELSE user_id END, 'removed'
+                        FROM friendships
+                        WHERE (user_id = $1 OR friend_id = $1)
+                            AND state = 'accepted'
+                RETURNING friend_id
+            "#,
+            uuid,
+        )
+        .fetch_all(&mut **tx)
+        .await?;
+
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                DELETE FROM friendships WHERE user_id = $1 OR friend_id = $1
+            "#,
+            uuid,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                DELETE FROM blocks WHERE user_id = $1 OR blocked_id = $1
+            "#,
+            uuid,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        let attachments = sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                DELETE FROM attachments WHERE user_id = $1
+                RETURNING attachment_id
+            "#,
+            uuid,
+        )
+        .fetch_all(&mut **tx)
+        .await?;
+
+        let res = sqlx::query!(
             // language=PostgreSQL
             r#"
                 DELETE FROM users WHERE user_id = $1
             "#,
             uuid,
         )
-        .fetch_one(conn)
+        .execute(&mut **tx)
         .await?;
 
-        Ok(())
+        match res.rows_affected() {
+            0 => Err(RepositoryError::NotFound),
+            _ => Ok(DeletedUser {
+                friends: friends.into_iter().map(|r| r.friend_id.into()).collect(),
+                attachments: attachments
+                    .into_iter()
+                    .map(|r| r.attachment_id.into())
+                    .collect(),
+            }),
+        }
     }
 }
 
-/// Insert two frienship rows as a transaction :
-/// * A -> B
-/// * B -> A
+/// Asks `user_b` to be friends with `user_a`, with its audit row, as a transaction:
+/// * a `pending` A -> B row and a `requested` event for A -> B
+/// * if B already asked A, its request is accepted instead, see `AnswerFriendshipRequest`
+///
+/// Fails with `RepositoryError::Blocked` if one of them blocked the other, with
+/// `RepositoryError::Conflict` if they are friends or A already asked. Two requests of each other
+/// at once can't both be inserted, by the unique index of the pair: the second one accepts the
+/// first instead.
 #[derive(Copy, Clone)]
 pub struct InsertFriendshipRequest {
     pub user_a: UserId,
@@ -114,27 +236,227 @@ impl InsertFriendshipRequest {
         }
     }
 
-    pub async fn execute(self, conn: &PgPool) -> Result<(), Error> {
+    pub async fn execute(self, conn: &PgPool) -> Result<FriendshipEvent, RepositoryError> {
+        let mut tx = conn.begin().await?;
+        let event = self.execute_in(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(event)
+    }
+
+    #[instrument(name = "InsertFriendshipRequest", skip_all, fields(user_id = %self.user_a, friend_id = %self.user_b))]
+    pub async fn execute_in(
+        self,
+        tx: &mut PgTransaction<'_>,
+    ) -> Result<FriendshipEvent, RepositoryError> {
         let uuid_a: Uuid = self.user_a.into();
         let uuid_b: Uuid = self.user_b.into();
 
+        if IsBlockedRequest::new(self.user_a, self.user_b)
+            .execute_in(tx)
+            .await?
+        {
+            return Err(RepositoryError::Blocked);
+        }
+
+        if let Some(event) = self.answer_existing(tx).await? {
+            return Ok(event);
+        }
+
+        // Within a savepoint, the transaction can still be used once it fails.
+        let mut savepoint = tx.begin().await?;
+        let inserted = sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                INSERT INTO friendships (user_id, friend_id, state)
+                    VALUES ($1, $2, 'pending');
+            "#,
+            uuid_a,
+            uuid_b,
+        )
+        .execute(&mut *savepoint)
+        .await
+        .map_err(RepositoryError::from);
+
+        match inserted {
+            Ok(_) => savepoint.commit().await?,
+            // The row of the pair was inserted since it was looked up, now it can be seen.
+            Err(RepositoryError::Conflict) => {
+                savepoint.rollback().await?;
+
+                return self
+                    .answer_existing(tx)
+                    .await?
+                    .ok_or(RepositoryError::Conflict);
+            }
+            Err(e) => return Err(e),
+        }
+
         sqlx::query!(
             // language=PostgreSQL
             r#"
-                INSERT INTO friendships (user_id, friend_id)
-                    VALUES ($1, $2);
+                INSERT INTO friendship_events (user_id, friend_id, kind)
+                    VALUES ($1, $2, 'requested');
             "#,
             uuid_a,
             uuid_b,
         )
-        .fetch_one(conn)
+        .execute(&mut **tx)
         .await?;
 
-        Ok(())
+        Ok(FriendshipEvent::new(
+            self.user_a,
+            self.user_b,
+            FriendshipState::Pending,
+        ))
+    }
+
+    /// Of the row of the pair, locked: accepts the request of B to A, otherwise fails with
+    /// `RepositoryError::Conflict`. `None` without one.
+    async fn answer_existing(
+        self,
+        tx: &mut PgTransaction<'_>,
+    ) -> Result<Option<FriendshipEvent>, RepositoryError> {
+        let uuid_a: Uuid = self.user_a.into();
+        let uuid_b: Uuid = self.user_b.into();
+
+        let existing = sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                SELECT user_id, state FROM friendships
+                    WHERE (user_id = $1
+                        AND friend_id = $2)
+                    OR (user_id = $2
+                        AND friend_id = $1)
+                    FOR UPDATE
+            "#,
+            uuid_a,
+            uuid_b,
+        )
+        .fetch_optional(&mut **tx)
+        .await?;
+
+        let Some(existing) = existing else {
+            return Ok(None);
+        };
+        let state = FriendshipState::from_str(existing.state.as_str())?;
+
+        match (state, existing.user_id == uuid_b) {
+            (FriendshipState::Pending, true) => {
+                AnswerFriendshipRequest::accept(self.user_a, self.user_b)
+                    .execute_in(tx)
+                    .await
+                    .map(Some)
+            }
+            _ => Err(RepositoryError::Conflict),
+        }
     }
 }
 
-/// Removes frienship in both ways in a transaction
+/// Accepts or declines the request `requester` made to `user`, with its audit row, as a
+/// transaction. Accepted, the row becomes `accepted` with an `added` event for user -> requester.
+/// Declined, it is removed with a `declined` event.
+///
+/// Fails with `RepositoryError::NotFound` if there is no such pending request.
+#[derive(Copy, Clone)]
+pub struct AnswerFriendshipRequest {
+    pub user_id: UserId,
+    pub requester_id: UserId,
+    pub accept: bool,
+}
+
+impl AnswerFriendshipRequest {
+    pub fn accept(user: impl Userlike, requester: impl Userlike) -> Self {
+        Self {
+            user_id: user.get_id(),
+            requester_id: requester.get_id(),
+            accept: true,
+        }
+    }
+
+    pub fn decline(user: impl Userlike, requester: impl Userlike) -> Self {
+        Self {
+            accept: false,
+            ..Self::accept(user, requester)
+        }
+    }
+
+    pub async fn execute(self, conn: &PgPool) -> Result<FriendshipEvent, RepositoryError> {
+        let mut tx = conn.begin().await?;
+        let event = self.execute_in(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(event)
+    }
+
+    #[instrument(name = "AnswerFriendshipRequest", skip_all, fields(user_id = %self.user_id, requester_id = %self.requester_id, accept = self.accept))]
+    pub async fn execute_in(
+        self,
+        tx: &mut PgTransaction<'_>,
+    ) -> Result<FriendshipEvent, RepositoryError> {
+        let uuid: Uuid = self.user_id.into();
+        let requester: Uuid = self.requester_id.into();
+
+        let res = match self.accept {
+            true => {
+                sqlx::query!(
+                    // language=PostgreSQL
+                    r#"
+                        UPDATE friendships SET state = 'accepted', date = NOW()
+                            WHERE user_id = $1
+                                AND friend_id = $2
+                                AND state = 'pending'
+                    "#,
+                    requester,
+                    uuid,
+                )
+                .execute(&mut **tx)
+                .await?
+            }
+            false => {
+                sqlx::query!(
+                    // language=PostgreSQL
+                    r#"
+                        DELETE FROM friendships
+                            WHERE user_id = $1
+                                AND friend_id = $2
+                                AND state = 'pending'
+                    "#,
+                    requester,
+                    uuid,
+                )
+                .execute(&mut **tx)
+                .await?
+            }
+        };
+
+        if res.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound);
+        }
+
+        let (state, kind) = match self.accept {
+            true => (FriendshipState::Accepted, "added"),
+            false => (FriendshipState::Declined, "declined"),
+        };
+
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                INSERT INTO friendship_events (user_id, friend_id, kind)
+                    VALUES ($1, $2, $3);
+            "#,
+            uuid,
+            requester,
+            kind,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(FriendshipEvent::new(self.user_id, self.requester_id, state))
+    }
+}
+
+/// Removes an accepted frienship in both ways and inserts its audit row in a transaction
 #[derive(Copy, Clone)]
 pub struct RemoveFriendshipRequest {
     pub user_a: UserId,
@@ -149,23 +471,49 @@ impl RemoveFriendshipRequest {
         }
     }
 
-    pub async fn execute(self, conn: &PgPool) -> Result<(), Error> {
+    pub async fn execute(self, conn: &PgPool) -> Result<(), RepositoryError> {
+        let mut tx = conn.begin().await?;
+        self.execute_in(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "RemoveFriendshipRequest", skip_all, fields(user_id = %self.user_a, friend_id = %self.user_b))]
+    pub async fn execute_in(self, tx: &mut PgTransaction<'_>) -> Result<(), RepositoryError> {
         let uuid_a: Uuid = self.user_a.into();
         let uuid_b: Uuid = self.user_b.into();
 
-        sqlx::query!(
+        let res = sqlx::query!(
             // language=PostgreSQL
             r#"
                 DELETE FROM friendships
-                    WHERE (user_id = $1
+                    WHERE ((user_id = $1
                         AND friend_id = $2)
                     OR (user_id = $2
-                        AND friend_id = $1)
+                        AND friend_id = $1))
+                    AND state = 'accepted'
+            "#,
+            uuid_a,
+            uuid_b,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound);
+        }
+
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                INSERT INTO friendship_events (user_id, friend_id, kind)
+                    VALUES ($1, $2, 'removed');
             "#,
             uuid_a,
             uuid_b,
         )
-        .execute(conn)
+        .execute(&mut **tx)
         .await?;
 
         Ok(())
@@ -184,21 +532,241 @@ impl GetFriendsOfUserRequest {
         }
     }
 
-    pub fn stream<'a>(self, conn: &'a PgPool) -> impl Stream<Item = Result<UserId, Error>> + 'a {
+    pub fn stream<'a>(
+        self,
+        conn: &'a PgPool,
+    ) -> impl Stream<Item = Result<UserId, RepositoryError>> + 'a {
+        self.stream_with(conn)
+    }
+
+    pub fn stream_in<'a>(
+        self,
+        tx: &'a mut PgTransaction<'_>,
+    ) -> impl Stream<Item = Result<UserId, RepositoryError>> + 'a {
+        self.stream_with(&mut **tx)
+    }
+
+    fn stream_with<'a>(
+        self,
+        executor: impl PgExecutor<'a> + 'a,
+    ) -> impl Stream<Item = Result<UserId, RepositoryError>> + 'a {
         let uuid: Uuid = self.user_id.into();
 
         sqlx::query!(
             // language=PostgreSQL
             r#"
-                SELECT friend_id FROM friendships WHERE user_id = $1 OR friend_id = $1
+                SELECT CASE WHEN user_id = $1 THEN friend_id ELSE user_id END AS "friend_id!"
+                    FROM friendships
+                    WHERE (user_id = $1 OR friend_id = $1)
+                        AND state = 'accepted'
             "#,
             uuid,
         )
-        .fetch(conn)
+        .fetch(executor)
         .map(|record| Ok(record.map(|record| UserId::from(record.friend_id))?))
+        .instrument(tracing::info_span!("GetFriendsOfUserRequest", user_id = %uuid))
     }
 }
 
+/// Friends of the user with their names, by name.
+#[derive(Copy, Clone)]
+pub struct GetFriendUsersRequest {
+    pub user_id: UserId,
+}
+
+impl GetFriendUsersRequest {
+    pub fn new(user: impl Userlike) -> Self {
+        Self {
+            user_id: user.get_id(),
+        }
+    }
+
+    pub fn stream<'a>(
+        self,
+        conn: &'a PgPool,
+    ) -> impl Stream<Item = Result<User, RepositoryError>> + 'a {
+        self.stream_with(conn)
+    }
+
+    pub fn stream_in<'a>(
+        self,
+        tx: &'a mut PgTransaction<'_>,
+    ) -> impl Stream<Item = Result<User, RepositoryError>> + 'a {
+        self.stream_with(&mut **tx)
+    }
+
+    fn stream_with<'a>(
+        self,
+        executor: impl PgExecutor<'a> + 'a,
+    ) -> impl Stream<Item = Result<User, RepositoryError>> + 'a {
+        let uuid: Uuid = self.user_id.into();
+
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                SELECT users.user_id, users.name FROM friendships
+                    JOIN users ON users.user_id = CASE
+                        WHEN friendships.user_id = $1 THEN friendships.friend_id
+                        ELSE friendships.user_id
+                    END
+                    WHERE (friendships.user_id = $1 OR friendships.friend_id = $1)
+                        AND friendships.state = 'accepted'
+                    ORDER BY users.name
+            "#,
+            uuid,
+        )
+        .fetch(executor)
+        .map(|record| {
+            let record = record?;
+
+            Ok(User {
+                id: record.user_id.into(),
+                name: record.name,
+            })
+        })
+        .instrument(tracing::info_span!("GetFriendUsersRequest", user_id = %uuid))
+    }
+}
+
+/// Pending requests made by the user and to it, oldest first.
+#[derive(Copy, Clone)]
+pub struct GetFriendRequestsRequest {
+    pub user_id: UserId,
+}
+
+impl GetFriendRequestsRequest {
+    pub fn new(user: impl Userlike) -> Self {
+        Self {
+            user_id: user.get_id(),
+        }
+    }
+
+    pub fn stream<'a>(
+        self,
+        conn: &'a PgPool,
+    ) -> impl Stream<Item = Result<FriendshipEvent, RepositoryError>> + 'a {
+        self.stream_with(conn)
+    }
+
+    pub fn stream_in<'a>(
+        self,
+        tx: &'a mut PgTransaction<'_>,
+    ) -> impl Stream<Item = Result<FriendshipEvent, RepositoryError>> + 'a {
+        self.stream_with(&mut **tx)
+    }
+
+    fn stream_with<'a>(
+        self,
+        executor: impl PgExecutor<'a> + 'a,
+    ) -> impl Stream<Item = Result<FriendshipEvent, RepositoryError>> + 'a {
+        let uuid: Uuid = self.user_id.into();
+
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                SELECT user_id, friend_id FROM friendships
+                    WHERE (user_id = $1 OR friend_id = $1)
+                        AND state = 'pending'
+                    ORDER BY date
+            "#,
+            uuid,
+        )
+        .fetch(executor)
+        .map(|record| {
+            let record = record?;
+
+            Ok(FriendshipEvent::new(
+                UserId::from(record.user_id),
+                UserId::from(record.friend_id),
+                FriendshipState::Pending,
+            ))
+        })
+        .instrument(tracing::info_span!("GetFriendRequestsRequest", user_id = %uuid))
+    }
+}
+
+/// An audit row of `GetFriendshipEventsRequest`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FriendshipAudit {
+    /// A request, `Pending`, or its acceptance, `Accepted` by the initiator.
+    Event(FriendshipEvent),
+    /// By the first user, or by a block of either one.
+    Removed(UserId, UserId),
+}
+
+/// Friendships requested, added and removed with the user after `since`, oldest first, from the
+/// audit rows. Declined requests are not part of them.
+#[derive(Copy, Clone)]
+pub struct GetFriendshipEventsRequest {
+    pub user_id: UserId,
+    pub since: DateTime<Utc>,
+}
+
+impl GetFriendshipEventsRequest {
+    pub fn new(user: impl Userlike, since: DateTime<Utc>) -> Self {
+        Self {
+            user_id: user.get_id(),
+            since,
+        }
+    }
+
+    pub fn stream<'a>(
+        self,
+        conn: &'a PgPool,
+    ) -> impl Stream<Item = Result<(FriendshipAudit, DateTime<Utc>), RepositoryError>> + 'a {
+        self.stream_with(conn)
+    }
+
+    pub fn stream_in<'a>(
+        self,
+        tx: &'a mut PgTransaction<'_>,
+    ) -> impl Stream<Item = Result<(FriendshipAudit, DateTime<Utc>), RepositoryError>> + 'a {
+        self.stream_with(&mut **tx)
+    }
+
+    fn stream_with<'a>(
+        self,
+        executor: impl PgExecutor<'a> + 'a,
+    ) -> impl Stream<Item = Result<(FriendshipAudit, DateTime<Utc>), RepositoryError>> + 'a {
+        let uuid: Uuid = self.user_id.into();
+
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                SELECT user_id, friend_id, kind, date FROM friendship_events
+                    WHERE (user_id = $1 OR friend_id = $1)
+                        AND kind IN ('requested', 'added', 'removed')
+                        AND date > $2
+                    ORDER BY date
+            "#,
+            uuid,
+            self.since.naive_utc(),
+        )
+        .fetch(executor)
+        .map(|record| {
+            let record = record?;
+            let (user, friend) = (record.user_id.into(), record.friend_id.into());
+            let audit = match record.kind.as_str() {
+                "requested" => FriendshipAudit::Event(FriendshipEvent::new(
+                    user,
+                    friend,
+                    FriendshipState::Pending,
+                )),
+                "added" => FriendshipAudit::Event(FriendshipEvent::new(
+                    user,
+                    friend,
+                    FriendshipState::Accepted,
+                )),
+                _ => FriendshipAudit::Removed(user, friend),
+            };
+
+            Ok((audit, record.date.and_utc()))
+        })
+        .instrument(tracing::info_span!("GetFriendshipEventsRequest", user_id = %uuid))
+    }
+}
+
+#[derive(Clone)]
 pub struct GetUserByNameRequest {
     pub name: String,
 }
@@ -208,7 +776,19 @@ impl GetUserByNameRequest {
         Self { name }
     }
 
-    pub async fn execute(self, conn: &PgPool) -> Result<User, Error> {
+    pub async fn execute(self, conn: &PgPool) -> Result<User, RepositoryError> {
+        self.execute_with(conn).await
+    }
+
+    pub async fn execute_in(self, tx: &mut PgTransaction<'_>) -> Result<User, RepositoryError> {
+        self.execute_with(&mut **tx).await
+    }
+
+    #[instrument(name = "GetUserByNameRequest", skip_all, fields(name = %self.name))]
+    async fn execute_with<'e>(
+        self,
+        executor: impl PgExecutor<'e>,
+    ) -> Result<User, RepositoryError> {
         let res = sqlx::query!(
             // language=PostgreSQL
             r#"
@@ -216,7 +796,7 @@ impl GetUserByNameRequest {
             "#,
             self.name,
         )
-        .fetch_one(conn)
+        .fetch_one(executor)
         .await?;
 
         Ok(User {
@@ -225,3 +805,407 @@ impl GetUserByNameRequest {
         })
     }
 }
+
+/// The user named `name`, along with the hash of its password. `None` of the users created
+/// without one, they can't log in.
+#[derive(Clone)]
+pub struct GetUserCredentialsRequest {
+    pub name: String,
+}
+
+impl GetUserCredentialsRequest {
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+
+    pub async fn execute(self, conn: &PgPool) -> Result<(User, Option<String>), RepositoryError> {
+        self.execute_with(conn).await
+    }
+
+    pub async fn execute_in(
+        self,
+        tx: &mut PgTransaction<'_>,
+    ) -> Result<(User, Option<String>), RepositoryError> {
+        self.execute_with(&mut **tx).await
+    }
+
+    #[instrument(name = "GetUserCredentialsRequest", skip_all, fields(name = %self.name))]
+    async fn execute_with<'e>(
+        self,
+        executor: impl PgExecutor<'e>,
+    ) -> Result<(User, Option<String>), RepositoryError> {
+        let res = sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                SELECT user_id, password_hash FROM users WHERE name = $1
+            "#,
+            self.name,
+        )
+        .fetch_one(executor)
+        .await?;
+
+        let user = User {
+            id: res.user_id.into(),
+            name: self.name,
+        };
+
+        Ok((user, res.password_hash))
+    }
+}
+
+/// Users whose name contains `query`, case insensitive, by name. A page of at most `limit` users
+/// named after `after`, the last name of the previous page.
+#[derive(Clone)]
+pub struct SearchUsersRequest {
+    pub query: String,
+    pub after: Option<String>,
+    pub limit: u32,
+}
+
+impl SearchUsersRequest {
+    pub fn new(query: String, after: Option<String>, limit: u32) -> Self {
+        Self {
+            query,
+            after,
+            limit,
+        }
+    }
+
+    pub async fn execute(self, conn: &PgPool) -> Result<Vec<User>, RepositoryError> {
+        self.execute_with(conn).await
+    }
+
+    pub async fn execute_in(
+        self,
+        tx: &mut PgTransaction<'_>,
+    ) -> Result<Vec<User>, RepositoryError> {
+        self.execute_with(&mut **tx).await
+    }
+
+    #[instrument(name = "SearchUsersRequest", skip_all, fields(query = %self.query))]
+    async fn execute_with<'e>(
+        self,
+        executor: impl PgExecutor<'e>,
+    ) -> Result<Vec<User>, RepositoryError> {
+        let pattern = format!("%{}%", escape_like(&self.query));
+
+        let users = sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                SELECT user_id, name FROM users
+                    WHERE name ILIKE $1 AND ($2::VARCHAR IS NULL OR name > $2)
+                    ORDER BY name
+                    LIMIT $3
+            "#,
+            pattern,
+            self.after,
+            self.limit as i64,
+        )
+        .fetch_all(executor)
+        .await?
+        .into_iter()
+        .map(|record| User {
+            id: record.user_id.into(),
+            name: record.name,
+        })
+        .collect();
+
+        Ok(users)
+    }
+}
+
+/// So that `%` and `_` in a query match themselves.
+fn escape_like(query: &str) -> String {
+    let mut escaped = String::with_capacity(query.len());
+
+    for c in query.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Moves the `last_seen_at` of a user forward, never backward. Stored in UTC, like the other
+/// dates of PostgreSQL.
+#[derive(Copy, Clone)]
+pub struct UpdateLastSeenRequest {
+    pub user_id: UserId,
+    pub seen_at: DateTime<Utc>,
+}
+
+impl UpdateLastSeenRequest {
+    pub fn new(user: impl Userlike, seen_at: DateTime<Utc>) -> Self {
+        Self {
+            user_id: user.get_id(),
+            seen_at,
+        }
+    }
+
+    pub async fn execute(self, conn: &PgPool) -> Result<(), RepositoryError> {
+        self.execute_with(conn).await
+    }
+
+    pub async fn execute_in(self, tx: &mut PgTransaction<'_>) -> Result<(), RepositoryError> {
+        self.execute_with(&mut **tx).await
+    }
+
+    #[instrument(name = "UpdateLastSeenRequest", skip_all, fields(user_id = %self.user_id))]
+    async fn execute_with<'e>(self, executor: impl PgExecutor<'e>) -> Result<(), RepositoryError> {
+        let uuid: Uuid = self.user_id.into();
+
+        let res = sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                UPDATE users SET last_seen_at = GREATEST(last_seen_at, $2) WHERE user_id = $1
+            "#,
+            uuid,
+            self.seen_at.naive_utc(),
+        )
+        .execute(executor)
+        .await?;
+
+        match res.rows_affected() {
+            0 => Err(RepositoryError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// `last_seen_at` of many users at once. Unknown users are left out.
+#[derive(Clone)]
+pub struct GetLastSeenRequest {
+    pub users: Vec<UserId>,
+}
+
+impl GetLastSeenRequest {
+    pub fn new(users: impl IntoIterator<Item = impl Userlike>) -> Self {
+        Self {
+            users: users.into_iter().map(|user| user.get_id()).collect(),
+        }
+    }
+
+    pub fn stream<'a>(
+        self,
+        conn: &'a PgPool,
+    ) -> impl Stream<Item = Result<(UserId, Option<DateTime<Utc>>), RepositoryError>> + 'a {
+        self.stream_with(conn)
+    }
+
+    pub fn stream_in<'a>(
+        self,
+        tx: &'a mut PgTransaction<'_>,
+    ) -> impl Stream<Item = Result<(UserId, Option<DateTime<Utc>>), RepositoryError>> + 'a {
+        self.stream_with(&mut **tx)
+    }
+
+    fn stream_with<'a>(
+        self,
+        executor: impl PgExecutor<'a> + 'a,
+    ) -> impl Stream<Item = Result<(UserId, Option<DateTime<Utc>>), RepositoryError>> + 'a {
+        let uuids: Vec<Uuid> = self.users.into_iter().map(Into::into).collect();
+        let span = tracing::info_span!("GetLastSeenRequest", users = uuids.len());
+
+        futures::stream::once(async move {
+            sqlx::query!(
+                // language=PostgreSQL
+                r#"
+                    SELECT user_id, last_seen_at FROM users WHERE user_id = ANY($1)
+                "#,
+                &uuids[..],
+            )
+            .fetch_all(executor)
+            .await
+        })
+        .map(|res| match res {
+            Ok(records) => futures::stream::iter(
+                records
+                    .into_iter()
+                    .map(|record| {
+                        let last_seen_at = record.last_seen_at.map(|at| at.and_utc());
+
+                        Ok((UserId::from(record.user_id), last_seen_at))
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            Err(e) => futures::stream::iter(vec![Err(RepositoryError::from(e))]),
+        })
+        .flatten()
+        .instrument(span)
+    }
+}
+
+/// Every user, eg. to read their partitions in ScyllaDB.
+#[derive(Copy, Clone, Default)]
+pub struct GetUserIdsRequest;
+
+impl GetUserIdsRequest {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn stream(self, conn: &PgPool) -> impl Stream<Item = Result<UserId, RepositoryError>> + '_ {
+        self.stream_with(conn)
+    }
+
+    pub fn stream_in<'a>(
+        self,
+        tx: &'a mut PgTransaction<'_>,
+    ) -> impl Stream<Item = Result<UserId, RepositoryError>> + 'a {
+        self.stream_with(&mut **tx)
+    }
+
+    fn stream_with<'a>(
+        self,
+        executor: impl PgExecutor<'a> + 'a,
+    ) -> impl Stream<Item = Result<UserId, RepositoryError>> + 'a {
+        sqlx::query!(
+            // language=PostgreSQL
+            r#"
+                SELECT user_id FROM users
+            "#,
+        )
+        .fetch(executor)
+        .map(|record| Ok(record.map(|record| UserId::from(record.user_id))?))
+        .instrument(tracing::info_span!("GetUserIdsRequest"))
+    }
+}
+
+/// Against the PostgreSQL of `DATABASE_URL`, with the tables of `migration/init_dev`. Nothing is
+/// committed.
+#[cfg(test)]
+#[tokio::test]
+#[ignore = "needs the PostgreSQL of DATABASE_URL"]
+async fn friends_of_user_test() {
+    use futures::TryStreamExt;
+
+    let pg = PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+        .await
+        .unwrap();
+    let mut tx = pg.begin().await.unwrap();
+    let name = |prefix: &str| format!("{prefix}{}", &Uuid::new_v4().simple().to_string()[..12]);
+
+    let requester = InsertUserRequest::new(name("r"), String::new())
+        .execute_in(&mut tx)
+        .await
+        .unwrap();
+    let answerer = InsertUserRequest::new(name("a"), String::new())
+        .execute_in(&mut tx)
+        .await
+        .unwrap();
+    InsertFriendshipRequest::new(requester.id, answerer.id)
+        .execute_in(&mut tx)
+        .await
+        .unwrap();
+    assert_eq!(
+        GetFriendsOfUserRequest::new(answerer.id)
+            .stream_in(&mut tx)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap(),
+        vec![]
+    );
+
+    // The row stays requester -> answerer once accepted, both find the other one.
+    AnswerFriendshipRequest::accept(answerer.id, requester.id)
+        .execute_in(&mut tx)
+        .await
+        .unwrap();
+    for (user, friend) in [(&answerer, &requester), (&requester, &answerer)] {
+        let friends: Vec<UserId> = GetFriendsOfUserRequest::new(user.id)
+            .stream_in(&mut tx)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(friends, vec![friend.id]);
+    }
+}
+
+/// Against the PostgreSQL of `DATABASE_URL`, with the tables of `migration/init_dev`. Nothing is
+/// committed.
+#[cfg(test)]
+#[tokio::test]
+#[ignore = "needs the PostgreSQL of DATABASE_URL"]
+async fn delete_user_test() {
+    use crate::attachments::InsertAttachmentRequest;
+    use models::attachments::Attachment;
+
+    let pg = PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+        .await
+        .unwrap();
+    let mut tx = pg.begin().await.unwrap();
+    let name = |prefix: &str| format!("{prefix}{}", &Uuid::new_v4().simple().to_string()[..12]);
+
+    let deleted = InsertUserRequest::new(name("d"), String::new())
+        .execute_in(&mut tx)
+        .await
+        .unwrap();
+    let friend = InsertUserRequest::new(name("f"), String::new())
+        .execute_in(&mut tx)
+        .await
+        .unwrap();
+    let requester = InsertUserRequest::new(name("r"), String::new())
+        .execute_in(&mut tx)
+        .await
+        .unwrap();
+    InsertFriendshipRequest::new(friend.id, deleted.id)
+        .execute_in(&mut tx)
+        .await
+        .unwrap();
+    AnswerFriendshipRequest::accept(deleted.id, friend.id)
+        .execute_in(&mut tx)
+        .await
+        .unwrap();
+    // Still pending, no removal to tell.
+    InsertFriendshipRequest::new(requester.id, deleted.id)
+        .execute_in(&mut tx)
+        .await
+        .unwrap();
+
+    let attachment = AttachmentId::new();
+    InsertAttachmentRequest::new(Attachment {
+        id: attachment,
+        user_id: deleted.id,
+        message_id: None,
+        filename: "a.png".to_string(),
+        content_type: "image/png".to_string(),
+        size: 1,
+        checksum: String::new(),
+    })
+    .execute_in(&mut tx)
+    .await
+    .unwrap();
+
+    let result = DeleteUserRequest::new(deleted.id)
+        .execute_in(&mut tx)
+        .await
+        .unwrap();
+    assert_eq!(result.friends, vec![friend.id]);
+    assert_eq!(result.attachments, vec![attachment]);
+
+    let deleted_id: Uuid = deleted.id.into();
+    let friend_id: Uuid = friend.id.into();
+    let removed: Vec<(Uuid, Uuid)> = sqlx::query_as(
+        "SELECT user_id, friend_id FROM friendship_events WHERE user_id = $1 AND kind = 'removed'",
+    )
+    .bind(deleted_id)
+    .fetch_all(&mut *tx)
+    .await
+    .unwrap();
+    assert_eq!(removed, vec![(deleted_id, friend_id)]);
+
+    for query in [
+        "SELECT COUNT(*) FROM users WHERE user_id = $1",
+        "SELECT COUNT(*) FROM friendships WHERE user_id = $1 OR friend_id = $1",
+        "SELECT COUNT(*) FROM attachments WHERE user_id = $1",
+    ] {
+        let count: i64 = sqlx::query_scalar(query)
+            .bind(deleted_id)
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(count, 0, "{query}");
+    }
+}
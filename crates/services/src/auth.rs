@@ -0,0 +1,263 @@
+//! Stateless authentication with JSON Web Tokens signed with HS256, issued to the users that
+//! give their password: only its PBKDF2-HMAC-SHA256 hash is stored.
+
+use base64::{
+    engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+
+use models::users::{UserId, Userlike};
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("malformed token")]
+    Malformed,
+    #[error("unsupported token algorithm `{0}`")]
+    Algorithm(String),
+    #[error("invalid token signature")]
+    Signature,
+    #[error("expired token")]
+    Expired,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    typ: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    /// The `UserId`.
+    sub: String,
+    /// Seconds since epoch.
+    iat: i64,
+    exp: i64,
+}
+
+/// Issues and verifies tokens of users. Can be shared between threads by using `Clone`.
+#[derive(Clone)]
+pub struct TokenAuthority {
+    key: Hmac<Sha256>,
+    ttl: chrono::Duration,
+}
+
+impl TokenAuthority {
+    const ALGORITHM: &'static str = "HS256";
+
+    pub fn new(secret: impl AsRef<[u8]>, ttl: chrono::Duration) -> Self {
+        Self {
+            key: Hmac::new_from_slice(secret.as_ref()).expect("HMAC accepts keys of any size"),
+            ttl,
+        }
+    }
+
+    pub fn issue(&self, user: impl Userlike) -> String {
+        self.issue_at(user, chrono::Utc::now().timestamp())
+    }
+
+    /// The user the token was issued to.
+    pub fn verify(&self, token: &str) -> Result<UserId, AuthError> {
+        self.verify_at(token, chrono::Utc::now().timestamp())
+    }
+
+    fn issue_at(&self, user: impl Userlike, now: i64) -> String {
+        let header = Header {
+            alg: Self::ALGORITHM.to_string(),
+            typ: "JWT".to_string(),
+        };
+        let claims = Claims {
+            sub: user.get_id().to_string(),
+            iat: now,
+            exp: now + self.ttl.num_seconds(),
+        };
+
+        let signed = format!("{}.{}", encode_part(&header), encode_part(&claims));
+        let signature = self.sign(&signed).finalize().into_bytes();
+
+        format!("{signed}.{}", URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn verify_at(&self, token: &str, now: i64) -> Result<UserId, AuthError> {
+        let (signed, signature) = token.rsplit_once('.').ok_or(AuthError::Malformed)?;
+        let (header, claims) = signed.split_once('.').ok_or(AuthError::Malformed)?;
+
+        let header: Header = decode_part(header)?;
+        if header.alg != Self::ALGORITHM {
+            return Err(AuthError::Algorithm(header.alg));
+        }
+
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| AuthError::Malformed)?;
+        self.sign(signed)
+            .verify_slice(&signature)
+            .map_err(|_| AuthError::Signature)?;
+
+        let claims: Claims = decode_part(claims)?;
+        if claims.exp <= now {
+            return Err(AuthError::Expired);
+        }
+
+        UserId::try_parse(claims.sub).map_err(|_| AuthError::Malformed)
+    }
+
+    fn sign(&self, signed: &str) -> Hmac<Sha256> {
+        let mut mac = self.key.clone();
+        mac.update(signed.as_bytes());
+        mac
+    }
+}
+
+/// The user a token claims to be issued to, without verifying it: for clients, that don't have
+/// the secret.
+pub fn subject(token: &str) -> Result<UserId, AuthError> {
+    let claims = token.split('.').nth(1).ok_or(AuthError::Malformed)?;
+    let claims: Claims = decode_part(claims)?;
+
+    UserId::try_parse(claims.sub).map_err(|_| AuthError::Malformed)
+}
+
+/// Of the hashes of new passwords, as recommended by OWASP for PBKDF2-HMAC-SHA256.
+const PASSWORD_ITERATIONS: u32 = 600_000;
+const PASSWORD_SCHEME: &str = "pbkdf2-sha256";
+
+/// To be stored instead of `password`: `pbkdf2-sha256$<iterations>$<salt>$<hash>`, in base64.
+/// Slow on purpose, to be run out of the async runtime.
+pub fn hash_password(password: &str) -> String {
+    let mut salt = [0; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+
+    hash_password_with(password, &salt, PASSWORD_ITERATIONS)
+}
+
+/// Whether `password` is the one of `hash`, made by `hash_password`. In constant time, false when
+/// `hash` is malformed.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    let parts: Vec<&str> = hash.split('$').collect();
+    let [PASSWORD_SCHEME, iterations, salt, expected] = parts[..] else {
+        return false;
+    };
+    let (Ok(iterations), Ok(salt), Ok(expected)) = (
+        iterations.parse(),
+        STANDARD_NO_PAD.decode(salt),
+        STANDARD_NO_PAD.decode(expected),
+    ) else {
+        return false;
+    };
+
+    pbkdf2_sha256(password.as_bytes(), &salt, iterations)
+        .ct_eq(&expected[..])
+        .into()
+}
+
+fn hash_password_with(password: &str, salt: &[u8], iterations: u32) -> String {
+    let hash = pbkdf2_sha256(password.as_bytes(), salt, iterations);
+
+    format!(
+        "{PASSWORD_SCHEME}${iterations}${}${}",
+        STANDARD_NO_PAD.encode(salt),
+        STANDARD_NO_PAD.encode(hash)
+    )
+}
+
+/// The first and only block of PBKDF2 (RFC 8018), of the size of a SHA-256 digest.
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let key = Hmac::<Sha256>::new_from_slice(password).expect("HMAC accepts keys of any size");
+
+    let mut mac = key.clone();
+    mac.update(salt);
+    mac.update(&1u32.to_be_bytes());
+    let mut block: [u8; 32] = mac.finalize().into_bytes().into();
+
+    let mut derived = block;
+    for _ in 1..iterations {
+        let mut mac = key.clone();
+        mac.update(&block);
+        block = mac.finalize().into_bytes().into();
+        derived.iter_mut().zip(block).for_each(|(d, b)| *d ^= b);
+    }
+
+    derived
+}
+
+fn encode_part(part: &impl Serialize) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(part).expect("header and claims are serializable"))
+}
+
+fn decode_part<T: DeserializeOwned>(part: &str) -> Result<T, AuthError> {
+    let json = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| AuthError::Malformed)?;
+
+    serde_json::from_slice(&json).map_err(|_| AuthError::Malformed)
+}
+
+#[cfg(test)]
+#[test]
+fn token_test() {
+    let alice = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let authority = TokenAuthority::new("secret", chrono::Duration::hours(1));
+
+    let token = authority.issue_at(alice, 1_000);
+    assert_eq!(authority.verify_at(&token, 1_000).unwrap(), alice);
+    assert_eq!(subject(&token).unwrap(), alice);
+    assert!(matches!(subject("not a token"), Err(AuthError::Malformed)));
+    assert!(matches!(
+        authority.verify_at(&token, 1_000 + 3_600),
+        Err(AuthError::Expired)
+    ));
+
+    let other = TokenAuthority::new("other secret", chrono::Duration::hours(1));
+    assert!(matches!(
+        other.verify_at(&token, 1_000),
+        Err(AuthError::Signature)
+    ));
+
+    // Claims can't be changed without the secret.
+    let (_, rest) = token.split_once('.').unwrap();
+    let (_, signature) = rest.split_once('.').unwrap();
+    let forged = format!(
+        "{}.{}.{signature}",
+        encode_part(&Header {
+            alg: "HS256".to_string(),
+            typ: "JWT".to_string()
+        }),
+        encode_part(&Claims {
+            sub: "21234567-1234-5678-1234-567812345678".to_string(),
+            iat: 1_000,
+            exp: 100_000,
+        })
+    );
+    assert!(matches!(
+        authority.verify_at(&forged, 1_000),
+        Err(AuthError::Signature)
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn password_test() {
+    // Test vectors of PBKDF2-HMAC-SHA256.
+    let hex = |bytes: [u8; 32]| bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    assert_eq!(
+        hex(pbkdf2_sha256(b"password", b"salt", 1)),
+        "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+    );
+    assert_eq!(
+        hex(pbkdf2_sha256(b"password", b"salt", 2)),
+        "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43"
+    );
+
+    let hash = hash_password_with("hunter22", b"0123456789abcdef", 10);
+    assert!(verify_password("hunter22", &hash));
+    assert!(!verify_password("hunter23", &hash));
+    assert!(!verify_password("hunter22", "hunter22"));
+    assert!(!verify_password("hunter22", &hash.replace("pbkdf2", "md5")));
+}
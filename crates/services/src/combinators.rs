@@ -0,0 +1,417 @@
+//! Stream combinators used to compose repository and realtime streams.
+
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::{Stream, TryStreamExt};
+use models::events::{Event, RecentEvents};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+/// Merges streams that are already sorted into a single sorted stream, without buffering more than
+/// one item per stream. Errors are forwarded as soon as they are received.
+pub struct MergeSortedStreams<S, T> {
+    streams: Vec<Pin<Box<S>>>,
+    heads: Vec<Option<T>>,
+    done: Vec<bool>,
+    order: Order,
+}
+
+// Streams are boxed and buffered items are never pinned.
+impl<S, T> Unpin for MergeSortedStreams<S, T> {}
+
+impl<S: Stream<Item = Result<T, E>>, T: Ord, E> MergeSortedStreams<S, T> {
+    pub fn new(streams: impl IntoIterator<Item = S>, order: Order) -> Self {
+        let streams: Vec<_> = streams.into_iter().map(Box::pin).collect();
+        let count = streams.len();
+
+        Self {
+            streams,
+            heads: (0..count).map(|_| None).collect(),
+            done: vec![false; count],
+            order,
+        }
+    }
+
+    /// Most recent first, for streams of messages.
+    pub fn descending(streams: impl IntoIterator<Item = S>) -> Self {
+        Self::new(streams, Order::Descending)
+    }
+
+    pub fn ascending(streams: impl IntoIterator<Item = S>) -> Self {
+        Self::new(streams, Order::Ascending)
+    }
+}
+
+impl<S: Stream<Item = Result<T, E>>, T: Ord, E> Stream for MergeSortedStreams<S, T> {
+    type Item = Result<T, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let mut pending = false;
+
+        // Every stream must have a head (or be exhausted) to know which item comes next.
+        for i in 0..this.streams.len() {
+            if this.heads[i].is_some() || this.done[i] {
+                continue;
+            }
+
+            match this.streams[i].as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(item))) => this.heads[i] = Some(item),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => this.done[i] = true,
+                Poll::Pending => pending = true,
+            }
+        }
+
+        if pending {
+            return Poll::Pending;
+        }
+
+        let order = this.order;
+        let next = this
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| head.as_ref().map(|head| (i, head)))
+            .reduce(|best, candidate| {
+                let better = match order {
+                    Order::Descending => candidate.1 > best.1,
+                    Order::Ascending => candidate.1 < best.1,
+                };
+
+                if better {
+                    candidate
+                } else {
+                    best
+                }
+            })
+            .map(|(i, _)| i);
+
+        match next {
+            Some(i) => Poll::Ready(this.heads[i].take().map(Ok)),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+/// Up to this many live items are buffered while the backfill runs.
+pub const MAX_STITCH_BUFFERED: usize = 10_000;
+
+/// Delivers a backfill then a live stream, exactly once: items of the live stream that were already
+/// in the backfill, or buffered twice, are dropped. `key` identifies items, those without a key
+/// are never dropped.
+///
+/// The live stream is polled first and buffered until the backfill ends, so that it subscribes
+/// before the backfill is queried and nothing published in between is missed. The buffered items
+/// are then sent by key, those without one first in the order they arrived, and the next live
+/// ones as they arrive. The stream ends when more than `max_buffered` items would be buffered,
+/// for the client to subscribe again rather than to miss some.
+pub struct StitchLive<B, L, T, K> {
+    backfill: Option<Pin<Box<B>>>,
+    live: Pin<Box<L>>,
+    live_done: bool,
+    buffered: VecDeque<T>,
+    max_buffered: usize,
+    overflowed: bool,
+    delivered: HashSet<K>,
+    key: fn(&T) -> Option<K>,
+}
+
+// Streams are boxed and buffered items are never pinned.
+impl<B, L, T, K> Unpin for StitchLive<B, L, T, K> {}
+
+impl<B, L, T, K, E> StitchLive<B, L, T, K>
+where
+    B: Stream<Item = Result<T, E>>,
+    L: Stream<Item = Result<T, E>>,
+    K: Eq + Hash + Ord,
+{
+    pub fn new(backfill: B, live: L, key: fn(&T) -> Option<K>) -> Self {
+        Self::with_limit(backfill, live, key, MAX_STITCH_BUFFERED)
+    }
+
+    pub fn with_limit(backfill: B, live: L, key: fn(&T) -> Option<K>, max_buffered: usize) -> Self {
+        Self {
+            backfill: Some(Box::pin(backfill)),
+            live: Box::pin(live),
+            live_done: false,
+            buffered: VecDeque::new(),
+            max_buffered,
+            overflowed: false,
+            delivered: HashSet::new(),
+            key,
+        }
+    }
+
+    fn is_delivered(&self, item: &T) -> bool {
+        (self.key)(item).is_some_and(|key| self.delivered.contains(&key))
+    }
+
+    /// Once the backfill ended, the buffered items by key.
+    fn sort_buffered(&mut self) {
+        self.buffered.make_contiguous().sort_by_key(self.key);
+    }
+}
+
+impl<B, L, T, K, E> Stream for StitchLive<B, L, T, K>
+where
+    B: Stream<Item = Result<T, E>>,
+    L: Stream<Item = Result<T, E>>,
+    K: Eq + Hash + Ord,
+{
+    type Item = Result<T, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        if this.overflowed {
+            return Poll::Ready(None);
+        }
+
+        if let Some(backfill) = this.backfill.as_mut() {
+            while !this.live_done {
+                match this.live.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(Ok(_))) if this.buffered.len() >= this.max_buffered => {
+                        tracing::warn!(
+                            max_buffered = this.max_buffered,
+                            "Too many live items during the backfill, ending the stream"
+                        );
+                        this.overflowed = true;
+                        return Poll::Ready(None);
+                    }
+                    Poll::Ready(Some(Ok(item))) => this.buffered.push_back(item),
+                    Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                    Poll::Ready(None) => this.live_done = true,
+                    Poll::Pending => break,
+                }
+            }
+
+            match backfill.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(item))) => {
+                    if let Some(key) = (this.key)(&item) {
+                        this.delivered.insert(key);
+                    }
+                    return Poll::Ready(Some(Ok(item)));
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => {
+                    this.backfill = None;
+                    this.sort_buffered();
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        while let Some(item) = this.buffered.pop_front() {
+            if !this.is_delivered(&item) {
+                if let Some(key) = (this.key)(&item) {
+                    this.delivered.insert(key);
+                }
+                return Poll::Ready(Some(Ok(item)));
+            }
+        }
+
+        if this.live_done {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            match this.live.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(item))) if this.is_delivered(&item) => continue,
+                other => return other,
+            }
+        }
+    }
+}
+
+/// Events remembered by each stream to drop the ones received twice.
+pub const RECENT_EVENTS: usize = 1024;
+
+/// The payloads of `events` not in `recent` yet. Event IDs are unique across channels, so the
+/// realtime streams of a consumer can share `recent`.
+pub fn deduplicated<T, E>(
+    events: impl Stream<Item = Result<Event<T>, E>>,
+    recent: Arc<Mutex<RecentEvents>>,
+) -> impl Stream<Item = Result<T, E>> {
+    events.try_filter_map(move |event| {
+        let fresh = recent.lock().unwrap().insert(event.event_id);
+        if fresh {
+            tracing::trace!(
+                event_id = %event.event_id,
+                trace_id = event.trace_id.as_deref(),
+                producer_id = %event.producer_id,
+                "Received event"
+            );
+        } else {
+            tracing::debug!(event_id = %event.event_id, "Dropped a duplicate event");
+        }
+
+        futures::future::ok(fresh.then(|| event.into_payload()))
+    })
+}
+
+#[cfg(test)]
+#[test]
+fn merge_sorted_test() {
+    use futures::{stream, StreamExt};
+
+    let streams = vec![
+        stream::iter(vec![Ok::<u32, ()>(9), Ok(4), Ok(1)]),
+        stream::iter(vec![Ok(8), Ok(7), Ok(2)]),
+        stream::iter(vec![]),
+        stream::iter(vec![Ok(5)]),
+    ];
+
+    let merged: Vec<_> =
+        futures::executor::block_on(MergeSortedStreams::descending(streams).collect());
+
+    assert_eq!(
+        merged,
+        vec![Ok(9), Ok(8), Ok(7), Ok(5), Ok(4), Ok(2), Ok(1)]
+    );
+}
+
+/// For any streams of messages sorted most recent first, many of the same date: the merge is
+/// sorted, keeps every message once, and a same message is never mistaken for another.
+#[cfg(test)]
+#[test]
+fn merge_sorted_messages_property() {
+    use std::collections::BTreeSet;
+
+    use futures::{stream, StreamExt};
+    use models::messages::Message;
+    use models::users::UserId;
+
+    let users: Vec<UserId> = (1..=3)
+        .map(|i| UserId::try_parse(format!("{i}1234567-1234-5678-1234-567812345678")).unwrap())
+        .collect();
+    let date = chrono::Utc::now();
+
+    // xorshift64, the cases are the same on each run.
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut random = move |bound: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % bound
+    };
+
+    for _ in 0..200 {
+        let streams: Vec<Vec<Message>> = (0..1 + random(4))
+            .map(|_| {
+                let mut messages: Vec<Message> = (0..random(12))
+                    .map(|_| Message {
+                        date: date + chrono::Duration::seconds(random(4) as i64),
+                        ..Message::new(users[random(3) as usize], String::new())
+                    })
+                    .collect();
+                messages.sort_by(|a, b| b.cmp(a));
+
+                messages
+            })
+            .collect();
+        let expected: BTreeSet<Message> = streams.iter().flatten().cloned().collect();
+
+        let merged: Vec<Message> = futures::executor::block_on(
+            MergeSortedStreams::descending(
+                streams
+                    .into_iter()
+                    .map(|messages| stream::iter(messages.into_iter().map(Ok::<_, ()>))),
+            )
+            .map(Result::unwrap)
+            .collect(),
+        );
+
+        assert!(merged.windows(2).all(|pair| pair[0] > pair[1]));
+        assert_eq!(merged.len(), expected.len());
+        assert!(merged.iter().rev().eq(expected.iter()));
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn stitch_live_test() {
+    use futures::{stream, StreamExt};
+
+    let backfill = stream::iter(vec![Ok::<u32, ()>(1), Ok(2), Ok(3)]);
+    // 2 and 3 were published while the backfill was running, 0 has no key.
+    let live = stream::iter(vec![Ok(2), Ok(3), Ok(0), Ok(4)]);
+
+    let stitched: Vec<_> = futures::executor::block_on(
+        StitchLive::new(backfill, live, |i: &u32| (*i != 0).then_some(*i)).collect(),
+    );
+
+    assert_eq!(stitched, vec![Ok(1), Ok(2), Ok(3), Ok(0), Ok(4)]);
+}
+
+/// Buffered items are sent by key without duplicates, and too many of them end the stream.
+#[cfg(test)]
+#[test]
+fn stitch_live_buffer_test() {
+    use futures::channel::mpsc;
+    use futures::{stream, StreamExt};
+
+    let waker = futures::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+
+    let (tx, backfill) = mpsc::unbounded();
+    let live = stream::iter(vec![Ok::<u32, ()>(7), Ok(0), Ok(5), Ok(7), Ok(1), Ok(6)]);
+    let mut stitched =
+        StitchLive::with_limit(backfill, live, |i: &u32| (*i != 0).then_some(*i), 10);
+
+    // The live items are buffered once the backfill is polled.
+    assert!(stitched.poll_next_unpin(&mut cx).is_pending());
+    tx.unbounded_send(Ok(1)).unwrap();
+    drop(tx);
+
+    let stitched: Vec<_> = futures::executor::block_on(stitched.collect());
+    assert_eq!(stitched, vec![Ok(1), Ok(0), Ok(5), Ok(6), Ok(7)]);
+
+    // The first item over the limit ends it, even once the backfill is done.
+    let (tx, backfill) = mpsc::unbounded::<Result<u32, ()>>();
+    let live = stream::iter((1..=4).map(Ok));
+    let mut stitched = StitchLive::with_limit(backfill, live, |i: &u32| Some(*i), 3);
+    let polled = stitched.poll_next_unpin(&mut cx);
+    assert!(matches!(polled, Poll::Ready(None)));
+    drop(tx);
+    assert_eq!(futures::executor::block_on(stitched.next()), None);
+
+    // Without keys, in the order they arrived.
+    let live = stream::iter(vec![Ok::<u32, ()>(3), Ok(1), Ok(2)]);
+    let stitched: Vec<_> = futures::executor::block_on(
+        StitchLive::new(stream::empty(), live, |_| None::<()>).collect(),
+    );
+    assert_eq!(stitched, vec![Ok(3), Ok(1), Ok(2)]);
+}
+
+/// An event received twice, even on another stream sharing `recent`, is only delivered once.
+#[cfg(test)]
+#[test]
+fn deduplicated_test() {
+    use futures::{stream, StreamExt};
+
+    let recent = Arc::new(Mutex::new(RecentEvents::new(RECENT_EVENTS)));
+    let first = Event::new(1);
+    let second = Event::new(2);
+
+    let events = stream::iter(vec![
+        Ok::<_, ()>(first.clone()),
+        Ok(second.clone()),
+        Ok(first),
+    ]);
+    let delivered: Vec<_> =
+        futures::executor::block_on(deduplicated(events, recent.clone()).collect());
+    assert_eq!(delivered, vec![Ok(1), Ok(2)]);
+
+    let events = stream::iter(vec![Ok::<_, ()>(second), Ok(Event::new(3))]);
+    let delivered: Vec<_> = futures::executor::block_on(deduplicated(events, recent).collect());
+    assert_eq!(delivered, vec![Ok(3)]);
+}
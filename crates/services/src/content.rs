@@ -0,0 +1,4 @@
+//! Cleaning of the content of messages, shared with the models so that `Message::try_new`
+//! applies the same rules.
+
+pub use models::content::{ContentError, ContentPolicy};
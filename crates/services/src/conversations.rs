@@ -0,0 +1,206 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use futures::{stream::select, Stream, TryStreamExt};
+use tracing::instrument;
+
+use crate::combinators::{deduplicated, RECENT_EVENTS};
+use crate::moderation::ModerationService;
+use models::{
+    conversations::{
+        ChatEvent, ChatSignal, ChatSignalKind, Conversation, ConversationId, Conversationlike,
+        DirectMessage,
+    },
+    events::RecentEvents,
+    messages::Message,
+    users::{UserId, Userlike},
+};
+use realtime::{
+    self,
+    senders::{PublishChatSignal, PublishDirectMessage},
+    Client,
+};
+use repository::{
+    blocks::IsBlockedRequest,
+    conversations::{
+        CreateConversationRequest, GetConversationRequest, GetConversationsOfUserRequest,
+        GetDirectMessagesRequest, InsertDirectMessageRequest,
+    },
+    PgPool, RepositoryError, Session,
+};
+
+pub trait ConversationlikeServices: Conversationlike {
+    fn get(&self) -> GetConversationRequest {
+        GetConversationRequest::new(self.get_id())
+    }
+
+    fn insert_message(&self, message: Message) -> InsertDirectMessageRequest {
+        InsertDirectMessageRequest::new(self.get_id(), message)
+    }
+
+    fn get_messages(&self) -> GetDirectMessagesRequest {
+        GetDirectMessagesRequest::new(self.get_id())
+    }
+}
+
+impl<T: Conversationlike> ConversationlikeServices for T {}
+
+#[derive(Clone, Copy)]
+pub struct ConversationServices(ConversationId);
+
+impl Conversationlike for ConversationServices {
+    fn get_id(&self) -> ConversationId {
+        self.0
+    }
+}
+
+impl ConversationServices {
+    pub fn new(conversation: impl Conversationlike) -> Self {
+        Self(conversation.get_id())
+    }
+
+    pub fn create(creator: impl Userlike, members: Vec<UserId>) -> CreateConversationRequest {
+        CreateConversationRequest::new(creator, members)
+    }
+
+    pub fn of_user(user: impl Userlike) -> GetConversationsOfUserRequest {
+        GetConversationsOfUserRequest::new(user)
+    }
+
+    /// `RepositoryError::Blocked` when `user` blocked one of `others` or was blocked by one of
+    /// them, checked before creating a conversation with them.
+    pub async fn ensure_not_blocked(
+        user: impl Userlike,
+        others: impl IntoIterator<Item = UserId>,
+        pg: &PgPool,
+    ) -> Result<(), RepositoryError> {
+        let user = user.get_id();
+
+        for other in others.into_iter().filter(|other| *other != user) {
+            if IsBlockedRequest::new(user, other).execute(pg).await? {
+                return Err(RepositoryError::Blocked);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Screens, stores then publishes a direct message. Users outside of the conversation get
+    /// `RepositoryError::NotFound` so that they can't probe which conversations exist, and
+    /// `RepositoryError::Blocked` when a block stands between them and another member.
+    #[instrument(name = "ConversationServices::send", skip_all, fields(conversation_id = %self.0))]
+    pub async fn send(
+        self,
+        user: impl Userlike,
+        content: String,
+        moderation: &dyn ModerationService,
+        session: &Session,
+        pg: &PgPool,
+        nats: Client,
+    ) -> Result<DirectMessage, Error> {
+        let user = user.get_id();
+        self.ensure_member(user, session, pg).await?;
+
+        let message = Message::new(user, content);
+        moderation.screen(&message).await?;
+
+        let message = self.insert_message(message).execute(session).await?;
+
+        let _ = PublishDirectMessage::new(message.clone())
+            .publish(nats)
+            .await;
+
+        Ok(message)
+    }
+
+    /// Past messages, most recent first.
+    #[instrument(name = "ConversationServices::history", skip_all, fields(conversation_id = %self.0))]
+    pub async fn history<'a>(
+        self,
+        user: impl Userlike,
+        session: &'a Session,
+        pg: &PgPool,
+    ) -> Result<impl Stream<Item = Result<DirectMessage, Error>> + 'a, Error> {
+        self.ensure_member(user.get_id(), session, pg).await?;
+
+        Ok(self.get_messages().stream(session).map_err(Error::from))
+    }
+
+    /// Messages sent from now on.
+    #[instrument(name = "ConversationServices::live", skip_all, fields(conversation_id = %self.0))]
+    pub async fn live<'a>(
+        self,
+        user: impl Userlike,
+        session: &Session,
+        pg: &PgPool,
+        nats: Client,
+    ) -> Result<impl Stream<Item = Result<DirectMessage, Error>> + 'a, Error> {
+        self.ensure_member(user.get_id(), session, pg).await?;
+
+        let messages =
+            realtime::receivers::direct_messages_of_conversation_enveloped(self.get_id(), nats);
+        let recent = Arc::new(Mutex::new(RecentEvents::new(RECENT_EVENTS)));
+
+        Ok(deduplicated(messages, recent).map_err(Error::from))
+    }
+
+    /// Direct messages and signals sent from now on.
+    #[instrument(name = "ConversationServices::live_events", skip_all, fields(conversation_id = %self.0))]
+    pub async fn live_events<'a>(
+        self,
+        user: impl Userlike,
+        session: &Session,
+        pg: &PgPool,
+        nats: Client,
+    ) -> Result<impl Stream<Item = Result<ChatEvent, Error>> + 'a, Error> {
+        self.ensure_member(user.get_id(), session, pg).await?;
+
+        let recent = Arc::new(Mutex::new(RecentEvents::new(RECENT_EVENTS)));
+        let messages = realtime::receivers::direct_messages_of_conversation_enveloped(
+            self.get_id(),
+            nats.clone(),
+        );
+        let messages = deduplicated(messages, recent.clone()).map_ok(ChatEvent::Message);
+        let signals =
+            realtime::receivers::chat_signals_of_conversation_enveloped(self.get_id(), nats);
+        let signals = deduplicated(signals, recent).map_ok(ChatEvent::Signal);
+
+        Ok(select(messages, signals).map_err(Error::from))
+    }
+
+    /// Publishes a signal of a member, not stored. Membership is not checked: it is for users
+    /// already receiving `live_events`.
+    pub async fn signal(
+        self,
+        user: impl Userlike,
+        kind: ChatSignalKind,
+        nats: Client,
+    ) -> Result<(), Error> {
+        let signal = ChatSignal {
+            conversation_id: self.get_id(),
+            user_id: user.get_id(),
+            kind,
+        };
+
+        PublishChatSignal::new(signal).publish(nats).await?;
+
+        Ok(())
+    }
+
+    /// Not blocked by, nor blocking, another member.
+    async fn ensure_member(
+        self,
+        user: UserId,
+        session: &Session,
+        pg: &PgPool,
+    ) -> Result<Conversation, RepositoryError> {
+        let conversation = self.get().execute(session).await?;
+
+        if !conversation.is_member(user) {
+            return Err(RepositoryError::NotFound);
+        }
+        Self::ensure_not_blocked(user, conversation.members.iter().copied(), pg).await?;
+
+        Ok(conversation)
+    }
+}
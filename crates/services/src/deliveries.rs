@@ -0,0 +1,241 @@
+//! Notifications sent to the clients that acknowledge them, kept until they do so that they are
+//! sent again when the client subscribes again after losing its stream.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use models::{
+    events::EventId,
+    users::{UserId, Userlike},
+};
+
+/// Counters of the deliveries since the server started, but `pending` which is the number of
+/// notifications waiting for their acknowledgement.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeliveryStats {
+    pub sent: u64,
+    pub acknowledged: u64,
+    pub resent: u64,
+    /// Forgotten before being acknowledged: over `Deliveries::MAX_PENDING` of their user, over
+    /// the bound of every user, or older than the TTL.
+    pub dropped: u64,
+    pub pending: u64,
+}
+
+#[derive(Debug)]
+struct Pending<T> {
+    by_user: HashMap<UserId, VecDeque<(EventId, T)>>,
+    /// Of every user, in the order they were sent. Along with ones already acknowledged or
+    /// dropped, skipped once reached.
+    sent: VecDeque<(Instant, UserId, EventId)>,
+    stats: DeliveryStats,
+}
+
+impl<T> Pending<T> {
+    /// The oldest notifications, over `max_total` or older than `ttl`, along with the users
+    /// left without any.
+    fn prune(&mut self, max_total: usize, ttl: Duration) {
+        while let Some(&(sent_at, user, id)) = self.sent.front() {
+            if self.stats.pending <= max_total as u64 && sent_at.elapsed() < ttl {
+                break;
+            }
+            self.sent.pop_front();
+
+            // The older ones of the user were reached before.
+            let Some(of_user) = self.by_user.get_mut(&user) else {
+                continue;
+            };
+            if of_user.front().is_some_and(|(pending, _)| *pending == id) {
+                of_user.pop_front();
+                self.stats.dropped += 1;
+                self.stats.pending -= 1;
+            }
+            if of_user.is_empty() {
+                self.by_user.remove(&user);
+            }
+        }
+    }
+}
+
+/// The notifications of each user not acknowledged yet, in the order they were sent, for at most
+/// `PENDING_TTL` and `MAX_PENDING_TOTAL` of them. Kept in the memory of this server only: a
+/// client subscribing again to another one misses them.
+///
+/// They are of the user, not of a stream: when the user has two streams at once, eg. on two
+/// devices, each one sends again what the other did not acknowledge yet when subscribing. Clients
+/// drop the ones they already received by their `event_id`.
+///
+/// Can be shared between threads by using `Clone`.
+#[derive(Clone, Debug)]
+pub struct Deliveries<T> {
+    pending: Arc<Mutex<Pending<T>>>,
+    max_total: usize,
+    ttl: Duration,
+}
+
+impl<T: Clone> Default for Deliveries<T> {
+    fn default() -> Self {
+        Self::with_limits(Self::MAX_PENDING_TOTAL, Self::PENDING_TTL)
+    }
+}
+
+impl<T: Clone> Deliveries<T> {
+    /// Of each user, the oldest ones are dropped beyond.
+    pub const MAX_PENDING: usize = 256;
+    /// Of every user, the oldest ones are dropped beyond.
+    pub const MAX_PENDING_TOTAL: usize = 100_000;
+    /// After which a notification is dropped, the client is not coming back for it.
+    pub const PENDING_TTL: Duration = Duration::from_secs(10 * 60);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(max_total: usize, ttl: Duration) -> Self {
+        Self {
+            pending: Arc::new(Mutex::new(Pending {
+                by_user: HashMap::new(),
+                sent: VecDeque::new(),
+                stats: DeliveryStats::default(),
+            })),
+            max_total,
+            ttl,
+        }
+    }
+
+    /// Records `notification` as sent to `user`, under the returned ID.
+    pub fn sent(&self, user: impl Userlike, notification: T) -> EventId {
+        let id = EventId::new();
+        let user = user.get_id();
+        let mut pending = self.pending.lock().unwrap();
+        let of_user = pending.by_user.entry(user).or_default();
+
+        of_user.push_back((id, notification));
+        let dropped = of_user.len().saturating_sub(Self::MAX_PENDING);
+        of_user.drain(..dropped);
+
+        pending.sent.push_back((Instant::now(), user, id));
+        pending.stats.sent += 1;
+        pending.stats.dropped += dropped as u64;
+        pending.stats.pending += 1;
+        pending.stats.pending -= dropped as u64;
+        pending.prune(self.max_total, self.ttl);
+
+        id
+    }
+
+    /// Forgets the notifications of `ids` sent to `user`, returns how many there were. Unknown
+    /// IDs, eg. already acknowledged, are ignored.
+    pub fn acknowledge(
+        &self,
+        user: impl Userlike,
+        ids: impl IntoIterator<Item = EventId>,
+    ) -> usize {
+        let user = user.get_id();
+        let mut pending = self.pending.lock().unwrap();
+        let Some(of_user) = pending.by_user.get_mut(&user) else {
+            return 0;
+        };
+
+        let before = of_user.len();
+        for id in ids {
+            if let Some(position) = of_user.iter().position(|(sent, _)| *sent == id) {
+                of_user.remove(position);
+            }
+        }
+        let acknowledged = before - of_user.len();
+        if of_user.is_empty() {
+            pending.by_user.remove(&user);
+        }
+
+        pending.stats.acknowledged += acknowledged as u64;
+        pending.stats.pending -= acknowledged as u64;
+
+        acknowledged
+    }
+
+    /// The notifications of `user` not acknowledged yet, oldest first, to send again. They are
+    /// still pending, under the same IDs.
+    pub fn unacknowledged(&self, user: impl Userlike) -> Vec<(EventId, T)> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.prune(self.max_total, self.ttl);
+        let unacknowledged: Vec<(EventId, T)> = pending
+            .by_user
+            .get(&user.get_id())
+            .map(|of_user| of_user.iter().cloned().collect())
+            .unwrap_or_default();
+
+        pending.stats.resent += unacknowledged.len() as u64;
+
+        unacknowledged
+    }
+
+    pub fn stats(&self) -> DeliveryStats {
+        self.pending.lock().unwrap().stats
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn deliveries_test() {
+    let alice = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let bob = UserId::try_parse("21234567-1234-5678-1234-567812345678").unwrap();
+
+    let deliveries = Deliveries::new();
+    let first = deliveries.sent(alice, "first");
+    let second = deliveries.sent(alice, "second");
+    deliveries.sent(bob, "other");
+
+    // Only the ones of the user are acknowledged by it.
+    assert_eq!(deliveries.acknowledge(bob, [first]), 0);
+    assert_eq!(deliveries.acknowledge(alice, [first, EventId::new()]), 1);
+    assert_eq!(deliveries.acknowledge(alice, [first]), 0);
+    assert_eq!(deliveries.unacknowledged(alice), vec![(second, "second")]);
+    // Still pending until acknowledged.
+    assert_eq!(deliveries.unacknowledged(alice).len(), 1);
+
+    for i in 0..Deliveries::<&str>::MAX_PENDING {
+        deliveries.sent(bob, if i % 2 == 0 { "even" } else { "odd" });
+    }
+    let unacknowledged = deliveries.unacknowledged(bob);
+    assert_eq!(unacknowledged.len(), Deliveries::<&str>::MAX_PENDING);
+    assert_eq!(unacknowledged[0].1, "even");
+
+    assert_eq!(
+        deliveries.stats(),
+        DeliveryStats {
+            sent: 3 + Deliveries::<&str>::MAX_PENDING as u64,
+            acknowledged: 1,
+            resent: 2 + Deliveries::<&str>::MAX_PENDING as u64,
+            dropped: 1,
+            pending: 1 + Deliveries::<&str>::MAX_PENDING as u64,
+        }
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn deliveries_limits_test() {
+    let alice = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let bob = UserId::try_parse("21234567-1234-5678-1234-567812345678").unwrap();
+
+    // The oldest are dropped first, whoever they were sent to.
+    let deliveries = Deliveries::with_limits(2, Duration::from_secs(60));
+    let first = deliveries.sent(alice, "first");
+    let second = deliveries.sent(bob, "second");
+    assert_eq!(deliveries.acknowledge(bob, [second]), 1);
+    let third = deliveries.sent(bob, "third");
+    deliveries.sent(alice, "fourth");
+    assert_eq!(deliveries.acknowledge(alice, [first]), 0);
+    assert_eq!(deliveries.unacknowledged(bob), vec![(third, "third")]);
+    assert_eq!(deliveries.stats().dropped, 1);
+
+    let expiring = Deliveries::with_limits(10, Duration::ZERO);
+    expiring.sent(alice, "expired");
+    assert!(expiring.unacknowledged(alice).is_empty());
+    assert!(expiring.pending.lock().unwrap().by_user.is_empty());
+    assert_eq!(expiring.stats().pending, 0);
+}
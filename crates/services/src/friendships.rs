@@ -0,0 +1,428 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::{
+    future::Either,
+    stream::{select, StreamExt, TryStreamExt},
+    Stream,
+};
+
+use models::{
+    events::RecentEvents,
+    friendships::{BlockUpdate, FriendshipEvent, FriendshipUpdate},
+    users::{UserId, Userlike},
+};
+use realtime::connection::ConnectionMonitor;
+use realtime::senders::{PublishFriendshipEvent, SenderError};
+use realtime::{self, Client};
+use repository::{PgPool, RepositoryError};
+use tracing::instrument;
+
+use crate::combinators::{deduplicated, RECENT_EVENTS};
+use crate::users::UserlikeServices;
+
+/// Friend sets cached at most, the oldest loaded is evicted first.
+const MAX_ENTRIES: usize = 100_000;
+/// After which a friend set is loaded again, in case an update was missed.
+const ENTRY_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// In memory friend sets, loaded from PostgreSQL on first use then kept fresh by `listen`, at
+/// most `MAX_ENTRIES` of them for `ENTRY_TTL`. Nothing is cached while `listen` is not running,
+/// sets could not be kept fresh. Can be shared between threads by using `Clone`.
+#[derive(Clone, Debug)]
+pub struct FriendCache {
+    inner: Arc<Mutex<Inner>>,
+    max_entries: usize,
+    ttl: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    friends: HashMap<UserId, Entry>,
+    /// Loaded first, evicted first. Users loaded again since are skipped.
+    loaded: VecDeque<(UserId, Instant)>,
+    /// Updates received while the friends of a user are loaded, applied to them once loaded.
+    loading: HashMap<UserId, Vec<FriendChange>>,
+    listening: bool,
+}
+
+#[derive(Debug)]
+struct Entry {
+    friends: HashSet<UserId>,
+    loaded_at: Instant,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum FriendChange {
+    New(UserId),
+    Removed(UserId),
+}
+
+impl Default for FriendCache {
+    fn default() -> Self {
+        Self::with_limits(MAX_ENTRIES, ENTRY_TTL)
+    }
+}
+
+impl FriendCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(max_entries: usize, ttl: Duration) -> Self {
+        Self {
+            inner: Arc::default(),
+            max_entries,
+            ttl,
+        }
+    }
+
+    #[instrument(name = "FriendCache::get", skip_all, fields(user_id = %user.get_id()))]
+    pub async fn get(
+        &self,
+        user: impl Userlike,
+        pg: &PgPool,
+    ) -> Result<HashSet<UserId>, RepositoryError> {
+        let user = user.get_id();
+
+        if let Some(friends) = self.begin_load(user) {
+            return Ok(friends);
+        }
+
+        let friends = user.get_friends().stream(pg).try_collect().await;
+        let friends = match friends {
+            Ok(friends) => friends,
+            Err(e) => {
+                self.cancel_load(user);
+                return Err(e);
+            }
+        };
+
+        Ok(self.finish_load(user, friends))
+    }
+
+    /// The cached friends of `user`, else starts buffering its updates until `finish_load`.
+    fn begin_load(&self, user: UserId) -> Option<HashSet<UserId>> {
+        let mut inner = self.inner.lock().unwrap();
+
+        match inner.friends.get(&user) {
+            Some(entry) if entry.loaded_at.elapsed() < self.ttl => {
+                return Some(entry.friends.clone());
+            }
+            Some(_expired) => {
+                inner.friends.remove(&user);
+            }
+            None => {}
+        }
+
+        if inner.listening {
+            inner.loading.entry(user).or_default();
+        }
+
+        None
+    }
+
+    /// `friends`, as loaded, along with the updates received meanwhile. Not cached when the cache
+    /// was cleared meanwhile, or when another load of the user already filled it.
+    fn finish_load(&self, user: UserId, mut friends: HashSet<UserId>) -> HashSet<UserId> {
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(entry) = inner.friends.get(&user) {
+            return entry.friends.clone();
+        }
+        let Some(changes) = inner.loading.remove(&user) else {
+            return friends;
+        };
+
+        for change in changes {
+            match change {
+                FriendChange::New(friend) => friends.insert(friend),
+                FriendChange::Removed(friend) => friends.remove(&friend),
+            };
+        }
+
+        let loaded_at = Instant::now();
+        inner.friends.insert(
+            user,
+            Entry {
+                friends: friends.clone(),
+                loaded_at,
+            },
+        );
+        inner.loaded.push_back((user, loaded_at));
+        self.evict(&mut inner);
+
+        friends
+    }
+
+    fn cancel_load(&self, user: UserId) {
+        self.inner.lock().unwrap().loading.remove(&user);
+    }
+
+    /// The oldest entries, beyond `max_entries` or expired.
+    fn evict(&self, inner: &mut Inner) {
+        while let Some(&(user, loaded_at)) = inner.loaded.front() {
+            let over = inner.loaded.len() > self.max_entries;
+            if !over && loaded_at.elapsed() < self.ttl {
+                break;
+            }
+
+            inner.loaded.pop_front();
+            if inner
+                .friends
+                .get(&user)
+                .is_some_and(|e| e.loaded_at == loaded_at)
+            {
+                inner.friends.remove(&user);
+            }
+        }
+    }
+
+    pub fn invalidate(&self, user: impl Userlike) {
+        let mut inner = self.inner.lock().unwrap();
+        let user = user.get_id();
+
+        inner.friends.remove(&user);
+        inner.loading.remove(&user);
+    }
+
+    /// Loads in progress are not cached either.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.friends.clear();
+        inner.loaded.clear();
+        inner.loading.clear();
+    }
+
+    fn change(inner: &mut Inner, user: UserId, change: FriendChange) {
+        if let Some(changes) = inner.loading.get_mut(&user) {
+            changes.push(change);
+        }
+
+        if let Some(entry) = inner.friends.get_mut(&user) {
+            match change {
+                FriendChange::New(friend) => entry.friends.insert(friend),
+                FriendChange::Removed(friend) => entry.friends.remove(&friend),
+            };
+        }
+    }
+
+    fn apply(&self, update: FriendshipUpdate) {
+        let mut inner = self.inner.lock().unwrap();
+
+        match update {
+            FriendshipUpdate::New(a, b) => {
+                Self::change(&mut inner, a, FriendChange::New(b));
+                Self::change(&mut inner, b, FriendChange::New(a));
+            }
+            FriendshipUpdate::Removed(a, b) => {
+                Self::change(&mut inner, a, FriendChange::Removed(b));
+                Self::change(&mut inner, b, FriendChange::Removed(a));
+            }
+        }
+    }
+
+    fn remove_user(&self, user: UserId) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.friends.remove(&user);
+        inner.loading.remove(&user);
+        for entry in inner.friends.values_mut() {
+            entry.friends.remove(&user);
+        }
+        for changes in inner.loading.values_mut() {
+            changes.push(FriendChange::Removed(user));
+        }
+    }
+
+    fn set_listening(&self, listening: bool) {
+        self.inner.lock().unwrap().listening = listening;
+    }
+
+    /// Current friends as a stream, to be chained with realtime updates.
+    pub fn stream<'a>(
+        self,
+        user: impl Userlike + 'a,
+        pg: &'a PgPool,
+    ) -> impl Stream<Item = Result<UserId, RepositoryError>> + 'a {
+        futures::stream::once(async move { self.get(user, pg).await })
+            .map_ok(|friends| futures::stream::iter(friends).map(Ok))
+            .try_flatten()
+    }
+
+    /// Applies friendship updates and user deletions until the NATS subscription ends, to be spawned
+    /// once. Updates could have been missed when it ends, so the cache is cleared and no longer
+    /// used.
+    #[instrument(name = "FriendCache::listen", skip_all)]
+    pub async fn listen(self, nats: Client) {
+        // A friendship received twice could be applied again after its removal.
+        let recent = Arc::new(Mutex::new(RecentEvents::new(RECENT_EVENTS)));
+        let updates = realtime::receivers::friendships_updates_enveloped(nats.clone());
+        let updates = deduplicated(updates, recent.clone()).map_ok(Either::Left);
+        let removed = realtime::receivers::removed_users_enveloped(nats);
+        let removed = deduplicated(removed, recent).map_ok(Either::Right);
+
+        let mut events = Box::pin(select(updates, removed));
+        self.set_listening(true);
+
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(Either::Left(update)) => self.apply(update),
+                Ok(Either::Right(user)) => self.remove_user(user),
+                Err(_decoding) => {}
+            }
+        }
+
+        tracing::warn!("Friendship updates ended, friends are no longer cached");
+        self.set_listening(false);
+        self.clear();
+    }
+
+    /// Clears the cache each time NATS reconnects, as updates could have been missed while it was
+    /// lost. To be spawned once, along with `listen`.
+    pub async fn clear_on_reconnect(self, connection: ConnectionMonitor) {
+        loop {
+            connection.reconnected().await;
+            self.clear();
+        }
+    }
+}
+
+/// Publishes a friend request or its answer, then the friendship it makes, if any, for the
+/// `FriendCache` and the streams following friends.
+#[instrument(skip_all, fields(initiator_id = %event.initiator, target_id = %event.target))]
+pub async fn publish_friendship_event(
+    event: FriendshipEvent,
+    nats: Client,
+) -> Result<(), SenderError> {
+    PublishFriendshipEvent::new(event)
+        .publish(nats.clone())
+        .await?;
+
+    if let Some(FriendshipUpdate::New(user, friend)) = event.update() {
+        user.realtime_friend_with(friend).publish(nats).await?;
+    }
+
+    Ok(())
+}
+
+/// Blocks involving a user, in both ways, as `(user, blocked)`.
+#[derive(Clone, Debug, Default)]
+pub struct Blocks(HashSet<(UserId, UserId)>);
+
+impl Blocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply(&mut self, update: BlockUpdate) {
+        match update {
+            BlockUpdate::Blocked(user, blocked) => {
+                self.0.insert((user, blocked));
+            }
+            BlockUpdate::Unblocked(user, blocked) => {
+                self.0.remove(&(user, blocked));
+            }
+        }
+    }
+
+    /// Whether one of the users blocked the other.
+    pub fn between(&self, a: impl Userlike, b: impl Userlike) -> bool {
+        let (a, b) = (a.get_id(), b.get_id());
+
+        self.0.contains(&(a, b)) || self.0.contains(&(b, a))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn friend_cache_updates_test() {
+    let alice = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let bob = UserId::try_parse("21234567-1234-5678-1234-567812345678").unwrap();
+    let charlie = UserId::try_parse("31234567-1234-5678-1234-567812345678").unwrap();
+
+    let cache = FriendCache::new();
+    let cached = |user| {
+        let inner = cache.inner.lock().unwrap();
+        inner.friends.get(&user).map(|entry| entry.friends.clone())
+    };
+    cache.set_listening(true);
+    assert_eq!(cache.begin_load(alice), None);
+    cache.finish_load(alice, HashSet::from([bob]));
+
+    cache.apply(FriendshipUpdate::New(charlie, alice));
+    cache.apply(FriendshipUpdate::Removed(alice, bob));
+
+    // Users that are not cached are not loaded by updates.
+    assert_eq!(cached(charlie), None);
+    assert_eq!(cached(alice), Some(HashSet::from([charlie])));
+
+    cache.remove_user(charlie);
+    assert_eq!(cache.begin_load(alice), Some(HashSet::new()));
+}
+
+/// Updates received while a friend set is loaded are applied to it, and nothing is cached when
+/// friendship updates are not received.
+#[cfg(test)]
+#[test]
+fn friend_cache_loading_test() {
+    let alice = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let bob = UserId::try_parse("21234567-1234-5678-1234-567812345678").unwrap();
+    let charlie = UserId::try_parse("31234567-1234-5678-1234-567812345678").unwrap();
+
+    let cache = FriendCache::with_limits(1, Duration::from_secs(60));
+    assert_eq!(cache.begin_load(alice), None);
+    cache.finish_load(alice, HashSet::from([bob]));
+    assert_eq!(cache.begin_load(alice), None, "not listening");
+
+    cache.set_listening(true);
+    assert_eq!(cache.begin_load(alice), None);
+    // Loaded before Bob was removed and Charlie added.
+    cache.apply(FriendshipUpdate::Removed(bob, alice));
+    cache.apply(FriendshipUpdate::New(alice, charlie));
+    let friends = cache.finish_load(alice, HashSet::from([bob]));
+    assert_eq!(friends, HashSet::from([charlie]));
+    assert_eq!(cache.begin_load(alice), Some(HashSet::from([charlie])));
+
+    // Cleared while loading.
+    assert_eq!(cache.begin_load(bob), None);
+    cache.clear();
+    cache.finish_load(bob, HashSet::new());
+    assert_eq!(cache.begin_load(bob), None);
+
+    // Alice is evicted for Bob.
+    assert_eq!(cache.begin_load(alice), None);
+    cache.finish_load(alice, HashSet::new());
+    cache.finish_load(bob, HashSet::new());
+    assert_eq!(cache.begin_load(bob), Some(HashSet::new()));
+    assert_eq!(cache.begin_load(alice), None);
+
+    let expiring = FriendCache::with_limits(1, Duration::ZERO);
+    expiring.set_listening(true);
+    expiring.begin_load(alice);
+    expiring.finish_load(alice, HashSet::new());
+    assert_eq!(expiring.begin_load(alice), None);
+}
+
+#[cfg(test)]
+#[test]
+fn blocks_test() {
+    let alice = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let bob = UserId::try_parse("21234567-1234-5678-1234-567812345678").unwrap();
+
+    let mut blocks = Blocks::new();
+    blocks.apply(BlockUpdate::Blocked(alice, bob));
+    blocks.apply(BlockUpdate::Blocked(bob, alice));
+    assert!(blocks.between(bob, alice));
+
+    // Bob is still blocked by Alice.
+    blocks.apply(BlockUpdate::Unblocked(bob, alice));
+    assert!(blocks.between(bob, alice));
+
+    blocks.apply(BlockUpdate::Unblocked(alice, bob));
+    assert!(!blocks.between(alice, bob));
+}
@@ -1,3 +1,14 @@
+pub mod auth;
+pub mod combinators;
+pub mod content;
+pub mod deliveries;
+pub mod conversations;
 pub mod messages;
+pub mod moderation;
 pub mod friendships;
+pub mod notifications;
+pub mod policy;
+pub mod presence;
+pub mod rate_limit;
+pub mod reactions;
 pub mod users;
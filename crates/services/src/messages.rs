@@ -1,9 +1,17 @@
 use std::ops::Deref;
 
-use models::users::Userlike;
+use models::users::{UserId, Userlike};
 use models::messages::{Messagelike, MessageId, Message};
-use realtime::senders::{PublishMessage, PublishSeenMessage};
-use repository::messages::{AddSeenTagRequest, InsertMessageRequest, RemoveSeenTagRequest};
+use tracing::instrument;
+use realtime::senders::{
+    PublishMessage, PublishSeenMessage, PublishSeenMessages, PublishUnseenMessage,
+};
+use repository::messages::{
+    AddSeenTagRequest, AddSeenTagsRequest, InsertMessageRequest, RemoveSeenTagRequest,
+};
+use repository::reactions::GetReactionSummaryRequest;
+
+use crate::moderation::{ModerationError, ModerationService};
 
 pub trait MessagelikeServices: Messagelike {
     fn seen_by(&self, user: impl Userlike) -> AddSeenTagRequest {
@@ -17,6 +25,24 @@ pub trait MessagelikeServices: Messagelike {
     fn realtime_seen_by(self, user: impl Userlike) -> PublishSeenMessage {
         PublishSeenMessage::new(self, user)
     }
+
+    fn realtime_unseen_by(self, user: impl Userlike) -> PublishUnseenMessage {
+        PublishUnseenMessage::new(self, user)
+    }
+
+    fn seen_by_many(&self, users: impl IntoIterator<Item = UserId>) -> AddSeenTagsRequest {
+        AddSeenTagsRequest::by_users(self.get_id(), users)
+    }
+
+    fn realtime_seen_by_many(self, users: impl IntoIterator<Item = UserId>) -> PublishSeenMessages {
+        let message = self.get_id();
+
+        PublishSeenMessages::new(users.into_iter().map(|u| (u, message)).collect())
+    }
+
+    fn reaction_summary(&self) -> GetReactionSummaryRequest {
+        GetReactionSummaryRequest::new(self.get_id())
+    }
 }
 
 impl<T: Messagelike> MessagelikeServices for T {}
@@ -43,10 +69,19 @@ impl MessageServices {
         Self (message)
     }
 
-    pub fn insert(&self) -> InsertMessageRequest {
-        InsertMessageRequest::new(self.user_id, self.content.clone())
+    /// The message is screened first, nothing is stored nor published if it is rejected.
+    #[instrument(name = "MessageServices::insert", skip_all, fields(message_id = %self.id))]
+    pub async fn insert(
+        &self,
+        moderation: &dyn ModerationService,
+    ) -> Result<InsertMessageRequest, ModerationError> {
+        moderation.screen(self).await?;
+
+        Ok(InsertMessageRequest::new(self.user_id, self.content.clone())
+            .with_body(self.body.clone())
             .with_datetime(self.date)
             .with_id(self.id)
+            .with_reply_to(self.reply_to))
     }
 
     pub fn realtime_publish(self) -> PublishMessage {
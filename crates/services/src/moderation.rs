@@ -0,0 +1,91 @@
+//! Screening of messages before they are stored and published.
+
+use async_trait::async_trait;
+use regex::{Regex, RegexBuilder};
+use thiserror::Error;
+
+use models::messages::Message;
+
+#[derive(Error, Debug)]
+pub enum ModerationError {
+    #[error("message rejected: {reason}")]
+    Rejected { reason: String },
+    #[error("invalid moderation pattern")]
+    Pattern(#[from] regex::Error),
+}
+
+/// Implement it to plug your own screening (external API, classifier…).
+#[async_trait]
+pub trait ModerationService: Send + Sync {
+    async fn screen(&self, message: &Message) -> Result<(), ModerationError>;
+}
+
+/// Accepts everything.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoModeration;
+
+#[async_trait]
+impl ModerationService for NoModeration {
+    async fn screen(&self, _message: &Message) -> Result<(), ModerationError> {
+        Ok(())
+    }
+}
+
+/// Rejects messages containing one of `words` (whole word, case insensitive) or matching one of
+/// `patterns`.
+#[derive(Clone, Debug, Default)]
+pub struct WordListModeration {
+    rules: Vec<Regex>,
+}
+
+impl WordListModeration {
+    pub fn new(
+        words: impl IntoIterator<Item = impl AsRef<str>>,
+        patterns: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Self, ModerationError> {
+        let words = words
+            .into_iter()
+            .map(|word| format!(r"\b{}\b", regex::escape(word.as_ref())));
+        let patterns = patterns.into_iter().map(|p| p.as_ref().to_string());
+
+        let rules = words
+            .chain(patterns)
+            .map(|rule| RegexBuilder::new(&rule).case_insensitive(true).build())
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { rules })
+    }
+}
+
+#[async_trait]
+impl ModerationService for WordListModeration {
+    async fn screen(&self, message: &Message) -> Result<(), ModerationError> {
+        match self
+            .rules
+            .iter()
+            .find(|rule| rule.is_match(&message.content))
+        {
+            Some(rule) => Err(ModerationError::Rejected {
+                reason: format!("matches `{}`", rule.as_str()),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn word_list_test() {
+    use models::users::UserId;
+
+    let moderation = WordListModeration::new(["spam"], [r"https?://\S+"]).unwrap();
+    let user = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let screen = |content: &str| {
+        futures::executor::block_on(moderation.screen(&Message::new(user, content.to_string())))
+    };
+
+    assert!(screen("Nice day today").is_ok());
+    assert!(screen("antispam filters").is_ok());
+    assert!(screen("Buy SPAM now").is_err());
+    assert!(screen("see http://example.com").is_err());
+}
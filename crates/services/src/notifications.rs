@@ -0,0 +1,493 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use futures::{
+    stream::{select, StreamExt, TryStreamExt},
+    Stream,
+};
+
+use models::{
+    conversations::{ConversationId, DirectMessage},
+    events::RecentEvents,
+    friendships::{BlockUpdate, FriendUpdate, FriendshipState, FriendshipUpdate},
+    messages::{Message, MessageId},
+    notifications::Notification,
+    users::{User, UserId, Userlike},
+};
+use realtime::senders::{PublishSystemNotice, SenderError};
+use realtime::{self, Client};
+use repository::{
+    messages::GetMentionsRequest,
+    users::{FriendshipAudit, GetFriendshipEventsRequest},
+    PgPool, RepositoryError, Session,
+};
+use tracing::instrument;
+use tracing_futures::Instrument;
+
+use crate::combinators::{deduplicated, StitchLive, RECENT_EVENTS};
+use crate::conversations::{ConversationServices, ConversationlikeServices};
+use crate::friendships::{Blocks, FriendCache};
+use crate::users::{UserIdServices, UserlikeServices};
+
+/// Raw realtime events, before being filtered for a user.
+enum Event {
+    Friend(FriendUpdate, bool),
+    Block(BlockUpdate),
+    Message(Message),
+    Seen(UserId, MessageId),
+    Unseen(UserId, MessageId),
+    Direct(DirectMessage),
+    FriendRequest(UserId),
+    FriendAccepted(UserId),
+    System(String),
+}
+
+#[derive(Clone)]
+pub struct NotificationServices(User);
+
+impl Userlike for NotificationServices {
+    fn get_id(&self) -> UserId {
+        self.id
+    }
+}
+
+impl Deref for NotificationServices {
+    type Target = User;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl NotificationServices {
+    pub fn new(user: User) -> Self {
+        Self(user)
+    }
+
+    /// Every notification of the user. The friend list comes from the `FriendCache` then is kept up
+    /// to date with the realtime friendship updates, blocks the same way from PostgreSQL, once
+    /// subscribed to their updates so that none is missed while they are loaded. Nothing
+    /// is sent about users blocked by the user or that blocked it. Members of conversations don't
+    /// change, whether the user is one is looked up once per conversation.
+    pub fn stream<'a>(
+        self,
+        pg: &'a PgPool,
+        session: &'a Session,
+        nats: Client,
+        cache: FriendCache,
+    ) -> impl Stream<Item = Result<Notification, Error>> + 'a {
+        let self_id = self.get_id();
+        let user = self.0.clone();
+        let recent = Arc::new(Mutex::new(RecentEvents::new(RECENT_EVENTS)));
+
+        let initial_friends = cache
+            .stream(self_id, pg)
+            .map_ok(|f| Event::Friend(FriendUpdate::New(f), false))
+            .map_err(Error::from);
+
+        let updates = realtime::receivers::friendships_updates_enveloped(nats.clone());
+        let updates = deduplicated(updates, recent.clone())
+            .try_filter_map(move |f| async move {
+                Ok(match f {
+                    FriendshipUpdate::New(user, friend) | FriendshipUpdate::New(friend, user)
+                        if user == self_id =>
+                    {
+                        Some(Event::Friend(FriendUpdate::New(friend), true))
+                    }
+                    FriendshipUpdate::Removed(user, friend)
+                    | FriendshipUpdate::Removed(friend, user)
+                        if user == self_id =>
+                    {
+                        Some(Event::Friend(FriendUpdate::Removed(friend), true))
+                    }
+                    _other => None,
+                })
+            })
+            .map_err(Error::from);
+
+        let initial_blocks = self
+            .get_blocks()
+            .stream(pg)
+            .map_ok(|(user, blocked)| Event::Block(BlockUpdate::Blocked(user, blocked)))
+            .map_err(Error::from);
+
+        let block_updates = realtime::receivers::blocks_updates_enveloped(nats.clone());
+        let block_updates = deduplicated(block_updates, recent.clone())
+            .try_filter(move |update| {
+                let (BlockUpdate::Blocked(user, blocked) | BlockUpdate::Unblocked(user, blocked)) =
+                    update;
+
+                futures::future::ready(*user == self_id || *blocked == self_id)
+            })
+            .map_ok(Event::Block)
+            .map_err(Error::from);
+
+        let friend_requests = realtime::receivers::friendship_events_enveloped(nats.clone());
+        let friend_requests = deduplicated(friend_requests, recent.clone())
+            .try_filter_map(move |event| async move {
+                Ok(match event.state {
+                    _ if event.target != self_id => None,
+                    FriendshipState::Pending => Some(Event::FriendRequest(event.initiator)),
+                    FriendshipState::Accepted => Some(Event::FriendAccepted(event.initiator)),
+                    FriendshipState::Declined | FriendshipState::Blocked => None,
+                })
+            })
+            .map_err(Error::from);
+
+        let notices = realtime::receivers::system_notices_enveloped(nats.clone());
+        let notices = deduplicated(notices, recent.clone())
+            .map_ok(Event::System)
+            .map_err(Error::from);
+
+        let messages = realtime::receivers::new_messages_enveloped(nats.clone());
+        let messages = deduplicated(messages, recent.clone())
+            .map_ok(Event::Message)
+            .map_err(Error::from);
+
+        // The tags of a batch share the same event, they are flattened once deduplicated.
+        let seen = realtime::receivers::seen_messages_enveloped(nats.clone());
+        let seen = deduplicated(seen, recent.clone())
+            .map_err(Error::from)
+            .map_ok(|tags| futures::stream::iter(tags.into_iter().map(Ok)))
+            .try_flatten()
+            .map_ok(|(by, message)| Event::Seen(by, message));
+
+        let unseen = realtime::receivers::unseen_messages_enveloped(nats.clone());
+        let unseen = deduplicated(unseen, recent.clone())
+            .map_ok(|(by, message)| Event::Unseen(by, message))
+            .map_err(Error::from);
+
+        let memberships = Arc::new(Mutex::new(HashMap::<ConversationId, bool>::new()));
+        let direct_messages = realtime::receivers::direct_messages_enveloped(nats);
+        let direct_messages = deduplicated(direct_messages, recent)
+            .map_err(Error::from)
+            .try_filter_map(move |message| {
+                let memberships = memberships.clone();
+
+                async move {
+                    let conversation = message.conversation_id;
+                    let known = memberships.lock().unwrap().get(&conversation).copied();
+
+                    let member = match known {
+                        Some(member) => member,
+                        None => {
+                            let member = match conversation.get().execute(session).await {
+                                Ok(conversation) => conversation.members.contains(&self_id),
+                                Err(RepositoryError::NotFound) => false,
+                                // Not cached, the next message of the conversation tries again.
+                                Err(e) => {
+                                    tracing::warn!(error = %e, %conversation, "Can't get the members");
+                                    return Ok(None);
+                                }
+                            };
+                            memberships.lock().unwrap().insert(conversation, member);
+
+                            member
+                        }
+                    };
+
+                    Ok(member.then_some(Event::Direct(message)))
+                }
+            });
+
+        let stream = select(
+            select(
+                select(
+                    select(initial_friends.chain(updates), friend_requests),
+                    notices,
+                ),
+                // Nothing is dropped, a block applied twice is a no-op.
+                StitchLive::new(initial_blocks, block_updates, |_| None::<()>),
+            ),
+            select(select(messages, direct_messages), select(seen, unseen)),
+        );
+        let mut blocks = Blocks::new();
+
+        stream
+            .scan(HashSet::<UserId>::new(), move |friends, event| {
+                let res = match event {
+                    Ok(Event::Friend(FriendUpdate::New(friend), notify)) => {
+                        friends.insert(friend);
+                        notify.then_some(Ok(Notification::NewFriend(friend)))
+                    }
+                    Ok(Event::Friend(FriendUpdate::Removed(friend), notify)) => {
+                        friends.remove(&friend);
+                        notify.then_some(Ok(Notification::FriendRemoved(friend)))
+                    }
+                    Ok(Event::Block(update)) => {
+                        blocks.apply(update);
+                        None
+                    }
+                    Ok(Event::Message(message)) if message.user_id == self_id => None,
+                    Ok(Event::Message(message)) if blocks.between(self_id, message.user_id) => None,
+                    Ok(Event::Message(message)) if message.mentions_user(&user) => {
+                        Some(Ok(Notification::Mention(message)))
+                    }
+                    Ok(Event::Message(message)) if friends.contains(&message.user_id) => {
+                        Some(Ok(Notification::NewMessage(message)))
+                    }
+                    Ok(Event::Message(_)) => None,
+                    Ok(Event::Seen(by, message))
+                        if message.user_id() == self_id
+                            && by != self_id
+                            && !blocks.between(self_id, by) =>
+                    {
+                        Some(Ok(Notification::MessageSeen { message, by }))
+                    }
+                    Ok(Event::Seen(..)) => None,
+                    Ok(Event::Unseen(by, message))
+                        if message.user_id() == self_id
+                            && by != self_id
+                            && !blocks.between(self_id, by) =>
+                    {
+                        Some(Ok(Notification::MessageUnseen { message, by }))
+                    }
+                    Ok(Event::Unseen(..)) => None,
+                    Ok(Event::Direct(message))
+                        if message.message.user_id == self_id
+                            || blocks.between(self_id, message.message.user_id) =>
+                    {
+                        None
+                    }
+                    Ok(Event::Direct(message)) => Some(Ok(Notification::DirectMessage(message))),
+                    Ok(Event::FriendRequest(requester)) if blocks.between(self_id, requester) => {
+                        None
+                    }
+                    Ok(Event::FriendRequest(requester)) => {
+                        Some(Ok(Notification::FriendRequest(requester)))
+                    }
+                    Ok(Event::FriendAccepted(friend)) if blocks.between(self_id, friend) => None,
+                    Ok(Event::FriendAccepted(friend)) => {
+                        Some(Ok(Notification::FriendAccepted(friend)))
+                    }
+                    Ok(Event::System(notice)) => Some(Ok(Notification::System(notice))),
+                    Err(e) => Some(Err(e)),
+                };
+
+                async { Some(res) } // https://users.rust-lang.org/t/lifetime-confusing-on-futures-scan/42204
+            })
+            .filter_map(|e| async { e })
+            .instrument(tracing::info_span!("NotificationServices::stream", user_id = %self_id))
+    }
+
+    /// Like `stream`, for a client that already received the timeline up to `after`: messages of
+    /// friends posted since are sent first, oldest first, then live notifications without
+    /// duplicates.
+    pub fn stream_after<'a>(
+        self,
+        after: MessageId,
+        pg: &'a PgPool,
+        session: &'a Session,
+        nats: Client,
+        cache: FriendCache,
+    ) -> impl Stream<Item = Result<Notification, Error>> + 'a {
+        let user = UserIdServices::new(self.get_id());
+        let live = self.stream(pg, session, nats, cache);
+
+        let backfill = futures::stream::once(async move {
+            let mut missed: Vec<Message> = user
+                .get_timeline(pg, session)
+                .await
+                .try_take_while(|message| {
+                    futures::future::ok(message.id.timestamp() > after.timestamp())
+                })
+                .try_collect()
+                .await?;
+            missed.reverse();
+
+            Ok::<_, Error>(futures::stream::iter(missed).map(|m| Ok(Notification::NewMessage(m))))
+        })
+        .try_flatten();
+
+        StitchLive::new(backfill, live, backfill_key)
+    }
+
+    /// Like `stream`, for a client that was last notified at `since`: what it missed is sent first,
+    /// oldest first, then live notifications. Friend requests, their acceptances and friendship
+    /// changes come from the audit rows, messages of friends, mentions and direct messages from
+    /// ScyllaDB. Read tags have no date, only the messages of the user posted since are told as
+    /// seen. Removed tags, system notices and presences are not stored, they are not sent again.
+    /// Blocked users are filtered out like live. Messages and seen tags are deduplicated, a
+    /// friendship changed while the backfill runs may be sent twice.
+    pub fn stream_since<'a>(
+        self,
+        since: DateTime<Utc>,
+        pg: &'a PgPool,
+        session: &'a Session,
+        nats: Client,
+        cache: FriendCache,
+    ) -> impl Stream<Item = Result<Notification, Error>> + 'a {
+        let user = self.0.clone();
+        let live = self.stream(pg, session, nats, cache);
+
+        let backfill = futures::stream::once(missed_since(user, since, pg, session))
+            .map_ok(|missed| futures::stream::iter(missed).map(Ok))
+            .try_flatten();
+
+        StitchLive::new(backfill, live, backfill_key)
+    }
+}
+
+/// Of `stream_since`, oldest first.
+#[instrument(name = "NotificationServices::stream_since", skip_all, fields(user_id = %user.id))]
+async fn missed_since(
+    user: User,
+    since: DateTime<Utc>,
+    pg: &PgPool,
+    session: &Session,
+) -> Result<Vec<Notification>, Error> {
+    let self_id = user.id;
+    let services = UserIdServices::new(self_id);
+    let blocks = services
+        .get_blocks()
+        .stream(pg)
+        .try_fold(Blocks::new(), |mut blocks, (user, blocked)| {
+            blocks.apply(BlockUpdate::Blocked(user, blocked));
+            futures::future::ok(blocks)
+        })
+        .await?;
+    let mut missed: Vec<(DateTime<Utc>, Notification)> = Vec::new();
+
+    let audits: Vec<(FriendshipAudit, DateTime<Utc>)> =
+        GetFriendshipEventsRequest::new(self_id, since)
+            .stream(pg)
+            .try_collect()
+            .await?;
+    for (audit, date) in audits {
+        let notifications = match audit {
+            FriendshipAudit::Event(event) if blocks.between(event.initiator, event.target) => {
+                vec![]
+            }
+            FriendshipAudit::Event(event) => match event.state {
+                FriendshipState::Pending if event.target == self_id => {
+                    vec![Notification::FriendRequest(event.initiator)]
+                }
+                FriendshipState::Accepted if event.target == self_id => vec![
+                    Notification::FriendAccepted(event.initiator),
+                    Notification::NewFriend(event.initiator),
+                ],
+                FriendshipState::Accepted => vec![Notification::NewFriend(event.target)],
+                _ => vec![],
+            },
+            FriendshipAudit::Removed(a, b) => {
+                vec![Notification::FriendRemoved(if a == self_id {
+                    b
+                } else {
+                    a
+                })]
+            }
+        };
+        missed.extend(notifications.into_iter().map(|n| (date, n)));
+    }
+
+    // Like live, a message of a friend that mentions the user is a mention.
+    let messages: Vec<Message> = services
+        .get_timeline(pg, session)
+        .await
+        .try_take_while(|message| futures::future::ok(message.date > since))
+        .try_collect()
+        .await?;
+    let mentions: Vec<Message> = GetMentionsRequest::new(user.name.as_str(), since)
+        .stream(session)
+        .try_collect()
+        .await?;
+    let mut sent = HashSet::new();
+    for message in messages.into_iter().chain(mentions) {
+        if message.user_id == self_id
+            || blocks.between(self_id, message.user_id)
+            || !sent.insert(message.id)
+        {
+            continue;
+        }
+
+        let date = message.date;
+        let notification = match message.mentions_user(&user) {
+            true => Notification::Mention(message),
+            false => Notification::NewMessage(message),
+        };
+        missed.push((date, notification));
+    }
+
+    let own: Vec<Message> = services
+        .get_messages()
+        .between(since, Utc::now())
+        .stream(session)
+        .try_collect()
+        .await?;
+    if !own.is_empty() {
+        let friends: Vec<UserId> = services.get_friends().stream(pg).try_collect().await?;
+
+        for friend in friends {
+            if blocks.between(self_id, friend) {
+                continue;
+            }
+
+            let tags = UserIdServices::new(friend)
+                .get_read_tags()
+                .execute(session)
+                .await?;
+            missed.extend(own.iter().filter(|m| tags.contains(&m.id)).map(|m| {
+                let seen = Notification::MessageSeen {
+                    message: m.id,
+                    by: friend,
+                };
+
+                (m.date, seen)
+            }));
+        }
+    }
+
+    let conversations: Vec<ConversationId> = ConversationServices::of_user(self_id)
+        .stream(session)
+        .try_collect()
+        .await?;
+    for conversation in conversations {
+        let messages: Vec<DirectMessage> = conversation
+            .get_messages()
+            .stream(session)
+            .try_take_while(|message| futures::future::ok(message.message.date > since))
+            .try_collect()
+            .await?;
+
+        missed.extend(
+            messages
+                .into_iter()
+                .filter(|message| {
+                    let sender = message.message.user_id;
+
+                    sender != self_id && !blocks.between(self_id, sender)
+                })
+                .map(|message| (message.message.date, Notification::DirectMessage(message))),
+        );
+    }
+
+    missed.sort_by_key(|(date, _)| *date);
+
+    Ok(missed.into_iter().map(|(_, n)| n).collect())
+}
+
+/// Publishes `text` to every user connected to a notification stream.
+pub async fn publish_system_notice(text: String, nats: Client) -> Result<(), SenderError> {
+    PublishSystemNotice::new(text).publish(nats).await
+}
+
+/// Of the notifications that can be both in a backfill and live.
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum BackfillKey {
+    Message(MessageId),
+    Seen(MessageId, UserId),
+}
+
+fn backfill_key(notification: &Notification) -> Option<BackfillKey> {
+    match notification {
+        Notification::NewMessage(message) | Notification::Mention(message) => {
+            Some(BackfillKey::Message(message.id))
+        }
+        Notification::DirectMessage(message) => Some(BackfillKey::Message(message.message.id)),
+        Notification::MessageSeen { message, by } => Some(BackfillKey::Seen(*message, *by)),
+        _ => None,
+    }
+}
@@ -0,0 +1,220 @@
+//! Timeout, retries and circuit breaking around repository requests, so that they are configured
+//! once instead of at each call.
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use repository::RepositoryError;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PolicySettings {
+    /// Of each attempt.
+    pub timeout: Duration,
+    pub retries: u32,
+    /// Waited before the first retry, doubled for each following one.
+    pub backoff: Duration,
+    /// Consecutive failures opening the circuit.
+    pub failure_threshold: u32,
+    pub open_for: Duration,
+}
+
+#[derive(Debug, Default)]
+struct CircuitBreaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn allows(&self, now: Instant) -> bool {
+        !matches!(self.open_until, Some(until) if now < until)
+    }
+
+    /// Once open, a single failure is enough to reopen it until a request succeeds.
+    fn record(&mut self, success: bool, settings: &PolicySettings, now: Instant) {
+        if success {
+            self.consecutive_failures = 0;
+            self.open_until = None;
+            return;
+        }
+
+        self.consecutive_failures += 1;
+        if self.consecutive_failures >= settings.failure_threshold {
+            self.open_until = Some(now + settings.open_for);
+        }
+    }
+}
+
+/// Without settings, requests are run as is. Can be shared between threads by using `Clone`,
+/// clones share the same circuit breaker.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    settings: Option<PolicySettings>,
+    breaker: Arc<Mutex<CircuitBreaker>>,
+}
+
+impl Policy {
+    pub fn new(settings: PolicySettings) -> Self {
+        Self {
+            settings: Some(settings),
+            breaker: Arc::default(),
+        }
+    }
+
+    /// Runs as is.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    fn check_circuit(&self) -> Result<(), RepositoryError> {
+        match self.breaker.lock().unwrap().allows(Instant::now()) {
+            true => Ok(()),
+            false => Err(RepositoryError::Unavailable),
+        }
+    }
+
+    fn record<T>(&self, settings: &PolicySettings, res: &Result<T, RepositoryError>) {
+        let success = !matches!(res, Err(e) if e.is_transient());
+
+        self.breaker
+            .lock()
+            .unwrap()
+            .record(success, settings, Instant::now());
+    }
+
+    /// Of the writes that can't be retried, eg. an insert that conflicts with itself once applied:
+    /// timed out and circuit broken, but run once.
+    pub async fn execute_once<T, Fut>(&self, request: Fut) -> Result<T, RepositoryError>
+    where
+        Fut: Future<Output = Result<T, RepositoryError>>,
+    {
+        let Some(settings) = self.settings else {
+            return request.await;
+        };
+
+        self.check_circuit()?;
+        let res = match tokio::time::timeout(settings.timeout, request).await {
+            Ok(res) => res,
+            Err(_elapsed) => Err(RepositoryError::Timeout),
+        };
+        self.record(&settings, &res);
+
+        res
+    }
+
+    /// `request` builds a new attempt, eg. `|| request.clone().execute(pg)`. Only for the
+    /// requests that can be applied twice with the same result, reads and upserts: an attempt that
+    /// timed out or lost its connection may still have been applied.
+    pub async fn execute<T, F, Fut>(&self, mut request: F) -> Result<T, RepositoryError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, RepositoryError>>,
+    {
+        let Some(settings) = self.settings else {
+            return request().await;
+        };
+
+        let mut backoff = settings.backoff;
+
+        for attempt in 0..=settings.retries {
+            self.check_circuit()?;
+
+            let res = match tokio::time::timeout(settings.timeout, request()).await {
+                Ok(res) => res,
+                Err(_elapsed) => Err(RepositoryError::Timeout),
+            };
+            self.record(&settings, &res);
+
+            match res {
+                Err(e) if e.is_transient() && attempt < settings.retries => {
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                res => return res,
+            }
+        }
+
+        unreachable!("the last attempt always returns")
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn circuit_breaker_test() {
+    let settings = PolicySettings {
+        timeout: Duration::from_secs(1),
+        retries: 0,
+        backoff: Duration::ZERO,
+        failure_threshold: 2,
+        open_for: Duration::from_secs(10),
+    };
+    let now = Instant::now();
+    let mut breaker = CircuitBreaker::default();
+
+    breaker.record(false, &settings, now);
+    assert!(breaker.allows(now));
+    breaker.record(false, &settings, now);
+    assert!(!breaker.allows(now + Duration::from_secs(5)));
+
+    // Half open: one more failure reopens it, a success closes it.
+    let later = now + Duration::from_secs(10);
+    assert!(breaker.allows(later));
+    breaker.record(false, &settings, later);
+    assert!(!breaker.allows(later));
+    breaker.record(true, &settings, later);
+    assert!(breaker.allows(later));
+}
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn policy_retry_test() {
+    let policy = Policy::new(PolicySettings {
+        timeout: Duration::from_secs(1),
+        retries: 2,
+        backoff: Duration::from_millis(100),
+        failure_threshold: 10,
+        open_for: Duration::from_secs(10),
+    });
+
+    let attempts = std::cell::Cell::new(0);
+    let res = policy
+        .execute(|| {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+
+            async move {
+                match attempt {
+                    1 => Err(RepositoryError::Timeout),
+                    _ => Ok(attempt),
+                }
+            }
+        })
+        .await;
+    assert_eq!(res.unwrap(), 2);
+
+    // Writes are never retried.
+    let res: Result<(), _> = policy
+        .execute_once(async {
+            attempts.set(attempts.get() + 1);
+            Err(RepositoryError::Timeout)
+        })
+        .await;
+    assert!(matches!(res, Err(RepositoryError::Timeout)));
+    assert_eq!(attempts.get(), 3);
+
+    // Not transient, not retried.
+    let res: Result<(), _> = policy
+        .execute(|| async { Err(RepositoryError::NotFound) })
+        .await;
+    assert!(matches!(res, Err(RepositoryError::NotFound)));
+
+    let res: Result<(), _> = policy
+        .execute(|| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        })
+        .await;
+    assert!(matches!(res, Err(RepositoryError::Timeout)));
+}
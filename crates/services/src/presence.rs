@@ -0,0 +1,367 @@
+//! Who is online. Heartbeats are kept in memory, shared between servers through NATS, and
+//! persisted as `last_seen_at` so that offline users still have a last seen date.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures::{
+    stream::{select, StreamExt},
+    Future, Stream, TryStreamExt,
+};
+use tracing::instrument;
+use tracing_futures::Instrument;
+
+use models::{
+    events::RecentEvents,
+    users::{Presence, UserId, Userlike},
+};
+use realtime::{self, senders::PublishPresence, Client};
+use repository::{
+    users::{GetLastSeenRequest, UpdateLastSeenRequest},
+    PgPool, RepositoryError,
+};
+use task_manager::TaskManager;
+
+use crate::combinators::{deduplicated, RECENT_EVENTS};
+use crate::friendships::FriendCache;
+
+/// Last heartbeat of each user seen by this server. Can be shared between threads by using
+/// `Clone`.
+#[derive(Clone, Debug, Default)]
+pub struct PresenceServices {
+    heartbeats: Arc<RwLock<HashMap<UserId, DateTime<Utc>>>>,
+}
+
+impl PresenceServices {
+    /// A user is online while its last heartbeat is more recent than this.
+    pub const ONLINE_TIMEOUT_SECS: i64 = 60;
+    /// How often `friends_presence_updates` looks for friends going offline.
+    pub const OFFLINE_CHECK_SECS: u64 = 10;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a heartbeat of the user, then persists and publishes it in the `TaskManager` so
+    /// that a client disconnecting does not cancel it.
+    pub fn heartbeat(
+        &self,
+        user: impl Userlike,
+        pg: PgPool,
+        nats: Client,
+        task_manager: &TaskManager,
+    ) -> impl Future<Output = Result<(), RepositoryError>> {
+        let user = user.get_id();
+        let now = Utc::now();
+        let span = tracing::info_span!("PresenceServices::heartbeat", user_id = %user);
+
+        self.record(user, now);
+
+        task_manager.spawn_await_result(
+            async move {
+                UpdateLastSeenRequest::new(user, now).execute(&pg).await?;
+
+                let presence = Presence {
+                    user_id: user,
+                    online: true,
+                    last_seen_at: Some(now),
+                };
+                let _ = PublishPresence::new(presence).publish(nats).await;
+
+                Ok(())
+            }
+            .instrument(span),
+        )
+    }
+
+    /// Presence of each known user of `users`, unknown users are left out.
+    #[instrument(name = "PresenceServices::get_presence", skip_all)]
+    pub async fn get_presence(
+        &self,
+        users: impl IntoIterator<Item = impl Userlike>,
+        pg: &PgPool,
+    ) -> Result<Vec<Presence>, RepositoryError> {
+        let now = Utc::now();
+
+        GetLastSeenRequest::new(users)
+            .stream(pg)
+            .map_ok(|(user, last_seen_at)| self.presence_at(user, last_seen_at, now))
+            .try_collect()
+            .await
+    }
+
+    pub async fn get_friends_presence(
+        &self,
+        user: impl Userlike,
+        pg: &PgPool,
+        friend_cache: &FriendCache,
+    ) -> Result<Vec<Presence>, RepositoryError> {
+        let friends = friend_cache.get(user, pg).await?;
+
+        self.get_presence(friends, pg).await
+    }
+
+    /// Presence of the users of `users` that are friends of `user`, or `user` itself. Others are
+    /// left out: presence is only visible by friends.
+    pub async fn get_presence_of_friends(
+        &self,
+        user: impl Userlike,
+        users: impl IntoIterator<Item = impl Userlike>,
+        pg: &PgPool,
+        friend_cache: &FriendCache,
+    ) -> Result<Vec<Presence>, RepositoryError> {
+        let user = user.get_id();
+        let friends = friend_cache.get(user, pg).await?;
+
+        let users = users
+            .into_iter()
+            .map(|u| u.get_id())
+            .filter(|u| *u == user || friends.contains(u))
+            .collect::<Vec<_>>();
+
+        self.get_presence(users, pg).await
+    }
+
+    /// Presence of the friends of `user`, then their changes: friends coming online and friends
+    /// timing out. Heartbeats of friends already online are not repeated.
+    pub fn friends_presence_updates<'a>(
+        self,
+        user: impl Userlike + 'a,
+        pg: &'a PgPool,
+        nats: Client,
+        friend_cache: FriendCache,
+    ) -> impl Stream<Item = Result<Presence, RepositoryError>> + 'a {
+        enum Event {
+            Heartbeat(Presence),
+            Check,
+        }
+
+        struct State<E> {
+            events: E,
+            online: HashMap<UserId, DateTime<Utc>>,
+            pending: VecDeque<Presence>,
+        }
+
+        let user = user.get_id();
+
+        let recent = Arc::new(Mutex::new(RecentEvents::new(RECENT_EVENTS)));
+        let heartbeats = realtime::receivers::presences_enveloped(nats);
+        let heartbeats = deduplicated(heartbeats, recent)
+            .filter_map(|presence| futures::future::ready(presence.ok().map(Event::Heartbeat)));
+        let checks = futures::stream::unfold(
+            tokio::time::interval(Duration::from_secs(Self::OFFLINE_CHECK_SECS)),
+            |mut interval| async move {
+                interval.tick().await;
+                Some((Event::Check, interval))
+            },
+        );
+        let events = Box::pin(select(heartbeats, checks));
+
+        let initial = async move {
+            let friends = self.get_friends_presence(user, pg, &friend_cache).await?;
+            let state = State {
+                events,
+                online: online_since(&friends),
+                pending: friends.into(),
+            };
+
+            Ok::<_, RepositoryError>((self, friend_cache, state))
+        };
+
+        futures::stream::once(initial)
+            .map_ok(move |(presence, friend_cache, state)| {
+                futures::stream::unfold(state, move |mut state| {
+                    let (presence, friend_cache) = (presence.clone(), friend_cache.clone());
+
+                    async move {
+                        loop {
+                            if let Some(update) = state.pending.pop_front() {
+                                return Some((Ok(update), state));
+                            }
+
+                            match state.events.next().await? {
+                                Event::Heartbeat(heartbeat) => {
+                                    match friend_cache.get(user, pg).await {
+                                        Ok(friends) if friends.contains(&heartbeat.user_id) => {
+                                            state
+                                                .pending
+                                                .extend(came_online(&mut state.online, heartbeat));
+                                        }
+                                        Ok(_) => {}
+                                        Err(e) => return Some((Err(e), state)),
+                                    }
+                                }
+                                Event::Check => {
+                                    let now = Utc::now();
+                                    state
+                                        .pending
+                                        .extend(presence.went_offline(&mut state.online, now));
+                                }
+                            }
+                        }
+                    }
+                })
+            })
+            .try_flatten()
+            .instrument(
+                tracing::info_span!("PresenceServices::friends_presence_updates", user_id = %user),
+            )
+    }
+
+    /// Friends of `online` that timed out, removed from it.
+    fn went_offline(
+        &self,
+        online: &mut HashMap<UserId, DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Vec<Presence> {
+        let offline: Vec<Presence> = online
+            .iter()
+            .map(|(user, seen_at)| self.presence_at(*user, Some(*seen_at), now))
+            .filter(|presence| !presence.online)
+            .collect();
+
+        for presence in &offline {
+            online.remove(&presence.user_id);
+        }
+
+        offline
+    }
+
+    fn record(&self, user: UserId, seen_at: DateTime<Utc>) {
+        let mut heartbeats = self.heartbeats.write().unwrap();
+        let last = heartbeats.entry(user).or_insert(seen_at);
+
+        *last = seen_at.max(*last);
+    }
+
+    /// The persisted date can be late on the heartbeats: it is written after them.
+    fn presence_at(
+        &self,
+        user: UserId,
+        last_seen_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Presence {
+        let heartbeat = self.heartbeats.read().unwrap().get(&user).copied();
+        let last_seen_at = heartbeat.max(last_seen_at);
+        let timeout = chrono::Duration::seconds(Self::ONLINE_TIMEOUT_SECS);
+
+        Presence {
+            user_id: user,
+            online: last_seen_at.is_some_and(|at| now - at < timeout),
+            last_seen_at,
+        }
+    }
+
+    /// Records heartbeats received by other servers until the NATS subscription ends, to be
+    /// spawned once.
+    #[instrument(name = "PresenceServices::listen", skip_all)]
+    pub async fn listen(self, nats: Client) {
+        let presences = realtime::receivers::presences_enveloped(nats);
+        let recent = Arc::new(Mutex::new(RecentEvents::new(RECENT_EVENTS)));
+        let mut presences = Box::pin(deduplicated(presences, recent));
+
+        while let Some(presence) = presences.next().await {
+            if let Ok(Presence {
+                user_id,
+                last_seen_at: Some(seen_at),
+                ..
+            }) = presence
+            {
+                self.record(user_id, seen_at);
+            }
+        }
+    }
+}
+
+fn online_since(presences: &[Presence]) -> HashMap<UserId, DateTime<Utc>> {
+    presences
+        .iter()
+        .filter(|presence| presence.online)
+        .filter_map(|presence| Some((presence.user_id, presence.last_seen_at?)))
+        .collect()
+}
+
+/// `heartbeat` if its user was not online yet.
+fn came_online(
+    online: &mut HashMap<UserId, DateTime<Utc>>,
+    heartbeat: Presence,
+) -> Option<Presence> {
+    let seen_at = heartbeat.last_seen_at?;
+
+    match online.insert(heartbeat.user_id, seen_at) {
+        Some(_) => None,
+        None => Some(heartbeat),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn presence_timeout_test() {
+    let alice = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let bob = UserId::try_parse("21234567-1234-5678-1234-567812345678").unwrap();
+
+    let now = DateTime::parse_from_rfc3339("2023-11-14T12:00:00Z")
+        .unwrap()
+        .to_utc();
+    let minutes_ago = |minutes| now - chrono::Duration::minutes(minutes);
+
+    let presence = PresenceServices::new();
+    presence.record(alice, minutes_ago(5));
+    presence.record(alice, minutes_ago(10));
+
+    // Older heartbeats do not move the last seen date backward.
+    let alice_presence = presence.presence_at(alice, None, now);
+    assert!(!alice_presence.online);
+    assert_eq!(alice_presence.last_seen_at, Some(minutes_ago(5)));
+
+    presence.record(alice, now);
+    assert!(
+        presence
+            .presence_at(alice, Some(minutes_ago(5)), now)
+            .online
+    );
+
+    // Persisted only, from before this server started.
+    let bob_presence = presence.presence_at(bob, Some(minutes_ago(30)), now);
+    assert!(!bob_presence.online);
+    assert_eq!(bob_presence.last_seen_at, Some(minutes_ago(30)));
+
+    assert!(!presence.presence_at(bob, None, now).online);
+}
+
+#[cfg(test)]
+#[test]
+fn presence_updates_test() {
+    let alice = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+    let bob = UserId::try_parse("21234567-1234-5678-1234-567812345678").unwrap();
+
+    let now = DateTime::parse_from_rfc3339("2023-11-14T12:00:00Z")
+        .unwrap()
+        .to_utc();
+    let heartbeat = |user, seen_at| Presence {
+        user_id: user,
+        online: true,
+        last_seen_at: Some(seen_at),
+    };
+
+    let presence = PresenceServices::new();
+    let mut online = online_since(&[heartbeat(alice, now), presence.presence_at(bob, None, now)]);
+    assert_eq!(online.len(), 1);
+
+    // Already online.
+    assert!(came_online(&mut online, heartbeat(alice, now)).is_none());
+    assert!(came_online(&mut online, heartbeat(bob, now)).is_some());
+
+    let later = now + chrono::Duration::seconds(PresenceServices::ONLINE_TIMEOUT_SECS);
+    presence.record(bob, later);
+
+    let offline = presence.went_offline(&mut online, later);
+    assert_eq!(offline.len(), 1);
+    assert_eq!(offline[0].user_id, alice);
+    assert!(!offline[0].online);
+    assert_eq!(online.keys().collect::<Vec<_>>(), [&bob]);
+}
@@ -0,0 +1,245 @@
+//! Per-user token buckets, used to limit how fast a user can post.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use async_nats::jetstream::kv::{Operation, Store};
+use async_trait::async_trait;
+use thiserror::Error;
+use tracing::instrument;
+
+use models::users::{UserId, Userlike};
+
+#[derive(Error, Debug)]
+pub enum RateLimitError {
+    #[error("rate limited, retry in {}ms", retry_after.as_millis())]
+    RateLimited { retry_after: Duration },
+    #[error("rate limit backend error")]
+    Backend(#[source] async_nats::Error),
+}
+
+/// `burst` tokens at most, refilled at `per_minute` tokens per minute.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimitPolicy {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl RateLimitPolicy {
+    fn refill_per_ms(self) -> f64 {
+        self.per_minute as f64 / 60_000.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TokenBucket {
+    tokens: f64,
+    /// Milli-seconds since epoch of the last refill.
+    updated_at: i64,
+}
+
+impl TokenBucket {
+    pub fn full(policy: RateLimitPolicy, now: i64) -> Self {
+        Self {
+            tokens: policy.burst as f64,
+            updated_at: now,
+        }
+    }
+
+    /// Refills then takes one token. When empty, returns how long to wait for the next one.
+    pub fn take(&mut self, policy: RateLimitPolicy, now: i64) -> Result<(), Duration> {
+        let elapsed = (now - self.updated_at).max(0) as f64;
+        self.tokens = (self.tokens + elapsed * policy.refill_per_ms()).min(policy.burst as f64);
+        self.updated_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let missing_ms = (1.0 - self.tokens) / policy.refill_per_ms();
+        Err(Duration::from_millis(missing_ms.ceil() as u64))
+    }
+
+    fn encode(self) -> String {
+        format!("{} {}", self.tokens, self.updated_at)
+    }
+
+    fn decode(value: &[u8]) -> Option<Self> {
+        let value = std::str::from_utf8(value).ok()?;
+        let (tokens, updated_at) = value.split_once(' ')?;
+
+        Some(Self {
+            tokens: tokens.parse().ok()?,
+            updated_at: updated_at.parse().ok()?,
+        })
+    }
+}
+
+/// Where the buckets are stored. In memory buckets are per server, a shared backend is needed to
+/// limit users across several servers.
+#[async_trait]
+pub trait RateLimitBackend: Send + Sync {
+    async fn take(&self, user: UserId, policy: RateLimitPolicy) -> Result<(), RateLimitError>;
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryBackend {
+    buckets: Mutex<HashMap<UserId, TokenBucket>>,
+}
+
+#[async_trait]
+impl RateLimitBackend for InMemoryBackend {
+    async fn take(&self, user: UserId, policy: RateLimitPolicy) -> Result<(), RateLimitError> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        buckets
+            .entry(user)
+            .or_insert_with(|| TokenBucket::full(policy, now))
+            .take(policy, now)
+            .map_err(|retry_after| RateLimitError::RateLimited { retry_after })
+    }
+}
+
+/// Buckets stored in a NATS key-value bucket, updated with optimistic concurrency.
+pub struct NatsKvBackend {
+    store: Store,
+}
+
+impl NatsKvBackend {
+    /// Concurrent updates of the same bucket are retried this many times.
+    const MAX_ATTEMPTS: usize = 5;
+
+    pub fn new(store: Store) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl RateLimitBackend for NatsKvBackend {
+    async fn take(&self, user: UserId, policy: RateLimitPolicy) -> Result<(), RateLimitError> {
+        let key = user.to_string();
+        let mut last_error = None;
+
+        for _ in 0..Self::MAX_ATTEMPTS {
+            let now = chrono::Utc::now().timestamp_millis();
+            let entry = self
+                .store
+                .entry(key.as_str())
+                .await
+                .map_err(RateLimitError::Backend)?;
+
+            // Revision 0 only succeeds if the key does not exist yet.
+            let (mut bucket, revision) = match entry {
+                Some(entry) if entry.operation == Operation::Put => (
+                    TokenBucket::decode(&entry.value)
+                        .unwrap_or_else(|| TokenBucket::full(policy, now)),
+                    entry.revision,
+                ),
+                Some(entry) => (TokenBucket::full(policy, now), entry.revision),
+                None => (TokenBucket::full(policy, now), 0),
+            };
+
+            let taken = bucket.take(policy, now);
+
+            match self
+                .store
+                .update(key.as_str(), bucket.encode().into(), revision)
+                .await
+            {
+                Ok(_) => {
+                    return taken.map_err(|retry_after| RateLimitError::RateLimited { retry_after })
+                }
+                // Someone else updated the bucket in between.
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(RateLimitError::Backend(last_error.unwrap()))
+    }
+}
+
+/// Can be shared between threads by using `Clone`. The policy is shared by the clones.
+#[derive(Clone)]
+pub struct RateLimiter {
+    policy: Arc<RwLock<RateLimitPolicy>>,
+    backend: Arc<dyn RateLimitBackend>,
+}
+
+impl RateLimiter {
+    pub fn new(policy: RateLimitPolicy, backend: impl RateLimitBackend + 'static) -> Self {
+        Self {
+            policy: Arc::new(RwLock::new(policy)),
+            backend: Arc::new(backend),
+        }
+    }
+
+    /// Applies to the next checks, the tokens left in the buckets are kept.
+    pub fn set_policy(&self, policy: RateLimitPolicy) {
+        *self.policy.write().unwrap() = policy;
+    }
+
+    pub fn in_memory(policy: RateLimitPolicy) -> Self {
+        Self::new(policy, InMemoryBackend::default())
+    }
+
+    pub fn nats_kv(policy: RateLimitPolicy, store: Store) -> Self {
+        Self::new(policy, NatsKvBackend::new(store))
+    }
+
+    /// Consumes one token of the user, fails with `RateLimitError::RateLimited` when there is none.
+    #[instrument(name = "RateLimiter::check", skip_all, fields(user_id = %user.get_id()))]
+    pub async fn check(&self, user: impl Userlike) -> Result<(), RateLimitError> {
+        let policy = *self.policy.read().unwrap();
+
+        self.backend.take(user.get_id(), policy).await
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn token_bucket_test() {
+    let policy = RateLimitPolicy {
+        burst: 2,
+        per_minute: 60,
+    };
+    let mut bucket = TokenBucket::full(policy, 0);
+
+    assert!(bucket.take(policy, 0).is_ok());
+    assert!(bucket.take(policy, 0).is_ok());
+    assert_eq!(bucket.take(policy, 0), Err(Duration::from_millis(1000)));
+
+    // One token per second.
+    assert!(bucket.take(policy, 1_500).is_ok());
+    assert_eq!(bucket.take(policy, 1_500), Err(Duration::from_millis(500)));
+
+    assert_eq!(
+        TokenBucket::decode(bucket.encode().as_bytes()),
+        Some(bucket)
+    );
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn set_policy_test() {
+    let limiter = RateLimiter::in_memory(RateLimitPolicy {
+        burst: 1,
+        per_minute: 1,
+    });
+    let user = UserId::try_parse("11234567-1234-5678-1234-567812345678").unwrap();
+
+    assert!(limiter.check(user).await.is_ok());
+    assert!(limiter.check(user).await.is_err());
+
+    // A token per millisecond.
+    limiter.set_policy(RateLimitPolicy {
+        burst: 1,
+        per_minute: 60_000,
+    });
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    assert!(limiter.check(user).await.is_ok());
+}
@@ -0,0 +1,63 @@
+use std::ops::Deref;
+
+use models::reactions::{Reaction, ReactionUpdate};
+use models::users::Userlike;
+use realtime::senders::PublishReactionUpdate;
+use repository::reactions::{AddReactionRequest, RemoveReactionRequest};
+use repository::{PgPool, RepositoryError};
+use tracing::instrument;
+
+use crate::friendships::FriendCache;
+
+#[derive(Clone)]
+pub struct ReactionServices(Reaction);
+
+impl Deref for ReactionServices {
+    type Target = Reaction;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl ReactionServices {
+    pub fn new(reaction: Reaction) -> Self {
+        Self(reaction)
+    }
+
+    /// Only the author of the message and its friends can react. Others get
+    /// `RepositoryError::NotFound` so that they can't probe which messages exist.
+    #[instrument(name = "ReactionServices::ensure_visible", skip_all, fields(message_id = %self.message_id))]
+    pub async fn ensure_visible(
+        &self,
+        pg: &PgPool,
+        friend_cache: &FriendCache,
+    ) -> Result<(), RepositoryError> {
+        let author = self.message_id.user_id();
+
+        if author == self.user_id.get_id() {
+            return Ok(());
+        }
+
+        match friend_cache.get(self.user_id, pg).await?.contains(&author) {
+            true => Ok(()),
+            false => Err(RepositoryError::NotFound),
+        }
+    }
+
+    pub fn add(&self) -> AddReactionRequest {
+        AddReactionRequest::new(self.0.clone())
+    }
+
+    pub fn remove(&self) -> RemoveReactionRequest {
+        RemoveReactionRequest::new(self.0.clone())
+    }
+
+    pub fn realtime_added(self) -> PublishReactionUpdate {
+        PublishReactionUpdate::new(ReactionUpdate::Added(self.0))
+    }
+
+    pub fn realtime_removed(self) -> PublishReactionUpdate {
+        PublishReactionUpdate::new(ReactionUpdate::Removed(self.0))
+    }
+}
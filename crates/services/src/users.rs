@@ -1,30 +1,59 @@
-use std::{collections::HashSet, ops::Deref};
+use std::{
+    collections::HashSet,
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
 
 use anyhow::Error;
 use futures::{
     future::Either,
-    stream::{select, select_all, StreamExt, TryStreamExt},
-    Stream,
+    stream::{select, StreamExt, TryStreamExt},
+    Future, Stream,
 };
 
+use crate::combinators::{deduplicated, MergeSortedStreams, RECENT_EVENTS};
+use crate::messages::MessagelikeServices;
+use crate::friendships::FriendCache;
 use realtime::{self, Client};
 use repository::{
-    messages::{GetLastMessagesOfUserRequest, InsertMessageRequest},
-    users::GetUserByNameRequest,
-    PgPool, Session,
+    attachments::AttachmentStorage,
+    conversations::DeleteConversationsOfUserRequest,
+    messages::{
+        AddSeenTagsRequest, DeleteMentionsOfNameRequest, DeleteMessagesOfUserRequest,
+        DeleteReadTagsOfUserRequest, GetLastMessagesOfUserRequest,
+        GetReadTagsOfUserRequest, InsertMessageRequest,
+    },
+    reactions::DeleteReactionsOfUserRequest,
+    users::{GetUserByNameRequest, GetUserCredentialsRequest, SearchUsersRequest},
+    PgPool, RepositoryError, Session, TimeBucket,
 };
+use task_manager::TaskManager;
+use tracing::instrument;
+use tracing_futures::Instrument;
 
 use models::{
+    cursor::Cursor,
+    events::RecentEvents,
     friendships::{FriendUpdate, FriendshipUpdate},
-    messages::Message,
+    messages::{Message, MessageId, TimelineEntry},
+    reactions::ReactionSummary,
     users::{User, UserId, Userlike},
 };
+use repository::blocks::{BlockUserRequest, GetBlocksOfUserRequest, UnblockUserRequest};
 use repository::users::{
-    DeleteUserRequest, GetFriendsOfUserRequest, InsertFriendshipRequest, InsertUserRequest,
+    AnswerFriendshipRequest, DeleteUserRequest, GetFriendRequestsRequest, GetFriendUsersRequest,
+    GetFriendsOfUserRequest, GetUser, InsertFriendshipRequest, InsertUserRequest,
     RemoveFriendshipRequest,
 };
 
+/// Of `count_unread_messages`, for the users that never saw a message.
+const UNREAD_WINDOW_DAYS: i64 = 7;
+
 pub trait UserlikeServices: Userlike {
+    fn get_user(&self) -> GetUser {
+        GetUser::new(self.get_id())
+    }
+
     fn delete(&self) -> DeleteUserRequest {
         DeleteUserRequest::new(self.get_id())
     }
@@ -37,6 +66,30 @@ pub trait UserlikeServices: Userlike {
         RemoveFriendshipRequest::new(self.get_id(), other.get_id())
     }
 
+    fn accept_friend(&self, requester: impl Userlike) -> AnswerFriendshipRequest {
+        AnswerFriendshipRequest::accept(self.get_id(), requester.get_id())
+    }
+
+    fn decline_friend(&self, requester: impl Userlike) -> AnswerFriendshipRequest {
+        AnswerFriendshipRequest::decline(self.get_id(), requester.get_id())
+    }
+
+    fn get_friend_requests(&self) -> GetFriendRequestsRequest {
+        GetFriendRequestsRequest::new(self.get_id())
+    }
+
+    fn block(&self, other: impl Userlike) -> BlockUserRequest {
+        BlockUserRequest::new(self.get_id(), other.get_id())
+    }
+
+    fn unblock(&self, other: impl Userlike) -> UnblockUserRequest {
+        UnblockUserRequest::new(self.get_id(), other.get_id())
+    }
+
+    fn get_blocks(&self) -> GetBlocksOfUserRequest {
+        GetBlocksOfUserRequest::new(self.get_id())
+    }
+
     fn insert_message(&self, content: String) -> InsertMessageRequest {
         InsertMessageRequest::new(self.get_id(), content)
     }
@@ -49,8 +102,46 @@ pub trait UserlikeServices: Userlike {
         GetFriendsOfUserRequest::new(self.get_id())
     }
 
-    fn insert(name: String) -> InsertUserRequest {
-        InsertUserRequest::new(name)
+    fn get_friend_users(&self) -> GetFriendUsersRequest {
+        GetFriendUsersRequest::new(self.get_id())
+    }
+
+    fn get_read_tags(&self) -> GetReadTagsOfUserRequest {
+        GetReadTagsOfUserRequest::new(self.get_id())
+    }
+
+    /// Of `auth::hash_password`.
+    fn insert(name: String, password_hash: String) -> InsertUserRequest {
+        InsertUserRequest::new(name, password_hash)
+    }
+
+    fn mark_seen(&self, messages: impl IntoIterator<Item = MessageId>) -> AddSeenTagsRequest {
+        AddSeenTagsRequest::by_user(self.get_id(), messages)
+    }
+
+    fn realtime_mark_seen(
+        self,
+        messages: impl IntoIterator<Item = MessageId>,
+    ) -> realtime::senders::PublishSeenMessages {
+        let user = self.get_id();
+
+        realtime::senders::PublishSeenMessages::new(messages.into_iter().map(|m| (user, m)).collect())
+    }
+
+    fn delete_messages(&self) -> DeleteMessagesOfUserRequest {
+        DeleteMessagesOfUserRequest::new(self.get_id())
+    }
+
+    fn delete_read_tags(&self) -> DeleteReadTagsOfUserRequest {
+        DeleteReadTagsOfUserRequest::new(self.get_id())
+    }
+
+    fn delete_reactions(&self) -> DeleteReactionsOfUserRequest {
+        DeleteReactionsOfUserRequest::new(self.get_id())
+    }
+
+    fn delete_conversations(&self) -> DeleteConversationsOfUserRequest {
+        DeleteConversationsOfUserRequest::new(self.get_id())
     }
 
     fn realtime_friend_with(self, other: impl Userlike) -> realtime::senders::PublishFriendship {
@@ -63,6 +154,18 @@ pub trait UserlikeServices: Userlike {
     ) -> realtime::senders::PublishRemoveFriendship {
         realtime::senders::PublishRemoveFriendship::new(self, other)
     }
+
+    fn realtime_block(self, other: impl Userlike) -> realtime::senders::PublishBlock {
+        realtime::senders::PublishBlock::new(self, other)
+    }
+
+    fn realtime_unblock(self, other: impl Userlike) -> realtime::senders::PublishUnblock {
+        realtime::senders::PublishUnblock::new(self, other)
+    }
+
+    fn realtime_removed(self) -> realtime::senders::PublishUserRemoved {
+        realtime::senders::PublishUserRemoved::new(self)
+    }
 }
 
 impl<T: Userlike> UserlikeServices for T {}
@@ -94,21 +197,130 @@ impl UserIdServices {
         conn: &'a PgPool,
         session: &'a Session,
     ) -> impl Stream<Item = Result<Message, Error>> + 'a {
-        get_timeline(self, conn, session).await
+        get_timeline(self, None, conn, session).await
+    }
+
+    /// Timeline where each message tells whether the user has already seen it.
+    pub async fn get_timeline_with_read_status<'a>(
+        self,
+        conn: &'a PgPool,
+        session: &'a Session,
+    ) -> impl Stream<Item = Result<TimelineEntry, Error>> + 'a {
+        self.get_timeline_with_read_status_from(None, conn, session).await
+    }
+
+    /// Like `get_timeline_with_read_status`, from the messages of `starting_from`.
+    #[instrument(name = "UserIdServices::get_timeline_with_read_status", skip_all, fields(user_id = %self.0))]
+    async fn get_timeline_with_read_status_from<'a>(
+        self,
+        starting_from: Option<TimeBucket>,
+        conn: &'a PgPool,
+        session: &'a Session,
+    ) -> impl Stream<Item = Result<TimelineEntry, Error>> + 'a {
+        let read = match self.get_read_tags().execute(session).await {
+            Ok(read) => read,
+            Err(e) => return Either::Left(futures::stream::iter([Err(e.into())])),
+        };
+
+        let timeline = get_timeline(self, starting_from, conn, session)
+            .await
+            .map_ok(move |message| TimelineEntry {
+                read: read.contains(&message.id),
+                reactions: ReactionSummary::new(message.id),
+                message,
+            });
+
+        Either::Right(timeline)
+    }
+
+    /// Timeline with read status and reaction counts, one more query per message.
+    pub async fn get_timeline_with_reactions<'a>(
+        self,
+        conn: &'a PgPool,
+        session: &'a Session,
+    ) -> impl Stream<Item = Result<TimelineEntry, Error>> + 'a {
+        self.get_timeline_with_reactions_before(None, conn, session).await
+    }
+
+    /// Like `get_timeline_with_reactions`, from the message posted before the one of `before`, the
+    /// last one of a previous page. Reactions are only counted for the messages sent.
+    pub async fn get_timeline_with_reactions_before<'a>(
+        self,
+        before: Option<Cursor>,
+        conn: &'a PgPool,
+        session: &'a Session,
+    ) -> impl Stream<Item = Result<TimelineEntry, Error>> + 'a {
+        let starting_from = before.map(TimeBucket::from);
+
+        self.get_timeline_with_read_status_from(starting_from, conn, session)
+            .await
+            .try_skip_while(move |entry| {
+                futures::future::ok(before.is_some_and(|before| !before.is_after(&entry.message)))
+            })
+            .and_then(move |mut entry| async move {
+                entry.reactions = entry.message.id.reaction_summary().execute(session).await?;
+
+                Ok(entry)
+            })
+    }
+
+    /// Timeline without the messages already seen by the user.
+    pub async fn get_unread_messages<'a>(
+        self,
+        conn: &'a PgPool,
+        session: &'a Session,
+    ) -> impl Stream<Item = Result<Message, Error>> + 'a {
+        self.get_timeline_with_read_status(conn, session)
+            .await
+            .try_filter_map(|entry| futures::future::ok((!entry.read).then_some(entry.message)))
+    }
+
+    /// Number of messages of `get_unread_messages` posted since the last one seen by the user, or
+    /// over the last `UNREAD_WINDOW_DAYS` when none: only the buckets since are queried.
+    #[instrument(name = "UserIdServices::count_unread_messages", skip_all, fields(user_id = %self.0))]
+    pub async fn count_unread_messages(
+        self,
+        conn: &PgPool,
+        session: &Session,
+    ) -> Result<u64, Error> {
+        let read = self.get_read_tags().execute(session).await?;
+        let now = chrono::Utc::now();
+        let since = read
+            .iter()
+            .map(|message| message.datetime())
+            .max()
+            .unwrap_or_else(|| now - chrono::Duration::days(UNREAD_WINDOW_DAYS));
+        let friends: Vec<UserId> = self.get_friends().stream(conn).try_collect().await?;
+
+        let mut count = 0;
+        for friend in friends {
+            count += friend
+                .get_messages()
+                .between(since, now)
+                .stream(session)
+                .try_filter(|message| futures::future::ready(!read.contains(&message.id)))
+                .try_fold(0, |count, _| futures::future::ok(count + 1))
+                .await?;
+        }
+
+        Ok(count)
     }
 
     pub fn real_time_timeline<'a>(
         self,
         pg: &'a PgPool,
         nats: Client,
+        cache: FriendCache,
     ) -> impl Stream<Item = Result<Message, Error>> + 'a {
         let self_id = self.get_id();
-        let initial_friends = self
-            .get_friends()
-            .stream(pg)
-            .map_ok(|f| FriendUpdate::New(f));
-
-        let updates = realtime::receivers::friendships_updates(nats.clone()).filter_map(
+        let initial_friends = cache
+            .stream(self_id, pg)
+            .map_ok(|f| FriendUpdate::New(f))
+            .map_err(Error::from);
+
+        let recent = Arc::new(Mutex::new(RecentEvents::new(RECENT_EVENTS)));
+        let updates = realtime::receivers::friendships_updates_enveloped(nats.clone());
+        let updates = deduplicated(updates, recent.clone()).filter_map(
             move |f| async move {
                 match f {
                     Ok(
@@ -125,7 +337,8 @@ impl UserIdServices {
         ).map_err(|e| e.into());
 
         let friends = initial_friends.chain(updates);
-        let messages = realtime::receivers::new_messages(nats.clone());
+        let messages = realtime::receivers::new_messages_enveloped(nats.clone());
+        let messages = deduplicated(messages, recent);
 
         let stream = select(friends.map(Either::Left), messages.map(Either::Right));
 
@@ -153,7 +366,8 @@ impl UserIdServices {
 
                 async { res } // https://users.rust-lang.org/t/lifetime-confusing-on-futures-scan/42204
             })
-            .filter_map(|e| async { e });
+            .filter_map(|e| async { e })
+            .instrument(tracing::info_span!("UserIdServices::real_time_timeline", user_id = %self_id));
 
         stream
     }
@@ -183,37 +397,117 @@ impl Deref for UserServices {
 }
 
 impl UserServices {
+    pub fn new(user: User) -> Self {
+        Self(user)
+    }
+
     pub fn get_by_name(name: String) -> GetUserByNameRequest {
         GetUserByNameRequest::new(name)
     }
 
+    /// To be checked with `auth::verify_password`.
+    pub fn get_credentials(name: String) -> GetUserCredentialsRequest {
+        GetUserCredentialsRequest::new(name)
+    }
+
+    pub fn search(query: String, after: Option<String>, limit: u32) -> SearchUsersRequest {
+        SearchUsersRequest::new(query, after, limit)
+    }
+
+    /// Deletes the user and everything attached to it. Runs in the `TaskManager` so that a client
+    /// disconnecting does not leave the deletion half done.
+    ///
+    /// ScyllaDB data is removed first: messages, read tags, reactions, mentions of the name and
+    /// direct messages. If it fails the user still exists and the deletion can be retried.
+    /// Friendships, with their `removed` events, blocks, attachments and the user are then removed
+    /// in a single PostgreSQL transaction. Once it is committed, the files of the attachments are
+    /// removed from `storage` and friends are notified. When `anonymize` is set, messages and
+    /// direct messages are kept with an empty content instead of being deleted.
+    pub fn delete_cascade(
+        &self,
+        pg: PgPool,
+        session: Arc<Session>,
+        nats: Client,
+        storage: Option<Arc<dyn AttachmentStorage>>,
+        task_manager: &TaskManager,
+        anonymize: bool,
+    ) -> impl Future<Output = Result<(), Error>> {
+        let user = self.id;
+        let name = self.name.clone();
+
+        let span = tracing::info_span!("UserServices::delete_cascade", user_id = %user);
+
+        task_manager.spawn_await_result(async move {
+            let (messages, conversations) = match anonymize {
+                true => (
+                    user.delete_messages().anonymize(),
+                    user.delete_conversations().anonymize(),
+                ),
+                false => (user.delete_messages(), user.delete_conversations()),
+            };
+            messages.execute(&session).await?;
+            user.delete_read_tags().execute(&session).await?;
+            user.delete_reactions().execute(&session).await?;
+            DeleteMentionsOfNameRequest::new(name).execute(&session).await?;
+            conversations.execute(&session).await?;
+
+            let deleted = user.delete().execute(&pg).await?;
+
+            if let Some(storage) = storage {
+                for attachment in deleted.attachments {
+                    if let Err(e) = storage.remove(attachment).await {
+                        tracing::warn!(error = %e, %attachment, "Can't remove the attachment");
+                    }
+                }
+            }
+
+            for friend in deleted.friends {
+                let _ = user.realtime_remove_friend(friend).publish(nats.clone()).await;
+            }
+            user.realtime_removed().publish(nats).await?;
+
+            Ok(())
+        }.instrument(span))
+    }
+
     pub async fn get_timeline<'a>(
         &'a self,
         conn: &'a PgPool,
         session: &'a Session,
     ) -> impl Stream<Item = Result<Message, Error>> + 'a {
-        get_timeline(self, conn, session).await
+        get_timeline(self, None, conn, session).await
     }
 }
 
+#[instrument(name = "get_timeline", skip_all, fields(user_id = %user.get_id()))]
 async fn get_timeline<'a>(
     user: impl Userlike + 'a,
+    starting_from: Option<TimeBucket>,
     conn: &'a PgPool,
     session: &'a Session,
 ) -> impl Stream<Item = Result<Message, Error>> + 'a {
     let friends = user
         .get_friends()
         .stream(conn)
-        .collect::<Vec<Result<UserId, Error>>>()
+        .collect::<Vec<Result<UserId, RepositoryError>>>()
         .await;
 
     let friends_streams: Vec<_> = friends
         .into_iter()
         .filter_map(|f| f.ok())
-        .map(|f| Box::pin(f.get_messages().stream(session)))
+        .map(|f| {
+            let messages = f.get_messages();
+            let messages = match starting_from {
+                Some(bucket) => messages.starting_from(bucket),
+                None => messages,
+            };
+
+            messages.stream(session).map_err(Error::from)
+        })
         .collect();
 
-    let stream = select_all(friends_streams);
+    // Each friend stream is already sorted by date, most recent first.
+    let stream = MergeSortedStreams::descending(friends_streams);
 
     stream
 }
@@ -3,17 +3,48 @@
 
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::Future;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::futures::TaskLocalFuture;
 use tokio::task::JoinHandle;
 
+tokio::task_local! {
+    static TRACE_ID: Option<String>;
+}
+
+/// Runs `task` with `trace_id`, eg. the request ID of an RPC, kept by the tasks it spawns with
+/// `spawn` and `spawn_await_result`.
+pub fn with_trace_id<F: Future>(
+    trace_id: Option<String>,
+    task: F,
+) -> TaskLocalFuture<Option<String>, F> {
+    TRACE_ID.scope(trace_id, task)
+}
+
+/// The trace ID of the running task, set by `with_trace_id`.
+pub fn trace_id() -> Option<String> {
+    TRACE_ID.try_with(Clone::clone).ok().flatten()
+}
+
 #[derive(Clone, Debug)]
 pub struct TaskManager {
     sender: Arc<mpsc::UnboundedSender<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+    /// Number of tasks `drain` waits for, spawned but not finished yet.
+    in_flight: Arc<watch::Sender<usize>>,
     _worker_handle: Arc<JoinHandle<()>>,
 }
 
+/// Counts a task as in flight until dropped, even if the task panics.
+struct InFlight(Arc<watch::Sender<usize>>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.send_modify(|count| *count -= 1);
+    }
+}
+
 impl TaskManager {
     pub fn new() -> Self {
         let (sender, mut receiver) = mpsc::unbounded_channel();
@@ -26,10 +57,25 @@ impl TaskManager {
 
         Self {
             sender: Arc::new(sender),
+            in_flight: Arc::new(watch::channel(0).0),
             _worker_handle: Arc::new(worker),
         }
     }
 
+    fn track(&self) -> InFlight {
+        self.in_flight.send_modify(|count| *count += 1);
+
+        InFlight(self.in_flight.clone())
+    }
+
+    fn send(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let _ = self
+            .sender
+            .send(Box::pin(task))
+            .map_err(|e| format!("{e}"))
+            .expect("Can't send task");
+    }
+
     /// Use this function to "push and forget" or if you want to await for the result by yourself.
     pub fn spawn<F, R>(&self, task: F) -> oneshot::Receiver<R>
     where
@@ -38,17 +84,15 @@ impl TaskManager {
     {
         let (sender, receiver) = oneshot::channel();
 
+        let in_flight = self.track();
         let wrapped = async move {
             let r = task.await;
 
             let _ = sender.send(r);
+            drop(in_flight);
         };
 
-        let _ = self
-            .sender
-            .send(Box::pin(wrapped))
-            .map_err(|e| format!("{e}"))
-            .expect("Can't send task");
+        self.send(with_trace_id(trace_id(), wrapped));
 
         receiver
     }
@@ -61,20 +105,57 @@ impl TaskManager {
     {
         let (sender, receiver) = oneshot::channel();
 
+        let in_flight = self.track();
         let wrapped = async move {
             let r = task.await;
 
             let _ = sender.send(r);
+            drop(in_flight);
         };
 
-        let _ = self
-            .sender
-            .send(Box::pin(wrapped))
-            .map_err(|e| format!("{e}"))
-            .expect("Can't send task");
+        self.send(with_trace_id(trace_id(), wrapped));
 
         async { receiver.await.unwrap() }
     }
+
+    /// Use this function to run a job on a schedule (archival, cleanup...). The first run happens after
+    /// one `period`, the schedule lives as long as the worker and is not waited for by `drain`.
+    pub fn spawn_periodic<F, Fut>(&self, period: Duration, task: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let wrapped = async move {
+            let mut interval = tokio::time::interval(period);
+            // The first tick completes immediately.
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                task().await;
+            }
+        };
+
+        self.send(wrapped);
+    }
+
+    /// Use this function for tasks living as long as the server (listeners...): unlike `spawn`, `drain`
+    /// does not wait for them.
+    pub fn spawn_background<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.send(task);
+    }
+
+    /// Waits for the tasks spawned with `spawn` and `spawn_await_result` to finish, including the ones
+    /// spawned while waiting. Periodic and background tasks are not waited for.
+    pub async fn drain(&self) {
+        let mut in_flight = self.in_flight.subscribe();
+
+        // The sender lives in `self`, it can't be dropped while waiting.
+        let _ = in_flight.wait_for(|count| *count == 0).await;
+    }
 }
 
 #[cfg(test)]
@@ -107,6 +188,20 @@ async fn simple_test() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+#[cfg(test)]
+#[tokio::test]
+async fn trace_id_test() {
+    let tm = TaskManager::new();
+
+    let spawned = with_trace_id(Some("request".to_string()), async {
+        tm.spawn_await_result(async { trace_id() }).await
+    })
+    .await;
+
+    assert_eq!(spawned.as_deref(), Some("request"));
+    assert_eq!(tm.spawn_await_result(async { trace_id() }).await, None);
+}
+
 #[cfg(test)]
 #[tokio::test]
 async fn await_test() -> Result<(), anyhow::Error> {
@@ -137,6 +232,55 @@ async fn await_test() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+#[cfg(test)]
+#[tokio::test]
+async fn periodic_test() -> Result<(), anyhow::Error> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let tm = TaskManager::new();
+    let runs = Arc::new(AtomicUsize::new(0));
+
+    let counter = runs.clone();
+    tm.spawn_periodic(Duration::from_millis(100), move || {
+        let counter = counter.clone();
+        async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(runs.load(Ordering::SeqCst), 0);
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(runs.load(Ordering::SeqCst) >= 2);
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn drain_test() -> Result<(), anyhow::Error> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let tm = TaskManager::new();
+    let written = Arc::new(AtomicBool::new(false));
+
+    let flag = written.clone();
+    let _written = tm.spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        flag.store(true, Ordering::SeqCst);
+    });
+    tm.spawn_background(futures::future::pending());
+
+    tokio::time::timeout(Duration::from_secs(1), tm.drain()).await?;
+    assert!(written.load(Ordering::SeqCst));
+
+    // Nothing in flight.
+    tokio::time::timeout(Duration::from_millis(10), tm.drain()).await?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 #[tokio::test]
 async fn sanity_check() -> Result<(), anyhow::Error> {
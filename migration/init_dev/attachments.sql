@@ -0,0 +1,14 @@
+-- Files uploaded by `user_id`. `message_id` is set once the attachment is posted with a message.
+CREATE TABLE IF NOT EXISTS attachments (
+    attachment_id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(user_id),
+    filename VARCHAR(255) NOT NULL,
+    content_type VARCHAR(127) NOT NULL,
+    size BIGINT NOT NULL,
+    -- Hex SHA-256 of the data.
+    checksum VARCHAR(64) NOT NULL DEFAULT '',
+    message_id VARCHAR(64),
+    date TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX attachment_user_id_index ON attachments USING HASH (user_id);
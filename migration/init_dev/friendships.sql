@@ -1,14 +1,43 @@
 CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
 
+-- `user_id` asked `friend_id` to be friends, they are once `state` is `accepted`. Declined
+-- requests are removed, blocks are in `blocks`.
 CREATE TABLE IF NOT EXISTS friendships (
     friendship_id SERIAL,
     user_id UUID NOT NULL REFERENCES users(user_id),
     friend_id UUID NOT NULL REFERENCES users(user_id),
+    state VARCHAR(16) NOT NULL DEFAULT 'accepted',
+    date TIMESTAMP NOT NULL DEFAULT NOW(),
     PRIMARY KEY(friendship_id)
 );
 
 CREATE INDEX friendship_user_id_index ON friendships USING HASH (user_id);
 CREATE INDEX friendship_friend_id_index ON friendships USING HASH (friend_id);
+-- One row per pair of users, whoever asked: of two requests of each other at once, only one is
+-- inserted.
+CREATE UNIQUE INDEX friendship_pair_index
+    ON friendships (LEAST(user_id, friend_id), GREATEST(user_id, friend_id));
+
+-- Audit trail of friendships, kept when users are deleted: `requested`, `added`, `declined` or
+-- `removed` by `user_id`.
+CREATE TABLE IF NOT EXISTS friendship_events (
+    event_id SERIAL,
+    user_id UUID NOT NULL,
+    friend_id UUID NOT NULL,
+    kind VARCHAR(16) NOT NULL,
+    date TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY(event_id)
+);
+
+-- `user_id` blocked `blocked_id`: they can't be friends nor see each other.
+CREATE TABLE IF NOT EXISTS blocks (
+    user_id UUID NOT NULL REFERENCES users(user_id),
+    blocked_id UUID NOT NULL REFERENCES users(user_id),
+    date TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY(user_id, blocked_id)
+);
+
+CREATE INDEX block_blocked_id_index ON blocks USING HASH (blocked_id);
 
 INSERT INTO friendships (user_id, friend_id) VALUES ('11234567-1234-5678-1234-567812345678', '21234567-1234-5678-1234-567812345678');
 INSERT INTO friendships (user_id, friend_id) VALUES ('11234567-1234-5678-1234-567812345678', '31234567-1234-5678-1234-567812345678');
\ No newline at end of file
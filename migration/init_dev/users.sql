@@ -2,9 +2,19 @@ CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
 
 CREATE TABLE IF NOT EXISTS users (
     user_id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
-    name VARCHAR(16) NOT NULL
+    -- Users log in by name.
+    name VARCHAR(16) NOT NULL UNIQUE,
+    -- PBKDF2-HMAC-SHA256 of the password, NULL of the users that can't log in.
+    password_hash VARCHAR(128),
+    last_seen_at TIMESTAMP
 );
 
-INSERT INTO users (user_id, name) VALUES ('11234567-1234-5678-1234-567812345678', 'Alice');
-INSERT INTO users (user_id, name) VALUES ('21234567-1234-5678-1234-567812345678', 'Bob');
-INSERT INTO users (user_id, name) VALUES ('31234567-1234-5678-1234-567812345678', 'Charlie');
\ No newline at end of file
+-- Of the databases created before passwords.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS password_hash VARCHAR(128);
+-- Of the databases created before presences.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS last_seen_at TIMESTAMP;
+
+-- Their password is `password`.
+INSERT INTO users (user_id, name, password_hash) VALUES ('11234567-1234-5678-1234-567812345678', 'Alice', 'pbkdf2-sha256$600000$dHNuLWRldi1zZWVkLXVzcg$E48TcT1iLw1Ab/WGEkQ5AMKW5TBifT5oLT7llzESIOY');
+INSERT INTO users (user_id, name, password_hash) VALUES ('21234567-1234-5678-1234-567812345678', 'Bob', 'pbkdf2-sha256$600000$dHNuLWRldi1zZWVkLXVzcg$E48TcT1iLw1Ab/WGEkQ5AMKW5TBifT5oLT7llzESIOY');
+INSERT INTO users (user_id, name, password_hash) VALUES ('31234567-1234-5678-1234-567812345678', 'Charlie', 'pbkdf2-sha256$600000$dHNuLWRldi1zZWVkLXVzcg$E48TcT1iLw1Ab/WGEkQ5AMKW5TBifT5oLT7llzESIOY');
\ No newline at end of file
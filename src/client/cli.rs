@@ -1,13 +1,34 @@
+use super::filter::NotificationFilter;
+use super::output::Output;
+use super::prompt::Prompt;
 use super::Connector;
 use anyhow::Error;
-use futures::{FutureExt, StreamExt};
+use futures::FutureExt;
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::Arc;
+
+use proto::{FriendshipState, Presence};
 
 enum Action {
-    Timeline,
-    Post,
+    Timeline { unread_only: bool },
+    /// Asks for the content when it is not given.
+    Post(Option<String>),
     AddFriend(String),
+    AcceptFriend(String),
+    DeclineFriend(String),
     RmFriend(String),
+    Friends,
+    Requests,
+    Unread,
+    Read(String),
+    MarkUnread(String),
+    Whoami,
+    Search(String),
+    Dm { user: String, content: String },
+    Inbox,
+    Mute(String),
+    Unmute(String),
     Close,
 }
 
@@ -23,145 +44,473 @@ impl FromStr for Action {
         let argument = splitted.get(1).map(|s| *s);
 
         match (action, argument) {
-            ("timeline", _) => Ok(Self::Timeline),
-            ("post", _) => Ok(Self::Post),
+            ("timeline", Some("unread")) => Ok(Self::Timeline { unread_only: true }),
+            ("timeline", _) => Ok(Self::Timeline { unread_only: false }),
+            ("post", Some(_)) => Ok(Self::Post(Some(splitted[1..].join(" ")))),
+            ("post", None) => Ok(Self::Post(None)),
             ("add_friend", Some(s)) => Ok(Self::AddFriend(s.to_string())),
+            ("accept_friend", Some(s)) => Ok(Self::AcceptFriend(s.to_string())),
+            ("decline_friend", Some(s)) => Ok(Self::DeclineFriend(s.to_string())),
             ("rm_friend", Some(s)) => Ok(Self::RmFriend(s.to_string())),
             ("add_friend", None) => Err(Error::msg("Missing argument for action `add_friend`")),
+            ("accept_friend", None) => {
+                Err(Error::msg("Missing argument for action `accept_friend`"))
+            }
+            ("decline_friend", None) => {
+                Err(Error::msg("Missing argument for action `decline_friend`"))
+            }
             ("rm_friend", None) => Err(Error::msg("Missing argument for action `rm_friend`")),
+            ("friends", _) => Ok(Self::Friends),
+            ("requests", _) => Ok(Self::Requests),
+            ("read", Some(s)) => Ok(Self::Read(s.to_string())),
+            ("read", None) => Err(Error::msg("Missing argument for action `read`")),
+            ("unread", Some(s)) => Ok(Self::MarkUnread(s.to_string())),
+            ("unread", None) => Ok(Self::Unread),
+            ("whoami", _) => Ok(Self::Whoami),
+            ("search", Some(s)) => Ok(Self::Search(s.to_string())),
+            ("search", None) => Err(Error::msg("Missing argument for action `search`")),
+            ("dm", Some(user)) if splitted.len() > 2 => Ok(Self::Dm {
+                user: user.to_string(),
+                content: splitted[2..].join(" "),
+            }),
+            ("dm", _) => Err(Error::msg("Missing arguments for action `dm`")),
+            ("inbox", _) => Ok(Self::Inbox),
+            ("mute", Some(s)) => Ok(Self::Mute(s.to_string())),
+            ("mute", None) => Err(Error::msg("Missing argument for action `mute`")),
+            ("unmute", Some(s)) => Ok(Self::Unmute(s.to_string())),
+            ("unmute", None) => Err(Error::msg("Missing argument for action `unmute`")),
             ("close", _) => Ok(Self::Close),
             (s, _) => Err(Error::msg(format!("Invalid action {s}"))),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+const SEARCH_PAGE_SIZE: u32 = 10;
+/// Last messages shown of each conversation.
+const INBOX_MESSAGES: u32 = 5;
+
 pub struct Cli {
     client: Connector,
+    output: Output,
+    filter: NotificationFilter,
+    /// Of the timeline.
+    page_size: u32,
+    /// `None` when the user can't be prompted, eg. for the next page.
+    prompt: Option<Arc<Prompt>>,
 }
 
 impl Cli {
-    async fn post(&self) -> Result<(), Error> {
-        let content: String = asking::text()
-            .message("Write your post (enter to commit)\n")
-            .ask()
-            .await?;
+    async fn post(&self, content: Option<String>) -> Result<(), Error> {
+        let content = match content {
+            Some(content) => content,
+            None => match &self.prompt {
+                Some(prompt) => prompt.text("Write your post (enter to commit)\n> ")?,
+                None => return Err(Error::msg("Missing content for action `post`")),
+            },
+        };
 
         self.client
             .clone()
             .post_message(content)
             .then(|res| async {
                 match res.as_ref() {
-                    Ok(_) => println!("✅ Successfully posted message"),
-                    Err(e) => println!("❌ Error: {e}"),
+                    Ok(message) => self.output.posted(message),
+                    Err(e) => self.output.status(format!("❌ Error: {e}")),
                 };
                 res
             })
             .await
+            .map(|_| ())
     }
 
-    async fn timeline(&self) -> Result<(), Error> {
-        let mut timeline_stream = self.client.clone().get_timeline_stream().await?;
+    async fn timeline(&self, unread_only: bool) -> Result<(), Error> {
+        // Of the pages up to the current one, the first has none.
+        let mut tokens = vec![String::new()];
 
         loop {
-            println!("Gathering next posts...");
+            let page = tokens.len();
+            self.output.status("Gathering posts...");
 
-            let posts = timeline_stream.next().await;
+            let (posts, next_page_token) = self
+                .client
+                .clone()
+                .timeline_page(unread_only, self.page_size, tokens[page - 1].clone())
+                .await?;
 
-            match posts {
-                Some(Ok(posts)) => {
-                    for post in posts {
-                        println!("Post from {}: ({})", post.user_id, post.timestamp);
-                        println!("{}", post.content);
-                    }
-                }
-                Some(Err(e)) => {
-                    println!("❌ Error: {e}");
-                    break;
-                }
-                None => {
-                    println!("😭 No posts to see 😭 Try again later 😭");
-                    break;
+            if posts.is_empty() && page == 1 {
+                self.output.status("😭 No posts to see 😭 Try again later 😭");
+                break;
+            }
+
+            self.output.status(format!("──── Page {page} ────"));
+            for post in &posts {
+                let author = self.client.name_of(&post.user_id).await;
+                let presence = self.client.presence_of(&post.user_id);
+                self.output.post(post, &author, presence.as_ref());
+            }
+
+            let has_next = !next_page_token.is_empty();
+            if !has_next && page == 1 {
+                break;
+            }
+            let Some(prompt) = &self.prompt else {
+                if has_next {
+                    self.output.status("More posts are left, see them with a larger --limit");
                 }
+                break;
+            };
+
+            let mut choices = Vec::new();
+            if has_next {
+                choices.push("(n)ext");
             }
+            if page > 1 {
+                choices.push("(p)revious");
+                choices.push("(f)irst");
+            }
+            choices.push("(q)uit");
+            let message = format!("{} ? ", choices.join(", "));
 
-            let action: bool = asking::yn().message("Continue timeline ?\n").ask().await?;
+            loop {
+                let choice = prompt.text(&message)?;
 
-            if !action {
+                match choice.trim() {
+                    "n" | "next" if has_next => tokens.push(next_page_token.clone()),
+                    "p" | "previous" if page > 1 => drop(tokens.pop()),
+                    "f" | "first" if page > 1 => tokens.truncate(1),
+                    "q" | "quit" => return Ok(()),
+                    choice => {
+                        self.output.status(format!("Invalid choice `{choice}`"));
+                        continue;
+                    }
+                }
                 break;
             }
         }
+
         Ok(())
     }
 
     async fn add_friend(&self, friend_id: String) -> Result<(), Error> {
-        println!("Sent `add_friend` request...");
+        self.output.status("Sent `add_friend` request...");
         self.client
             .clone()
             .add_friend(friend_id.clone())
             .then(|res| async {
                 match res.as_ref() {
-                    Ok(_) => println!("✅ Successfully added friend {friend_id}"),
-                    Err(e) => println!("❌ Error: {e}"),
+                    Ok(state) => self.output.friendship(&friend_id, *state),
+                    Err(e) => self.output.status(format!("❌ Error: {e}")),
+                };
+                res
+            })
+            .await
+            .map(|_| ())
+    }
+
+    async fn answer_friend(&self, friend_id: String, accept: bool) -> Result<(), Error> {
+        self.client
+            .clone()
+            .answer_friend(friend_id.clone(), accept)
+            .then(|res| async {
+                let state = match accept {
+                    true => FriendshipState::Accepted,
+                    false => FriendshipState::Declined,
+                };
+                match res.as_ref() {
+                    Ok(_) => self.output.friendship(&friend_id, state),
+                    Err(e) => self.output.status(format!("❌ Error: {e}")),
                 };
                 res
             })
             .await
     }
 
+    async fn requests(&self) -> Result<(), Error> {
+        let requests = self.client.clone().list_friend_requests().await?;
+
+        if requests.is_empty() {
+            self.output.status("No pending friend requests");
+        }
+
+        for request in requests {
+            self.output.friend_request(&request, &self.client.user_id);
+        }
+
+        Ok(())
+    }
+
     async fn rm_friend(&self, friend_id: String) -> Result<(), Error> {
-        println!("Sent `remove_friend` request...");
+        self.output.status("Sent `remove_friend` request...");
         self.client
             .clone()
             .rm_friend(friend_id.clone())
             .then(|res| async {
                 match res.as_ref() {
-                    Ok(_) => println!("✅ Successfully removed friend {friend_id}"),
-                    Err(e) => println!("❌ Error: {e}"),
+                    Ok(_) => self.output.friendship(&friend_id, FriendshipState::NotFriends),
+                    Err(e) => self.output.status(format!("❌ Error: {e}")),
+                };
+                res
+            })
+            .await
+    }
+
+    async fn friends(&self) -> Result<(), Error> {
+        let friends = self.client.clone().list_friends().await?;
+        let presences: HashMap<String, Presence> = self
+            .client
+            .clone()
+            .get_friends_presence()
+            .await?
+            .into_iter()
+            .map(|presence| (presence.user_id.clone(), presence))
+            .collect();
+
+        if friends.is_empty() {
+            self.output.status("😭 No friends yet 😭");
+        }
+        if let Some(prompt) = &self.prompt {
+            prompt.add_names(friends.iter().map(|friend| friend.name.clone()));
+        }
+
+        for friend in friends {
+            self.output.friend(&friend, presences.get(&friend.user_id));
+        }
+
+        Ok(())
+    }
+
+    async fn unread(&self) -> Result<(), Error> {
+        let count = self.client.clone().get_unread_count().await?;
+
+        self.output.unread(count);
+
+        Ok(())
+    }
+
+    async fn tag(&self, message_id: String, read: bool) -> Result<(), Error> {
+        self.client
+            .clone()
+            .tag_message(message_id.clone(), read)
+            .then(|res| async {
+                match res.as_ref() {
+                    Ok(_) => self.output.tagged(&message_id, read),
+                    Err(e) => self.output.status(format!("❌ Error: {e}")),
                 };
                 res
             })
             .await
     }
 
-    pub async fn interactivity_loop_inner(&self) -> Result<(), Error> {
+    async fn search(&self, query: String) -> Result<(), Error> {
+        let mut page_token = String::new();
+
         loop {
-            let action: Result<Action, Error> = asking::text()
-                .message("What do you want to do ? (timeline/post/add_friend/rm_friend/close)\n")
-                .ask()
-                .await?
-                .parse();
+            let (users, next_page_token) = self
+                .client
+                .clone()
+                .search_users(query.clone(), SEARCH_PAGE_SIZE, page_token)
+                .await?;
 
-            let action = match action {
-                Ok(action) => action,
-                Err(e) => {
-                    println!("Error: {e}");
-                    continue;
-                }
+            if users.is_empty() {
+                self.output.status(format!("😭 Nobody named like `{query}` 😭"));
+            }
+
+            for user in &users {
+                self.output.user(user);
+            }
+
+            let Some(prompt) = &self.prompt else {
+                break;
             };
+            if next_page_token.is_empty() || !prompt.confirm("Next page ?")? {
+                break;
+            }
+
+            page_token = next_page_token;
+        }
+
+        Ok(())
+    }
+
+    async fn dm(&self, user: String, content: String) -> Result<(), Error> {
+        let user = self.client.clone().resolve_user(user).await?;
+        let conversation = self.client.clone().conversation_with(vec![user]).await?;
+
+        self.client
+            .clone()
+            .send_direct_message(conversation, content)
+            .then(|res| async {
+                match res.as_ref() {
+                    Ok(message) => self.output.sent(message),
+                    Err(e) => self.output.status(format!("❌ Error: {e}")),
+                };
+                res
+            })
+            .await
+            .map(|_| ())
+    }
+
+    async fn inbox(&self) -> Result<(), Error> {
+        let conversations = self.client.clone().conversations().await?;
+
+        if conversations.is_empty() {
+            self.output.status("📭 No conversations yet");
+        }
+
+        for conversation in conversations {
+            self.output.conversation(&conversation);
+
+            let messages = self
+                .client
+                .clone()
+                .direct_messages(conversation.conversation_id, INBOX_MESSAGES)
+                .await?;
+
+            // Oldest first, like a chat.
+            for message in messages.iter().rev() {
+                let author = match &message.message {
+                    Some(message) => self.client.name_of(&message.user_id).await,
+                    None => String::new(),
+                };
+                self.output.direct_message(message, &author);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Only hides the notifications of the user, they can still be seen in the timeline.
+    async fn mute(&self, user: String) -> Result<(), Error> {
+        let user_id = self.client.clone().resolve_user(user).await?;
+
+        let changed = self.filter.mute(user_id.clone());
+        self.output.muted(&user_id, true, changed);
+
+        Ok(())
+    }
+
+    async fn unmute(&self, user: String) -> Result<(), Error> {
+        let user_id = self.client.clone().resolve_user(user).await?;
+
+        let changed = self.filter.unmute(&user_id);
+        self.output.muted(&user_id, false, changed);
+
+        Ok(())
+    }
+
+    async fn whoami(&self) -> Result<(), Error> {
+        let user = self.client.clone().whoami().await?;
+
+        self.output.user(&user);
+
+        Ok(())
+    }
+
+    pub fn new(
+        client: Connector,
+        output: Output,
+        filter: NotificationFilter,
+        page_size: u32,
+    ) -> Self {
+        Self {
+            client,
+            output,
+            filter,
+            page_size,
+            prompt: None,
+        }
+    }
+
+    async fn execute(&self, action: Action) -> Result<(), Error> {
+        match action {
+            // Handled by the loops.
+            Action::Close => Ok(()),
+            Action::AddFriend(id) => self.add_friend(id).await,
+            Action::AcceptFriend(id) => self.answer_friend(id, true).await,
+            Action::DeclineFriend(id) => self.answer_friend(id, false).await,
+            Action::RmFriend(id) => self.rm_friend(id).await,
+            Action::Friends => self.friends().await,
+            Action::Requests => self.requests().await,
+            Action::Unread => self.unread().await,
+            Action::Read(id) => self.tag(id, true).await,
+            Action::MarkUnread(id) => self.tag(id, false).await,
+            Action::Whoami => self.whoami().await,
+            Action::Search(query) => self.search(query).await,
+            Action::Dm { user, content } => self.dm(user, content).await,
+            Action::Inbox => self.inbox().await,
+            Action::Mute(user) => self.mute(user).await,
+            Action::Unmute(user) => self.unmute(user).await,
+            Action::Post(content) => self.post(content).await,
+            Action::Timeline { unread_only } => self.timeline(unread_only).await,
+        }
+    }
+
+    /// Until `close`, or the end of the input.
+    pub async fn interactivity_loop(mut self, prompt: Prompt) -> Result<(), Error> {
+        // Completed in actions, best effort.
+        if let Ok(friends) = self.client.clone().list_friends().await {
+            prompt.add_names(friends.into_iter().map(|friend| friend.name));
+        }
+        let prompt = Arc::new(prompt);
+        self.prompt = Some(prompt.clone());
 
-            let res = match action {
-                Action::Close => break,
-                Action::AddFriend(id) => self.add_friend(id).await,
-                Action::RmFriend(id) => self.rm_friend(id).await,
-                Action::Post => self.post().await,
-                Action::Timeline => self.timeline().await,
+        loop {
+            self.output.status("What do you want to do ? (timeline [unread]/unread [message_id]/read message_id/post [text]/search name/dm user text/inbox/mute user/unmute user/add_friend/accept_friend/decline_friend/rm_friend/friends/requests/whoami/close)");
+            let Some(line) = prompt.action("> ")? else {
+                break;
             };
 
-            match res {
-                Ok(_) => (),
+            let action = match line.parse() {
+                Ok(Action::Close) => break,
+                Ok(action) => action,
                 Err(e) => {
-                    println!("Raised error: {e}");
+                    self.output.status(format!("Error: {e}"));
+                    continue;
                 }
+            };
+
+            if let Err(e) = self.execute(action).await {
+                self.output.status(format!("Raised error: {e}"));
             }
         }
 
         Ok(())
     }
 
-    pub async fn interactivity_loop(client: Connector) -> Result<(), Error> {
-        let cli = Self { client: client };
+    /// Runs the actions of a script, one per line, without prompting. Blank lines and the ones
+    /// starting with `#` are skipped, `close` ends it. Stops at the first action that fails,
+    /// unless `keep_going`, then fails at the end if any did.
+    pub async fn run_script(self, script: &str, keep_going: bool) -> Result<(), Error> {
+        let mut failed = 0;
+
+        for (i, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            self.output.status(format!("> {line}"));
+
+            let res = match line.parse() {
+                Ok(Action::Close) => break,
+                Ok(action) => self.execute(action).await,
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = res {
+                let e = e.context(format!("line {}: `{line}`", i + 1));
+                if !keep_going {
+                    return Err(e);
+                }
 
-        cli.interactivity_loop_inner().await
+                self.output.status(format!("Raised error: {e:#}"));
+                failed += 1;
+            }
+        }
+
+        match failed {
+            0 => Ok(()),
+            failed => Err(Error::msg(format!("{failed} actions of the script failed"))),
+        }
     }
 }
@@ -1,49 +1,202 @@
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+
 use anyhow::Error;
-use futures::future;
 
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 
 mod cli;
 mod connector;
+mod filter;
+mod metrics;
+mod output;
+mod prompt;
 
 use cli::Cli;
 use connector::*;
-use proto::social_network_client::SocialNetworkClient;
+use filter::{NotificationFilter, Topic};
+use metrics::Metrics;
+use output::{ColorMode, Format, Output};
+use prompt::Prompt;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// `https://` endpoints need the `tls` feature.
     #[arg(short, long, default_value_t = String::from("http://[::1]:50051"))]
     addr: String,
+    /// PEM certificate of the authority that signed the one of the server.
+    #[arg(long)]
+    ca: Option<PathBuf>,
+    /// Authenticates with this token instead of asking for a name.
+    #[arg(long)]
+    token: Option<String>,
+    /// Logs in as this user instead of asking for a name.
+    #[arg(short, long, conflicts_with = "token")]
+    user: Option<String>,
+    /// Of `--user`, or of the account created by `signup`, instead of asking for it.
+    #[arg(long, conflicts_with = "token")]
+    password: Option<String>,
+    /// How timeline entries, friend lists and notifications are printed.
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    output: Format,
+    /// Whether text output is colored.
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto)]
+    color: ColorMode,
+    /// Only subscribe to these notifications, eg. `--only messages,receipts`. All by default.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    only: Vec<Topic>,
+    /// Users, by id or name, whose notifications are not shown. Can be repeated.
+    #[arg(long)]
+    mute: Vec<String>,
+    /// Posts per page of the timeline, at most 100.
+    #[arg(short, long, default_value_t = 10)]
+    limit: u32,
+    /// Times idempotent calls, eg. reading the timeline, are retried when the server is
+    /// unavailable or too slow. 0 to never retry.
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+    /// Wait before the first retry, doubled after each one, up to 2s.
+    #[arg(long, default_value_t = 100)]
+    retry_backoff_ms: u64,
+    /// Prints the latency of each call, its retries and the time between the items of streams
+    /// to stderr.
+    #[arg(short, long)]
+    verbose: bool,
+    /// Prints a summary of the latencies, retries and stream gaps to stderr on exit.
+    #[arg(long)]
+    metrics: bool,
+    /// Interactive session when none.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Prints the completion script of a shell, eg. `client completions bash >
+    /// ~/.local/share/bash-completion/completions/client`.
+    Completions { shell: Shell },
+    /// Runs the actions of a script, one per line, eg. `timeline` or `post Hello`. `-` reads it
+    /// from stdin.
+    Run {
+        script: PathBuf,
+        /// Runs the next actions when one fails, instead of stopping.
+        #[arg(long)]
+        keep_going: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let args = Args::parse();
-    let name: String = asking::text()
-        .message("What is you user name (Alice, Bob, Charlie) ?\n")
-        .ask_and_wait()?;
-    let client = SocialNetworkClient::connect(args.addr)
-        .await?
-        .auth_by_name(name)
-        .await?;
-
-    println!("Your UUID: {}", client.user_id);
-
-    // Subscribe to real-time messages :
-    let notifs = client.clone().handle_notifs();
-
-    let f = Cli::interactivity_loop(client);
-
-    match future::select(Box::pin(notifs), Box::pin(f)).await {
-        future::Either::Left(_) => {
-            // Handle disconections better. (try to reconnect)
-            println!("❌ Disconnected from server.");
-            Err(Error::msg("disconnected"))
+
+    let script = match args.command {
+        Some(Command::Completions { shell }) => {
+            let bin_name = env!("CARGO_BIN_NAME");
+            clap_complete::generate(shell, &mut Args::command(), bin_name, &mut std::io::stdout());
+
+            return Ok(());
+        }
+        Some(Command::Run { script, keep_going }) if script.as_os_str() == "-" => {
+            if args.token.is_none() && (args.user.is_none() || args.password.is_none()) {
+                return Err(Error::msg(
+                    "a script read from stdin needs --user and --password or --token to log in",
+                ));
+            }
+
+            let mut content = String::new();
+            std::io::stdin().read_to_string(&mut content)?;
+            Some((content, keep_going))
+        }
+        Some(Command::Run { script, keep_going }) => {
+            Some((std::fs::read_to_string(script)?, keep_going))
+        }
+        None => None,
+    };
+
+    let output = Output::new(args.output, args.color);
+    let client = connect(args.addr, args.ca.as_deref()).await?;
+    let prompt = Prompt::new(Prompt::default_history())?;
+
+    let client = match args.token {
+        Some(token) => {
+            let client = client.auth_with_token(token)?;
+            // Fails early when the token is rejected.
+            let user = client.clone().whoami().await?;
+            output.status(format!("Authenticated as {}", user.name));
+            client
+        }
+        None => {
+            let name: String = match args.user {
+                Some(name) => name,
+                None => prompt.text(
+                    "What is you user name (Alice, Bob, Charlie), or `signup <name>` ?\n> ",
+                )?,
+            };
+
+            let password = match args.password {
+                Some(password) => password,
+                // Not echoed, nor kept in the history of the prompt.
+                None => rpassword::prompt_password("Password: ")?,
+            };
+
+            match name.split_whitespace().collect::<Vec<_>>()[..] {
+                ["signup", name] => {
+                    let client = client.create_user(name.to_string(), password).await?;
+                    output.status(format!("✅ Account {name} created"));
+                    client
+                }
+                _ => client.auth_by_name(name.trim().to_string(), password).await?,
+            }
         }
-        future::Either::Right(_) => {
-            println!("Bye !");
-            Ok(())
+    };
+
+    let metrics = Metrics::new(args.verbose);
+    let client = client
+        .with_retry_policy(RetryPolicy {
+            retries: args.retries,
+            initial_backoff: Duration::from_millis(args.retry_backoff_ms),
+            ..RetryPolicy::default()
+        })
+        .with_metrics(metrics.clone());
+
+    output.status(format!("Your UUID: {}", client.user_id));
+
+    let mut muted = Vec::with_capacity(args.mute.len());
+    for user in args.mute {
+        muted.push(client.clone().resolve_user(user).await?);
+    }
+    let filter = NotificationFilter::new(&args.only, muted);
+
+    let cli = Cli::new(client.clone(), output, filter.clone(), args.limit);
+
+    // Live notifications would be mixed with the output of the script.
+    if let Some((script, keep_going)) = script {
+        let res = cli.run_script(&script, keep_going).await;
+        if args.metrics {
+            eprint!("{}", metrics.summary());
         }
+        return res;
+    }
+
+    // Subscribe to real-time messages and presence of friends, and stay online while connected.
+    // They reconnect on their own until the interactive loop is closed.
+    tokio::spawn(client.clone().keep_notified(output, filter));
+    tokio::spawn(client.clone().keep_presence_updated(output));
+    tokio::spawn(
+        client
+            .clone()
+            .send_heartbeats(std::time::Duration::from_secs(30), output),
+    );
+
+    let res = cli.interactivity_loop(prompt).await;
+    if args.metrics {
+        eprint!("{}", metrics.summary());
     }
+    res?;
+
+    output.status("Bye !");
+    Ok(())
 }
@@ -1,65 +1,739 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
 use anyhow::Error;
 use async_trait::async_trait;
-use futures::stream::StreamExt;
-use futures::Stream;
-use tonic::transport::Channel;
+use futures::stream::{Stream, StreamExt, TryStreamExt};
+use futures::Future;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::codec::CompressionEncoding;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request, Response, Status};
 
+use models::events::{EventId, RecentEvents};
+use models::messages::MessageId;
+use models::proto::{timestamp_from_secs, timestamp_secs};
+use models::users::UserId;
+use proto::notifications_response::Event;
 use proto::social_network_client::SocialNetworkClient;
 use proto::{
-    FriendRequest, Message, NotificationsRequest, PostMessageRequest, TimelineRequest,
-    UserByNameRequest,
+    ConversationRequest, ConversationResponse, CreateConversationRequest, CreateUserRequest,
+    DirectMessage, FriendRequest, FriendsPresenceRequest, FriendshipEvent, FriendshipState,
+    HeartbeatRequest, LoginRequest, Message, MessageTagRequest, NotificationAck,
+    NotificationsRequest, NotificationsResponse, PostMessageRequest, Presence, SearchUsersRequest,
+    SendDirectMessageRequest, TimelineRequest, UserByNameRequest, UserRequest, UserResponse,
 };
+use services::auth::subject;
+
+use super::filter::NotificationFilter;
+use super::metrics::Metrics;
+use super::output::{notification_user, Output};
+
+const RECONNECT_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Of the notifications received, the last ones to drop when the server sends them again.
+const RECENT_NOTIFICATIONS: usize = 1024;
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Connects to `addr`, over TLS for `https://` endpoints. The certificate of the server is
+/// checked against `ca`, a PEM file, eg. of a self-signed certificate.
+pub async fn connect(
+    addr: String,
+    ca: Option<&Path>,
+) -> Result<SocialNetworkClient<Channel>, Error> {
+    let mut endpoint = Endpoint::from_shared(addr)?;
+
+    if endpoint.uri().scheme_str() == Some("https") {
+        endpoint = with_tls(endpoint, ca)?;
+    } else if ca.is_some() {
+        return Err(Error::msg("a CA certificate needs an https:// endpoint"));
+    }
+
+    let channel = endpoint.connect().await?;
+
+    Ok(SocialNetworkClient::new(channel).accept_compressed(CompressionEncoding::Gzip))
+}
+
+#[cfg(feature = "tls")]
+fn with_tls(endpoint: Endpoint, ca: Option<&Path>) -> Result<Endpoint, Error> {
+    use tonic::transport::{Certificate, ClientTlsConfig};
+
+    let mut tls_config = ClientTlsConfig::new();
+    if let Some(ca) = ca {
+        tls_config = tls_config.ca_certificate(Certificate::from_pem(std::fs::read(ca)?));
+    }
+
+    Ok(endpoint.tls_config(tls_config)?)
+}
+
+#[cfg(not(feature = "tls"))]
+fn with_tls(_endpoint: Endpoint, _ca: Option<&Path>) -> Result<Endpoint, Error> {
+    Err(Error::msg(
+        "https:// endpoints need the client to be built with the `tls` feature",
+    ))
+}
+
+/// How idempotent calls are retried when the server can't be reached or is too slow, eg.
+/// while it restarts.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// After the first attempt, 0 to never retry.
+    pub retries: u32,
+    /// Doubled after each retry, up to `max_backoff`.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn is_transient(status: &Status) -> bool {
+        matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded)
+    }
+
+    /// Random between half and all of the exponential backoff, so that clients disconnected
+    /// together don't all retry at once.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        let jitter = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos()) as f64
+            / 1e9;
+
+        backoff.mul_f64(0.5 + jitter / 2.0)
+    }
+}
+
+/// Names of the users by id, shared by the clones of a `Connector`.
+type NameCache = Arc<Mutex<HashMap<String, String>>>;
 
-/// Placeholder authentication system. It is used to store the user_id along with the gRPC client.
+/// Last known presence of the friends by id, shared by the clones of a `Connector`.
+type PresenceCache = Arc<Mutex<HashMap<String, Presence>>>;
+
+/// Placeholder authentication system. It is used to store the user_id along with the gRPC client,
+/// and the token when the server requires one.
 #[derive(Clone, Debug)]
 pub struct Connector<T = SocialNetworkClient<Channel>> {
     pub user_id: String,
+    token: Option<String>,
+    names: NameCache,
+    presences: PresenceCache,
+    retry: RetryPolicy,
+    metrics: Metrics,
     _inner: T,
 }
 
 impl Connector<SocialNetworkClient<Channel>> {
-    pub async fn handle_notifs(self) -> Result<(), Error> {
+    fn request<M>(&self, message: M) -> Request<M> {
+        let mut request = Request::new(message);
+
+        if let Some(token) = &self.token {
+            if let Ok(value) = format!("Bearer {token}").parse() {
+                request.metadata_mut().insert("authorization", value);
+            }
+        }
+
+        request
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Calls `call` with the request, again on transient errors: only for idempotent RPCs. Of
+    /// streams, only the call is retried, not the items.
+    async fn call_idempotent<M, R, F, Fut>(
+        &self,
+        method: &'static str,
+        message: M,
+        call: F,
+    ) -> Result<R, Status>
+    where
+        M: Clone,
+        F: FnMut(SocialNetworkClient<Channel>, Request<M>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, Status>>,
+    {
+        self.call_with_retries(method, self.retry.retries, message, call)
+            .await
+    }
+
+    async fn call_once<M, R, F, Fut>(
+        &self,
+        method: &'static str,
+        message: M,
+        call: F,
+    ) -> Result<R, Status>
+    where
+        M: Clone,
+        F: FnMut(SocialNetworkClient<Channel>, Request<M>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, Status>>,
+    {
+        self.call_with_retries(method, 0, message, call).await
+    }
+
+    async fn call_with_retries<M, R, F, Fut>(
+        &self,
+        method: &'static str,
+        retries: u32,
+        message: M,
+        mut call: F,
+    ) -> Result<R, Status>
+    where
+        M: Clone,
+        F: FnMut(SocialNetworkClient<Channel>, Request<M>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, Status>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            let start = Instant::now();
+            let res = call(self._inner.clone(), self.request(message.clone())).await;
+
+            match res {
+                Err(status) if attempt < retries && RetryPolicy::is_transient(&status) => {
+                    tokio::time::sleep(self.retry.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                res => {
+                    self.metrics
+                        .rpc(method, start.elapsed(), attempt, res.is_ok());
+                    return res.map(Response::into_inner);
+                }
+            }
+        }
+    }
+
+    /// The name of a user, fetched once per session. Its id when it can't be fetched.
+    pub async fn name_of(&self, user_id: &str) -> String {
+        if let Some(name) = self.names.lock().unwrap().get(user_id) {
+            return name.clone();
+        }
+
+        let request = UserRequest {
+            user_id: user_id.to_string(),
+        };
+
+        let user = self
+            .call_idempotent("GetUser", request, |mut client, request| async move {
+                client.get_user(request).await
+            })
+            .await;
+
+        match user {
+            Ok(user) => {
+                let name = user.name;
+                self.names
+                    .lock()
+                    .unwrap()
+                    .insert(user_id.to_string(), name.clone());
+                name
+            }
+            Err(_) => user_id.to_string(),
+        }
+    }
+
+    /// Of a friend, `None` for the others or until it is received.
+    pub fn presence_of(&self, user_id: &str) -> Option<Presence> {
+        self.presences.lock().unwrap().get(user_id).cloned()
+    }
+
+    /// Keeps `presence_of` up to date with the presence updates of the friends, subscribed again
+    /// after an exponential backoff when the stream is lost.
+    pub async fn keep_presence_updated(self, output: Output) {
+        let mut backoff = RECONNECT_MIN_BACKOFF;
+
+        loop {
+            let request = UserRequest {
+                user_id: self.user_id.clone(),
+            };
+
+            let stream = self
+                .call_once(
+                    "PresenceUpdates",
+                    request,
+                    |mut client, request| async move { client.presence_updates(request).await },
+                )
+                .await;
+
+            let ended = match stream {
+                Ok(stream) => {
+                    backoff = RECONNECT_MIN_BACKOFF;
+                    let mut stream = self.metrics.gaps("PresenceUpdates", stream);
+
+                    loop {
+                        match stream.next().await {
+                            Some(Ok(presence)) => {
+                                self.presences
+                                    .lock()
+                                    .unwrap()
+                                    .insert(presence.user_id.clone(), presence);
+                            }
+                            Some(Err(e)) => break Err(e),
+                            None => break Ok(()),
+                        }
+                    }
+                }
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = ended {
+                output.status(format!(
+                    "❌ Presence updates lost ({e}), reconnecting in {backoff:?}"
+                ));
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF);
+        }
+    }
+
+    /// Keeps the user notified: when the stream fails or is closed by the server, it is
+    /// subscribed again after an exponential backoff, asking for what was missed since the last
+    /// notification received.
+    pub async fn keep_notified(self, output: Output, filter: NotificationFilter) {
+        let mut since = 0;
+        let mut backoff = RECONNECT_MIN_BACKOFF;
+        let mut recent = RecentEvents::new(RECENT_NOTIFICATIONS);
+
+        loop {
+            let ended = match self.clone().subscribe_notifications(since, &filter).await {
+                Ok(stream) => {
+                    output.status("✅ Subscribed to real-time notifications");
+                    backoff = RECONNECT_MIN_BACKOFF;
+                    // Only the notifications since the first subscription are of interest.
+                    if since == 0 {
+                        since = now_secs();
+                    }
+
+                    self.handle_notifs(stream, output, &filter, &mut since, &mut recent)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+
+            match ended {
+                Ok(()) => output.status(format!(
+                    "Closed notification stream, reconnecting in {backoff:?}"
+                )),
+                Err(e) => output.status(format!(
+                    "❌ Notification stream lost ({e}), reconnecting in {backoff:?}"
+                )),
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF);
+        }
+    }
+
+    /// `since` is in seconds since epoch, 0 for live notifications only.
+    async fn subscribe_notifications(
+        self,
+        since: u64,
+        filter: &NotificationFilter,
+    ) -> Result<impl Stream<Item = Result<NotificationsResponse, Status>> + Unpin, Error> {
         let request = NotificationsRequest {
             user_id: self.user_id.clone(),
+            after_message_id: None,
+            kinds: filter.kinds(),
+            since: timestamp_from_secs(since),
+            muted_user_ids: filter.muted(),
+            acknowledge: true,
         };
 
-        let mut stream = self
-            ._inner
-            .clone()
-            .real_time_notifications(request)
-            .await?
-            .into_inner();
+        let stream = self
+            .call_once(
+                "RealTimeNotifications",
+                request,
+                |mut client, request| async move { client.real_time_notifications(request).await },
+            )
+            .await?;
 
-        println!("✅ Subscribed to real-time notifications");
+        Ok(self.metrics.gaps("RealTimeNotifications", stream))
+    }
+
+    /// Acknowledges the notifications of its `event_ids` until the sender is dropped. The ones
+    /// lost with the stream are sent again by the server.
+    fn acknowledge_notifications(&self) -> mpsc::Sender<NotificationAck> {
+        let (tx, rx) = mpsc::channel(128);
+        let mut client = self._inner.clone();
+        let request = self.request(ReceiverStream::new(rx));
+
+        tokio::spawn(async move { client.ack_notifications(request).await });
+
+        tx
+    }
+
+    /// Prints the notifications until the stream ends, `since` is moved forward as they come.
+    /// The ones in `recent` are received again, only acknowledged.
+    async fn handle_notifs(
+        &self,
+        mut stream: impl Stream<Item = Result<NotificationsResponse, Status>> + Unpin,
+        output: Output,
+        filter: &NotificationFilter,
+        since: &mut u64,
+        recent: &mut RecentEvents,
+    ) -> Result<(), Error> {
+        let acks = self.acknowledge_notifications();
 
         while let Some(notification) = stream.next().await {
-            notification?.message.map(|message| {
-                println!(
-                    "{} a posté un nouveau message : {}",
-                    message.user_id, message.content
-                );
-            });
-        }
+            let notification = notification?;
+
+            if let Ok(event_id) = EventId::try_parse(&notification.event_id) {
+                let ack = NotificationAck {
+                    user_id: self.user_id.clone(),
+                    event_ids: vec![notification.event_id.clone()],
+                };
+                let _ = acks.send(ack).await;
 
-        println!("Closed notification stream.");
+                if !recent.insert(event_id) {
+                    continue;
+                }
+            }
+
+            // Missed messages come first, oldest first, with their own date. Friendship changes
+            // have none, at worst some are sent again after a reconnection.
+            *since = match &notification.event {
+                Some(Event::NewMessage(message)) => {
+                    timestamp_secs(message.timestamp.as_ref()).max(*since)
+                }
+                _ => now_secs().max(*since),
+            };
+
+            // Users muted since the subscription are still sent by the server.
+            if filter.allows(&notification) {
+                // System notices come from no one.
+                let user = match notification_user(&notification) {
+                    "" => String::new(),
+                    user_id => self.name_of(user_id).await,
+                };
+                output.notification(&notification, &user);
+            }
+        }
 
         Ok(())
     }
 
-    pub async fn add_friend(self, friend_id: String) -> Result<(), Error> {
+    /// Keeps the user online while the server can be reached. A failed heartbeat is skipped, the
+    /// next one may succeed once reconnected.
+    pub async fn send_heartbeats(self, period: Duration, output: Output) {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let request = HeartbeatRequest {
+                user_id: self.user_id.clone(),
+            };
+
+            let res = self
+                .call_once("Heartbeat", request, |mut client, request| async move {
+                    client.heartbeat(request).await
+                })
+                .await;
+
+            if let Err(e) = res {
+                output.status(format!("❌ Heartbeat failed: {e}"));
+            }
+        }
+    }
+
+    pub async fn get_friends_presence(self) -> Result<Vec<Presence>, Error> {
+        let request = FriendsPresenceRequest {
+            user_id: self.user_id.clone(),
+        };
+
+        let response = self
+            .call_idempotent(
+                "FriendsPresence",
+                request,
+                |mut client, request| async move { client.friends_presence(request).await },
+            )
+            .await?;
+
+        let mut presences = self.presences.lock().unwrap();
+        for presence in &response.friends {
+            presences.insert(presence.user_id.clone(), presence.clone());
+        }
+
+        Ok(response.friends)
+    }
+
+    /// The user the session is bound to, as known by the server.
+    pub async fn whoami(self) -> Result<UserResponse, Error> {
+        let request = UserRequest {
+            user_id: self.user_id.clone(),
+        };
+
+        let response = self
+            .call_idempotent("GetUser", request, |mut client, request| async move {
+                client.get_user(request).await
+            })
+            .await?;
+
+        Ok(response)
+    }
+
+    /// A page of the users whose name contains `query`, and the token of the next one, empty on
+    /// the last page.
+    pub async fn search_users(
+        self,
+        query: String,
+        page_size: u32,
+        page_token: String,
+    ) -> Result<(Vec<UserResponse>, String), Error> {
+        let request = SearchUsersRequest {
+            user_id: self.user_id.clone(),
+            query,
+            page_size,
+            page_token,
+        };
+
+        let response = self
+            .call_idempotent("SearchUsers", request, |mut client, request| async move {
+                client.search_users(request).await
+            })
+            .await?;
+
+        Ok((response.users, response.next_page_token))
+    }
+
+    /// The id of a user, given as an id or a name.
+    pub async fn resolve_user(self, user: String) -> Result<String, Error> {
+        if UserId::try_parse(&user).is_ok() {
+            return Ok(user);
+        }
+
+        let response = self
+            .call_idempotent(
+                "GetUserByName",
+                UserByNameRequest { name: user },
+                |mut client, request| async move { client.get_user_by_name(request).await },
+            )
+            .await?;
+
+        Ok(response.user_id)
+    }
+
+    pub async fn conversations(self) -> Result<Vec<ConversationResponse>, Error> {
+        let request = UserRequest {
+            user_id: self.user_id.clone(),
+        };
+
+        let response = self
+            .call_idempotent("Conversations", request, |mut client, request| async move {
+                client.conversations(request).await
+            })
+            .await?;
+
+        Ok(response.conversations)
+    }
+
+    /// The conversation of the user with exactly `members`, created when there is none.
+    pub async fn conversation_with(self, members: Vec<String>) -> Result<String, Error> {
+        let mut wanted: Vec<&str> = members.iter().map(String::as_str).collect();
+        wanted.push(&self.user_id);
+        wanted.sort_unstable();
+        wanted.dedup();
+
+        let conversations = self.clone().conversations().await?;
+        let existing = conversations.into_iter().find(|conversation| {
+            let mut members: Vec<&str> =
+                conversation.member_ids.iter().map(String::as_str).collect();
+            members.sort_unstable();
+
+            members == wanted
+        });
+        if let Some(conversation) = existing {
+            return Ok(conversation.conversation_id);
+        }
+
+        let request = CreateConversationRequest {
+            user_id: self.user_id.clone(),
+            member_ids: members,
+        };
+
+        let response = self
+            .call_once(
+                "CreateConversation",
+                request,
+                |mut client, request| async move { client.create_conversation(request).await },
+            )
+            .await?;
+
+        Ok(response.conversation_id)
+    }
+
+    pub async fn send_direct_message(
+        self,
+        conversation_id: String,
+        content: String,
+    ) -> Result<DirectMessage, Error> {
+        let request = SendDirectMessageRequest {
+            user_id: self.user_id.clone(),
+            conversation_id,
+            content,
+        };
+
+        let response = self
+            .call_once(
+                "SendDirectMessage",
+                request,
+                |mut client, request| async move { client.send_direct_message(request).await },
+            )
+            .await?;
+
+        Ok(response)
+    }
+
+    /// The last `limit` messages of a conversation, most recent first.
+    pub async fn direct_messages(
+        self,
+        conversation_id: String,
+        limit: u32,
+    ) -> Result<Vec<DirectMessage>, Error> {
+        let request = ConversationRequest {
+            user_id: self.user_id.clone(),
+            conversation_id,
+            limit,
+        };
+
+        let messages = self
+            .call_idempotent(
+                "DirectMessages",
+                request,
+                |mut client, request| async move { client.direct_messages(request).await },
+            )
+            .await
+            .map(|stream| self.metrics.gaps("DirectMessages", stream))?
+            .try_collect()
+            .await?;
+
+        Ok(messages)
+    }
+
+    /// By name.
+    pub async fn list_friends(self) -> Result<Vec<UserResponse>, Error> {
+        let request = UserRequest {
+            user_id: self.user_id.clone(),
+        };
+
+        let response = self
+            .call_idempotent("ListFriends", request, |mut client, request| async move {
+                client.list_friends(request).await
+            })
+            .await?;
+
+        Ok(response.friends)
+    }
+
+    pub async fn get_unread_count(self) -> Result<u64, Error> {
+        let request = UserRequest {
+            user_id: self.user_id.clone(),
+        };
+
+        let response = self
+            .call_idempotent("UnreadCount", request, |mut client, request| async move {
+                client.unread_count(request).await
+            })
+            .await?;
+
+        Ok(response.count)
+    }
+
+    /// `Pending` until the friend accepts, `Accepted` if it had asked first.
+    pub async fn add_friend(self, friend_id: String) -> Result<FriendshipState, Error> {
         let request = FriendRequest {
             user_id: self.user_id.clone(),
             friend_id,
         };
 
-        let response = self._inner.clone().add_friend(request).await?.into_inner();
+        let response = self
+            .call_once("AddFriend", request, |mut client, request| async move {
+                client.add_friend(request).await
+            })
+            .await?;
 
         match response.success {
-            true => Ok(()),
+            true => Ok(response.state()),
             false => Err(Error::msg("Server returned an error").context("calling `add_friend`")),
         }
     }
 
+    /// Accepts or declines the request of `friend_id`.
+    pub async fn answer_friend(self, friend_id: String, accept: bool) -> Result<(), Error> {
+        let request = FriendRequest {
+            user_id: self.user_id.clone(),
+            friend_id,
+        };
+
+        let (method, response) = match accept {
+            true => (
+                "AcceptFriend",
+                self.call_once("AcceptFriend", request, |mut client, request| async move {
+                    client.accept_friend(request).await
+                })
+                .await?,
+            ),
+            false => (
+                "DeclineFriend",
+                self.call_once("DeclineFriend", request, |mut client, request| async move {
+                    client.decline_friend(request).await
+                })
+                .await?,
+            ),
+        };
+
+        match response.success {
+            true => Ok(()),
+            false => {
+                Err(Error::msg("Server returned an error").context(format!("calling `{method}`")))
+            }
+        }
+    }
+
+    /// Pending requests made by the user and to it, oldest first.
+    pub async fn list_friend_requests(self) -> Result<Vec<FriendshipEvent>, Error> {
+        let request = UserRequest {
+            user_id: self.user_id.clone(),
+        };
+
+        let response = self
+            .call_idempotent(
+                "ListFriendRequests",
+                request,
+                |mut client, request| async move { client.list_friend_requests(request).await },
+            )
+            .await?;
+
+        Ok(response.requests)
+    }
+
     pub async fn rm_friend(self, friend_id: String) -> Result<(), Error> {
         let request = FriendRequest {
             user_id: self.user_id.clone(),
@@ -67,11 +741,10 @@ impl Connector<SocialNetworkClient<Channel>> {
         };
 
         let response = self
-            ._inner
-            .clone()
-            .remove_friend(request)
-            .await?
-            .into_inner();
+            .call_once("RemoveFriend", request, |mut client, request| async move {
+                client.remove_friend(request).await
+            })
+            .await?;
 
         match response.success {
             true => Ok(()),
@@ -79,47 +752,101 @@ impl Connector<SocialNetworkClient<Channel>> {
         }
     }
 
-    pub async fn post_message(self, content: String) -> Result<(), Error> {
-        let request = PostMessageRequest {
+    /// Tags the message as read, or unread again when `read` is false.
+    pub async fn tag_message(self, message_id: String, read: bool) -> Result<(), Error> {
+        let request = MessageTagRequest {
             user_id: self.user_id.clone(),
-            content,
+            message_id,
+            ..Default::default()
         };
 
+        let method = match read {
+            true => "TagReadMessage",
+            false => "TagUnreadMessage",
+        };
+
+        // Tagging twice is the same as once.
         let response = self
-            ._inner
-            .clone()
-            .post_message(request)
-            .await?
-            .into_inner();
+            .call_idempotent(method, request, |mut client, request| async move {
+                match read {
+                    true => client.tag_read_message(request).await,
+                    false => client.tag_unread_message(request).await,
+                }
+            })
+            .await?;
 
         match response.success {
             true => Ok(()),
-            false => Err(Error::msg("Server returned an error").context("calling `post_`")),
+            false => Err(Error::msg("Server returned an error").context("calling `tag_message`")),
+        }
+    }
+
+    /// The message as stored by the server.
+    pub async fn post_message(self, content: String) -> Result<Message, Error> {
+        // Generated here so that the server can recognize a retried request.
+        let message_id = MessageId::new_now(UserId::try_parse(&self.user_id)?);
+
+        let request = PostMessageRequest {
+            user_id: self.user_id.clone(),
+            content,
+            message_id: Some(message_id.to_string()),
+            attachment_ids: Vec::new(),
+            body: None,
+            reply_to: None,
+        };
+
+        let response = self
+            .call_idempotent("PostMessage", request, |mut client, request| async move {
+                client.post_message(request).await
+            })
+            .await?;
+
+        match (response.success, response.message) {
+            (true, Some(message)) => Ok(message),
+            _ => Err(Error::msg("Server returned an error").context("calling `post_`")),
         }
     }
 
-    pub async fn get_timeline_stream(
+    /// A page of the timeline, most recent first, and the token of the next one, empty on the
+    /// last page.
+    pub async fn timeline_page(
         self,
-    ) -> Result<impl Stream<Item = Result<Vec<Message>, Error>>, Error> {
+        unread_only: bool,
+        page_size: u32,
+        page_token: String,
+    ) -> Result<(Vec<Message>, String), Error> {
         let request = TimelineRequest {
             user_id: self.user_id.clone(),
+            unread_only,
+            page_size,
+            page_token,
         };
 
-        let stream = self._inner.clone().timeline(request).await?.into_inner();
+        let page = self
+            .call_idempotent("Timeline", request, |mut client, request| async move {
+                client.timeline(request).await
+            })
+            .await
+            .map(|stream| self.metrics.gaps("Timeline", stream))?
+            .next()
+            .await
+            .transpose()?;
 
-        let stream = stream.map(|response| match response {
-            Ok(response) => Ok(response.messages),
-            Err(e) => Err(Error::msg(format!("error {e}"))),
-        });
-
-        Ok(stream)
+        Ok(page.map_or_else(Default::default, |page| {
+            (page.messages, page.next_page_token)
+        }))
     }
 }
 
 #[async_trait]
 pub trait Auth<T> {
     fn auth(self, user_id: String) -> Result<Connector<T>, Error>;
-    async fn auth_by_name(self, name: String) -> Result<Connector<T>, Error>;
+    /// With a token issued beforehand, attached to every request.
+    fn auth_with_token(self, token: String) -> Result<Connector<T>, Error>;
+    /// The password is not checked by the servers that don't use tokens.
+    async fn auth_by_name(self, name: String, password: String) -> Result<Connector<T>, Error>;
+    /// Creates the account, then authenticates as it.
+    async fn create_user(self, name: String, password: String) -> Result<Connector<T>, Error>;
 }
 
 #[async_trait]
@@ -127,11 +854,58 @@ impl Auth<SocialNetworkClient<Channel>> for SocialNetworkClient<Channel> {
     fn auth(self, user_id: String) -> Result<Connector<Self>, Error> {
         Ok(Connector {
             user_id,
+            token: None,
+            names: NameCache::default(),
+            presences: PresenceCache::default(),
+            retry: RetryPolicy::default(),
+            metrics: Metrics::default(),
+            _inner: self,
+        })
+    }
+
+    fn auth_with_token(self, token: String) -> Result<Connector<Self>, Error> {
+        // Checked by the server, which has the secret.
+        let user_id = subject(&token)?;
+
+        Ok(Connector {
+            user_id: user_id.to_string(),
+            token: Some(token),
+            names: NameCache::default(),
+            presences: PresenceCache::default(),
+            retry: RetryPolicy::default(),
+            metrics: Metrics::default(),
             _inner: self,
         })
     }
 
-    async fn auth_by_name(self, name: String) -> Result<Connector<Self>, Error> {
+    async fn auth_by_name(self, name: String, password: String) -> Result<Connector<Self>, Error> {
+        let login = self
+            .clone()
+            .login(LoginRequest {
+                name: name.clone(),
+                password,
+            })
+            .await;
+
+        match login {
+            Ok(res) => {
+                let res = res.into_inner();
+
+                return Ok(Connector {
+                    user_id: res.user_id,
+                    token: Some(res.token),
+                    names: NameCache::default(),
+                    presences: PresenceCache::default(),
+                    retry: RetryPolicy::default(),
+                    metrics: Metrics::default(),
+                    _inner: self,
+                });
+            }
+            // The server does not use tokens.
+            Err(status) if status.code() == Code::FailedPrecondition => {}
+            Err(status) => return Err(status.into()),
+        }
+
         let res = self
             .clone()
             .get_user_by_name(UserByNameRequest { name })
@@ -140,6 +914,30 @@ impl Auth<SocialNetworkClient<Channel>> for SocialNetworkClient<Channel> {
 
         Ok(Connector {
             user_id: res.user_id,
+            token: None,
+            names: NameCache::default(),
+            presences: PresenceCache::default(),
+            retry: RetryPolicy::default(),
+            metrics: Metrics::default(),
+            _inner: self,
+        })
+    }
+
+    async fn create_user(self, name: String, password: String) -> Result<Connector<Self>, Error> {
+        // The generated method, not this one.
+        let request = CreateUserRequest { name, password };
+        let res = SocialNetworkClient::create_user(&mut self.clone(), request)
+            .await?
+            .into_inner();
+
+        Ok(Connector {
+            user_id: res.user_id,
+            // The server does not use tokens.
+            token: Some(res.token).filter(|token| !token.is_empty()),
+            names: NameCache::default(),
+            presences: PresenceCache::default(),
+            retry: RetryPolicy::default(),
+            metrics: Metrics::default(),
             _inner: self,
         })
     }
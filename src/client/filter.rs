@@ -0,0 +1,103 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use clap::ValueEnum;
+
+use proto::{NotificationKind, NotificationsResponse};
+
+use super::output::notification_user;
+
+/// Groups of notifications the user can subscribe to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Topic {
+    /// Posts of friends, mentions and direct messages.
+    Messages,
+    /// New and removed friends, friend requests and the accepted ones.
+    Friendships,
+    /// Messages seen, or tagged as unread again, by their readers.
+    Receipts,
+    /// Notices of the operators of the server.
+    System,
+    /// Friends coming online and going offline.
+    Presence,
+}
+
+impl Topic {
+    fn kinds(self) -> &'static [NotificationKind] {
+        match self {
+            Topic::Messages => &[
+                NotificationKind::NewMessage,
+                NotificationKind::Mention,
+                NotificationKind::DirectMessage,
+            ],
+            Topic::Friendships => &[
+                NotificationKind::NewFriend,
+                NotificationKind::FriendRemoved,
+                NotificationKind::FriendRequest,
+                NotificationKind::FriendAccepted,
+            ],
+            Topic::Receipts => &[
+                NotificationKind::MessageSeen,
+                NotificationKind::MessageUnseen,
+            ],
+            Topic::System => &[NotificationKind::System],
+            Topic::Presence => &[NotificationKind::Presence],
+        }
+    }
+}
+
+/// Which notifications are subscribed to. The muted users are shared by the clones, and also
+/// filtered out locally: muting someone takes effect right away, not only once the stream is
+/// subscribed again.
+#[derive(Clone, Debug, Default)]
+pub struct NotificationFilter {
+    /// All of them when empty.
+    kinds: Vec<NotificationKind>,
+    muted: Arc<Mutex<HashSet<String>>>,
+}
+
+impl NotificationFilter {
+    pub fn new(topics: &[Topic], muted: impl IntoIterator<Item = String>) -> Self {
+        let mut kinds: Vec<NotificationKind> = topics
+            .iter()
+            .flat_map(|topic| topic.kinds())
+            .copied()
+            .collect();
+        kinds.sort_unstable();
+        kinds.dedup();
+
+        Self {
+            kinds,
+            muted: Arc::new(Mutex::new(muted.into_iter().collect())),
+        }
+    }
+
+    pub fn kinds(&self) -> Vec<i32> {
+        self.kinds.iter().map(|kind| *kind as i32).collect()
+    }
+
+    pub fn muted(&self) -> Vec<String> {
+        let mut muted: Vec<String> = self.muted.lock().unwrap().iter().cloned().collect();
+        muted.sort_unstable();
+
+        muted
+    }
+
+    /// `false` if the user was already muted.
+    pub fn mute(&self, user_id: String) -> bool {
+        self.muted.lock().unwrap().insert(user_id)
+    }
+
+    /// `false` if the user was not muted.
+    pub fn unmute(&self, user_id: &str) -> bool {
+        self.muted.lock().unwrap().remove(user_id)
+    }
+
+    pub fn allows(&self, notification: &NotificationsResponse) -> bool {
+        !self
+            .muted
+            .lock()
+            .unwrap()
+            .contains(notification_user(notification))
+    }
+}
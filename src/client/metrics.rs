@@ -0,0 +1,140 @@
+//! Measures of the client, to tell whether slowness comes from it, the network or the server: the
+//! latency of the RPCs as seen by the client, their retries, and the gaps between the items of
+//! streams.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::stream::{Stream, StreamExt};
+
+#[derive(Clone, Copy, Debug, Default)]
+struct RpcStats {
+    calls: u64,
+    errors: u64,
+    retries: u64,
+    total: Duration,
+    max: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct StreamStats {
+    items: u64,
+    total_gap: Duration,
+    max_gap: Duration,
+}
+
+/// Shared by the clones. When `verbose`, each measure is also printed to stderr as it is made.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    verbose: bool,
+    rpcs: Arc<Mutex<BTreeMap<&'static str, RpcStats>>>,
+    streams: Arc<Mutex<BTreeMap<&'static str, StreamStats>>>,
+}
+
+impl Metrics {
+    pub fn new(verbose: bool) -> Self {
+        Self {
+            verbose,
+            ..Self::default()
+        }
+    }
+
+    /// `latency` of the last attempt, until the response or, for streams, its headers.
+    pub fn rpc(&self, method: &'static str, latency: Duration, retries: u32, ok: bool) {
+        if self.verbose {
+            let retries = match retries {
+                0 => String::new(),
+                1 => " after 1 retry".to_string(),
+                n => format!(" after {n} retries"),
+            };
+            let status = match ok {
+                true => "",
+                false => " failed",
+            };
+            eprintln!("⏱  {method}{status} in {latency:?}{retries}");
+        }
+
+        let mut rpcs = self.rpcs.lock().unwrap();
+        let stats = rpcs.entry(method).or_default();
+        stats.calls += 1;
+        stats.errors += u64::from(!ok);
+        stats.retries += u64::from(retries);
+        stats.total += latency;
+        stats.max = stats.max.max(latency);
+    }
+
+    /// Records the time between the items of `stream`, the first one since the call.
+    pub fn gaps<S: Stream>(&self, name: &'static str, stream: S) -> impl Stream<Item = S::Item> {
+        let metrics = self.clone();
+        let mut last = Instant::now();
+
+        stream.map(move |item| {
+            let now = Instant::now();
+            metrics.stream_item(name, now - last);
+            last = now;
+            item
+        })
+    }
+
+    fn stream_item(&self, name: &'static str, gap: Duration) {
+        if self.verbose {
+            eprintln!("⏱  {name} item after {gap:?}");
+        }
+
+        let mut streams = self.streams.lock().unwrap();
+        let stats = streams.entry(name).or_default();
+        stats.items += 1;
+        stats.total_gap += gap;
+        stats.max_gap = stats.max_gap.max(gap);
+    }
+
+    /// A table of the measures made so far.
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+
+        let _ = writeln!(
+            summary,
+            "{:<24} {:>6} {:>6} {:>7} {:>12} {:>12}",
+            "RPC", "calls", "errors", "retries", "mean", "max"
+        );
+        for (method, stats) in self.rpcs.lock().unwrap().iter() {
+            let _ = writeln!(
+                summary,
+                "{:<24} {:>6} {:>6} {:>7} {:>12} {:>12}",
+                method,
+                stats.calls,
+                stats.errors,
+                stats.retries,
+                format!("{:?}", mean(stats.total, stats.calls)),
+                format!("{:?}", stats.max),
+            );
+        }
+
+        let _ = writeln!(
+            summary,
+            "\n{:<24} {:>6} {:>12} {:>12}",
+            "Stream", "items", "mean gap", "max gap"
+        );
+        for (name, stats) in self.streams.lock().unwrap().iter() {
+            let _ = writeln!(
+                summary,
+                "{:<24} {:>6} {:>12} {:>12}",
+                name,
+                stats.items,
+                format!("{:?}", mean(stats.total_gap, stats.items)),
+                format!("{:?}", stats.max_gap),
+            );
+        }
+
+        summary
+    }
+}
+
+fn mean(total: Duration, count: u64) -> Duration {
+    match count {
+        0 => Duration::ZERO,
+        count => total / count.min(u32::MAX as u64) as u32,
+    }
+}
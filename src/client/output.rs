@@ -0,0 +1,536 @@
+use std::fmt::Display;
+use std::io::IsTerminal;
+
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use models::proto::timestamp_secs;
+use proto::notifications_response::Event;
+use proto::{
+    ConversationResponse, DirectMessage, FriendshipEvent, FriendshipState, Message,
+    NotificationKind, NotificationsResponse, Presence, UserResponse,
+};
+
+/// How timeline entries, friend lists and notifications are printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// For humans: names, local dates and colors.
+    #[default]
+    Text,
+    /// A JSON object per line on stdout, with ids and timestamps, so that it can be piped into
+    /// `jq`. The other messages go to stderr.
+    Json,
+}
+
+/// When text is colored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorMode {
+    /// When stdout is a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+const BOLD: &str = "1";
+const DIM: &str = "2";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+const CYAN: &str = "36";
+
+/// Prints what the client receives. Names of users are resolved by the caller, they are only
+/// used by the text format.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Output {
+    format: Format,
+    colors: bool,
+}
+
+impl Output {
+    pub fn new(format: Format, color: ColorMode) -> Self {
+        let colors = match color {
+            ColorMode::Auto => std::io::stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        };
+
+        Self {
+            format,
+            colors: colors && format == Format::Text,
+        }
+    }
+
+    /// Progress and status messages, kept out of stdout in JSON.
+    pub fn status(self, line: impl Display) {
+        match self.format {
+            Format::Text => println!("{line}"),
+            Format::Json => eprintln!("{line}"),
+        }
+    }
+
+    /// `presence` of the author, `None` when it is not a friend or is unknown.
+    pub fn post(self, post: &Message, author: &str, presence: Option<&Presence>) {
+        match self.format {
+            Format::Text => {
+                let state = match post.read {
+                    true => self.paint(DIM, "✓ read"),
+                    false => self.paint(YELLOW, "● unread"),
+                };
+                println!(
+                    "{} {}{} {state} {}",
+                    self.paint(DIM, local_date(timestamp_secs(post.timestamp.as_ref()))),
+                    self.presence_marker(presence),
+                    self.paint(BOLD, author),
+                    self.paint(DIM, format!("#{}", post.message_id)),
+                );
+                if let Some(reply_to) = &post.reply_to {
+                    println!("{}", self.paint(DIM, format!("↪ en réponse à #{reply_to}")));
+                }
+                println!("{}", post.content);
+            }
+            Format::Json => print_json(&Post::from(post)),
+        }
+    }
+
+    /// A message just posted by the user.
+    pub fn posted(self, post: &Message) {
+        match self.format {
+            Format::Text => println!(
+                "✅ Posted message {} on {}",
+                self.paint(BOLD, format!("#{}", post.message_id)),
+                local_date(timestamp_secs(post.timestamp.as_ref()))
+            ),
+            Format::Json => print_json(&Post::from(post)),
+        }
+    }
+
+    /// A direct message just sent by the user.
+    pub fn sent(self, direct_message: &DirectMessage) {
+        match self.format {
+            Format::Text => println!("✅ Message sent"),
+            Format::Json => print_json(&Direct::from(direct_message)),
+        }
+    }
+
+    pub fn unread(self, count: u64) {
+        match self.format {
+            Format::Text => println!("📬 {count} unread messages"),
+            Format::Json => print_json(&Unread { unread: count }),
+        }
+    }
+
+    /// A message just marked as read or unread by the user.
+    pub fn tagged(self, message_id: &str, read: bool) {
+        match self.format {
+            Format::Text => match read {
+                true => println!("✅ Marked message {message_id} as read"),
+                false => println!("✅ Marked message {message_id} as unread"),
+            },
+            Format::Json => print_json(&Tagged { message_id, read }),
+        }
+    }
+
+    /// The friendship with `friend_id` once the user asked, answered or removed it.
+    pub fn friendship(self, friend_id: &str, state: FriendshipState) {
+        match self.format {
+            Format::Text => match state {
+                FriendshipState::Accepted => println!("✅ Successfully added friend {friend_id}"),
+                FriendshipState::Pending => println!("✅ Asked {friend_id} to be friends"),
+                FriendshipState::Declined => println!("✅ Declined the request of {friend_id}"),
+                FriendshipState::NotFriends => {
+                    println!("✅ Successfully removed friend {friend_id}")
+                }
+                FriendshipState::Blocked => println!("🚫 {friend_id} is blocked"),
+            },
+            Format::Json => print_json(&Friendship {
+                friend_id,
+                state: match state {
+                    FriendshipState::NotFriends => "not_friends",
+                    FriendshipState::Pending => "pending",
+                    FriendshipState::Accepted => "accepted",
+                    FriendshipState::Declined => "declined",
+                    FriendshipState::Blocked => "blocked",
+                },
+            }),
+        }
+    }
+
+    /// `changed` is false when the user already was, or was not, muted.
+    pub fn muted(self, user_id: &str, muted: bool, changed: bool) {
+        match self.format {
+            Format::Text => match (muted, changed) {
+                (true, true) => println!("🔇 {user_id} muted"),
+                (true, false) => println!("{user_id} is already muted"),
+                (false, true) => println!("🔊 {user_id} unmuted"),
+                (false, false) => println!("{user_id} was not muted"),
+            },
+            Format::Json => print_json(&Muted {
+                user_id,
+                muted,
+                changed,
+            }),
+        }
+    }
+
+    pub fn user(self, user: &UserResponse) {
+        match self.format {
+            Format::Text => println!(
+                "👤 {} {}",
+                self.paint(BOLD, &user.name),
+                self.paint(DIM, format!("({})", user.user_id))
+            ),
+            Format::Json => print_json(&User {
+                user_id: &user.user_id,
+                name: &user.name,
+            }),
+        }
+    }
+
+    /// `presence` is `None` when it could not be fetched.
+    pub fn friend(self, friend: &UserResponse, presence: Option<&Presence>) {
+        let (online, last_seen_at) = presence.map_or((false, 0), |p| {
+            (p.online, timestamp_secs(p.last_seen_at.as_ref()))
+        });
+
+        match self.format {
+            Format::Text => {
+                let name = self.paint(BOLD, &friend.name);
+                let id = self.paint(DIM, format!("({})", friend.user_id));
+
+                match (online, last_seen_at) {
+                    (true, _) => println!("🟢 {name} {id} is {}", self.paint(GREEN, "online")),
+                    (false, 0) => println!("⚪ {name} {id} was never seen"),
+                    (false, last_seen_at) => println!(
+                        "⚪ {name} {id} was last seen on {}",
+                        local_date(last_seen_at)
+                    ),
+                }
+            }
+            Format::Json => print_json(&Friend {
+                user_id: &friend.user_id,
+                name: &friend.name,
+                online,
+                // Never seen.
+                last_seen_at: (last_seen_at != 0).then_some(last_seen_at),
+            }),
+        }
+    }
+
+    /// A pending request, made by `user_id` or to it.
+    pub fn friend_request(self, request: &FriendshipEvent, user_id: &str) {
+        let incoming = request.target_id == user_id;
+
+        match self.format {
+            Format::Text => match incoming {
+                true => println!(
+                    "📩 {} wants to be your friend",
+                    self.paint(BOLD, &request.initiator_id)
+                ),
+                false => println!(
+                    "📤 {} did not answer your request yet",
+                    self.paint(BOLD, &request.target_id)
+                ),
+            },
+            Format::Json => print_json(&FriendRequest {
+                initiator_id: &request.initiator_id,
+                target_id: &request.target_id,
+                incoming,
+            }),
+        }
+    }
+
+    pub fn direct_message(self, direct_message: &DirectMessage, author: &str) {
+        match self.format {
+            Format::Text => self.print_direct_message(direct_message, author),
+            Format::Json => print_json(&Direct::from(direct_message)),
+        }
+    }
+
+    pub fn conversation(self, conversation: &ConversationResponse) {
+        match self.format {
+            Format::Text => println!(
+                "📨 {} with {}",
+                self.paint(BOLD, &conversation.conversation_id),
+                conversation.member_ids.join(", ")
+            ),
+            Format::Json => print_json(&Conversation {
+                conversation_id: &conversation.conversation_id,
+                member_ids: &conversation.member_ids,
+            }),
+        }
+    }
+
+    /// `user` is the name of the one of `notification_user`.
+    pub fn notification(self, notification: &NotificationsResponse, user: &str) {
+        match self.format {
+            Format::Text => self.print_notification(notification, user),
+            Format::Json => print_json(&Notification::from(notification)),
+        }
+    }
+
+    fn presence_marker(self, presence: Option<&Presence>) -> String {
+        match presence {
+            Some(presence) if presence.online => format!("{} ", self.paint(GREEN, "●")),
+            Some(_) => format!("{} ", self.paint(DIM, "○")),
+            None => String::new(),
+        }
+    }
+
+    fn paint(self, style: &str, text: impl Display) -> String {
+        match self.colors {
+            true => format!("\x1b[{style}m{text}\x1b[0m"),
+            false => text.to_string(),
+        }
+    }
+
+    fn print_direct_message(self, direct_message: &DirectMessage, author: &str) {
+        if let Some(message) = &direct_message.message {
+            println!(
+                "💬 {} {} {} : {}",
+                self.paint(DIM, format!("[{}]", direct_message.conversation_id)),
+                self.paint(DIM, local_date(timestamp_secs(message.timestamp.as_ref()))),
+                self.paint(CYAN, author),
+                message.content
+            );
+        }
+    }
+
+    fn print_notification(self, notification: &NotificationsResponse, user: &str) {
+        let name = self.paint(CYAN, user);
+
+        match (notification.kind(), &notification.event) {
+            (NotificationKind::NewMessage, Some(Event::NewMessage(message))) => println!(
+                "{} {name} a posté un nouveau message : {}",
+                self.paint(DIM, local_date(timestamp_secs(message.timestamp.as_ref()))),
+                message.content
+            ),
+            (NotificationKind::Mention, Some(Event::NewMessage(message))) => println!(
+                "{} {name} vous a mentionné : {}",
+                self.paint(DIM, local_date(timestamp_secs(message.timestamp.as_ref()))),
+                message.content
+            ),
+            (NotificationKind::NewFriend, _) => println!("{name} est maintenant votre ami"),
+            (NotificationKind::FriendRemoved, _) => println!("{name} n'est plus votre ami"),
+            (NotificationKind::FriendRequest, _) => println!("{name} vous demande en ami"),
+            (NotificationKind::FriendAccepted, _) => {
+                println!("{name} a accepté votre demande d'ami")
+            }
+            (NotificationKind::System, Some(Event::Notice(notice))) => {
+                println!("📢 {}", self.paint(YELLOW, &notice.text))
+            }
+            (NotificationKind::MessageSeen, Some(Event::ReadReceipt(read))) => println!(
+                "{name} a lu votre message {}",
+                self.paint(DIM, format!("#{}", read.message_id))
+            ),
+            (NotificationKind::MessageUnseen, Some(Event::ReadReceipt(read))) => println!(
+                "{name} a marqué votre message {} comme non lu",
+                self.paint(DIM, format!("#{}", read.message_id))
+            ),
+            (NotificationKind::DirectMessage, Some(Event::DirectMessage(direct_message))) => {
+                self.print_direct_message(direct_message, user);
+            }
+            (NotificationKind::Presence, Some(Event::Presence(presence))) => {
+                match presence.online {
+                    true => println!("🟢 {name} est en ligne"),
+                    false => println!("⚪ {name} est hors ligne"),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The id of the user a notification comes from: the author of its message, the friend, the
+/// reader or the one whose presence changed. Empty for system notices.
+pub fn notification_user(notification: &NotificationsResponse) -> &str {
+    match &notification.event {
+        Some(Event::NewMessage(message)) => &message.user_id,
+        Some(Event::DirectMessage(direct_message)) => direct_message
+            .message
+            .as_ref()
+            .map_or("", |message| &message.user_id),
+        Some(Event::Friendship(friendship)) => &friendship.friend,
+        Some(Event::ReadReceipt(read)) => &read.user_id,
+        Some(Event::Presence(presence)) => &presence.user_id,
+        Some(Event::Notice(_)) | None => "",
+    }
+}
+
+/// Seconds since epoch, in the timezone of the client.
+fn local_date(timestamp: u64) -> String {
+    match DateTime::from_timestamp(timestamp as i64, 0) {
+        Some(date) => date
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+fn print_json(value: &impl Serialize) {
+    match serde_json::to_string(value) {
+        Ok(line) => println!("{line}"),
+        Err(e) => eprintln!("❌ Error: {e}"),
+    }
+}
+
+#[derive(Serialize)]
+struct Post<'a> {
+    message_id: &'a str,
+    user_id: &'a str,
+    /// Seconds since epoch.
+    timestamp: u64,
+    content: &'a str,
+    read: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    mentions: &'a [String],
+}
+
+impl<'a> From<&'a Message> for Post<'a> {
+    fn from(message: &'a Message) -> Self {
+        Self {
+            message_id: &message.message_id,
+            user_id: &message.user_id,
+            timestamp: timestamp_secs(message.timestamp.as_ref()),
+            content: &message.content,
+            read: message.read,
+            reply_to: message.reply_to.as_deref(),
+            mentions: &message.mentions,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct User<'a> {
+    user_id: &'a str,
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct Friend<'a> {
+    user_id: &'a str,
+    name: &'a str,
+    online: bool,
+    last_seen_at: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Unread {
+    unread: u64,
+}
+
+#[derive(Serialize)]
+struct Tagged<'a> {
+    message_id: &'a str,
+    read: bool,
+}
+
+#[derive(Serialize)]
+struct Friendship<'a> {
+    friend_id: &'a str,
+    state: &'static str,
+}
+
+#[derive(Serialize)]
+struct Muted<'a> {
+    user_id: &'a str,
+    muted: bool,
+    /// Not muted, or unmuted, already.
+    changed: bool,
+}
+
+#[derive(Serialize)]
+struct FriendRequest<'a> {
+    initiator_id: &'a str,
+    target_id: &'a str,
+    /// Made to the user.
+    incoming: bool,
+}
+
+#[derive(Serialize)]
+struct Notification<'a> {
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<Post<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    direct_message: Option<Direct<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    online: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct Direct<'a> {
+    conversation_id: &'a str,
+    message: Option<Post<'a>>,
+}
+
+impl<'a> From<&'a DirectMessage> for Direct<'a> {
+    fn from(direct_message: &'a DirectMessage) -> Self {
+        Self {
+            conversation_id: &direct_message.conversation_id,
+            message: direct_message.message.as_ref().map(Post::from),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Conversation<'a> {
+    conversation_id: &'a str,
+    member_ids: &'a [String],
+}
+
+impl<'a> From<&'a NotificationsResponse> for Notification<'a> {
+    fn from(notification: &'a NotificationsResponse) -> Self {
+        let kind = match notification.kind() {
+            NotificationKind::NewMessage => "new_message",
+            NotificationKind::NewFriend => "new_friend",
+            NotificationKind::FriendRemoved => "friend_removed",
+            NotificationKind::MessageSeen => "message_seen",
+            NotificationKind::Mention => "mention",
+            NotificationKind::MessageUnseen => "message_unseen",
+            NotificationKind::DirectMessage => "direct_message",
+            NotificationKind::FriendRequest => "friend_request",
+            NotificationKind::FriendAccepted => "friend_accepted",
+            NotificationKind::System => "system",
+            NotificationKind::Presence => "presence",
+        };
+
+        let mut json = Self {
+            kind,
+            user_id: None,
+            message_id: None,
+            message: None,
+            direct_message: None,
+            text: None,
+            online: None,
+        };
+        match &notification.event {
+            Some(Event::NewMessage(message)) => json.message = Some(Post::from(message)),
+            Some(Event::DirectMessage(direct_message)) => {
+                json.direct_message = Some(Direct::from(direct_message))
+            }
+            Some(Event::Friendship(friendship)) => json.user_id = Some(&friendship.friend),
+            Some(Event::ReadReceipt(read)) => {
+                json.user_id = Some(&read.user_id);
+                json.message_id = Some(&read.message_id);
+            }
+            Some(Event::Presence(presence)) => {
+                json.user_id = Some(&presence.user_id);
+                json.online = Some(presence.online);
+            }
+            Some(Event::Notice(notice)) => json.text = Some(&notice.text),
+            None => {}
+        }
+
+        json
+    }
+}
@@ -0,0 +1,176 @@
+//! Line editing of the interactive loop: a history kept between sessions, searched with Ctrl-R,
+//! and tab completion of the actions and of the names of friends.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+/// Completed as the first word of a line.
+const ACTIONS: &[&str] = &[
+    "timeline",
+    "unread",
+    "read",
+    "post",
+    "search",
+    "dm",
+    "inbox",
+    "mute",
+    "unmute",
+    "add_friend",
+    "accept_friend",
+    "decline_friend",
+    "rm_friend",
+    "friends",
+    "requests",
+    "whoami",
+    "close",
+];
+
+type Names = Arc<Mutex<BTreeSet<String>>>;
+
+struct ActionHelper {
+    names: Names,
+}
+
+impl Completer for ActionHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..];
+
+        let candidates: Vec<String> = match start {
+            0 => ACTIONS
+                .iter()
+                .filter(|action| action.starts_with(word))
+                .map(|action| action.to_string())
+                .collect(),
+            _ => self
+                .names
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|name| name.starts_with(word))
+                .cloned()
+                .collect(),
+        };
+
+        let pairs = candidates
+            .into_iter()
+            .map(|candidate| Pair {
+                display: candidate.clone(),
+                replacement: candidate,
+            })
+            .collect();
+
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ActionHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ActionHelper {}
+
+impl Validator for ActionHelper {}
+
+impl Helper for ActionHelper {}
+
+/// Reads the lines typed by the user. The actions are added to the history, saved to `history`
+/// as they are typed.
+pub struct Prompt {
+    editor: Mutex<Editor<ActionHelper, DefaultHistory>>,
+    history: Option<PathBuf>,
+    names: Names,
+}
+
+impl Prompt {
+    pub fn new(history: Option<PathBuf>) -> Result<Self, Error> {
+        let names = Names::default();
+
+        let mut editor = Editor::new()?;
+        editor.set_helper(Some(ActionHelper {
+            names: names.clone(),
+        }));
+        if let Some(history) = &history {
+            // Not there yet on the first session.
+            let _ = editor.load_history(history);
+        }
+
+        Ok(Self {
+            editor: Mutex::new(editor),
+            history,
+            names,
+        })
+    }
+
+    /// In the data directory of the user, eg. `~/.local/share/tsn/history`.
+    pub fn default_history() -> Option<PathBuf> {
+        let directory = dirs::data_dir()?.join("tsn");
+        std::fs::create_dir_all(&directory).ok()?;
+
+        Some(directory.join("history"))
+    }
+
+    /// Completed after the action, eg. `dm Bob`.
+    pub fn add_names(&self, names: impl IntoIterator<Item = String>) {
+        self.names.lock().unwrap().extend(names);
+    }
+
+    /// An action, `None` at the end of the input, eg. on Ctrl-D.
+    pub fn action(&self, message: &str) -> Result<Option<String>, Error> {
+        let line = self.read_line(message)?;
+
+        if let Some(line) = line.as_deref().filter(|line| !line.trim().is_empty()) {
+            let mut editor = self.editor.lock().unwrap();
+            editor.add_history_entry(line)?;
+            if let Some(history) = &self.history {
+                if let Err(e) = editor.save_history(history) {
+                    eprintln!("❌ Can't save the history: {e}");
+                }
+            }
+        }
+
+        Ok(line)
+    }
+
+    /// A line that is not an action, eg. the content of a post. Empty at the end of the input.
+    pub fn text(&self, message: &str) -> Result<String, Error> {
+        Ok(self.read_line(message)?.unwrap_or_default())
+    }
+
+    pub fn confirm(&self, message: &str) -> Result<bool, Error> {
+        let answer = self.text(&format!("{message} [y/n] "))?;
+
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+    }
+
+    /// Ctrl-C clears the line rather than closing the client.
+    fn read_line(&self, message: &str) -> Result<Option<String>, Error> {
+        let mut editor = self.editor.lock().unwrap();
+
+        // Blocks on the terminal, without blocking the notifications printed meanwhile.
+        match tokio::task::block_in_place(|| editor.readline(message)) {
+            Ok(line) => Ok(Some(line)),
+            Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+            Err(ReadlineError::Eof) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! Checks the configs of the servers and generates new ones, without connecting to anything, eg.
+//! in the pipeline of a deploy.
+
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use config::TEMPLATE;
+
+use tsn_server::cli::ConfigArgs;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Checks a config as the servers would load it, `TSN_` environment variables included.
+    /// Exits with an error when it is invalid.
+    Validate {
+        #[command(flatten)]
+        config: ConfigArgs,
+    },
+    /// Prints a commented config with every field.
+    Generate,
+}
+
+fn main() -> ExitCode {
+    match Args::parse().command {
+        Command::Validate { config } => match config.validate("./config/config.dev.json") {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::FAILURE,
+        },
+        Command::Generate => {
+            print!("{TEMPLATE}");
+            ExitCode::SUCCESS
+        }
+    }
+}
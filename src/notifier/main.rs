@@ -0,0 +1,37 @@
+//! Serves only the notification streams, fed by the NATS events of the servers, so that the
+//! fan-out to clients can be scaled apart from the write path.
+
+use clap::Parser;
+use config::ServiceKind;
+
+use tsn_server::api::ServerState;
+use tsn_server::cli::ConfigArgs;
+use tsn_server::logging;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let configs = args.config.watch("./config/config.notifier.dev.json")?;
+    let config = configs.borrow().clone();
+    let log = logging::init(&config.telemetry())?;
+
+    let server_state = ServerState::new(config.clone()).await?;
+
+    tokio::spawn(tsn_server::apply_reloads(
+        configs,
+        server_state.clone(),
+        log,
+    ));
+
+    let served = tsn_server::serve(&config, &server_state, ServiceKind::Notifier).await;
+    logging::shutdown();
+
+    served
+}
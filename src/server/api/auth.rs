@@ -0,0 +1,78 @@
+use std::str::FromStr;
+
+use models::users::{UserId, UserIdParsingError};
+use services::auth::TokenAuthority;
+use thiserror::Error;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use super::helpers::ErrorStatus;
+
+#[derive(Error, Debug)]
+pub enum AuthorizationError {
+    #[error("invalid user_id")]
+    UserId(#[from] UserIdParsingError),
+    #[error("missing authorization token")]
+    MissingToken,
+    #[error("user_id is not the authenticated user")]
+    OtherUser,
+}
+
+/// Inserted in the extensions of requests carrying a valid token.
+#[derive(Clone, Copy, Debug)]
+pub struct AuthenticatedUser(pub UserId);
+
+/// Rejects requests with an invalid token. Requests without token go through, so that public RPCs
+/// can be called: the others check the `AuthenticatedUser` with `authorize`.
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    authority: Option<TokenAuthority>,
+}
+
+impl AuthInterceptor {
+    pub fn new(authority: Option<TokenAuthority>) -> Self {
+        Self { authority }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let Some(authority) = &self.authority else {
+            return Ok(request);
+        };
+        let Some(header) = request.metadata().get("authorization") else {
+            return Ok(request);
+        };
+
+        let token = header
+            .to_str()
+            .ok()
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("malformed authorization header"))?;
+        let user = authority.verify(token).map_err(Status::error_auth)?;
+        tracing::Span::current().record("user_id", tracing::field::display(user));
+
+        request.extensions_mut().insert(AuthenticatedUser(user));
+        Ok(request)
+    }
+}
+
+/// Parses the `user_id` of a request and, when authentication is enabled, checks that it is the
+/// authenticated user.
+pub fn authorize<T>(
+    authority: Option<&TokenAuthority>,
+    request: &Request<T>,
+    user_id: &str,
+) -> Result<UserId, AuthorizationError> {
+    let user = UserId::from_str(user_id)?;
+
+    if authority.is_none() {
+        return Ok(user);
+    }
+
+    match request.extensions().get::<AuthenticatedUser>() {
+        Some(AuthenticatedUser(authenticated)) if *authenticated == user => Ok(user),
+        Some(_) => Err(AuthorizationError::OtherUser),
+        None => Err(AuthorizationError::MissingToken),
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::Request;
+use tonic::transport::NamedService;
+use tower::{Layer, Service};
+
+use config::CompressionConfig;
+
+use super::helpers::method_name;
+
+/// Header listing the encodings accepted by the client for the responses.
+const ACCEPT_ENCODING_HEADER: &str = "grpc-accept-encoding";
+
+/// Keeps the responses of the methods left out of a `CompressionConfig` uncompressed, tonic only
+/// enables compression per service: clients are made to look like they don't accept it.
+#[derive(Clone, Debug, Default)]
+pub struct CompressionLayer {
+    /// Every method when `None`.
+    methods: Option<Arc<HashSet<String>>>,
+}
+
+impl CompressionLayer {
+    pub fn new(config: &CompressionConfig) -> Self {
+        let methods = match config.methods.is_empty() {
+            true => None,
+            false => Some(Arc::new(config.methods.iter().cloned().collect())),
+        };
+
+        Self { methods }
+    }
+}
+
+impl<S> Layer<S> for CompressionLayer {
+    type Service = CompressionService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CompressionService {
+            inner,
+            methods: self.methods.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CompressionService<S> {
+    inner: S,
+    methods: Option<Arc<HashSet<String>>>,
+}
+
+impl<S, B> Service<Request<B>> for CompressionService<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        if let Some(methods) = &self.methods {
+            let compressed =
+                method_name(request.uri().path()).is_some_and(|method| methods.contains(method));
+
+            if !compressed {
+                request.headers_mut().remove(ACCEPT_ENCODING_HEADER);
+            }
+        }
+
+        self.inner.call(request)
+    }
+}
+
+impl<S: NamedService> NamedService for CompressionService<S> {
+    const NAME: &'static str = S::NAME;
+}
@@ -0,0 +1,91 @@
+use futures::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use proto::health::v1::health_check_response::ServingStatus;
+use proto::health::v1::health_server::Health;
+use proto::health::v1::{HealthCheckRequest, HealthCheckResponse};
+
+use super::helpers::until_shutdown;
+use super::ServerState;
+
+/// Serves `grpc.health.v1.Health` for the whole server, the empty service name, and each of
+/// `services`: SERVING once the connections are ready and while NATS is connected, as no
+/// notification can be delivered while it is lost. NOT_SERVING otherwise, and once the server is
+/// shutting down.
+#[derive(Clone)]
+pub struct HealthService {
+    state: ServerState,
+    services: Vec<&'static str>,
+}
+
+impl HealthService {
+    pub fn new(state: ServerState, services: Vec<&'static str>) -> Self {
+        Self { state, services }
+    }
+
+    fn is_served(&self, service: &str) -> bool {
+        service.is_empty() || self.services.contains(&service)
+    }
+
+    fn status(&self) -> ServingStatus {
+        let connections = &self.state.connections;
+
+        match connections.is_ready() && connections.nats_connection().is_connected() {
+            true if !*self.state.shutdown.borrow() => ServingStatus::Serving,
+            _ => ServingStatus::NotServing,
+        }
+    }
+
+    fn response(status: ServingStatus) -> HealthCheckResponse {
+        HealthCheckResponse {
+            status: status.into(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        if !self.is_served(&request.get_ref().service) {
+            return Err(Status::not_found("unknown service"));
+        }
+
+        Ok(Response::new(Self::response(self.status())))
+    }
+
+    type WatchStream =
+        std::pin::Pin<Box<dyn Stream<Item = Result<HealthCheckResponse, Status>> + Send>>;
+
+    /// Ends once the server is shutting down, after NOT_SERVING.
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        if !self.is_served(&request.get_ref().service) {
+            let unknown =
+                futures::stream::iter([Ok(Self::response(ServingStatus::ServiceUnknown))]);
+            return Ok(Response::new(Box::pin(unknown)));
+        }
+
+        let connections = self.state.connections.clone();
+        let changes = futures::stream::select(
+            connections.nats_connection().states().map(drop),
+            futures::stream::once(async move { connections.ready().await }),
+        );
+
+        let health = self.clone();
+        let statuses = changes.map(move |()| health.status());
+        let not_serving = futures::stream::once(async { ServingStatus::NotServing });
+        let mut last = None;
+        let stream = until_shutdown(statuses, self.state.shutdown.subscribe())
+            .chain(not_serving)
+            .filter(move |status| futures::future::ready(last.replace(*status) != Some(*status)))
+            .map(Self::response)
+            .map(Ok);
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
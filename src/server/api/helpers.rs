@@ -1,6 +1,19 @@
 use std::error::Error;
+use std::time::Duration;
 
-use tonic::Status;
+use futures::{Stream, StreamExt};
+use models::attachments::AttachmentError;
+use models::ModelError;
+use repository::RepositoryError;
+use services::auth::AuthError;
+use services::content::ContentError;
+use services::moderation::ModerationError;
+use services::rate_limit::RateLimitError;
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+use tonic::{Request, Status};
+
+use super::auth::AuthorizationError;
 
 pub trait ErrorStatus {
     fn error_internal(error: impl std::fmt::Display) -> Status {
@@ -10,6 +23,224 @@ pub trait ErrorStatus {
     fn error_invalid_argument(error: impl std::fmt::Display) -> Status {
         Status::invalid_argument(format!("{error}"))
     }
+
+    fn error_repository(error: RepositoryError) -> Status {
+        match error {
+            RepositoryError::NotFound => Status::not_found(format!("{error}")),
+            RepositoryError::Conflict => Status::already_exists(format!("{error}")),
+            RepositoryError::Blocked => Status::permission_denied(format!("{error}")),
+            RepositoryError::Timeout => Status::deadline_exceeded(format!("{error}")),
+            RepositoryError::Unavailable => Status::unavailable(format!("{error}")),
+            RepositoryError::Db(_) | RepositoryError::Archive(_) | RepositoryError::Storage(_) => {
+                Status::error_internal(error)
+            }
+        }
+    }
+
+    /// Keeps the status of the repository, model and moderation errors wrapped by services.
+    fn error_services(error: anyhow::Error) -> Status {
+        let error = match error.downcast::<RepositoryError>() {
+            Ok(error) => return Status::error_repository(error),
+            Err(error) => error,
+        };
+
+        let error = match error.downcast::<ModelError>() {
+            Ok(error) => return Status::error_invalid_argument(error),
+            Err(error) => error,
+        };
+
+        match error.downcast::<ModerationError>() {
+            Ok(error) => Status::error_moderation(error),
+            Err(error) => Status::error_internal(error),
+        }
+    }
+
+    fn error_moderation(error: ModerationError) -> Status {
+        match error {
+            ModerationError::Rejected { .. } => Status::invalid_argument(format!("{error}")),
+            ModerationError::Pattern(_) => Status::error_internal(error),
+        }
+    }
+
+    fn error_content(error: ContentError) -> Status {
+        Status::invalid_argument(format!("{error}"))
+    }
+
+    fn error_attachment(error: AttachmentError) -> Status {
+        match error {
+            AttachmentError::ContentType(_) => Status::invalid_argument(format!("{error}")),
+            AttachmentError::TooLarge { .. } => Status::resource_exhausted(format!("{error}")),
+        }
+    }
+
+    fn error_rate_limit(error: RateLimitError) -> Status {
+        match error {
+            RateLimitError::RateLimited { .. } => Status::resource_exhausted(format!("{error}")),
+            RateLimitError::Backend(_) => Status::unavailable(format!("{error}")),
+        }
+    }
+
+    fn error_auth(error: AuthError) -> Status {
+        Status::unauthenticated(format!("{error}"))
+    }
+
+    fn error_authorization(error: AuthorizationError) -> Status {
+        match error {
+            AuthorizationError::UserId(_) => Status::error_invalid_argument(error),
+            AuthorizationError::MissingToken => Status::unauthenticated(format!("{error}")),
+            AuthorizationError::OtherUser => Status::permission_denied(format!("{error}")),
+        }
+    }
 }
 
 impl ErrorStatus for Status {}
+
+/// Name of the method of a request path such as `/social_network.v1.Notifier/PresenceUpdates`,
+/// the same in each service and version.
+pub fn method_name(path: &str) -> Option<&str> {
+    let (_, method) = path.strip_prefix("/social_network.")?.split_once('/')?;
+
+    Some(method)
+}
+
+/// `stream` until the server shuts down.
+pub fn until_shutdown<S: Stream>(
+    stream: S,
+    mut shutdown: watch::Receiver<bool>,
+) -> impl Stream<Item = S::Item> {
+    stream.take_until(async move {
+        let _ = shutdown.wait_for(|closed| *closed).await;
+    })
+}
+
+/// Deadline of a request, set by the client with the `grpc-timeout` header. Unary handlers are
+/// already dropped by tonic once it is reached, streams are produced by spawned tasks that need it.
+pub fn deadline<T>(request: &Request<T>) -> Option<Instant> {
+    let timeout = request.metadata().get("grpc-timeout")?.to_str().ok()?;
+
+    Some(Instant::now() + parse_grpc_timeout(timeout)?)
+}
+
+/// At most 8 digits followed by a unit.
+pub fn parse_grpc_timeout(timeout: &str) -> Option<Duration> {
+    // Not split inside a character.
+    if !timeout.is_ascii() {
+        return None;
+    }
+    let (value, unit) = timeout.split_at(timeout.len().checked_sub(1)?);
+    if value.is_empty() || value.len() > 8 || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let value: u64 = value.parse().ok()?;
+
+    Some(match unit {
+        "H" => Duration::from_secs(value * 60 * 60),
+        "M" => Duration::from_secs(value * 60),
+        "S" => Duration::from_secs(value),
+        "m" => Duration::from_millis(value),
+        "u" => Duration::from_micros(value),
+        "n" => Duration::from_nanos(value),
+        _ => return None,
+    })
+}
+
+/// In milliseconds while they fit in the 8 digits, in seconds above.
+pub fn encode_grpc_timeout(timeout: Duration) -> String {
+    const MAX: u128 = 99_999_999;
+
+    match timeout.as_millis() {
+        millis if millis <= MAX => format!("{millis}m"),
+        _ => format!("{}S", u128::from(timeout.as_secs()).min(MAX)),
+    }
+}
+
+/// Sends the items of `stream` to a client until one of them ends. Stops as soon as the client
+/// disconnects, even while waiting for the next item, so that no more database queries are made
+/// for it. Once `deadline` is reached, the client gets a `DeadlineExceeded` status.
+pub async fn forward<S, T>(
+    stream: S,
+    tx: mpsc::Sender<Result<T, Status>>,
+    deadline: Option<Instant>,
+) where
+    S: Stream<Item = Result<T, Status>>,
+{
+    let mut stream = std::pin::pin!(stream);
+    let expired = async move {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+    let mut expired = std::pin::pin!(expired);
+
+    loop {
+        tokio::select! {
+            item = stream.next() => {
+                let Some(item) = item else {
+                    break;
+                };
+
+                if tx.send(item).await.is_err() {
+                    break;
+                }
+            }
+            () = &mut expired => {
+                let _sent = tx.send(Err(Status::deadline_exceeded("deadline exceeded"))).await;
+                break;
+            }
+            () = tx.closed() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn grpc_timeout_test() {
+    for (timeout, expected) in [
+        ("2H", Duration::from_secs(2 * 60 * 60)),
+        ("3M", Duration::from_secs(3 * 60)),
+        ("4S", Duration::from_secs(4)),
+        ("5m", Duration::from_millis(5)),
+        ("6u", Duration::from_micros(6)),
+        ("7n", Duration::from_nanos(7)),
+        ("99999999m", Duration::from_millis(99_999_999)),
+    ] {
+        assert_eq!(parse_grpc_timeout(timeout), Some(expected), "{timeout}");
+    }
+
+    for timeout in [
+        "",
+        "m",
+        "5",
+        "5s",
+        "5 m",
+        "+5m",
+        "-5m",
+        "123456789m",
+        "5é",
+        "é",
+    ] {
+        assert_eq!(parse_grpc_timeout(timeout), None, "{timeout}");
+    }
+
+    assert_eq!(encode_grpc_timeout(Duration::from_millis(1500)), "1500m");
+    assert_eq!(
+        encode_grpc_timeout(Duration::from_millis(99_999_999)),
+        "99999999m"
+    );
+    // Over the 8 digits of milliseconds, then of seconds.
+    assert_eq!(
+        encode_grpc_timeout(Duration::from_millis(100_000_000)),
+        "100000S"
+    );
+    assert_eq!(
+        encode_grpc_timeout(Duration::from_secs(u64::MAX)),
+        "99999999S"
+    );
+    for timeout in [Duration::from_millis(1500), Duration::from_secs(100_000)] {
+        assert_eq!(
+            parse_grpc_timeout(&encode_grpc_timeout(timeout)),
+            Some(timeout)
+        );
+    }
+}
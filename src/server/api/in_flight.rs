@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::{HeaderMap, Request, Response};
+use hyper::body::{Bytes, HttpBody, SizeHint};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tonic::Status;
+use tower::{Layer, Service};
+
+use config::ConcurrencyConfig;
+
+use super::helpers::method_name;
+
+/// Rejects the requests over the limits of a `ConcurrencyConfig` with `UNAVAILABLE` instead of
+/// queuing them, so that a stampede on one method leaves the others available. Unlike tower's
+/// `ConcurrencyLimit`, which releases its permit once the response head is sent, permits are held
+/// until the response body is dropped: streams count for as long as they are open.
+#[derive(Clone, Debug, Default)]
+pub struct InFlightLimitLayer {
+    global: Option<Arc<Semaphore>>,
+    per_method: Arc<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl InFlightLimitLayer {
+    pub fn new(config: &ConcurrencyConfig) -> Self {
+        let per_method = config
+            .per_method
+            .iter()
+            .map(|(method, limit)| (method.clone(), Arc::new(Semaphore::new(*limit))))
+            .collect();
+
+        Self {
+            global: config
+                .max_in_flight
+                .map(|limit| Arc::new(Semaphore::new(limit))),
+            per_method: Arc::new(per_method),
+        }
+    }
+
+    /// The permits of the request, or what is over its limit. The limit of the method is checked
+    /// first, so that requests it rejects don't take a permit of the global limit.
+    fn acquire<'a>(&self, path: &'a str) -> Result<Vec<OwnedSemaphorePermit>, &'a str> {
+        let method = method_name(path).unwrap_or(path);
+        let limits = self
+            .per_method
+            .get(method)
+            .map(|semaphore| (semaphore, method))
+            .into_iter()
+            .chain(
+                self.global
+                    .iter()
+                    .map(|semaphore| (semaphore, "the server")),
+            );
+
+        limits
+            .map(|(semaphore, limited)| semaphore.clone().try_acquire_owned().map_err(|_| limited))
+            .collect()
+    }
+}
+
+impl<S> Layer<S> for InFlightLimitLayer {
+    type Service = InFlightLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        InFlightLimitService {
+            inner,
+            limits: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InFlightLimitService<S> {
+    inner: S,
+    limits: InFlightLimitLayer,
+}
+
+impl<S, B> Service<Request<B>> for InFlightLimitService<S>
+where
+    S: Service<Request<B>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let permits = match self.limits.acquire(request.uri().path()) {
+            Ok(permits) => permits,
+            Err(limited) => {
+                tracing::warn!(limited, "Too many requests in flight");
+                let status =
+                    Status::unavailable(format!("too many requests in flight on {limited}"));
+
+                return Box::pin(std::future::ready(Ok(status.to_http())));
+            }
+        };
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = response.await?;
+
+            Ok(response.map(|body| {
+                PermitBody {
+                    body,
+                    _permits: permits,
+                }
+                .boxed_unsync()
+            }))
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for InFlightLimitService<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+/// Releases the permits of its request when dropped.
+struct PermitBody {
+    body: BoxBody,
+    _permits: Vec<OwnedSemaphorePermit>,
+}
+
+impl HttpBody for PermitBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.body).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.body).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use http::header::CONTENT_TYPE;
+use http::{HeaderMap, Request, Response, StatusCode};
+use hyper::body::{Bytes, HttpBody, SizeHint};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::Body;
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tonic::{Code, Status};
+use tower::{Layer, Service};
+
+use proto::NotificationsResponse;
+use services::deliveries::Deliveries;
+
+use super::helpers::method_name;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct RpcCounters {
+    requests: u64,
+    seconds: f64,
+}
+
+/// Counts the RPCs by method and status code, with their duration until the end of their
+/// response: streams are counted once they end, `CANCELLED` when the client leaves first. The
+/// counters are shared by the clones, and served to Prometheus by `exporter`, along with the ones
+/// of the notification deliveries when set.
+#[derive(Clone, Debug, Default)]
+pub struct RpcMetricsLayer {
+    rpcs: Arc<Mutex<BTreeMap<(String, i32), RpcCounters>>>,
+    deliveries: Option<Deliveries<NotificationsResponse>>,
+}
+
+impl RpcMetricsLayer {
+    pub fn with_deliveries(self, deliveries: Deliveries<NotificationsResponse>) -> Self {
+        Self {
+            deliveries: Some(deliveries),
+            ..self
+        }
+    }
+
+    fn record(&self, method: String, code: Code, started: Instant) {
+        let mut rpcs = self.rpcs.lock().unwrap();
+        let counters = rpcs.entry((method, code as i32)).or_default();
+        counters.requests += 1;
+        counters.seconds += started.elapsed().as_secs_f64();
+    }
+
+    /// In the text format of Prometheus.
+    pub fn render(&self) -> String {
+        let rpcs = self.rpcs.lock().unwrap();
+        let mut text = String::new();
+
+        let _ = writeln!(
+            text,
+            "# HELP tsn_rpc_requests_total RPCs by method and status code, once they ended.\n\
+             # TYPE tsn_rpc_requests_total counter"
+        );
+        for ((method, code), counters) in rpcs.iter() {
+            let _ = writeln!(
+                text,
+                "tsn_rpc_requests_total{{method=\"{method}\",code=\"{:?}\"}} {}",
+                Code::from_i32(*code),
+                counters.requests
+            );
+        }
+
+        let _ = writeln!(
+            text,
+            "# HELP tsn_rpc_duration_seconds_total Time spent in the RPCs counted by tsn_rpc_requests_total.\n\
+             # TYPE tsn_rpc_duration_seconds_total counter"
+        );
+        for ((method, code), counters) in rpcs.iter() {
+            let _ = writeln!(
+                text,
+                "tsn_rpc_duration_seconds_total{{method=\"{method}\",code=\"{:?}\"}} {}",
+                Code::from_i32(*code),
+                counters.seconds
+            );
+        }
+
+        if let Some(deliveries) = &self.deliveries {
+            let stats = deliveries.stats();
+            let counters = [
+                (
+                    "sent",
+                    "Notifications sent to clients acknowledging them.",
+                    stats.sent,
+                ),
+                (
+                    "acknowledged",
+                    "Of the sent notifications, acknowledged.",
+                    stats.acknowledged,
+                ),
+                (
+                    "resent",
+                    "Notifications sent again, unacknowledged.",
+                    stats.resent,
+                ),
+                (
+                    "dropped",
+                    "Notifications forgotten unacknowledged.",
+                    stats.dropped,
+                ),
+            ];
+            for (name, help, value) in counters {
+                let _ = writeln!(
+                    text,
+                    "# HELP tsn_notifications_{name}_total {help}\n\
+                     # TYPE tsn_notifications_{name}_total counter\n\
+                     tsn_notifications_{name}_total {value}"
+                );
+            }
+
+            let _ = writeln!(
+                text,
+                "# HELP tsn_notifications_pending Notifications waiting for their acknowledgement.\n\
+                 # TYPE tsn_notifications_pending gauge\n\
+                 tsn_notifications_pending {}",
+                stats.pending
+            );
+        }
+
+        text
+    }
+
+    /// Binds `addr`, so that startup fails when it is taken. The returned future serves the
+    /// counters on `/metrics` over HTTP/1.1.
+    pub fn exporter(
+        &self,
+        addr: SocketAddr,
+    ) -> Result<impl Future<Output = Result<(), hyper::Error>> + Send, hyper::Error> {
+        let metrics = self.clone();
+        let make_service = make_service_fn(move |_| {
+            let metrics = metrics.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let response = match request.uri().path() {
+                        "/metrics" => Response::builder()
+                            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                            .body(Body::from(metrics.render())),
+                        _ => Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty()),
+                    };
+
+                    std::future::ready(response)
+                }))
+            }
+        });
+
+        Ok(hyper::Server::try_bind(&addr)?.serve(make_service))
+    }
+}
+
+impl<S> Layer<S> for RpcMetricsLayer {
+    type Service = RpcMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcMetricsService {
+            inner,
+            metrics: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcMetricsService<S> {
+    inner: S,
+    metrics: RpcMetricsLayer,
+}
+
+impl<S, B> Service<Request<B>> for RpcMetricsService<S>
+where
+    S: Service<Request<B>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let started = Instant::now();
+        let path = request.uri().path();
+        let method = method_name(path).unwrap_or(path).to_string();
+        let metrics = self.metrics.clone();
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = match response.await {
+                Ok(response) => response,
+                Err(e) => {
+                    metrics.record(method, Code::Unknown, started);
+                    return Err(e);
+                }
+            };
+
+            // Set in the headers when there is no message, eg. for errors.
+            let code = grpc_status(response.headers());
+
+            Ok(response.map(|body| {
+                MetricsBody {
+                    body,
+                    code,
+                    recorded: Some((metrics, method, started)),
+                }
+                .boxed_unsync()
+            }))
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for RpcMetricsService<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+fn grpc_status(headers: &HeaderMap) -> Option<Code> {
+    let code = headers.get("grpc-status")?.to_str().ok()?.parse().ok()?;
+
+    Some(Code::from_i32(code))
+}
+
+/// Records its RPC when dropped, with the status of its trailers.
+struct MetricsBody {
+    body: BoxBody,
+    code: Option<Code>,
+    recorded: Option<(RpcMetricsLayer, String, Instant)>,
+}
+
+impl HttpBody for MetricsBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = Pin::new(&mut self.body).poll_data(cx);
+        if let Poll::Ready(Some(Err(status))) = &data {
+            self.code = Some(status.code());
+        }
+
+        data
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let trailers = Pin::new(&mut self.body).poll_trailers(cx);
+        match &trailers {
+            Poll::Ready(Ok(Some(trailers))) => self.code = grpc_status(trailers).or(self.code),
+            Poll::Ready(Err(status)) => self.code = Some(status.code()),
+            _ => {}
+        }
+
+        trailers
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+impl Drop for MetricsBody {
+    fn drop(&mut self) {
+        if let Some((metrics, method, started)) = self.recorded.take() {
+            metrics.record(method, self.code.unwrap_or(Code::Cancelled), started);
+        }
+    }
+}
@@ -1,56 +1,542 @@
 use anyhow::Error;
+use futures::future::Either;
 use futures::{Stream, StreamExt, TryFutureExt, TryStreamExt};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
 use std::pin::Pin;
 use std::str::FromStr;
-use tokio::sync::mpsc;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::{mpsc, watch};
 use tokio::task::JoinHandle;
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::{Request, Response, Status};
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{instrument, Instrument};
 
 use config::ServerConfig;
-use models::messages::{Message, MessageId, Messagelike};
-use models::users::{User, UserId, Userlike};
+use models::attachments::{Attachment, AttachmentId, AttachmentPolicy};
+use models::conversations::{ChatSignalKind, ConversationId, DirectMessage};
+use models::cursor::{Cursor, CursorKey, SearchCursor};
+use models::events::EventId;
+use models::friendships::FriendshipEvent;
+use models::messages::{Message, MessageBody, MessageId, Messagelike, TimelineEntry};
+use models::notifications::NotificationKind;
+use models::reactions::Reaction;
+use models::users::{User, UserId};
 use proto::social_network_server::SocialNetwork;
 use proto::*;
+use realtime::connection::until_reconnected;
+use repository::attachments::{
+    AttachToMessageRequest, AttachmentStorage, DetachFromMessageRequest, FileAttachmentStorage,
+    InsertAttachmentRequest,
+};
+use repository::archive::{ArchiveOldBucketsRequest, FileArchiveSink};
+use repository::users::GetUserIdsRequest;
+use repository::{RepositoryError, TimeBucket};
+use services::auth::{hash_password, verify_password, TokenAuthority};
+use services::content::ContentPolicy;
+use services::conversations::{ConversationServices, ConversationlikeServices};
+use services::deliveries::Deliveries;
+use services::friendships::{publish_friendship_event, FriendCache};
 use services::messages::{MessageServices, MessagelikeServices};
+use services::moderation::{ModerationService, NoModeration, WordListModeration};
+use services::notifications::NotificationServices;
+use services::policy::{Policy, PolicySettings};
+use services::presence::PresenceServices;
+use services::rate_limit::{RateLimitPolicy, RateLimiter};
+use services::reactions::ReactionServices;
 use services::users::{UserIdServices, UserServices, UserlikeServices};
 use task_manager::TaskManager;
 
 use crate::connections::ServerConnections;
 
+mod auth;
+mod compression;
+mod health;
 mod helpers;
+mod in_flight;
+mod metrics;
+mod notifier;
+mod readiness;
+mod request_id;
+mod timeout;
+mod validation;
 
+pub use auth::AuthInterceptor;
+pub use compression::CompressionLayer;
+pub use health::HealthService;
+use auth::AuthorizationError;
 use helpers::*;
+use validation::{Validate, Violations};
+pub use in_flight::InFlightLimitLayer;
+pub use metrics::RpcMetricsLayer;
+pub use readiness::ReadinessLayer;
+pub use request_id::RequestIdLayer;
+pub use timeout::RpcTimeoutLayer;
+pub use validation::ValidationLayer;
+
+/// Of the paged RPCs, and when the client does not set one.
+const MAX_PAGE_SIZE: u32 = 100;
+const DEFAULT_PAGE_SIZE: u32 = 20;
 
 #[derive(Clone)]
 pub struct ServerState {
     connections: ServerConnections,
     task_manager: TaskManager,
-    _config: ServerConfig,
+    friend_cache: FriendCache,
+    presence: PresenceServices,
+    /// Notifications of the streams subscribed with `acknowledge`, until they are.
+    deliveries: Deliveries<NotificationsResponse>,
+    /// One per database, so that one being down does not open the circuit of the other.
+    pg_policy: Policy,
+    scylla_policy: Policy,
+    rate_limiter: Option<RateLimiter>,
+    moderation: Arc<dyn ModerationService>,
+    /// Replaced when the config is reloaded.
+    content: Arc<RwLock<ContentPolicy>>,
+    /// `None` when attachments are not configured.
+    attachments: Option<Arc<dyn AttachmentStorage>>,
+    auth: Option<TokenAuthority>,
+    /// Signs the page tokens, so that they are only decoded from what the servers sent.
+    cursor_key: CursorKey,
+    /// Set once the server is shutting down, ends the streams sent to clients.
+    shutdown: Arc<watch::Sender<bool>>,
+    config: ServerConfig,
 }
 
 impl ServerState {
     pub async fn new(config: ServerConfig) -> Result<Self, Error> {
-        Ok(Self {
-            connections: ServerConnections::new(&config).await?,
+        let connections = ServerConnections::new(&config).await?;
+        let rate_limiter = Self::rate_limiter(&config, &connections).await?;
+        let moderation = Self::moderation(&config)?;
+
+        let state = Self {
+            connections,
             task_manager: TaskManager::new(),
-            _config: config,
+            friend_cache: FriendCache::new(),
+            presence: PresenceServices::new(),
+            deliveries: Deliveries::new(),
+            pg_policy: Self::policy(&config),
+            scylla_policy: Self::policy(&config),
+            rate_limiter,
+            moderation,
+            content: Arc::new(RwLock::new(Self::content_policy(&config))),
+            attachments: Self::attachment_storage(&config),
+            auth: Self::auth(&config),
+            cursor_key: Self::cursor_key(&config),
+            shutdown: Arc::new(watch::channel(false).0),
+            config,
+        };
+
+        state.task_manager.spawn_background(
+            state
+                .friend_cache
+                .clone()
+                .listen(state.connections.get_nats()),
+        );
+        state
+            .task_manager
+            .spawn_background(state.presence.clone().listen(state.connections.get_nats()));
+        state.task_manager.spawn_background(
+            state
+                .friend_cache
+                .clone()
+                .clear_on_reconnect(state.connections.nats_connection().clone()),
+        );
+
+        Ok(state)
+    }
+
+    async fn rate_limiter(
+        config: &ServerConfig,
+        connections: &ServerConnections,
+    ) -> Result<Option<RateLimiter>, Error> {
+        let Some(rate_limit) = config.rate_limit.clone() else {
+            return Ok(None);
+        };
+
+        let policy = RateLimitPolicy {
+            burst: rate_limit.burst,
+            per_minute: rate_limit.per_minute,
+        };
+
+        let Some(bucket) = rate_limit.nats_kv_bucket else {
+            return Ok(Some(RateLimiter::in_memory(policy)));
+        };
+        // Even with `lazy_connections`, the bucket is needed to start.
+        connections.nats_connection().first_connected().await;
+
+        let jetstream = async_nats::jetstream::new(connections.get_nats());
+        let store = match jetstream.get_key_value(bucket.as_str()).await {
+            Ok(store) => store,
+            Err(_) => jetstream
+                .create_key_value(async_nats::jetstream::kv::Config {
+                    bucket,
+                    history: 1,
+                    ..Default::default()
+                })
+                .await
+                .map_err(Error::msg)?,
+        };
+        tracing::info!("Rate limits stored in NATS");
+
+        Ok(Some(RateLimiter::nats_kv(policy, store)))
+    }
+
+    fn auth(config: &ServerConfig) -> Option<TokenAuthority> {
+        let auth = config.auth.as_ref()?;
+
+        Some(TokenAuthority::new(
+            auth.secret(),
+            chrono::Duration::seconds(auth.token_ttl_secs as i64),
+        ))
+    }
+
+    /// Of the secret of `auth`, else only valid on this server until it restarts.
+    fn cursor_key(config: &ServerConfig) -> CursorKey {
+        match &config.auth {
+            Some(auth) => CursorKey::new(auth.secret().as_bytes()),
+            None => CursorKey::random(),
+        }
+    }
+
+    pub fn auth_interceptor(&self) -> AuthInterceptor {
+        AuthInterceptor::new(self.auth.clone())
+    }
+
+    fn authorize<T>(
+        &self,
+        request: &Request<T>,
+        user_id: &str,
+    ) -> Result<UserId, AuthorizationError> {
+        auth::authorize(self.auth.as_ref(), request, user_id)
+    }
+
+    /// Applies the settings of `config` that can change without a restart: the content limits
+    /// and the rate limits. Enabling or disabling rate limits needs a restart.
+    pub fn reload(&self, config: &ServerConfig) {
+        *self.content.write().unwrap() = Self::content_policy(config);
+
+        if let (Some(rate_limiter), Some(rate_limit)) = (&self.rate_limiter, &config.rate_limit) {
+            rate_limiter.set_policy(RateLimitPolicy {
+                burst: rate_limit.burst,
+                per_minute: rate_limit.per_minute,
+            });
+        }
+    }
+
+    fn content(&self) -> ContentPolicy {
+        *self.content.read().unwrap()
+    }
+
+    /// Of the notifications sent to clients acknowledging them.
+    pub fn deliveries(&self) -> Deliveries<NotificationsResponse> {
+        self.deliveries.clone()
+    }
+
+    /// Ends the timeline and notification streams so that their connections can be closed.
+    pub fn close_streams(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Whether its connections are ready, see `ServerConnections::is_ready`.
+    pub fn is_ready(&self) -> bool {
+        self.connections.is_ready()
+    }
+
+    /// Whether its connections are ready before `timeout`.
+    pub async fn wait_ready(&self, timeout: std::time::Duration) -> bool {
+        self.connections.wait_ready(timeout).await
+    }
+
+    /// Waits for the writes still in flight, eg. of clients that disconnected in the middle.
+    pub async fn drain(&self) {
+        self.task_manager.drain().await
+    }
+
+    /// Applies a client event of a chat, other than `join`.
+    async fn chat_event(
+        &self,
+        user: UserId,
+        conversation: ConversationServices,
+        event: chat_client_event::Event,
+    ) -> Result<(), Status> {
+        use chat_client_event::Event;
+
+        let nats = self.connections.get_nats();
+
+        match event {
+            Event::Join(_) => Err(Status::invalid_argument("already joined")),
+            Event::Send(content) => self
+                .send_direct_message(user, conversation, content)
+                .await
+                .map(|_| ()),
+            Event::Typing(_) => conversation
+                .signal(user, ChatSignalKind::Typing, nats)
+                .await
+                .map_err(Status::error_services),
+            Event::Ack(message_id) => {
+                let message_id =
+                    MessageId::try_parse(message_id).map_err(Status::error_invalid_argument)?;
+
+                conversation
+                    .signal(user, ChatSignalKind::Ack(message_id), nats)
+                    .await
+                    .map_err(Status::error_services)
+            }
+        }
+    }
+
+    /// Normalizes, rate limits then sends a direct message, by a chat or `SendDirectMessage`.
+    async fn send_direct_message(
+        &self,
+        user: UserId,
+        conversation: ConversationServices,
+        content: String,
+    ) -> Result<DirectMessage, Status> {
+        let content = self
+            .content()
+            .normalize(&content)
+            .map_err(Status::error_content)?;
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter
+                .check(user)
+                .await
+                .map_err(Status::error_rate_limit)?;
+        }
+
+        let state = self.clone();
+
+        self.task_manager
+            .spawn_await_result(
+                async move {
+                    conversation
+                        .send(
+                            user,
+                            content,
+                            state.moderation.as_ref(),
+                            state.connections.get_scylla(),
+                            state.connections.get_pg(),
+                            state.connections.get_nats(),
+                        )
+                        .await
+                        .map_err(Status::error_services)
+                }
+                .in_current_span(),
+            )
+            .await
+    }
+
+    /// The reaction of a request, on a message visible by the user.
+    async fn reaction(
+        &self,
+        request: Request<ReactionRequest>,
+    ) -> Result<ReactionServices, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let request = request.into_inner();
+
+        let message = MessageId::from_str(request.message_id.as_str())
+            .map_err(Status::error_invalid_argument)?;
+        let reaction =
+            Reaction::new(message, user, request.emoji).map_err(Status::error_invalid_argument)?;
+
+        let reaction = ReactionServices::new(reaction);
+        reaction
+            .ensure_visible(self.connections.get_pg(), &self.friend_cache)
+            .await
+            .map_err(Status::error_repository)?;
+
+        Ok(reaction)
+    }
+
+    /// Accepts or declines the request `friend_id` made to the user.
+    async fn answer_friend(
+        &self,
+        request: Request<FriendRequest>,
+        accept: bool,
+    ) -> Result<Response<FriendResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let request = request.into_inner();
+        let requester =
+            UserId::from_str(request.friend_id.as_str()).map_err(Status::error_invalid_argument)?;
+
+        let connections = self.connections.clone();
+        let policy = self.pg_policy.clone();
+
+        let event = self
+            .task_manager
+            .spawn_await_result(
+                async move {
+                    let answer = match accept {
+                        true => user.accept_friend(requester),
+                        false => user.decline_friend(requester),
+                    };
+
+                    // Only notify once the answer is committed.
+                    let event = policy
+                        .execute_once(answer.execute(connections.get_pg()))
+                        .map_err(Status::error_repository)
+                        .await?;
+
+                    let _ = publish_friendship_event(event, connections.get_nats()).await;
+
+                    Ok::<_, Status>(event)
+                }
+                .in_current_span(),
+            )
+            .await?;
+
+        let state: proto::FriendshipState = event.state.into();
+
+        Ok(Response::new(FriendResponse {
+            success: true,
+            state: state.into(),
+        }))
+    }
+
+    fn policy(config: &ServerConfig) -> Policy {
+        let Some(policy) = config.policy.clone() else {
+            return Policy::none();
+        };
+
+        Policy::new(PolicySettings {
+            timeout: std::time::Duration::from_millis(policy.timeout_ms),
+            retries: policy.retries,
+            backoff: std::time::Duration::from_millis(policy.backoff_ms),
+            failure_threshold: policy.failure_threshold,
+            open_for: std::time::Duration::from_secs(policy.open_secs),
         })
     }
+
+    fn moderation(config: &ServerConfig) -> Result<Arc<dyn ModerationService>, Error> {
+        let Some(moderation) = config.moderation.clone() else {
+            return Ok(Arc::new(NoModeration));
+        };
+
+        let moderation = WordListModeration::new(moderation.words, moderation.patterns)?;
+
+        Ok(Arc::new(moderation))
+    }
+
+    fn content_policy(config: &ServerConfig) -> ContentPolicy {
+        let Some(content) = &config.content else {
+            return ContentPolicy::default();
+        };
+
+        ContentPolicy::new(
+            content.max_chars.unwrap_or(Message::MAX_CONTENT_CHARS),
+            content.strip_control_chars,
+        )
+    }
+
+    fn attachment_storage(config: &ServerConfig) -> Option<Arc<dyn AttachmentStorage>> {
+        let attachments = config.attachments.as_ref()?;
+
+        Some(Arc::new(FileAttachmentStorage::new(&attachments.directory)))
+    }
+
+    /// Stores the chunks following the first one, returns the size and checksum of the
+    /// attachment.
+    async fn store_attachment(
+        storage: &dyn AttachmentStorage,
+        id: AttachmentId,
+        first: Vec<u8>,
+        chunks: &mut Streaming<AttachmentChunk>,
+        policy: &AttachmentPolicy,
+    ) -> Result<(u64, String), Status> {
+        let mut size = 0;
+        let mut hasher = Sha256::new();
+        let mut data = first;
+
+        loop {
+            size += data.len() as u64;
+            policy.check_size(size).map_err(Status::error_attachment)?;
+
+            if !data.is_empty() {
+                hasher.update(&data);
+                storage
+                    .append(id, &data)
+                    .await
+                    .map_err(Status::error_repository)?;
+            }
+
+            match chunks.message().await? {
+                Some(chunk) => data = chunk.data,
+                None => return Ok((size, format!("{:x}", hasher.finalize()))),
+            }
+        }
+    }
+
+    /// Archives old buckets in the background, when configured. To be called by a single kind of
+    /// server: the notifier leaves it to the main one. Once a run succeeded, the next ones start
+    /// from the most recent bucket it archived rather than from the first bucket of all.
+    pub fn schedule_archival(&self) {
+        let Some(archive) = self.config.archive.clone() else {
+            return;
+        };
+
+        let connections = self.connections.clone();
+        let retention = chrono::Duration::days(archive.retention_days as i64);
+        let archived_to: Arc<Mutex<Option<TimeBucket>>> = Default::default();
+
+        self.task_manager.spawn_periodic(
+            std::time::Duration::from_secs(archive.period_secs.get()),
+            move || {
+                let connections = connections.clone();
+                let archived_to = archived_to.clone();
+                let mut sink = FileArchiveSink::new(archive.directory.clone());
+
+                async move {
+                    connections.ready().await;
+                    let mut request = ArchiveOldBucketsRequest::new(retention);
+                    if let Some(bucket) = *archived_to.lock().unwrap() {
+                        request = request.ends_at(bucket);
+                    }
+
+                    let archived = async {
+                        let users: Vec<UserId> = GetUserIdsRequest::new()
+                            .stream(connections.get_pg())
+                            .try_collect()
+                            .await?;
+
+                        request
+                            .execute(&users, connections.get_scylla(), &mut sink)
+                            .await
+                    };
+                    match archived.await {
+                        Ok(archived) => {
+                            tracing::info!(archived, "Archived old messages");
+                            if let Some(bucket) = request.buckets().next() {
+                                *archived_to.lock().unwrap() = Some(bucket);
+                            }
+                        }
+                        Err(e) => tracing::error!(error = %e, "Archival failed"),
+                    }
+                }
+            },
+        );
+    }
 }
 
 #[tonic::async_trait]
 impl SocialNetwork for ServerState {
+    #[instrument(skip_all, fields(name = %request.get_ref().name))]
     async fn get_user_by_name(
         &self,
         request: Request<UserByNameRequest>,
     ) -> Result<Response<UserResponse>, Status> {
         let request = request.into_inner();
 
-        let user = UserServices::get_by_name(request.name)
-            .execute(self.connections.get_pg())
+        let request = UserServices::get_by_name(request.name);
+        let user = self
+            .pg_policy
+            .execute(|| request.clone().execute(self.connections.get_pg()))
             .await
-            .map_err(Status::error_internal)?;
+            .map_err(Status::error_repository)?;
 
         Ok(Response::new(UserResponse {
             name: user.name,
@@ -58,74 +544,392 @@ impl SocialNetwork for ServerState {
         }))
     }
 
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn get_user(
+        &self,
+        request: Request<UserRequest>,
+    ) -> Result<Response<UserResponse>, Status> {
+        let request = request.into_inner();
+        let user = UserId::from_str(&request.user_id).map_err(Status::error_invalid_argument)?;
+
+        let request = user.get_user();
+        let user = self
+            .pg_policy
+            .execute(|| request.execute(self.connections.get_pg()))
+            .await
+            .map_err(Status::error_repository)?;
+
+        Ok(Response::new(UserResponse {
+            name: user.name,
+            user_id: user.id.to_string(),
+        }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn search_users(
+        &self,
+        request: Request<SearchUsersRequest>,
+    ) -> Result<Response<SearchUsersResponse>, Status> {
+        self.authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let request = request.into_inner();
+
+        let page_size = match request.page_size {
+            0 => DEFAULT_PAGE_SIZE,
+            n => n.min(MAX_PAGE_SIZE),
+        };
+        let after = match request.page_token.as_str() {
+            "" => None,
+            token => Some(
+                SearchCursor::decode(token, &self.cursor_key)
+                    .map_err(Status::error_invalid_argument)?
+                    .into_after(),
+            ),
+        };
+
+        let request = UserServices::search(request.query, after, page_size);
+        let users = self
+            .pg_policy
+            .execute(|| request.clone().execute(self.connections.get_pg()))
+            .await
+            .map_err(Status::error_repository)?;
+
+        // Names are unique, the last one of a full page is where the next one starts.
+        let next_page_token = match users.len() as u32 == page_size {
+            true => users
+                .last()
+                .map(|user| SearchCursor::new(user.name.as_str()).encode(&self.cursor_key))
+                .unwrap_or_default(),
+            false => String::new(),
+        };
+
+        Ok(Response::new(SearchUsersResponse {
+            users: users
+                .into_iter()
+                .map(|user| UserResponse {
+                    user_id: user.id.to_string(),
+                    name: user.name,
+                })
+                .collect(),
+            next_page_token,
+        }))
+    }
+
+    #[instrument(skip_all, fields(name = %request.get_ref().name))]
+    async fn login(
+        &self,
+        request: Request<LoginRequest>,
+    ) -> Result<Response<LoginResponse>, Status> {
+        let Some(authority) = &self.auth else {
+            return Err(Status::failed_precondition("authentication is disabled"));
+        };
+        let LoginRequest { name, password } = request.into_inner();
+        let request = UserServices::get_credentials(name);
+
+        // Whether the name is unknown or the password is wrong is not told apart.
+        let (user, hash) = match self
+            .pg_policy
+            .execute(|| request.clone().execute(self.connections.get_pg()))
+            .await
+        {
+            Ok((user, Some(hash))) => (user, hash),
+            Ok((_, None)) | Err(RepositoryError::NotFound) => {
+                return Err(Status::unauthenticated("wrong name or password"));
+            }
+            Err(e) => return Err(Status::error_repository(e)),
+        };
+
+        let verified = tokio::task::spawn_blocking(move || verify_password(&password, &hash))
+            .await
+            .map_err(|_| Status::internal("the password could not be checked"))?;
+        if !verified {
+            tracing::info!(user_id = %user.id, "Login with a wrong password");
+            return Err(Status::unauthenticated("wrong name or password"));
+        }
+
+        Ok(Response::new(LoginResponse {
+            user_id: user.id.to_string(),
+            token: authority.issue(user.id),
+        }))
+    }
+
+    #[instrument(skip_all, fields(name = %request.get_ref().name))]
+    async fn create_user(
+        &self,
+        request: Request<CreateUserRequest>,
+    ) -> Result<Response<LoginResponse>, Status> {
+        let CreateUserRequest { name, password } = request.into_inner();
+        let password_hash = tokio::task::spawn_blocking(move || hash_password(&password))
+            .await
+            .map_err(|_| Status::internal("the password could not be hashed"))?;
+        let request = UserServices::insert(name, password_hash);
+
+        let connections = self.connections.clone();
+        let policy = self.pg_policy.clone();
+
+        let user = self
+            .task_manager
+            .spawn_await_result(
+                async move {
+                    policy
+                        .execute_once(request.execute(connections.get_pg()))
+                        .map_err(Status::error_repository)
+                        .await
+                }
+                .in_current_span(),
+            )
+            .await?;
+
+        tracing::info!(user_id = %user.id, "User created");
+
+        Ok(Response::new(LoginResponse {
+            user_id: user.id.to_string(),
+            token: self
+                .auth
+                .as_ref()
+                .map(|authority| authority.issue(user.id))
+                .unwrap_or_default(),
+        }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
     async fn add_friend(
         &self,
         request: Request<FriendRequest>,
     ) -> Result<Response<FriendResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
         let request = request.into_inner();
-
-        let user =
-            UserId::from_str(request.user_id.as_str()).map_err(Status::error_invalid_argument)?;
         let friend =
             UserId::from_str(request.friend_id.as_str()).map_err(Status::error_invalid_argument)?;
 
         let connections = self.connections.clone();
+        let policy = self.pg_policy.clone();
 
-        self.task_manager
-            .spawn_await_result(async move {
-                let realtime = user
-                    .realtime_friend_with(friend)
-                    .publish(connections.get_nats());
-
-                let persistence = user
-                    .friend_with(friend)
-                    .execute(connections.get_pg())
-                    .map_err(|e| Status::internal(e.to_string()));
-
-                let (_rt, persistance) = futures::join!(realtime, persistence);
-                persistance
-            })
+        let event = self
+            .task_manager
+            .spawn_await_result(
+                async move {
+                    // Only notify once the request is committed.
+                    let event = policy
+                        .execute_once(user.friend_with(friend).execute(connections.get_pg()))
+                        .map_err(Status::error_repository)
+                        .await?;
+
+                    let _ = publish_friendship_event(event, connections.get_nats()).await;
+
+                    Ok::<_, Status>(event)
+                }
+                .in_current_span(),
+            )
             .await?;
 
-        Ok(Response::new(FriendResponse { success: true }))
+        let state: proto::FriendshipState = event.state.into();
+
+        Ok(Response::new(FriendResponse {
+            success: true,
+            state: state.into(),
+        }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn accept_friend(
+        &self,
+        request: Request<FriendRequest>,
+    ) -> Result<Response<FriendResponse>, Status> {
+        self.answer_friend(request, true).await
     }
 
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn decline_friend(
+        &self,
+        request: Request<FriendRequest>,
+    ) -> Result<Response<FriendResponse>, Status> {
+        self.answer_friend(request, false).await
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
     async fn remove_friend(
         &self,
         request: Request<FriendRequest>,
     ) -> Result<Response<FriendResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
         let request = request.into_inner();
-
-        let user =
-            UserId::from_str(request.user_id.as_str()).map_err(Status::error_invalid_argument)?;
         let friend =
             UserId::from_str(request.friend_id.as_str()).map_err(Status::error_invalid_argument)?;
 
         let connections = self.connections.clone();
+        let policy = self.pg_policy.clone();
 
         self.task_manager
-            .spawn_await_result(async move {
-                let realtime = user
-                    .realtime_remove_friend(friend)
-                    .publish(connections.get_nats());
-
-                let persistence = user
-                    .remove_friend(friend)
-                    .execute(connections.get_pg())
-                    .map_err(|e| Status::internal(e.to_string()));
-
-                let (_rt, persistance) = futures::join!(realtime, persistence);
-                persistance
-            })
+            .spawn_await_result(
+                async move {
+                    // Only notify once the friendship is committed.
+                    policy
+                        .execute_once(user.remove_friend(friend).execute(connections.get_pg()))
+                        .map_err(Status::error_repository)
+                        .await?;
+
+                    let _ = user
+                        .realtime_remove_friend(friend)
+                        .publish(connections.get_nats())
+                        .await;
+
+                    Ok::<(), Status>(())
+                }
+                .in_current_span(),
+            )
             .await?;
 
-        Ok(Response::new(FriendResponse { success: true }))
+        Ok(Response::new(FriendResponse {
+            success: true,
+            state: proto::FriendshipState::NotFriends.into(),
+        }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn list_friends(
+        &self,
+        request: Request<UserRequest>,
+    ) -> Result<Response<FriendListResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+
+        let friends: Vec<User> = user
+            .get_friend_users()
+            .stream(self.connections.get_pg())
+            .try_collect()
+            .await
+            .map_err(Status::error_repository)?;
+
+        Ok(Response::new(FriendListResponse {
+            friends: friends
+                .into_iter()
+                .map(|friend| UserResponse {
+                    user_id: friend.id.to_string(),
+                    name: friend.name,
+                })
+                .collect(),
+        }))
     }
 
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn list_friend_requests(
+        &self,
+        request: Request<UserRequest>,
+    ) -> Result<Response<FriendRequestsResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+
+        let requests: Vec<FriendshipEvent> = user
+            .get_friend_requests()
+            .stream(self.connections.get_pg())
+            .try_collect()
+            .await
+            .map_err(Status::error_repository)?;
+
+        Ok(Response::new(FriendRequestsResponse {
+            requests: requests.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn block_user(
+        &self,
+        request: Request<BlockRequest>,
+    ) -> Result<Response<BlockResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let request = request.into_inner();
+        let blocked = UserId::from_str(request.blocked_id.as_str())
+            .map_err(Status::error_invalid_argument)?;
+
+        if blocked == user {
+            return Err(Status::invalid_argument("a user can't block itself"));
+        }
+
+        let connections = self.connections.clone();
+        let policy = self.pg_policy.clone();
+
+        self.task_manager
+            .spawn_await_result(
+                async move {
+                    let unfriended = policy
+                        .execute_once(user.block(blocked).execute(connections.get_pg()))
+                        .map_err(Status::error_repository)
+                        .await?;
+
+                    let _ = user
+                        .realtime_block(blocked)
+                        .publish(connections.get_nats())
+                        .await;
+                    if unfriended {
+                        let _ = user
+                            .realtime_remove_friend(blocked)
+                            .publish(connections.get_nats())
+                            .await;
+                    }
+
+                    Ok::<(), Status>(())
+                }
+                .in_current_span(),
+            )
+            .await?;
+
+        Ok(Response::new(BlockResponse { success: true }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn unblock_user(
+        &self,
+        request: Request<BlockRequest>,
+    ) -> Result<Response<BlockResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let request = request.into_inner();
+        let blocked = UserId::from_str(request.blocked_id.as_str())
+            .map_err(Status::error_invalid_argument)?;
+
+        let connections = self.connections.clone();
+        let policy = self.pg_policy.clone();
+
+        self.task_manager
+            .spawn_await_result(
+                async move {
+                    policy
+                        .execute_once(user.unblock(blocked).execute(connections.get_pg()))
+                        .map_err(Status::error_repository)
+                        .await?;
+
+                    let _ = user
+                        .realtime_unblock(blocked)
+                        .publish(connections.get_nats())
+                        .await;
+
+                    Ok::<(), Status>(())
+                }
+                .in_current_span(),
+            )
+            .await?;
+
+        Ok(Response::new(BlockResponse { success: true }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
     async fn post_message(
         &self,
         request: Request<PostMessageRequest>,
-    ) -> Result<Response<MessageStatusResponse>, Status> {
+    ) -> Result<Response<PostMessageResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
         let request = request.into_inner();
         let preview = request
             .content
@@ -134,118 +938,453 @@ impl SocialNetwork for ServerState {
             .chain("[…]".chars())
             .collect::<String>();
 
-        println!(
-            r#"User {} posted a new message: "{}""#,
-            request.user_id, preview
-        );
+        tracing::info!(preview, "Posting a new message");
 
-        let user =
-            UserId::from_str(request.user_id.as_str()).map_err(Status::error_invalid_argument)?;
+        let mut message = Message::builder(user)
+            .content(request.content)
+            .policy(self.content());
+        if let Some(id) = &request.message_id {
+            let id = MessageId::from_str(id).map_err(Status::error_invalid_argument)?;
+            message = message.id(id);
+        }
+        if let Some(reply_to) = &request.reply_to {
+            let reply_to = MessageId::from_str(reply_to).map_err(Status::error_invalid_argument)?;
+            message = message.reply_to(reply_to);
+        }
+        let attachments = request
+            .attachment_ids
+            .iter()
+            .map(AttachmentId::try_parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Status::error_invalid_argument)?;
+        let body = request
+            .body
+            .map(MessageBody::try_from)
+            .transpose()
+            .map_err(Status::error_invalid_argument)?
+            .unwrap_or_default();
+        let message = message
+            .body(body)
+            .build()
+            .map_err(Status::error_invalid_argument)?;
 
-        let message = Message::new(user, request.content);
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter
+                .check(user)
+                .await
+                .map_err(Status::error_rate_limit)?;
+        }
+
+        let attach = AttachToMessageRequest::new(user, message.id, attachments);
+        let posted: proto::Message = message.clone().into();
+        let services = MessageServices::new(message);
+        let insert = services
+            .insert(self.moderation.as_ref())
+            .await
+            .map_err(Status::error_moderation)?;
 
         let connections = self.connections.clone();
+        let policy = self.scylla_policy.clone();
+        let pg_policy = self.pg_policy.clone();
 
         self.task_manager
-            .spawn_await_result(async move {
-                let services = MessageServices::new(message);
-                let realtime = services
-                    .clone()
-                    .realtime_publish()
-                    .publish(connections.get_nats());
-
-                let persistence = services
-                    .insert()
-                    .execute(connections.get_scylla())
-                    .map_err(Status::error_internal);
+            .spawn_await_result(
+                async move {
+                    // Before the message is visible, so that it never shows without them.
+                    if !attach.attachments.is_empty() {
+                        let pg = connections.get_pg();
+
+                        pg_policy
+                            .execute(|| attach.clone().execute(pg))
+                            .await
+                            .map_err(|e| match e {
+                                RepositoryError::NotFound => Status::invalid_argument(
+                                    "unknown attachment, or attached to another message",
+                                ),
+                                e => Status::error_repository(e),
+                            })?;
+                    }
+
+                    let scylla = connections.get_scylla();
 
-                let (_rt, persistance) = futures::join!(realtime, persistence);
-                persistance
-            })
+                    match policy.execute_once(insert.execute(scylla)).await {
+                        Ok(_) => {
+                            let _ = services
+                                .realtime_publish()
+                                .publish(connections.get_nats())
+                                .await;
+                            Ok(())
+                        }
+                        // Retry of an already stored message, it has already been published.
+                        Err(RepositoryError::Conflict) => Ok(()),
+                        // May be stored, the attachments stay for a retry of the post.
+                        Err(e @ RepositoryError::Timeout) => Err(Status::error_repository(e)),
+                        Err(e) => {
+                            // Not posted, the attachments can be posted with another message.
+                            if !attach.attachments.is_empty() {
+                                let detach = DetachFromMessageRequest::new(&attach);
+                                let pg = connections.get_pg();
+                                let detached = pg_policy.execute(|| detach.clone().execute(pg));
+                                if let Err(e) = detached.await {
+                                    tracing::warn!(error = %e, "Can't detach the attachments");
+                                }
+                            }
+
+                            Err(Status::error_repository(e))
+                        }
+                    }
+                }
+                .in_current_span(),
+            )
             .await?;
 
-        let response = MessageStatusResponse { success: true };
+        let response = PostMessageResponse {
+            success: true,
+            message: Some(posted),
+        };
 
         Ok(Response::new(response))
     }
 
+    #[instrument(skip_all, fields(user_id, attachment_id))]
+    async fn upload_attachment(
+        &self,
+        mut request: Request<Streaming<AttachmentChunk>>,
+    ) -> Result<Response<AttachmentResponse>, Status> {
+        let (Some(storage), Some(config)) = (&self.attachments, &self.config.attachments) else {
+            return Err(Status::failed_precondition("attachments are not enabled"));
+        };
+
+        let Some(first) = request.get_mut().message().await? else {
+            return Err(Status::invalid_argument("no attachment was sent"));
+        };
+
+        let mut violations = Violations::default();
+        first.validate(&mut violations);
+        if let Some(status) = violations.into_status() {
+            return Err(status);
+        }
+
+        let user = self
+            .authorize(&request, &first.user_id)
+            .map_err(Status::error_authorization)?;
+        let policy = AttachmentPolicy::new(config.max_bytes, config.content_types.clone());
+        policy
+            .check_content_type(&first.content_type)
+            .map_err(Status::error_attachment)?;
+
+        let mut attachment = Attachment::new(user, first.filename, first.content_type);
+        tracing::Span::current()
+            .record("user_id", tracing::field::display(user))
+            .record("attachment_id", tracing::field::display(attachment.id));
+
+        let mut chunks = request.into_inner();
+        let stored = Self::store_attachment(
+            storage.as_ref(),
+            attachment.id,
+            first.data,
+            &mut chunks,
+            &policy,
+        )
+        .await;
+
+        let inserted = match stored {
+            Ok((size, checksum)) => {
+                attachment.size = size;
+                attachment.checksum = checksum;
+
+                let insert = InsertAttachmentRequest::new(attachment.clone());
+                let pg = self.connections.get_pg();
+                self.pg_policy
+                    .execute_once(insert.execute(pg))
+                    .await
+                    .map_err(Status::error_repository)
+            }
+            Err(status) => Err(status),
+        };
+
+        if let Err(status) = inserted {
+            if let Err(e) = storage.remove(attachment.id).await {
+                tracing::warn!(error = %e, "Can't remove the data of a failed upload");
+            }
+
+            return Err(status);
+        }
+
+        tracing::info!(size = attachment.size, "Attachment uploaded");
+
+        let url = config
+            .base_url
+            .as_deref()
+            .map(|base_url| attachment.url(base_url))
+            .unwrap_or_default();
+
+        Ok(Response::new(AttachmentResponse {
+            attachment_id: attachment.id.to_string(),
+            size: attachment.size,
+            attachment: Some(proto::Attachment {
+                url,
+                ..attachment.into()
+            }),
+        }))
+    }
+
     type TimelineStream = Pin<Box<dyn Stream<Item = Result<TimelineResponse, Status>> + Send>>;
 
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
     async fn timeline(
         &self,
         request: Request<TimelineRequest>,
     ) -> Result<Response<Self::TimelineStream>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let deadline = deadline(&request);
         let request = request.into_inner();
-        let user =
-            UserId::from_str(request.user_id.as_str()).map_err(Status::error_invalid_argument)?;
+        let before = match request.page_token.as_str() {
+            "" => None,
+            token => Some(
+                Cursor::decode(token, &self.cursor_key).map_err(Status::error_invalid_argument)?,
+            ),
+        };
+        let page_size = request.page_size.min(MAX_PAGE_SIZE) as usize;
 
         let connections = self.connections.clone();
+        let cursor_key = self.cursor_key.clone();
+        let shutdown = self.shutdown.subscribe();
 
         let (tx, rx) = mpsc::channel(128);
-        tokio::spawn(async move {
-            let mut stream = UserIdServices::new(user)
-                .get_timeline(connections.get_pg(), connections.get_scylla())
-                .await
-                .map_ok(|message| TimelineResponse {
-                    messages: vec![message.into()],
-                })
-                .map_err(Status::error_internal);
+        tokio::spawn(
+            async move {
+                let services = UserIdServices::new(user);
+                let (pg, scylla) = (connections.get_pg(), connections.get_scylla());
+
+                let unread_only = request.unread_only;
 
-            while let Some(item) = stream.next().await {
-                let _ = tx.send(item).await;
+                let entries = services
+                    .get_timeline_with_reactions_before(before, pg, scylla)
+                    .await
+                    .try_filter(move |entry| futures::future::ready(!(unread_only && entry.read)))
+                    .map_err(Status::error_internal);
+
+                let stream = match page_size {
+                    0 => entries
+                        .map_ok(|entry| TimelineResponse {
+                            messages: vec![entry.into()],
+                            next_page_token: String::new(),
+                        })
+                        .left_stream(),
+                    page_size => futures::stream::once(async move {
+                        // One more tells whether there is a next page.
+                        let mut entries: Vec<TimelineEntry> =
+                            entries.take(page_size + 1).try_collect().await?;
+
+                        let mut next_page_token = String::new();
+                        if entries.len() > page_size {
+                            entries.truncate(page_size);
+                            next_page_token = entries
+                                .last()
+                                .map(|entry| {
+                                    TimeBucket::from_datetime(entry.message.date)
+                                        .cursor(&entry.message)
+                                        .encode(&cursor_key)
+                                })
+                                .unwrap_or_default();
+                        }
+
+                        Ok(TimelineResponse {
+                            messages: entries.into_iter().map(Into::into).collect(),
+                            next_page_token,
+                        })
+                    })
+                    .right_stream(),
+                };
+
+                forward(until_shutdown(stream, shutdown), tx, deadline).await;
             }
-        });
+            .in_current_span(),
+        );
 
         let stream = ReceiverStream::new(rx);
         Ok(Response::new(Box::pin(stream)))
     }
 
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
     async fn tag_read_message(
         &self,
         request: Request<MessageTagRequest>,
     ) -> Result<Response<MessageStatusResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
         let request = request.into_inner();
-        let user =
-            UserId::from_str(request.user_id.as_str()).map_err(Status::error_invalid_argument)?;
 
         let message = MessageId::from_str(request.message_id.as_str())
             .map_err(Status::error_invalid_argument)?;
 
         let connections = self.connections.clone();
+        let policy = self.scylla_policy.clone();
 
         self.task_manager
-            .spawn_await_result(async move {
-                message
-                    .seen_by(user)
-                    .execute(connections.get_scylla())
-                    .map_err(|e| Status::internal(e.to_string()))
-                    .await
-            })
+            .spawn_await_result(
+                async move {
+                    policy
+                        .execute(|| message.seen_by(user).execute(connections.get_scylla()))
+                        .map_err(Status::error_repository)
+                        .await?;
+
+                    let _ = message
+                        .realtime_seen_by(user)
+                        .publish(connections.get_nats())
+                        .await;
+
+                    Ok::<(), Status>(())
+                }
+                .in_current_span(),
+            )
             .await?;
 
         Ok(Response::new(MessageStatusResponse { success: true }))
     }
 
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
     async fn tag_unread_message(
         &self,
         request: Request<MessageTagRequest>,
     ) -> Result<Response<MessageStatusResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
         let request = request.into_inner();
-        let user =
-            UserId::from_str(request.user_id.as_str()).map_err(Status::error_invalid_argument)?;
         let message = MessageId::from_str(request.message_id.as_str())
             .map_err(Status::error_invalid_argument)?;
 
         let connections = self.connections.clone();
+        let policy = self.scylla_policy.clone();
 
         self.task_manager
-            .spawn_await_result(async move {
-                message
-                    .unseen_by(user)
-                    .execute(connections.get_scylla())
-                    .map_err(|e| Status::internal(e.to_string()))
-                    .await
-            })
+            .spawn_await_result(
+                async move {
+                    policy
+                        .execute(|| message.unseen_by(user).execute(connections.get_scylla()))
+                        .map_err(Status::error_repository)
+                        .await?;
+
+                    let _ = message
+                        .realtime_unseen_by(user)
+                        .publish(connections.get_nats())
+                        .await;
+
+                    Ok::<(), Status>(())
+                }
+                .in_current_span(),
+            )
+            .await?;
+
+        Ok(Response::new(MessageStatusResponse { success: true }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn tag_read_messages(
+        &self,
+        request: Request<MessagesTagRequest>,
+    ) -> Result<Response<MessageStatusResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let request = request.into_inner();
+        let messages = request
+            .message_ids
+            .iter()
+            .map(MessageId::try_parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Status::error_invalid_argument)?;
+
+        let connections = self.connections.clone();
+        let policy = self.scylla_policy.clone();
+
+        self.task_manager
+            .spawn_await_result(
+                async move {
+                    let request = user.mark_seen(messages.iter().copied());
+                    policy
+                        .execute(|| request.clone().execute(connections.get_scylla()))
+                        .map_err(Status::error_repository)
+                        .await?;
+
+                    let _ = user
+                        .realtime_mark_seen(messages)
+                        .publish(connections.get_nats())
+                        .await;
+
+                    Ok::<(), Status>(())
+                }
+                .in_current_span(),
+            )
+            .await?;
+
+        Ok(Response::new(MessageStatusResponse { success: true }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn react(
+        &self,
+        request: Request<ReactionRequest>,
+    ) -> Result<Response<MessageStatusResponse>, Status> {
+        let reaction = self.reaction(request).await?;
+
+        let connections = self.connections.clone();
+        let policy = self.scylla_policy.clone();
+
+        self.task_manager
+            .spawn_await_result(
+                async move {
+                    policy
+                        .execute(|| reaction.add().execute(connections.get_scylla()))
+                        .map_err(Status::error_repository)
+                        .await?;
+
+                    let _ = reaction
+                        .realtime_added()
+                        .publish(connections.get_nats())
+                        .await;
+
+                    Ok::<(), Status>(())
+                }
+                .in_current_span(),
+            )
+            .await?;
+
+        Ok(Response::new(MessageStatusResponse { success: true }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn remove_reaction(
+        &self,
+        request: Request<ReactionRequest>,
+    ) -> Result<Response<MessageStatusResponse>, Status> {
+        let reaction = self.reaction(request).await?;
+
+        let connections = self.connections.clone();
+        let policy = self.scylla_policy.clone();
+
+        self.task_manager
+            .spawn_await_result(
+                async move {
+                    policy
+                        .execute(|| reaction.remove().execute(connections.get_scylla()))
+                        .map_err(Status::error_repository)
+                        .await?;
+
+                    let _ = reaction
+                        .realtime_removed()
+                        .publish(connections.get_nats())
+                        .await;
+
+                    Ok::<(), Status>(())
+                }
+                .in_current_span(),
+            )
             .await?;
 
         Ok(Response::new(MessageStatusResponse { success: true }))
@@ -254,33 +1393,524 @@ impl SocialNetwork for ServerState {
     type RealTimeNotificationsStream =
         Pin<Box<dyn Stream<Item = Result<NotificationsResponse, Status>> + Send>>;
 
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
     async fn real_time_notifications(
         &self,
         request: Request<NotificationsRequest>,
     ) -> Result<Response<Self::RealTimeNotificationsStream>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let deadline = deadline(&request);
         let request = request.into_inner();
-        let user =
-            UserId::from_str(request.user_id.as_str()).map_err(Status::error_invalid_argument)?;
+        let after = request
+            .after_message_id
+            .map(MessageId::try_parse)
+            .transpose()
+            .map_err(Status::error_invalid_argument)?;
+        let since = request
+            .since
+            .map(models::proto::decode_timestamp)
+            .transpose()
+            .map_err(|_| Status::invalid_argument("invalid since timestamp"))?;
+        if after.is_some() && since.is_some() {
+            return Err(Status::invalid_argument(
+                "after_message_id and since are exclusive",
+            ));
+        }
+        let kinds = request
+            .kinds
+            .iter()
+            .map(|kind| NotificationKind::try_from(*kind))
+            .collect::<Result<HashSet<_>, _>>()
+            .map_err(Status::error_invalid_argument)?;
+        let muted = request
+            .muted_user_ids
+            .iter()
+            .map(UserId::try_parse)
+            .collect::<Result<HashSet<_>, _>>()
+            .map_err(Status::error_invalid_argument)?;
+
+        // Only subscribed to when asked for, it starts with the presence of every friend.
+        let with_presence = kinds.is_empty() || kinds.contains(&NotificationKind::Presence);
+
+        // Sent again first, and kept until acknowledged as the next ones.
+        let deliveries = request.acknowledge.then(|| self.deliveries.clone());
+        let resent = match &deliveries {
+            Some(deliveries) => deliveries.unacknowledged(user),
+            None => Vec::new(),
+        };
 
         let connections = self.connections.clone();
+        let friend_cache = self.friend_cache.clone();
+        let presence = self.presence.clone();
 
+        let user = self
+            .pg_policy
+            .execute(|| user.get_user().execute(connections.get_pg()))
+            .await
+            .map_err(Status::error_repository)?;
+
+        let shutdown = self.shutdown.subscribe();
         let (tx, rx) = mpsc::channel(128);
-        tokio::spawn(async move {
-            let stream = UserIdServices::new(user)
-                .real_time_timeline(connections.get_pg(), connections.get_nats())
-                .map_err(Status::error_internal)
-                .map_ok(|message| NotificationsResponse {
-                    message: Some(message.into()),
+        tokio::spawn(
+            async move {
+                let presences = match with_presence {
+                    true => Either::Left(presence.friends_presence_updates(
+                        user.id,
+                        connections.get_pg(),
+                        connections.get_nats(),
+                        friend_cache.clone(),
+                    )),
+                    false => Either::Right(futures::stream::empty()),
+                };
+                let presences = presences
+                    .map_ok(models::notifications::Notification::Presence)
+                    .map_err(Error::from);
+
+                let user_id = user.id;
+                let notifications = NotificationServices::new(user);
+                let stream = match (after, since) {
+                    (Some(after), _) => Either::Left(notifications.stream_after(
+                        after,
+                        connections.get_pg(),
+                        connections.get_scylla(),
+                        connections.get_nats(),
+                        friend_cache,
+                    )),
+                    (None, Some(since)) => Either::Right(Either::Left(notifications.stream_since(
+                        since,
+                        connections.get_pg(),
+                        connections.get_scylla(),
+                        connections.get_nats(),
+                        friend_cache,
+                    ))),
+                    (None, None) => Either::Right(Either::Right(notifications.stream(
+                        connections.get_pg(),
+                        connections.get_scylla(),
+                        connections.get_nats(),
+                        friend_cache,
+                    ))),
+                };
+
+                let stream = futures::stream::select(stream, presences)
+                    .try_filter(move |notification| {
+                        futures::future::ready(
+                            (kinds.is_empty() || kinds.contains(&notification.kind()))
+                                && !notification
+                                    .user_id()
+                                    .is_some_and(|user| muted.contains(&user)),
+                        )
+                    })
+                    .map_err(Status::error_internal)
+                    .map_ok(move |notification| {
+                        let mut response: NotificationsResponse = notification.into();
+                        if let Some(deliveries) = &deliveries {
+                            let event_id = deliveries.sent(user_id, response.clone());
+                            response.event_id = event_id.to_string();
+                        }
+                        response
+                    });
+                let resent = resent.into_iter().map(|(event_id, mut response)| {
+                    response.event_id = event_id.to_string();
+                    response
                 });
+                let stream = futures::stream::iter(resent).map(Ok).chain(stream);
+                let stream = until_reconnected(stream, connections.nats_connection());
+
+                forward(until_shutdown(stream, shutdown), tx, deadline).await;
+            }
+            .in_current_span(),
+        );
 
-            // FIXME: Remove this Box::pin
-            let mut stream = Box::pin(stream);
+        let stream = ReceiverStream::new(rx);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    #[instrument(skip_all, fields(user_id))]
+    async fn ack_notifications(
+        &self,
+        mut request: Request<Streaming<NotificationAck>>,
+    ) -> Result<Response<NotificationAckResponse>, Status> {
+        let Some(first) = request.get_mut().message().await? else {
+            return Ok(Response::new(NotificationAckResponse::default()));
+        };
+
+        let user = self
+            .authorize(&request, &first.user_id)
+            .map_err(Status::error_authorization)?;
+        tracing::Span::current().record("user_id", tracing::field::display(user));
+
+        let mut acks = request.into_inner();
+        let mut ack = Some(first);
+        let mut acknowledged = 0;
+        while let Some(NotificationAck { user_id, event_ids }) = ack {
+            if UserId::try_parse(&user_id).ok() != Some(user) {
+                return Err(Status::invalid_argument("every ack must be of the same user_id"));
+            }
+            let event_ids = event_ids
+                .iter()
+                .map(EventId::try_parse)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(Status::error_invalid_argument)?;
+
+            acknowledged += self.deliveries.acknowledge(user, event_ids) as u64;
+            ack = acks.message().await?;
+        }
+
+        Ok(Response::new(NotificationAckResponse { acknowledged }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn heartbeat(
+        &self,
+        request: Request<HeartbeatRequest>,
+    ) -> Result<Response<HeartbeatResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+
+        self.presence
+            .heartbeat(
+                user,
+                self.connections.get_pg().clone(),
+                self.connections.get_nats(),
+                &self.task_manager,
+            )
+            .await
+            .map_err(Status::error_repository)?;
+
+        Ok(Response::new(HeartbeatResponse { success: true }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn friends_presence(
+        &self,
+        request: Request<FriendsPresenceRequest>,
+    ) -> Result<Response<FriendsPresenceResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+
+        let friends = self
+            .presence
+            .get_friends_presence(user, self.connections.get_pg(), &self.friend_cache)
+            .await
+            .map_err(Status::error_repository)?;
+
+        Ok(Response::new(FriendsPresenceResponse {
+            friends: friends.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn get_presence(
+        &self,
+        request: Request<UserListRequest>,
+    ) -> Result<Response<PresenceResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let request = request.into_inner();
 
-            while let Some(item) = stream.next().await {
-                let _ = tx.send(item).await;
+        let users = request
+            .user_ids
+            .iter()
+            .map(|id| UserId::from_str(id))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Status::error_invalid_argument)?;
+
+        let presences = self
+            .presence
+            .get_presence_of_friends(user, users, self.connections.get_pg(), &self.friend_cache)
+            .await
+            .map_err(Status::error_repository)?;
+
+        Ok(Response::new(PresenceResponse {
+            presences: presences.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    type PresenceUpdatesStream = Pin<Box<dyn Stream<Item = Result<Presence, Status>> + Send>>;
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn presence_updates(
+        &self,
+        request: Request<UserRequest>,
+    ) -> Result<Response<Self::PresenceUpdatesStream>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let deadline = deadline(&request);
+
+        let connections = self.connections.clone();
+        let presence = self.presence.clone();
+        let friend_cache = self.friend_cache.clone();
+        let shutdown = self.shutdown.subscribe();
+
+        let (tx, rx) = mpsc::channel(128);
+        tokio::spawn(
+            async move {
+                let stream = presence
+                    .friends_presence_updates(
+                        user,
+                        connections.get_pg(),
+                        connections.get_nats(),
+                        friend_cache,
+                    )
+                    .map_ok(Into::into)
+                    .map_err(Status::error_repository);
+                let stream = until_reconnected(stream, connections.nats_connection());
+
+                forward(until_shutdown(stream, shutdown), tx, deadline).await;
             }
-            // Client disconnected
-        });
+            .in_current_span(),
+        );
+
+        let stream = ReceiverStream::new(rx);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn unread_count(
+        &self,
+        request: Request<UserRequest>,
+    ) -> Result<Response<UnreadCountResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+
+        let count = UserIdServices::new(user)
+            .count_unread_messages(self.connections.get_pg(), self.connections.get_scylla())
+            .await
+            .map_err(Status::error_services)?;
+
+        Ok(Response::new(UnreadCountResponse { count }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn create_conversation(
+        &self,
+        request: Request<CreateConversationRequest>,
+    ) -> Result<Response<ConversationResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let members = request
+            .get_ref()
+            .member_ids
+            .iter()
+            .map(|id| UserId::from_str(id))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Status::error_invalid_argument)?;
+
+        let request = ConversationServices::create(user, members);
+        let connections = self.connections.clone();
+        let policy = self.scylla_policy.clone();
+        let pg_policy = self.pg_policy.clone();
+
+        let conversation = self
+            .task_manager
+            .spawn_await_result(
+                async move {
+                    pg_policy
+                        .execute(|| {
+                            let members = request.members.iter().copied();
+                            ConversationServices::ensure_not_blocked(
+                                user,
+                                members,
+                                connections.get_pg(),
+                            )
+                        })
+                        .map_err(Status::error_repository)
+                        .await?;
+
+                    policy
+                        .execute(|| request.clone().execute(connections.get_scylla()))
+                        .map_err(Status::error_repository)
+                        .await
+                }
+                .in_current_span(),
+            )
+            .await?;
+
+        tracing::info!(conversation_id = %conversation.id, "Conversation created");
+
+        Ok(Response::new(conversation.into()))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id))]
+    async fn conversations(
+        &self,
+        request: Request<UserRequest>,
+    ) -> Result<Response<ConversationsResponse>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let scylla = self.connections.get_scylla();
+
+        let conversations = ConversationServices::of_user(user)
+            .stream(scylla)
+            .and_then(|conversation| conversation.get().execute(scylla))
+            .map_ok(Into::into)
+            .try_collect()
+            .await
+            .map_err(Status::error_repository)?;
+
+        Ok(Response::new(ConversationsResponse { conversations }))
+    }
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id, conversation_id = %request.get_ref().conversation_id))]
+    async fn send_direct_message(
+        &self,
+        request: Request<SendDirectMessageRequest>,
+    ) -> Result<Response<proto::DirectMessage>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let request = request.into_inner();
+        let conversation = ConversationId::try_parse(&request.conversation_id)
+            .map_err(Status::error_invalid_argument)?;
+
+        let message = ServerState::send_direct_message(
+            self,
+            user,
+            ConversationServices::new(conversation),
+            request.content,
+        )
+        .await?;
+
+        Ok(Response::new(message.into()))
+    }
+
+    type DirectMessagesStream =
+        Pin<Box<dyn Stream<Item = Result<proto::DirectMessage, Status>> + Send>>;
+
+    #[instrument(skip_all, fields(user_id = %request.get_ref().user_id, conversation_id = %request.get_ref().conversation_id))]
+    async fn direct_messages(
+        &self,
+        request: Request<ConversationRequest>,
+    ) -> Result<Response<Self::DirectMessagesStream>, Status> {
+        let user = self
+            .authorize(&request, &request.get_ref().user_id)
+            .map_err(Status::error_authorization)?;
+        let deadline = deadline(&request);
+        let request = request.into_inner();
+        let conversation = ConversationId::try_parse(&request.conversation_id)
+            .map_err(Status::error_invalid_argument)?;
+        let limit = match request.limit {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+
+        let connections = self.connections.clone();
+        let shutdown = self.shutdown.subscribe();
+
+        let (tx, rx) = mpsc::channel(128);
+        tokio::spawn(
+            async move {
+                let history = ConversationServices::new(conversation)
+                    .history(user, connections.get_scylla(), connections.get_pg())
+                    .await;
+
+                let stream = match history {
+                    Ok(stream) => stream
+                        .take(limit)
+                        .map_ok(Into::into)
+                        .map_err(Status::error_services),
+                    Err(e) => {
+                        let _ = tx.send(Err(Status::error_services(e))).await;
+                        return;
+                    }
+                };
+
+                forward(until_shutdown(stream, shutdown), tx, deadline).await;
+            }
+            .in_current_span(),
+        );
+
+        let stream = ReceiverStream::new(rx);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    type ChatStream = Pin<Box<dyn Stream<Item = Result<ChatServerEvent, Status>> + Send>>;
+
+    #[instrument(skip_all, fields(user_id, conversation_id))]
+    async fn chat(
+        &self,
+        mut request: Request<Streaming<ChatClientEvent>>,
+    ) -> Result<Response<Self::ChatStream>, Status> {
+        let join = match request.get_mut().message().await? {
+            Some(ChatClientEvent {
+                event: Some(chat_client_event::Event::Join(join)),
+            }) => join,
+            _ => return Err(Status::invalid_argument("the first event must be `join`")),
+        };
+
+        let user = self
+            .authorize(&request, &join.user_id)
+            .map_err(Status::error_authorization)?;
+        let conversation = ConversationId::try_parse(&join.conversation_id)
+            .map_err(Status::error_invalid_argument)?;
+        tracing::Span::current()
+            .record("user_id", tracing::field::display(user))
+            .record("conversation_id", tracing::field::display(conversation));
+
+        let conversation = ConversationServices::new(conversation);
+        let live = conversation
+            .live_events(
+                user,
+                self.connections.get_scylla(),
+                self.connections.get_pg(),
+                self.connections.get_nats(),
+            )
+            .await
+            .map_err(Status::error_services)?
+            .map_ok(|event| -> ChatServerEvent { event.into() })
+            .map_err(Status::error_internal);
+        let live = until_reconnected(live, self.connections.nats_connection());
+        let mut live = Box::pin(until_shutdown(live, self.shutdown.subscribe()));
+
+        let mut events = request.into_inner();
+        let state = self.clone();
+
+        let (tx, rx) = mpsc::channel(128);
+        tokio::spawn(
+            async move {
+                loop {
+                    tokio::select! {
+                        event = events.message() => {
+                            let event = match event {
+                                Ok(Some(ChatClientEvent { event: Some(event) })) => event,
+                                Ok(Some(ChatClientEvent { event: None })) => continue,
+                                // The client closed its side or disconnected.
+                                Ok(None) | Err(_) => break,
+                            };
+
+                            let applied = state.chat_event(user, conversation, event).await;
+                            if let Err(status) = applied {
+                                let error = status.message().to_string();
+                                let event = Some(chat_server_event::Event::Error(error));
+                                if tx.send(Ok(ChatServerEvent { event })).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                        item = live.next() => {
+                            let Some(item) = item else {
+                                break;
+                            };
+
+                            if tx.send(item).await.is_err() {
+                                break;
+                            }
+                        }
+                        () = tx.closed() => break,
+                    }
+                }
+            }
+            .in_current_span(),
+        );
 
         let stream = ReceiverStream::new(rx);
         Ok(Response::new(Box::pin(stream)))
@@ -0,0 +1,36 @@
+use tonic::{Request, Response, Status, Streaming};
+
+use proto::notifier_server::Notifier;
+use proto::social_network_server::SocialNetwork;
+use proto::*;
+
+use super::ServerState;
+
+/// The same streams as `SocialNetwork`, both services can be served by a single server.
+#[tonic::async_trait]
+impl Notifier for ServerState {
+    type RealTimeNotificationsStream = <Self as SocialNetwork>::RealTimeNotificationsStream;
+
+    async fn real_time_notifications(
+        &self,
+        request: Request<NotificationsRequest>,
+    ) -> Result<Response<Self::RealTimeNotificationsStream>, Status> {
+        SocialNetwork::real_time_notifications(self, request).await
+    }
+
+    async fn ack_notifications(
+        &self,
+        request: Request<Streaming<NotificationAck>>,
+    ) -> Result<Response<NotificationAckResponse>, Status> {
+        SocialNetwork::ack_notifications(self, request).await
+    }
+
+    type PresenceUpdatesStream = <Self as SocialNetwork>::PresenceUpdatesStream;
+
+    async fn presence_updates(
+        &self,
+        request: Request<UserRequest>,
+    ) -> Result<Response<Self::PresenceUpdatesStream>, Status> {
+        SocialNetwork::presence_updates(self, request).await
+    }
+}
@@ -0,0 +1,75 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use http::{Request, Response};
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tonic::Status;
+use tower::{Layer, Service};
+
+use crate::connections::ServerConnections;
+
+use super::helpers::method_name;
+use super::ServerState;
+
+/// Rejects the requests with `UNAVAILABLE` until the connections of the server are ready, with
+/// `lazy_connections`. The health service is not a `social_network` one, it is always served.
+#[derive(Clone)]
+pub struct ReadinessLayer {
+    connections: ServerConnections,
+}
+
+impl ReadinessLayer {
+    pub fn new(state: &ServerState) -> Self {
+        Self {
+            connections: state.connections.clone(),
+        }
+    }
+}
+
+impl<S> Layer<S> for ReadinessLayer {
+    type Service = ReadinessService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ReadinessService {
+            inner,
+            connections: self.connections.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ReadinessService<S> {
+    inner: S,
+    connections: ServerConnections,
+}
+
+impl<S, B> Service<Request<B>> for ReadinessService<S>
+where
+    S: Service<Request<B>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        if method_name(request.uri().path()).is_some() && !self.connections.is_ready() {
+            let status = Status::unavailable("the server is not connected to its databases yet");
+
+            return Box::pin(std::future::ready(Ok(status.to_http())));
+        }
+
+        Box::pin(self.inner.call(request))
+    }
+}
+
+impl<S: NamedService> NamedService for ReadinessService<S> {
+    const NAME: &'static str = S::NAME;
+}
@@ -0,0 +1,64 @@
+use std::task::{Context, Poll};
+
+use http::{HeaderValue, Request};
+use tokio::task::futures::TaskLocalFuture;
+use tower::{Layer, Service};
+use tracing::instrument::{Instrument, Instrumented};
+
+/// Header carrying the request ID. Kept when set by the client, so that its logs can be matched
+/// with the server ones.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Runs each RPC in an `rpc` span with a request ID. Its `user_id` is recorded by the
+/// `AuthInterceptor` once the token is verified. The request ID is also the trace ID of the
+/// realtime events published while answering it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService { inner }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S, B> Service<Request<B>> for RequestIdService<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Instrumented<TaskLocalFuture<Option<String>, S::Future>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let request_id = match request.headers().get(REQUEST_ID_HEADER) {
+            Some(id) => id.clone(),
+            None => HeaderValue::from_str(&uuid::Uuid::new_v4().to_string())
+                .expect("UUIDs are valid header values"),
+        };
+
+        let trace_id = request_id.to_str().ok().map(str::to_string);
+        let span = tracing::info_span!(
+            "rpc",
+            request_id = trace_id.as_deref().unwrap_or_default(),
+            method = request.uri().path(),
+            user_id = tracing::field::Empty,
+        );
+        request.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+
+        // Entered while calling too, interceptors run there.
+        let response = span.in_scope(|| self.inner.call(request));
+
+        task_manager::with_trace_id(trace_id, response).instrument(span)
+    }
+}
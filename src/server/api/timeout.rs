@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use http::{HeaderValue, Request, Response};
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tonic::Status;
+use tower::{Layer, Service};
+
+use config::TimeoutConfig;
+
+use super::helpers::{encode_grpc_timeout, method_name, parse_grpc_timeout};
+
+const TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// The methods with a stream of requests or of responses, that the default timeout would cut:
+/// only their own one applies.
+const STREAMING_METHODS: &[&str] = &[
+    "Timeline",
+    "RealTimeNotifications",
+    "AckNotifications",
+    "PresenceUpdates",
+    "Chat",
+    "DirectMessages",
+    "UploadAttachment",
+];
+
+/// Applies the timeouts of a `TimeoutConfig`. Handlers that don't respond in time are dropped
+/// with `DeadlineExceeded`. Streams are produced once their response is sent, so the timeout is
+/// also set as the deadline of the request, unless the one of the client is shorter. The default
+/// one is not applied to the streaming methods.
+#[derive(Clone, Debug, Default)]
+pub struct RpcTimeoutLayer {
+    default: Option<Duration>,
+    per_method: Arc<HashMap<String, Duration>>,
+}
+
+impl RpcTimeoutLayer {
+    pub fn new(config: &TimeoutConfig) -> Self {
+        let per_method = config
+            .per_method_ms
+            .iter()
+            .map(|(method, ms)| (method.clone(), Duration::from_millis(*ms)))
+            .collect();
+
+        Self {
+            default: config.default_ms.map(Duration::from_millis),
+            per_method: Arc::new(per_method),
+        }
+    }
+
+    fn timeout(&self, path: &str) -> Option<Duration> {
+        let method = method_name(path);
+        if let Some(timeout) = method.and_then(|method| self.per_method.get(method)) {
+            return Some(*timeout);
+        }
+
+        match method {
+            Some(method) if STREAMING_METHODS.contains(&method) => None,
+            _ => self.default,
+        }
+    }
+}
+
+impl<S> Layer<S> for RpcTimeoutLayer {
+    type Service = RpcTimeoutService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcTimeoutService {
+            inner,
+            timeouts: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcTimeoutService<S> {
+    inner: S,
+    timeouts: RpcTimeoutLayer,
+}
+
+impl<S, B> Service<Request<B>> for RpcTimeoutService<S>
+where
+    S: Service<Request<B>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let Some(timeout) = self.timeouts.timeout(request.uri().path()) else {
+            return Box::pin(self.inner.call(request));
+        };
+
+        let client_timeout = request
+            .headers()
+            .get(TIMEOUT_HEADER)
+            .and_then(|value| parse_grpc_timeout(value.to_str().ok()?));
+        let timeout = client_timeout.map_or(timeout, |client| client.min(timeout));
+
+        if let Ok(value) = HeaderValue::from_str(&encode_grpc_timeout(timeout)) {
+            request.headers_mut().insert(TIMEOUT_HEADER, value);
+        }
+
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            match tokio::time::timeout(timeout, response).await {
+                Ok(response) => response,
+                Err(_) => Ok(
+                    Status::deadline_exceeded(format!("no response within {timeout:?}")).to_http(),
+                ),
+            }
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for RpcTimeoutService<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+#[cfg(test)]
+#[test]
+fn streaming_timeout_test() {
+    let layer = RpcTimeoutLayer::new(&TimeoutConfig {
+        default_ms: Some(5000),
+        per_method_ms: HashMap::from([("Chat".to_string(), 60_000)]),
+    });
+
+    let timeout =
+        |method: &str| layer.timeout(&format!("/social_network.v1.SocialNetwork/{method}"));
+    assert_eq!(timeout("PostMessage"), Some(Duration::from_secs(5)));
+    assert_eq!(timeout("RealTimeNotifications"), None);
+    assert_eq!(timeout("Chat"), Some(Duration::from_secs(60)));
+    assert_eq!(
+        layer.timeout("/grpc.health.v1.Health/Check"),
+        Some(Duration::from_secs(5))
+    );
+}
@@ -0,0 +1,560 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+
+use http::{Request, Response};
+use http_body::{LengthLimitError, Limited};
+use hyper::Body;
+use prost::Message as ProstMessage;
+use tonic::body::BoxBody;
+use tonic::transport::NamedService;
+use tonic::{Code, Status};
+use tower::{Layer, Service};
+
+use models::attachments::{Attachment, AttachmentId};
+use models::conversations::ConversationId;
+use models::messages::{MessageBody, MessageId};
+use models::notifications::NotificationKind;
+use models::reactions::Reaction;
+use models::users::{User, UserId};
+
+use super::helpers::method_name;
+use super::MAX_PAGE_SIZE;
+
+const BAD_REQUEST_TYPE_URL: &str = "type.googleapis.com/google.rpc.BadRequest";
+
+/// Fields of a request that are not valid.
+#[derive(Debug, Default)]
+pub struct Violations(Vec<proto::FieldViolation>);
+
+impl Violations {
+    fn add(&mut self, field: impl Into<String>, description: impl Into<String>) {
+        self.0.push(proto::FieldViolation {
+            field: field.into(),
+            description: description.into(),
+        });
+    }
+
+    fn user_id(&mut self, field: &str, value: &str) {
+        if let Err(e) = UserId::from_str(value) {
+            self.add(field, format!("invalid user id: {e}"));
+        }
+    }
+
+    fn user_ids(&mut self, field: &str, values: &[String]) {
+        for (i, value) in values.iter().enumerate() {
+            self.user_id(&format!("{field}[{i}]"), value);
+        }
+    }
+
+    fn message_id(&mut self, field: &str, value: &str) {
+        if let Err(e) = MessageId::try_parse(value) {
+            self.add(field, format!("invalid message id: {e}"));
+        }
+    }
+
+    fn optional_message_id(&mut self, field: &str, value: &Option<String>) {
+        if let Some(value) = value {
+            self.message_id(field, value);
+        }
+    }
+
+    fn attachment_ids(&mut self, field: &str, values: &[String]) {
+        for (i, value) in values.iter().enumerate() {
+            if let Err(e) = AttachmentId::try_parse(value) {
+                self.add(
+                    format!("{field}[{i}]"),
+                    format!("invalid attachment id: {e}"),
+                );
+            }
+        }
+    }
+
+    fn conversation_id(&mut self, field: &str, value: &str) {
+        if let Err(e) = ConversationId::try_parse(value) {
+            self.add(field, format!("invalid conversation id: {e}"));
+        }
+    }
+
+    fn message_ids(&mut self, field: &str, values: &[String]) {
+        for (i, value) in values.iter().enumerate() {
+            self.message_id(&format!("{field}[{i}]"), value);
+        }
+    }
+
+    fn chars(&mut self, field: &str, value: &str, max: usize) {
+        match value.chars().count() {
+            0 => self.add(field, "must not be empty"),
+            n if n > max => self.add(field, format!("longer than {max} characters")),
+            _ => {}
+        }
+    }
+
+    /// Media must be one of the `attachment_ids` of the message. Notices are only written by the
+    /// server.
+    fn body(&mut self, field: &str, value: &proto::MessageBody, attachment_ids: &[String]) {
+        use proto::message_body::Kind;
+
+        match &value.kind {
+            None => {}
+            Some(Kind::LinkPreview(link)) => {
+                self.chars(
+                    &format!("{field}.url"),
+                    &link.url,
+                    MessageBody::MAX_URL_CHARS,
+                );
+                if link.title.chars().count() > MessageBody::MAX_TITLE_CHARS {
+                    self.add(
+                        format!("{field}.title"),
+                        format!("longer than {} characters", MessageBody::MAX_TITLE_CHARS),
+                    );
+                }
+                if link.description.chars().count() > MessageBody::MAX_DESCRIPTION_CHARS {
+                    self.add(
+                        format!("{field}.description"),
+                        format!(
+                            "longer than {} characters",
+                            MessageBody::MAX_DESCRIPTION_CHARS
+                        ),
+                    );
+                }
+            }
+            Some(Kind::Media(media)) => {
+                if !attachment_ids.contains(&media.attachment_id) {
+                    self.add(
+                        format!("{field}.attachment_id"),
+                        "not one of the attachments of the message",
+                    );
+                }
+                if !media.content_type.contains('/') {
+                    self.add(format!("{field}.content_type"), "invalid content type");
+                }
+            }
+            Some(Kind::SystemNotice(_)) => self.add(field, "notices can't be posted"),
+        }
+    }
+
+    fn name(&mut self, field: &str, value: &str) {
+        self.chars(field, value, User::MAX_NAME_CHARS);
+
+        if !value.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            self.add(field, "only letters, digits and `_` are allowed");
+        }
+    }
+
+    /// Of new users, older ones may have shorter passwords.
+    fn new_password(&mut self, field: &str, value: &str) {
+        self.chars(field, value, User::MAX_PASSWORD_CHARS);
+
+        if value.chars().count() < User::MIN_PASSWORD_CHARS {
+            self.add(
+                field,
+                format!("shorter than {} characters", User::MIN_PASSWORD_CHARS),
+            );
+        }
+    }
+
+    /// `InvalidArgument` with a `google.rpc.BadRequest` detail, `None` when there are no
+    /// violations.
+    pub fn into_status(self) -> Option<Status> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let message = self
+            .0
+            .iter()
+            .map(|violation| format!("{}: {}", violation.field, violation.description))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let bad_request = proto::BadRequest {
+            field_violations: self.0,
+        };
+        let details = proto::StatusDetails {
+            code: Code::InvalidArgument as i32,
+            message: message.clone(),
+            details: vec![proto::AnyDetail {
+                type_url: BAD_REQUEST_TYPE_URL.to_string(),
+                value: bad_request.encode_to_vec(),
+            }],
+        };
+
+        Some(Status::with_details(
+            Code::InvalidArgument,
+            message,
+            details.encode_to_vec().into(),
+        ))
+    }
+}
+
+pub trait Validate {
+    fn validate(&self, violations: &mut Violations);
+}
+
+impl Validate for proto::UserByNameRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.name("name", &self.name);
+    }
+}
+
+impl Validate for proto::LoginRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.name("name", &self.name);
+        violations.chars("password", &self.password, User::MAX_PASSWORD_CHARS);
+    }
+}
+
+impl Validate for proto::SearchUsersRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+        violations.chars("query", &self.query, User::MAX_NAME_CHARS);
+        if self.page_size > MAX_PAGE_SIZE {
+            violations.add("page_size", format!("at most {MAX_PAGE_SIZE}"));
+        }
+    }
+}
+
+impl Validate for proto::CreateUserRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.name("name", &self.name);
+        violations.new_password("password", &self.password);
+    }
+}
+
+impl Validate for proto::FriendRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+        violations.user_id("friend_id", &self.friend_id);
+    }
+}
+
+impl Validate for proto::BlockRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+        violations.user_id("blocked_id", &self.blocked_id);
+    }
+}
+
+impl Validate for proto::PostMessageRequest {
+    fn validate(&self, violations: &mut Violations) {
+        // The content is checked by the handler once normalized, against the configured limit.
+        violations.user_id("user_id", &self.user_id);
+        violations.optional_message_id("message_id", &self.message_id);
+        violations.optional_message_id("reply_to", &self.reply_to);
+        violations.attachment_ids("attachment_ids", &self.attachment_ids);
+        if let Some(body) = &self.body {
+            violations.body("body", body, &self.attachment_ids);
+        }
+    }
+}
+
+impl Validate for proto::TimelineRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+        if self.page_size > MAX_PAGE_SIZE {
+            violations.add("page_size", format!("at most {MAX_PAGE_SIZE}"));
+        }
+    }
+}
+
+impl Validate for proto::MessageTagRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+        violations.message_id("message_id", &self.message_id);
+    }
+}
+
+impl Validate for proto::MessagesTagRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+        violations.message_ids("message_ids", &self.message_ids);
+    }
+}
+
+impl Validate for proto::ReactionRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+        violations.message_id("message_id", &self.message_id);
+        violations.chars("emoji", &self.emoji, Reaction::MAX_EMOJI_CHARS);
+    }
+}
+
+impl Validate for proto::NotificationsRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+        violations.optional_message_id("after_message_id", &self.after_message_id);
+        violations.user_ids("muted_user_ids", &self.muted_user_ids);
+
+        for (i, kind) in self.kinds.iter().enumerate() {
+            if let Err(e) = NotificationKind::try_from(*kind) {
+                violations.add(format!("kinds[{i}]"), e.to_string());
+            }
+        }
+        if self.after_message_id.is_some() && self.since.is_some() {
+            violations.add("since", "can't be set along with after_message_id");
+        }
+    }
+}
+
+impl Validate for proto::CreateConversationRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+        violations.user_ids("member_ids", &self.member_ids);
+    }
+}
+
+impl Validate for proto::SendDirectMessageRequest {
+    fn validate(&self, violations: &mut Violations) {
+        // The content is checked by the handler once normalized, as for posts.
+        violations.user_id("user_id", &self.user_id);
+        violations.conversation_id("conversation_id", &self.conversation_id);
+    }
+}
+
+impl Validate for proto::ConversationRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+        violations.conversation_id("conversation_id", &self.conversation_id);
+    }
+}
+
+impl Validate for proto::HeartbeatRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+    }
+}
+
+impl Validate for proto::FriendsPresenceRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+    }
+}
+
+impl Validate for proto::UserListRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+        violations.user_ids("user_ids", &self.user_ids);
+    }
+}
+
+impl Validate for proto::UserRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+    }
+}
+
+/// Checked by the handler on the first chunk, the one carrying the metadata.
+impl Validate for proto::AttachmentChunk {
+    fn validate(&self, violations: &mut Violations) {
+        violations.user_id("user_id", &self.user_id);
+        violations.chars("filename", &self.filename, Attachment::MAX_FILENAME_CHARS);
+        violations.chars(
+            "content_type",
+            &self.content_type,
+            Attachment::MAX_CONTENT_TYPE_CHARS,
+        );
+    }
+}
+
+/// Violations of an encoded request, `None` if it can't be decoded: the handler reports it.
+fn violations_of<M: ProstMessage + Default + Validate>(encoded: &[u8]) -> Option<Violations> {
+    let request = M::decode(encoded).ok()?;
+    let mut violations = Violations::default();
+    request.validate(&mut violations);
+
+    Some(violations)
+}
+
+type Validator = fn(&[u8]) -> Option<Violations>;
+
+/// Of each RPC sending a single request. Client streams are not buffered, they are validated by
+/// their handler.
+fn validator(path: &str) -> Option<Validator> {
+    use proto::*;
+
+    let method = method_name(path)?;
+
+    Some(match method {
+        "GetUserByName" => violations_of::<UserByNameRequest>,
+        "Login" => violations_of::<LoginRequest>,
+        "CreateUser" => violations_of::<CreateUserRequest>,
+        "SearchUsers" => violations_of::<SearchUsersRequest>,
+        "AddFriend" | "AcceptFriend" | "DeclineFriend" | "RemoveFriend" => {
+            violations_of::<FriendRequest>
+        }
+        "BlockUser" | "UnblockUser" => violations_of::<BlockRequest>,
+        "PostMessage" => violations_of::<PostMessageRequest>,
+        "Timeline" => violations_of::<TimelineRequest>,
+        "TagReadMessage" | "TagUnreadMessage" => violations_of::<MessageTagRequest>,
+        "TagReadMessages" => violations_of::<MessagesTagRequest>,
+        "React" | "RemoveReaction" => violations_of::<ReactionRequest>,
+        "RealTimeNotifications" => violations_of::<NotificationsRequest>,
+        "Heartbeat" => violations_of::<HeartbeatRequest>,
+        "FriendsPresence" => violations_of::<FriendsPresenceRequest>,
+        "GetPresence" => violations_of::<UserListRequest>,
+        "CreateConversation" => violations_of::<CreateConversationRequest>,
+        "SendDirectMessage" => violations_of::<SendDirectMessageRequest>,
+        "DirectMessages" => violations_of::<ConversationRequest>,
+        "GetUser" | "Conversations" | "ListFriends" | "ListFriendRequests" | "PresenceUpdates"
+        | "UnreadCount" => violations_of::<UserRequest>,
+        _ => return None,
+    })
+}
+
+/// A compression flag then a big endian length.
+const FRAME_HEADER_BYTES: usize = 5;
+
+/// The message of a gRPC frame: a compression flag, a big endian length, then the message.
+/// Compressed messages are left to the handler.
+fn frame_message(body: &[u8]) -> Option<&[u8]> {
+    let (&compressed, rest) = body.split_first()?;
+    let length = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+
+    match compressed {
+        0 => rest.get(4..4 + length),
+        _ => None,
+    }
+}
+
+/// Rejects the requests with invalid fields before they reach the service, with an
+/// `InvalidArgument` status detailing each field, and the requests longer than
+/// `max_message_bytes` with `ResourceExhausted`. Wraps the service rather than the server so that
+/// gRPC-web translates its responses too.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ValidationLayer {
+    max_message_bytes: Option<usize>,
+}
+
+impl ValidationLayer {
+    pub fn new(max_message_bytes: Option<usize>) -> Self {
+        Self { max_message_bytes }
+    }
+}
+
+impl<S> Layer<S> for ValidationLayer {
+    type Service = ValidationService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ValidationService {
+            inner,
+            max_message_bytes: self.max_message_bytes,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ValidationService<S> {
+    inner: S,
+    max_message_bytes: Option<usize>,
+}
+
+impl<S> Service<Request<Body>> for ValidationService<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        // The service that is ready is the one called, its clone waits for the next request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let Some(validate) = validator(request.uri().path()) else {
+            return Box::pin(inner.call(request));
+        };
+
+        let limit = self
+            .max_message_bytes
+            .map_or(usize::MAX, |max| max + FRAME_HEADER_BYTES);
+
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = match hyper::body::to_bytes(Limited::new(body, limit)).await {
+                Ok(body) => body,
+                Err(e) if e.is::<LengthLimitError>() => {
+                    let status = Status::resource_exhausted(format!(
+                        "message longer than {} bytes",
+                        limit - FRAME_HEADER_BYTES
+                    ));
+
+                    return Ok(status.to_http());
+                }
+                Err(e) => return Ok(Status::invalid_argument(format!("{e}")).to_http()),
+            };
+
+            let violations = frame_message(&body).and_then(validate);
+            if let Some(status) = violations.and_then(Violations::into_status) {
+                tracing::info!(message = status.message(), "Invalid request");
+                return Ok(status.to_http());
+            }
+
+            inner
+                .call(Request::from_parts(parts, Body::from(body)))
+                .await
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for ValidationService<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+#[cfg(test)]
+#[test]
+fn frame_message_test() {
+    let frame = |compressed: u8, length: u32, message: &[u8]| {
+        let mut frame = vec![compressed];
+        frame.extend_from_slice(&length.to_be_bytes());
+        frame.extend_from_slice(message);
+        frame
+    };
+
+    assert_eq!(frame_message(&frame(0, 3, b"abc")), Some(&b"abc"[..]));
+    assert_eq!(frame_message(&frame(0, 0, b"")), Some(&b""[..]));
+    // Left to the handler.
+    assert_eq!(frame_message(&frame(1, 3, b"abc")), None);
+    // Truncated header or message.
+    assert_eq!(frame_message(&[]), None);
+    assert_eq!(frame_message(&[0, 0, 0]), None);
+    assert_eq!(frame_message(&frame(0, 4, b"abc")), None);
+    // Only the length of the header is the message.
+    assert_eq!(frame_message(&frame(0, 2, b"abc")), Some(&b"ab"[..]));
+}
+
+#[cfg(test)]
+#[test]
+fn violations_status_test() {
+    assert!(Violations::default().into_status().is_none());
+
+    let request = proto::CreateUserRequest {
+        name: "bad name!".to_string(),
+        password: "short".to_string(),
+    };
+    let violations = violations_of::<proto::CreateUserRequest>(&request.encode_to_vec()).unwrap();
+    let status = violations.into_status().unwrap();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let details = proto::StatusDetails::decode(status.details()).unwrap();
+    assert_eq!(details.code, Code::InvalidArgument as i32);
+    assert_eq!(details.message, status.message());
+    let [detail] = &details.details[..] else {
+        panic!("one detail expected, got {:?}", details.details);
+    };
+    assert_eq!(detail.type_url, BAD_REQUEST_TYPE_URL);
+
+    let bad_request = proto::BadRequest::decode(&detail.value[..]).unwrap();
+    let fields: Vec<_> = bad_request
+        .field_violations
+        .iter()
+        .map(|violation| violation.field.as_str())
+        .collect();
+    assert_eq!(fields, vec!["name", "password"]);
+}
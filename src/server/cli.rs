@@ -0,0 +1,114 @@
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use config::{ConfigFormat, ServerConfig};
+use tokio::sync::watch;
+
+/// Prefix of the environment variables overriding the config, eg. `TSN_LISTENING_ADDR`.
+const ENV_PREFIX: &str = "TSN_";
+
+/// Where the config of the servers comes from, each line overriding the previous ones: built-in
+/// defaults, the config file and its profile, `TSN_` environment variables, then these flags.
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    /// Config file, which may only set some fields. The one of the development setup when it
+    /// exists otherwise.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Format of the config file, by default the one of its extension, or JSON.
+    #[arg(long)]
+    config_format: Option<ConfigFormat>,
+    /// Profile of the config file applied over the rest of it, eg. `prod`.
+    #[arg(long)]
+    profile: Option<String>,
+    /// Address to listen on, eg. `[::]:50051`.
+    #[arg(long)]
+    listening_addr: Option<String>,
+    /// Sets a field of the config, dotted for nested ones, eg. `--set postgresql.port=5433`. Can
+    /// be repeated.
+    #[arg(long, value_name = "KEY=VALUE")]
+    set: Vec<String>,
+}
+
+impl ConfigArgs {
+    pub fn load(&self, default_path: &str) -> Result<ServerConfig, Error> {
+        let mut config = ServerConfig::layered();
+        if let Some(profile) = &self.profile {
+            config = config.profile(profile);
+        }
+        let path = self.path(default_path);
+        if self.profile.is_some() && path.is_none() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "a profile is selected but there is no config file",
+            ));
+        }
+        if let Some(path) = path {
+            config = match self.config_format {
+                Some(format) => config.file_as(path, format)?,
+                None => config.file(path)?,
+            };
+        }
+        config = config.env(ENV_PREFIX)?;
+
+        if let Some(listening_addr) = &self.listening_addr {
+            config = config.set("listening_addr", listening_addr)?;
+        }
+        for field in &self.set {
+            let (key, value) = field.split_once('=').ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("expected KEY=VALUE, got `{field}`"),
+                )
+            })?;
+            config = config.set(key, value)?;
+        }
+
+        config.build()
+    }
+
+    /// Loaded as by `load`, then again with the same overrides each time the config file
+    /// changes.
+    pub fn watch(self, default_path: &str) -> Result<watch::Receiver<ServerConfig>, Error> {
+        let config = self.load(default_path)?;
+        let Some(path) = self.path(default_path) else {
+            return Ok(watch::channel(config).1);
+        };
+
+        let default_path = default_path.to_string();
+        Ok(ServerConfig::watch_with(path, config, move || {
+            self.load(&default_path)
+        }))
+    }
+
+    /// Prints whether the config loaded as by `load` is valid, and its problems. `false` when
+    /// there are some, or when it can't be loaded.
+    pub fn validate(&self, default_path: &str) -> bool {
+        let config = match self.load(default_path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("❌ Invalid config: {e}");
+                return false;
+            }
+        };
+
+        let problems = config.problems();
+        for problem in &problems {
+            eprintln!("❌ {problem}");
+        }
+        if problems.is_empty() {
+            println!("✅ The config is valid");
+        }
+
+        problems.is_empty()
+    }
+
+    fn path(&self, default_path: &str) -> Option<PathBuf> {
+        self.config.clone().or_else(|| {
+            Path::new(default_path)
+                .exists()
+                .then(|| default_path.into())
+        })
+    }
+}
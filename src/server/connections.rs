@@ -1,48 +1,181 @@
-use std::sync::Arc;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
 use anyhow::Error;
 use async_nats::Client as NatsClient;
-use config::ServerConfig;
-use once_cell::sync::OnceCell;
+use config::{ScyllaDbConfig, ServerConfig};
+use realtime::connection::{ConnectionMonitor, ConnectionState};
 use scylla::Session;
 use sqlx::PgPool;
+use tokio::sync::watch;
 
-static PG_POOL: OnceCell<PgPool> = OnceCell::new();
+/// Between the attempts to connect lazily, doubled up to the max.
+const RETRY_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const RETRY_MAX_BACKOFF: Duration = Duration::from_secs(30);
 
+/// The connections of a `ServerState`, opened with its config: two states never share theirs,
+/// but the clones of one do. With `lazy_connections`, `new` returns at once and the backends are
+/// connected to in the background until they can be reached: the connections can't be used
+/// before `is_ready`.
 #[derive(Clone)]
 pub struct ServerConnections {
     nats_client: NatsClient,
-    scylla_session: Arc<Session>,
+    nats_connection: ConnectionMonitor,
+    scylla_session: Arc<OnceLock<Session>>,
     pg_pool: Arc<PgPool>,
+    /// Set once PostgreSQL and ScyllaDB were connected to.
+    databases_ready: Arc<watch::Sender<bool>>,
 }
 
 impl ServerConnections {
     pub async fn new(config: &ServerConfig) -> Result<Self, Error> {
-        let pg_pool = PgPool::connect_with(config.postgresql.into_connect_options()).await?;
-        println!("Connected to PostreSQL");
+        let lazy = config.lazy_connections;
 
-        let scylla_session = config.scylladb.into_session_builder().build().await?;
-        println!("Connected to ScyllaDB");
+        let pg_options = config.postgresql.into_pool_options();
+        let pg_pool = match lazy {
+            true => pg_options.connect_lazy_with(config.postgresql.into_connect_options()),
+            false => {
+                let pool = pg_options
+                    .connect_with(config.postgresql.into_connect_options())
+                    .await?;
+                tracing::info!("Connected to PostgreSQL");
+                pool
+            }
+        };
 
-        let nats_client = config.nats.into_connect_options().connect().await?;
-        println!("Connected to NATS");
+        let scylla_session = Arc::new(OnceLock::new());
+        if !lazy {
+            let _ = scylla_session.set(Self::connect_scylla(&config.scylladb).await?);
+        }
 
-        Ok(Self {
+        let nats_connection = ConnectionMonitor::new();
+        let monitor = nats_connection.clone();
+        let mut nats = config
+            .nats
+            .into_connect_options()
+            .on_event(move |event| monitor.record(event));
+        if lazy {
+            nats = nats.retry_on_initial_connect();
+        }
+        let nats_client = nats.connect().await?;
+        if !lazy {
+            nats_connection.set(ConnectionState::Connected);
+            tracing::info!("Connected to NATS");
+        }
+
+        let connections = Self {
             nats_client,
-            scylla_session: Arc::new(scylla_session),
+            nats_connection,
+            scylla_session,
             pg_pool: Arc::new(pg_pool),
+            databases_ready: Arc::new(watch::channel(!lazy).0),
+        };
+        if lazy {
+            tokio::spawn(
+                connections
+                    .clone()
+                    .connect_databases(config.scylladb.clone()),
+            );
+        }
+
+        Ok(connections)
+    }
+
+    async fn connect_scylla(scylla: &ScyllaDbConfig) -> Result<Session, Error> {
+        let scylla_session = scylla.into_session_builder()?.build().await?;
+        tracing::info!("Connected to ScyllaDB");
+
+        if scylla.auto_create_keyspace {
+            repository::schema::create_scylla_schema(
+                &scylla_session,
+                &scylla.keyspace,
+                &scylla.replication(),
+            )
+            .await?;
+            tracing::info!(keyspace = %scylla.keyspace, "ScyllaDB keyspace and tables are ready");
+        }
+
+        Ok(scylla_session)
+    }
+
+    /// Of `lazy_connections`, until PostgreSQL then ScyllaDB can be reached.
+    async fn connect_databases(self, scylla: ScyllaDbConfig) {
+        retry("PostgreSQL", || async {
+            self.pg_pool.acquire().await.map(drop)
         })
+        .await;
+        tracing::info!("Connected to PostgreSQL");
+
+        let session = retry("ScyllaDB", || Self::connect_scylla(&scylla)).await;
+        let _ = self.scylla_session.set(session);
+
+        self.databases_ready.send_replace(true);
+    }
+
+    /// Whether every backend was connected to once. NATS may be reconnecting since, as told by
+    /// `nats_connection`.
+    pub fn is_ready(&self) -> bool {
+        *self.databases_ready.borrow() && self.nats_connection.has_connected()
+    }
+
+    /// Once `is_ready`.
+    pub async fn ready(&self) {
+        // The sender is kept by `self`, it can't fail.
+        let _ = self
+            .databases_ready
+            .subscribe()
+            .wait_for(|ready| *ready)
+            .await;
+        self.nats_connection.first_connected().await;
     }
 
+    /// Whether it is ready before `timeout`.
+    pub async fn wait_ready(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, self.ready()).await.is_ok()
+    }
+
+    /// Panics before `is_ready`.
     pub fn get_scylla(&self) -> &Session {
-        self.scylla_session.as_ref()
+        self.scylla_session
+            .get()
+            .expect("ScyllaDB is used before the connections are ready")
     }
 
+    /// Requests wait for a connection before `is_ready`, until the acquire timeout of the pool.
     pub fn get_pg(&self) -> &PgPool {
         self.pg_pool.as_ref()
     }
 
+    /// Commands are buffered before `is_ready`.
     pub fn get_nats(&self) -> NatsClient {
         self.nats_client.clone()
     }
+
+    /// Of `get_nats`, reconnected by the client when lost.
+    pub fn nats_connection(&self) -> &ConnectionMonitor {
+        &self.nats_connection
+    }
+}
+
+/// Calls `connect` until it succeeds, after an exponential backoff.
+async fn retry<T, E, F, Fut>(backend: &str, mut connect: F) -> T
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut backoff = RETRY_MIN_BACKOFF;
+
+    loop {
+        match connect().await {
+            Ok(connected) => return connected,
+            Err(e) => {
+                tracing::warn!(error = %e, ?backoff, "Can't connect to {backend} yet, retrying");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(RETRY_MAX_BACKOFF);
+            }
+        }
+    }
 }
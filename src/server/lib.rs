@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use config::{GrpcWebConfig, ServerConfig, ServiceKind, TlsConfig};
+use tokio::sync::watch;
+use tonic::transport::Server;
+
+pub mod api;
+pub mod cli;
+mod connections;
+pub mod logging;
+mod registry;
+
+use api::{RequestIdLayer, RpcMetricsLayer, ServerState};
+use logging::LogHandle;
+use registry::Registry;
+
+/// Serves the services of the configuration, `default` when there are none, until SIGINT or
+/// SIGTERM. Then waits for the tasks of `state` still in flight. The counters of the RPCs are
+/// served to Prometheus when `metrics_addr` is set in `telemetry`.
+pub async fn serve(
+    config: &ServerConfig,
+    state: &ServerState,
+    default: ServiceKind,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let transport = config.transport.clone().unwrap_or_default();
+
+    // gRPC-web is served over HTTP/1.1.
+    let mut server = Server::builder()
+        .accept_http1(config.grpc_web.is_some())
+        .http2_keepalive_interval(transport.keepalive_interval_secs.map(Duration::from_secs))
+        .http2_keepalive_timeout(transport.keepalive_timeout_secs.map(Duration::from_secs))
+        .max_concurrent_streams(transport.max_concurrent_streams)
+        .initial_stream_window_size(transport.initial_stream_window_size)
+        .initial_connection_window_size(transport.initial_connection_window_size);
+    if let Some(tls) = &config.tls {
+        tls.check_client_auth()?;
+        server = with_tls(server, tls)?;
+    }
+
+    let closing = state.clone();
+    let signal = async move {
+        shutdown_signal().await;
+        tracing::info!("Shutting down");
+        closing.close_streams();
+    };
+
+    let mut services = Vec::new();
+    for kind in config.services.iter().copied() {
+        // Routes can't be added twice.
+        if !services.contains(&kind) {
+            services.push(kind);
+        }
+    }
+    if services.is_empty() {
+        services.push(default);
+    }
+
+    let metrics = RpcMetricsLayer::default().with_deliveries(state.deliveries());
+    if let Some(addr) = config.telemetry().metrics_addr {
+        let exporter = metrics.exporter(addr)?;
+        tracing::info!(%addr, "Serving metrics");
+
+        tokio::spawn(async move {
+            if let Err(e) = exporter.await {
+                tracing::error!(error = %e, "Metrics are no longer served");
+            }
+        });
+    }
+
+    if !state.is_ready() {
+        let readiness = state.clone();
+
+        tokio::spawn(async move {
+            while !readiness.wait_ready(READY_WARNING_PERIOD).await {
+                tracing::warn!("Still connecting to the databases and NATS, RPCs are rejected");
+            }
+            tracing::info!("Connected to the databases and NATS, ready to serve");
+        });
+    }
+
+    let registry = services.into_iter().fold(
+        Registry::new(server.layer(RequestIdLayer), config, state, metrics),
+        Registry::register,
+    );
+    let router = registry.into_router().ok_or("no service to serve")?;
+
+    router
+        .serve_with_shutdown(config.listening_addr, signal)
+        .await?;
+
+    if tokio::time::timeout(DRAIN_TIMEOUT, state.drain())
+        .await
+        .is_err()
+    {
+        tracing::warn!("Some tasks were still running after {DRAIN_TIMEOUT:?}");
+    }
+
+    Ok(())
+}
+
+/// Applies the configs received until the sender is dropped: the log level, and what
+/// `ServerState::reload` can change. The other settings need a restart.
+pub async fn apply_reloads(
+    mut configs: watch::Receiver<ServerConfig>,
+    state: ServerState,
+    log: LogHandle,
+) {
+    while configs.changed().await.is_ok() {
+        let config = configs.borrow_and_update().clone();
+
+        if let Err(e) = log.set_level(&config.telemetry().log.level) {
+            tracing::warn!(error = %e, "Log level not reloaded");
+        }
+        state.reload(&config);
+    }
+}
+
+/// Of `lazy_connections`, between the warnings while connecting.
+const READY_WARNING_PERIOD: Duration = Duration::from_secs(30);
+
+/// Waited for the tasks in flight on shutdown, so that a stuck one does not block deploys.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Can't listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Can't listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+/// Client streams can't be sent by browsers, so `Chat` is only available over gRPC.
+fn grpc_web_config(grpc_web: &GrpcWebConfig) -> tonic_web::Config {
+    let config = match grpc_web.allowed_origins.is_empty() {
+        true => tonic_web::config().allow_all_origins(),
+        false => tonic_web::config().allow_origins(grpc_web.allowed_origins.clone()),
+    };
+
+    config
+        .allow_credentials(grpc_web.allow_credentials)
+        .max_age(grpc_web.max_age_secs.map(Duration::from_secs))
+        .expose_headers(["x-request-id"])
+}
+
+#[cfg(feature = "tls")]
+fn with_tls(server: Server, tls: &TlsConfig) -> Result<Server, Box<dyn std::error::Error>> {
+    use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+    let identity = Identity::from_pem(std::fs::read(&tls.certificate)?, std::fs::read(&tls.key)?);
+    let mut tls_config = ServerTlsConfig::new().identity(identity);
+
+    if let Some(client_ca) = &tls.client_ca {
+        tls_config = tls_config.client_ca_root(Certificate::from_pem(std::fs::read(client_ca)?));
+    }
+
+    Ok(server.tls_config(tls_config)?)
+}
+
+#[cfg(not(feature = "tls"))]
+fn with_tls(_server: Server, _tls: &TlsConfig) -> Result<Server, Box<dyn std::error::Error>> {
+    Err("TLS is configured but the server was built without the `tls` feature".into())
+}
@@ -0,0 +1,113 @@
+use std::error::Error;
+
+use config::{LogFormat, TelemetryConfig};
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+/// Changes the filter of the subscriber installed by `init`, eg. when the config is reloaded.
+#[derive(Clone)]
+pub struct LogHandle {
+    filter: reload::Handle<EnvFilter, Registry>,
+    /// The filter comes from `RUST_LOG`, which is kept.
+    from_env: bool,
+}
+
+impl LogHandle {
+    pub fn set_level(&self, level: &str) -> Result<(), Box<dyn Error>> {
+        if self.from_env {
+            return Ok(());
+        }
+
+        self.filter.reload(EnvFilter::try_new(level)?)?;
+        Ok(())
+    }
+}
+
+/// Installs the global subscriber, to be called once before anything is logged. Spans are also
+/// exported when `otlp_endpoint` is set, until `shutdown`.
+pub fn init(telemetry: &TelemetryConfig) -> Result<LogHandle, Box<dyn Error>> {
+    let (filter, from_env) = match EnvFilter::try_from_default_env() {
+        Ok(filter) => (filter, true),
+        Err(_) => (EnvFilter::try_new(&telemetry.log.level)?, false),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let output = match telemetry.log.format {
+        LogFormat::Text => fmt::layer().boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    let subscriber = tracing_subscriber::registry().with(filter).with(output);
+
+    #[cfg(feature = "otlp")]
+    subscriber.with(otlp_layer(telemetry)?).try_init()?;
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        if telemetry.otlp_endpoint.is_some() {
+            return Err(
+                "an OTLP endpoint is configured but the server was built without the `otlp` feature"
+                    .into(),
+            );
+        }
+        subscriber.try_init()?;
+    }
+
+    Ok(LogHandle {
+        filter: handle,
+        from_env,
+    })
+}
+
+/// Flushes the spans not exported yet, to be called before exiting.
+pub fn shutdown() {
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// `None` without an endpoint. Traces started by clients are sampled as they were, the others
+/// by `sampling_ratio`.
+#[cfg(feature = "otlp")]
+fn otlp_layer<S>(
+    telemetry: &TelemetryConfig,
+) -> Result<
+    Option<tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry::sdk::trace::Tracer>>,
+    Box<dyn Error>,
+>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry::sdk::trace::{self, Sampler};
+    use opentelemetry::sdk::Resource;
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+
+    let Some(endpoint) = &telemetry.otlp_endpoint else {
+        return Ok(None);
+    };
+
+    let sampler = Sampler::TraceIdRatioBased(telemetry.sampling_ratio.unwrap_or(1.0));
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint.clone()),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_sampler(Sampler::ParentBased(Box::new(sampler)))
+                .with_resource(Resource::new([KeyValue::new(
+                    "service.name",
+                    env!("CARGO_PKG_NAME"),
+                )])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)?;
+
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
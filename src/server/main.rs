@@ -1,30 +1,75 @@
-use clap::Parser;
-use proto::social_network_server::SocialNetworkServer;
-use tonic::transport::Server;
+use std::io::Write;
 
-mod api;
-mod connections;
+use clap::Parser;
+use config::{ServiceKind, TEMPLATE};
 
-use api::ServerState;
+use tsn_server::api::ServerState;
+use tsn_server::cli::ConfigArgs;
+use tsn_server::logging;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(short, long, default_value_t = String::from("./config/config.dev.json"))]
-    config: String,
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Checks the config, then exits, with an error when it is invalid.
+    #[arg(long)]
+    validate_config: bool,
+    /// Prints a commented config with every field, then exits.
+    #[arg(long, conflicts_with = "validate_config")]
+    print_default_config: bool,
+    /// Sends a notice to every connected user, then exits.
+    #[arg(long, value_name = "TEXT", conflicts_with_all = ["validate_config", "print_default_config"])]
+    notice: Option<String>,
+    /// Writes the `FileDescriptorSet` of the API to stdout, eg. to generate clients in other
+    /// languages, then exits.
+    #[arg(long, conflicts_with_all = ["validate_config", "print_default_config", "notice"])]
+    print_descriptor_set: bool,
 }
 
+const DEFAULT_CONFIG: &str = "./config/config.dev.json";
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
-    let config = config::ServerConfig::load_from_file(args.config)?;
+    if args.print_default_config {
+        print!("{TEMPLATE}");
+        return Ok(());
+    }
+    if args.print_descriptor_set {
+        std::io::stdout().write_all(proto::FILE_DESCRIPTOR_SET)?;
+        return Ok(());
+    }
+    if args.validate_config {
+        std::process::exit(match args.config.validate(DEFAULT_CONFIG) {
+            true => 0,
+            false => 1,
+        });
+    }
+
+    if let Some(text) = args.notice {
+        let config = args.config.load(DEFAULT_CONFIG)?;
+        let nats = config.nats.into_connect_options().connect().await?;
+        services::notifications::publish_system_notice(text, nats.clone()).await?;
+        nats.flush().await?;
+        return Ok(());
+    }
+
+    let configs = args.config.watch(DEFAULT_CONFIG)?;
+    let config = configs.borrow().clone();
+    let log = logging::init(&config.telemetry())?;
 
     let server_state = ServerState::new(config.clone()).await?;
+    server_state.schedule_archival();
+
+    tokio::spawn(tsn_server::apply_reloads(
+        configs,
+        server_state.clone(),
+        log,
+    ));
 
-    Server::builder()
-        .add_service(SocialNetworkServer::new(server_state))
-        .serve(config.listening_addr)
-        .await?;
+    let served = tsn_server::serve(&config, &server_state, ServiceKind::SocialNetwork).await;
+    logging::shutdown();
 
-    Ok(())
+    served
 }
@@ -0,0 +1,148 @@
+//! The tonic services that can be served, chosen by the configuration. A new service is a new
+//! `ServiceKind` with its arm in `Registry::register`. The health service is served along, for
+//! the registered ones.
+
+use std::convert::Infallible;
+
+use config::{ServerConfig, ServiceKind};
+use http::{Request, Response};
+use hyper::Body;
+use proto::health::v1::health_server::HealthServer;
+use proto::notifier_server::NotifierServer;
+use proto::social_network_server::SocialNetworkServer;
+use tonic::body::BoxBody;
+use tonic::codec::CompressionEncoding;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::server::Router;
+use tonic::transport::{NamedService, Server};
+use tower::layer::util::{Identity, Stack};
+use tower::{Layer, Service};
+
+use crate::api::{
+    CompressionLayer, HealthService, InFlightLimitLayer, ReadinessLayer, RequestIdLayer,
+    RpcMetricsLayer, RpcTimeoutLayer, ServerState, ValidationLayer,
+};
+use crate::grpc_web_config;
+
+type ServerLayers = Stack<RequestIdLayer, Identity>;
+
+/// The `Server` until a first service is added, then its `Router`.
+enum Routes {
+    Empty(Server<ServerLayers>),
+    Router(Router<ServerLayers>),
+}
+
+/// Adds services to a server, each wrapped with the layers of the configuration. The layers are
+/// shared by the services: so are the limits of requests in flight, and the counters of `metrics`.
+pub struct Registry<'a> {
+    config: &'a ServerConfig,
+    state: &'a ServerState,
+    metrics: RpcMetricsLayer,
+    readiness: ReadinessLayer,
+    in_flight: InFlightLimitLayer,
+    timeout: RpcTimeoutLayer,
+    validation: ValidationLayer,
+    compression: CompressionLayer,
+    routes: Routes,
+    /// Names of the services registered, for the health service.
+    served: Vec<&'static str>,
+}
+
+impl<'a> Registry<'a> {
+    pub fn new(
+        server: Server<ServerLayers>,
+        config: &'a ServerConfig,
+        state: &'a ServerState,
+        metrics: RpcMetricsLayer,
+    ) -> Self {
+        let transport = config.transport.clone().unwrap_or_default();
+
+        Self {
+            config,
+            state,
+            metrics,
+            readiness: ReadinessLayer::new(state),
+            in_flight: InFlightLimitLayer::new(&config.concurrency.clone().unwrap_or_default()),
+            timeout: RpcTimeoutLayer::new(&config.timeouts.clone().unwrap_or_default()),
+            validation: ValidationLayer::new(transport.max_message_bytes),
+            compression: CompressionLayer::new(&config.compression.clone().unwrap_or_default()),
+            routes: Routes::Empty(server),
+            served: Vec::new(),
+        }
+    }
+
+    pub fn register(self, kind: ServiceKind) -> Self {
+        let compressed = self.config.compression.is_some();
+        let auth = self.state.auth_interceptor();
+
+        match kind {
+            ServiceKind::SocialNetwork => {
+                let mut service = SocialNetworkServer::new(self.state.clone());
+                if compressed {
+                    service = service
+                        .accept_compressed(CompressionEncoding::Gzip)
+                        .send_compressed(CompressionEncoding::Gzip);
+                }
+
+                self.add(InterceptedService::new(service, auth))
+            }
+            ServiceKind::Notifier => {
+                let mut service = NotifierServer::new(self.state.clone());
+                if compressed {
+                    service = service
+                        .accept_compressed(CompressionEncoding::Gzip)
+                        .send_compressed(CompressionEncoding::Gzip);
+                }
+
+                self.add(InterceptedService::new(service, auth))
+            }
+        }
+    }
+
+    /// `None` when no service was registered. Probes are not authenticated.
+    pub fn into_router(self) -> Option<Router<ServerLayers>> {
+        if let Routes::Empty(_) = self.routes {
+            return None;
+        }
+
+        let health = HealthService::new(self.state.clone(), self.served.clone());
+        match self.add(HealthServer::new(health)).routes {
+            Routes::Empty(_) => None,
+            Routes::Router(router) => Some(router),
+        }
+    }
+
+    /// Wrapped rather than the server so that gRPC-web translates the responses of the layers.
+    fn add<S>(mut self, service: S) -> Self
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>
+            + NamedService
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.served.push(S::NAME);
+        let service = self.metrics.layer(
+            self.readiness.layer(
+                self.in_flight.layer(
+                    self.timeout
+                        .layer(self.validation.layer(self.compression.layer(service))),
+                ),
+            ),
+        );
+
+        self.routes = match (self.routes, &self.config.grpc_web) {
+            (Routes::Empty(mut server), Some(grpc_web)) => {
+                Routes::Router(server.add_service(grpc_web_config(grpc_web).enable(service)))
+            }
+            (Routes::Empty(mut server), None) => Routes::Router(server.add_service(service)),
+            (Routes::Router(router), Some(grpc_web)) => {
+                Routes::Router(router.add_service(grpc_web_config(grpc_web).enable(service)))
+            }
+            (Routes::Router(router), None) => Routes::Router(router.add_service(service)),
+        };
+
+        self
+    }
+}